@@ -3,11 +3,22 @@ use std::path::PathBuf;
 
 struct TreeSitterParser {
     name: &'static str,
+    /// The cargo feature that enables this parser.
+    feature: &'static str,
     src_dir: &'static str,
     extra_files: Vec<&'static str>,
 }
 
 impl TreeSitterParser {
+    fn is_enabled(&self) -> bool {
+        // Cargo sets CARGO_FEATURE_LANG_RUST for the lang-rust feature.
+        let var = format!(
+            "CARGO_FEATURE_{}",
+            self.feature.to_uppercase().replace('-', "_")
+        );
+        std::env::var_os(var).is_some()
+    }
+
     fn build(&self) {
         let dir = PathBuf::from(&self.src_dir);
 
@@ -59,191 +70,231 @@ fn main() {
     let parsers = vec![
         TreeSitterParser {
             name: "tree-sitter-bash",
+            feature: "lang-bash",
             src_dir: "vendor/tree-sitter-bash-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-c",
+            feature: "lang-c",
             src_dir: "vendor/tree-sitter-c-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-cpp",
+            feature: "lang-cpp",
             src_dir: "vendor/tree-sitter-cpp-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-c-sharp",
+            feature: "lang-c-sharp",
             src_dir: "vendor/tree-sitter-c-sharp-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-clojure",
+            feature: "lang-clojure",
             src_dir: "vendor/tree-sitter-clojure-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-commonlisp",
+            feature: "lang-commonlisp",
             src_dir: "vendor/tree-sitter-commonlisp-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-css",
+            feature: "lang-css",
             src_dir: "vendor/tree-sitter-css-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-dart",
+            feature: "lang-dart",
             src_dir: "vendor/tree-sitter-dart-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-elisp",
+            feature: "lang-elisp",
             src_dir: "vendor/tree-sitter-elisp-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-elixir",
+            feature: "lang-elixir",
             src_dir: "vendor/tree-sitter-elixir-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-elm",
+            feature: "lang-elm",
             src_dir: "vendor/tree-sitter-elm-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-elvish",
+            feature: "lang-elvish",
             src_dir: "vendor/tree-sitter-elvish-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-gleam",
+            feature: "lang-gleam",
             src_dir: "vendor/tree-sitter-gleam-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-go",
+            feature: "lang-go",
             src_dir: "vendor/tree-sitter-go-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-haskell",
+            feature: "lang-haskell",
             src_dir: "vendor/tree-sitter-haskell-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-hcl",
+            feature: "lang-hcl",
             src_dir: "vendor/tree-sitter-hcl-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-janet-simple",
+            feature: "lang-janet",
             src_dir: "vendor/tree-sitter-janet-simple-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-java",
+            feature: "lang-java",
             src_dir: "vendor/tree-sitter-java-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-javascript",
+            feature: "lang-javascript",
             src_dir: "vendor/tree-sitter-javascript-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-json",
+            feature: "lang-json",
             src_dir: "vendor/tree-sitter-json-src",
             extra_files: vec![],
         },
         TreeSitterParser {
             name: "tree-sitter-kotlin",
+            feature: "lang-kotlin",
             src_dir: "vendor/tree-sitter-kotlin-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-lua",
+            feature: "lang-lua",
             src_dir: "vendor/tree-sitter-lua-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-nix",
+            feature: "lang-nix",
             src_dir: "vendor/tree-sitter-nix-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-ocaml",
+            feature: "lang-ocaml",
             src_dir: "vendor/tree-sitter-ocaml-src/ocaml/src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-ocaml-interface",
+            feature: "lang-ocaml",
             src_dir: "vendor/tree-sitter-ocaml-src/interface/src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-php",
+            feature: "lang-php",
             src_dir: "vendor/tree-sitter-php-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-perl",
+            feature: "lang-perl",
             src_dir: "vendor/tree-sitter-perl-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-python",
+            feature: "lang-python",
             src_dir: "vendor/tree-sitter-python-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-ruby",
+            feature: "lang-ruby",
             src_dir: "vendor/tree-sitter-ruby-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-rust",
+            feature: "lang-rust",
             src_dir: "vendor/tree-sitter-rust-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-scala",
+            feature: "lang-scala",
             src_dir: "vendor/tree-sitter-scala-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-swift",
+            feature: "lang-swift",
             src_dir: "vendor/tree-sitter-swift-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-toml",
+            feature: "lang-toml",
             src_dir: "vendor/tree-sitter-toml-src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-tsx",
+            feature: "lang-typescript",
             src_dir: "vendor/tree-sitter-typescript-src/tsx/src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-typescript",
+            feature: "lang-typescript",
             src_dir: "vendor/tree-sitter-typescript-src/typescript/src",
             extra_files: vec!["scanner.c"],
         },
         TreeSitterParser {
             name: "tree-sitter-yaml",
+            feature: "lang-yaml",
             src_dir: "vendor/tree-sitter-yaml-src",
             extra_files: vec!["scanner.cc"],
         },
         TreeSitterParser {
             name: "tree-sitter-zig",
+            feature: "lang-zig",
             src_dir: "vendor/tree-sitter-zig-src",
             extra_files: vec![],
         },
     ];
 
+    // Only build the parsers for the languages that are enabled.
+    let parsers: Vec<_> = parsers.into_iter().filter(|p| p.is_enabled()).collect();
+
     // Only rerun if relevant files in the vendor/ directory change.
     println!("cargo:rerun-if-changed=vendor/highlights");
     for parser in &parsers {
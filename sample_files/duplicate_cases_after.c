@@ -0,0 +1,16 @@
+int classify(int c) {
+    switch (c) {
+    case 'a':
+        return VOWEL;
+    case 'b':
+        return CONSONANT;
+    case 'c':
+        return CONSONANT;
+    case 'd':
+        return CONSONANT;
+    case 'e':
+        return VOWEL;
+    default:
+        return OTHER;
+    }
+}
@@ -0,0 +1,13 @@
+CASES = [
+    ("empty", "", []),
+    ("single", "a", ["a"]),
+    ("pair", "a b", ["a", "b"]),
+    ("single", "a", ["a"]),
+    ("triple", "a b c", ["a", "b", "c"]),
+    ("pair", "a b", ["a", "b"]),
+]
+
+
+def test_split():
+    for name, src, expected in CASES:
+        assert split(src) == expected, name
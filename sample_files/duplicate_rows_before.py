@@ -0,0 +1,12 @@
+CASES = [
+    ("empty", "", []),
+    ("single", "a", ["a"]),
+    ("pair", "a b", ["a", "b"]),
+    ("single", "a", ["a"]),
+    ("pair", "a b", ["a", "b"]),
+]
+
+
+def test_split():
+    for name, src, expected in CASES:
+        assert split(src) == expected, name
@@ -0,0 +1,268 @@
+//! Extracting the public declarations of a file, so `--api-only` can
+//! diff just the signatures of functions and types.
+//!
+//! Each declaration is shown on a single line, up to the start of its
+//! body. Declarations inside classes and impl blocks are indented
+//! below their container.
+//!
+//! ```text
+//! pub struct Config { pub width: usize, }
+//! impl Config
+//!     pub fn new(width: usize) -> Self
+//! ```
+
+use tree_sitter as ts;
+
+use crate::parse::{
+    guess_language::Language,
+    tree_sitter_parser::{self as tsp, TreeSitterConfig},
+};
+
+/// Body nodes that contain the implementation of a declaration, rather
+/// than part of its API.
+const IMPLEMENTATION_BODIES: [&str; 3] = ["block", "statement_block", "constructor_body"];
+
+struct ApiConfig {
+    /// Nodes that declare something that may be part of the API.
+    declarations: &'static [&'static str],
+    /// Nodes whose body can contain declarations, such as classes and
+    /// impl blocks.
+    containers: &'static [&'static str],
+    /// Nodes whose children should be treated as if they were at the
+    /// same level, such as Python decorators.
+    transparent: &'static [&'static str],
+    /// Whether a declaration is visible outside its module.
+    is_public: fn(&ts::Node, &str) -> bool,
+}
+
+/// The name of the item declared by `node`. For declarations like Go's
+/// `type Foo struct {}`, the name is on the first child.
+fn declared_name<'a>(node: &ts::Node, src: &'a str) -> Option<&'a str> {
+    let name = node.child_by_field_name("name").or_else(|| {
+        node.named_child(0)
+            .and_then(|child| child.child_by_field_name("name"))
+    })?;
+    name.utf8_text(src.as_bytes()).ok()
+}
+
+fn has_child_kind(node: &ts::Node, kind: &str) -> bool {
+    node.named_children(&mut node.walk())
+        .any(|child| child.kind() == kind)
+}
+
+fn rust_is_public(node: &ts::Node, _src: &str) -> bool {
+    has_child_kind(node, "visibility_modifier")
+}
+
+fn go_is_public(node: &ts::Node, src: &str) -> bool {
+    declared_name(node, src).map_or(false, |name| {
+        name.chars().next().map_or(false, char::is_uppercase)
+    })
+}
+
+fn python_is_public(node: &ts::Node, src: &str) -> bool {
+    declared_name(node, src).map_or(false, |name| {
+        !name.starts_with('_') || (name.starts_with("__") && name.ends_with("__"))
+    })
+}
+
+fn js_is_public(node: &ts::Node, src: &str) -> bool {
+    let is_private = node.children(&mut node.walk()).any(|child| {
+        child.kind() == "accessibility_modifier"
+            && child.utf8_text(src.as_bytes()).ok() == Some("private")
+    });
+    let is_private_name = declared_name(node, src).map_or(false, |name| name.starts_with('#'));
+    !is_private && !is_private_name
+}
+
+fn java_is_public(node: &ts::Node, src: &str) -> bool {
+    node.named_children(&mut node.walk()).any(|child| {
+        child.kind() == "modifiers"
+            && child.utf8_text(src.as_bytes()).map_or(false, |modifiers| {
+                modifiers.contains("public") || modifiers.contains("protected")
+            })
+    })
+}
+
+fn api_config(language: Language) -> Option<ApiConfig> {
+    use Language::*;
+    let config = match language {
+        Rust => ApiConfig {
+            declarations: &[
+                "const_item",
+                "enum_item",
+                "function_item",
+                "function_signature_item",
+                "mod_item",
+                "static_item",
+                "struct_item",
+                "trait_item",
+                "type_item",
+                "union_item",
+            ],
+            containers: &["impl_item", "mod_item"],
+            transparent: &[],
+            is_public: rust_is_public,
+        },
+        Go => ApiConfig {
+            declarations: &[
+                "const_declaration",
+                "function_declaration",
+                "method_declaration",
+                "type_declaration",
+                "var_declaration",
+            ],
+            containers: &[],
+            transparent: &[],
+            is_public: go_is_public,
+        },
+        Python => ApiConfig {
+            declarations: &["class_definition", "function_definition"],
+            containers: &["class_definition"],
+            transparent: &["decorated_definition"],
+            is_public: python_is_public,
+        },
+        JavaScript | Jsx | TypeScript | Tsx => ApiConfig {
+            declarations: &[
+                "export_statement",
+                "method_definition",
+                "method_signature",
+                "public_field_definition",
+            ],
+            containers: &["export_statement"],
+            transparent: &[],
+            is_public: js_is_public,
+        },
+        Java => ApiConfig {
+            declarations: &[
+                "class_declaration",
+                "constructor_declaration",
+                "enum_declaration",
+                "field_declaration",
+                "interface_declaration",
+                "method_declaration",
+                "record_declaration",
+            ],
+            containers: &[
+                "class_declaration",
+                "enum_declaration",
+                "record_declaration",
+            ],
+            transparent: &[],
+            is_public: java_is_public,
+        },
+        _ => return None,
+    };
+    Some(config)
+}
+
+/// The body of a declaration. For a JavaScript `export` statement, this
+/// is the body of the exported declaration.
+fn body<'a>(node: &ts::Node<'a>) -> Option<ts::Node<'a>> {
+    node.child_by_field_name("body").or_else(|| {
+        node.child_by_field_name("declaration")
+            .and_then(|declaration| declaration.child_by_field_name("body"))
+    })
+}
+
+/// The text of `node` up to its body, on a single line.
+fn signature(node: &ts::Node, src: &str, is_container: bool) -> String {
+    let end = match body(node) {
+        Some(body) if is_container || IMPLEMENTATION_BODIES.contains(&body.kind()) => {
+            body.start_byte()
+        }
+        _ => node.end_byte(),
+    };
+    let text = &src[node.start_byte()..end];
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn collect_declarations(
+    node: &ts::Node,
+    src: &str,
+    config: &ApiConfig,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        let kind = child.kind();
+        if config.transparent.contains(&kind) {
+            collect_declarations(&child, src, config, depth, lines);
+            continue;
+        }
+
+        let is_declaration = config.declarations.contains(&kind) && (config.is_public)(&child, src);
+        let is_container = config.containers.contains(&kind);
+        if !is_declaration && !is_container {
+            continue;
+        }
+
+        let mut inner = vec![];
+        if is_container {
+            if let Some(body) = body(&child) {
+                collect_declarations(&body, src, config, depth + 1, &mut inner);
+            }
+        }
+
+        // Only show containers, such as impl blocks, if they contain
+        // something public.
+        if is_declaration || !inner.is_empty() {
+            lines.push(format!(
+                "{}{}",
+                "    ".repeat(depth),
+                signature(&child, src, is_container)
+            ));
+            lines.append(&mut inner);
+        }
+    }
+}
+
+/// The public declarations in `src`, one per line. Returns `None` if
+/// we don't know how to find declarations in `language`.
+pub fn declarations(language: Language, ts_config: &TreeSitterConfig, src: &str) -> Option<String> {
+    let config = api_config(language)?;
+    let tree = tsp::parse_to_tree(src, ts_config);
+
+    let mut lines = vec![];
+    collect_declarations(&tree.root_node(), src, &config, 0, &mut lines);
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_rust_declarations() {
+        let src = "pub fn foo(x: u32) -> u32 {\n    x + 1\n}\n\nfn private() {}\n\nstruct Private;\n\nimpl Foo {\n    pub fn bar(&self) {}\n    fn baz(&self) {}\n}\n";
+        let config = tsp::from_language(Language::Rust).unwrap();
+        assert_eq!(
+            declarations(Language::Rust, &config, src),
+            Some("pub fn foo(x: u32) -> u32\nimpl Foo\n    pub fn bar(&self)".into())
+        );
+    }
+
+    #[cfg(feature = "lang-python")]
+    #[test]
+    fn test_python_declarations() {
+        let src = "def _helper():\n    pass\n\nclass Foo:\n    def __init__(self):\n        pass\n\n    @property\n    def size(self):\n        return 1\n";
+        let config = tsp::from_language(Language::Python).unwrap();
+        assert_eq!(
+            declarations(Language::Python, &config, src),
+            Some("class Foo:\n    def __init__(self):\n    def size(self):".into())
+        );
+    }
+
+    #[cfg(feature = "lang-css")]
+    #[test]
+    fn test_unsupported_language() {
+        let config = tsp::from_language(Language::Css).unwrap();
+        assert_eq!(
+            declarations(Language::Css, &config, "a { color: red; }"),
+            None
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Checking that a file matches an expected file, for
+//! `--assert-equal`.
+//!
+//! This is intended for test suites that compare output against a
+//! golden file. Unlike `diff -u`, reformatting isn't a failure, and
+//! tokens that legitimately vary between runs, such as timestamps, can
+//! be ignored with `--ignore-token-regex`.
+
+use regex::Regex;
+
+use crate::{parse::syntax::MatchedPos, summary::DiffResult, summary::FileContent};
+
+/// Compile a `--ignore-token-regex`, which must match a whole token
+/// rather than part of one.
+pub fn token_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Are all the novel tokens in `positions` matched by an `ignored`
+/// regex?
+fn only_ignored_changes(src: &str, positions: &[MatchedPos], ignored: &[Regex]) -> bool {
+    let lines: Vec<&str> = src.lines().collect();
+    positions.iter().filter(|mp| mp.kind.is_novel()).all(|mp| {
+        let text = lines
+            .get(mp.pos.line.0)
+            .and_then(|line| line.get(mp.pos.start_col..mp.pos.end_col))
+            .unwrap_or_default();
+        ignored.iter().any(|re| re.is_match(text))
+    })
+}
+
+/// Is `diff_result` free of changes, other than novel tokens that
+/// entirely match one of the `ignored` regexes?
+pub fn is_equal(diff_result: &DiffResult, ignored: &[Regex]) -> bool {
+    if diff_result.status.is_some() {
+        return false;
+    }
+
+    match (&diff_result.lhs_src, &diff_result.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            only_ignored_changes(lhs_src, &diff_result.lhs_positions, ignored)
+                && only_ignored_changes(rhs_src, &diff_result.rhs_positions, ignored)
+        }
+        (lhs_src, rhs_src) => lhs_src == rhs_src,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse::syntax::{AtomKind, MatchKind, TokenKind},
+        positions::SingleLineSpan,
+    };
+
+    fn novel(line: usize, start_col: usize, end_col: usize) -> MatchedPos {
+        MatchedPos {
+            kind: MatchKind::Novel {
+                highlight: TokenKind::Atom(AtomKind::Normal),
+            },
+            pos: SingleLineSpan {
+                line: line.into(),
+                start_col,
+                end_col,
+            },
+        }
+    }
+
+    #[test]
+    fn test_token_regex() {
+        let re = token_regex(r"a|ab").unwrap();
+        assert!(re.is_match("ab"));
+        assert!(!re.is_match("abc"));
+    }
+
+    #[test]
+    fn test_only_ignored_changes() {
+        let src = "{\"id\": 1,\n \"time\": \"2022-06-01\"}";
+        let ignored = vec![token_regex(r#""\d{4}-\d{2}-\d{2}""#).unwrap()];
+
+        assert!(only_ignored_changes(src, &[novel(1, 9, 21)], &ignored));
+        assert!(!only_ignored_changes(src, &[novel(0, 7, 8)], &ignored));
+        assert!(only_ignored_changes(src, &[], &[]));
+    }
+}
@@ -0,0 +1,175 @@
+//! Timing difftastic on a corpus of files, so users can measure
+//! performance on their own code.
+//!
+//! The corpus is a directory containing pairs of files named
+//! `NAME_before.EXT` and `NAME_after.EXT`, like `sample_files/`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+use walkdir::WalkDir;
+
+use crate::{
+    buffer::Buffer,
+    diff_file_content,
+    files::{display_path, read_or_die},
+    options::{DiffOptions, DEFAULT_TAB_WIDTH},
+    parse::guess_language::Language,
+};
+
+/// If `path` is `NAME_before.EXT`, return `NAME_after.EXT`.
+fn after_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let (stem, extension) = match file_name.find('.') {
+        Some(i) => file_name.split_at(i),
+        None => (file_name, ""),
+    };
+
+    let name = stem.strip_suffix("_before")?;
+    Some(path.with_file_name(format!("{}_after{}", name, extension)))
+}
+
+/// Find all the before/after pairs in `dir`, sorted by path.
+fn find_pairs(dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut pairs: Vec<_> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let before = entry.into_path();
+            let after = after_path(&before)?;
+            if after.is_file() {
+                Some((before, after))
+            } else {
+                None
+            }
+        })
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+/// The `p`th percentile of `sorted`, using the nearest-rank method.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+}
+
+/// The peak memory usage of this process so far, from Linux's
+/// `/proc`. This covers the whole process, so it can't be split by
+/// language.
+fn peak_memory() -> Option<String> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .map(|value| value.trim().to_string())
+}
+
+/// Parse and diff every pair of files in `dir`, then print timings
+/// for each language.
+pub fn run(dir: &Path, language_override: Option<Language>) {
+    let pairs = find_pairs(dir);
+    if pairs.is_empty() {
+        eprintln!(
+            "No files named NAME_before.EXT with a matching NAME_after.EXT in {}",
+            display_path(dir)
+        );
+        std::process::exit(1);
+    }
+
+    let mut timings: FxHashMap<String, Vec<Duration>> = FxHashMap::default();
+    for (before, after) in &pairs {
+        let lhs_bytes = read_or_die(before);
+        let rhs_bytes = read_or_die(after);
+        let lhs_display_path = display_path(before);
+        let rhs_display_path = display_path(after);
+
+        let start = Instant::now();
+        let result = diff_file_content(
+            &Buffer {
+                display_path: &lhs_display_path,
+                path: None,
+                language_hint: language_override,
+                content: &lhs_bytes,
+            },
+            &Buffer {
+                display_path: &rhs_display_path,
+                path: None,
+                language_hint: language_override,
+                content: &rhs_bytes,
+            },
+            DEFAULT_TAB_WIDTH,
+            &DiffOptions::default(),
+        );
+        let elapsed = start.elapsed();
+
+        let language = result.language.unwrap_or_else(|| "Text".into());
+        timings.entry(language).or_default().push(elapsed);
+    }
+
+    // Print languages by name rather than in hash order.
+    let mut languages: Vec<_> = timings.into_iter().collect();
+    languages.sort_by(|(x, _), (y, _)| x.cmp(y));
+
+    println!(
+        "{:<40} {:>6} {:>10} {:>10} {:>10} {:>10}",
+        "Language", "Pairs", "p50", "p90", "p99", "max"
+    );
+    for (language, mut durations) in languages {
+        durations.sort();
+        println!(
+            "{:<40} {:>6} {:>10} {:>10} {:>10} {:>10}",
+            language,
+            durations.len(),
+            format_ms(percentile(&durations, 50)),
+            format_ms(percentile(&durations, 90)),
+            format_ms(percentile(&durations, 99)),
+            format_ms(*durations.last().unwrap()),
+        );
+    }
+
+    match peak_memory() {
+        Some(memory) => println!("\nPeak memory for the whole run: {}", memory),
+        None => println!("\nPeak memory is only reported on Linux."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_after_path() {
+        assert_eq!(
+            after_path(Path::new("dir/foo_before.rs")),
+            Some(PathBuf::from("dir/foo_after.rs"))
+        );
+    }
+
+    #[test]
+    fn test_after_path_not_before() {
+        assert_eq!(after_path(Path::new("dir/foo_after.rs")), None);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let durations: Vec<_> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&durations, 50), Duration::from_millis(5));
+        assert_eq!(percentile(&durations, 90), Duration::from_millis(9));
+        assert_eq!(percentile(&durations, 99), Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,51 @@
+//! The content of each side of a diff, with the path and language
+//! to use for it.
+//!
+//! This is the input to [`crate::diff_file_content`]. Content doesn't
+//! need to be on disk, so editors can diff unsaved buffers.
+
+use std::path::Path;
+
+use crate::{files::is_dev_null, options::DiffOptions, parse::guess_language::Language};
+
+/// Content to diff, which may not have been saved to disk (e.g. an
+/// editor buffer).
+pub struct Buffer<'a> {
+    /// The path shown in headers. This is also used for language
+    /// detection.
+    pub display_path: &'a str,
+    /// The file that this content came from, if any. We use the
+    /// `.editorconfig` for this path.
+    pub path: Option<&'a Path>,
+    /// The language to use, rather than guessing. If the two sides
+    /// disagree, we use the RHS.
+    pub language_hint: Option<Language>,
+    pub content: &'a [u8],
+}
+
+/// The buffer for a file that we've read from `path`.
+pub fn file_buffer<'a>(
+    display_path: &'a str,
+    path: &'a Path,
+    content: &'a [u8],
+    in_vcs: bool,
+    diff_options: &DiffOptions,
+) -> Buffer<'a> {
+    // Under a VCS the paths are temporary files, so find
+    // .editorconfig from the display path instead: git runs external
+    // diff tools from the top of the work tree, and passes paths
+    // relative to it.
+    let settings_path = if is_dev_null(path) {
+        None
+    } else if in_vcs {
+        Some(Path::new(display_path))
+    } else {
+        Some(path)
+    };
+    Buffer {
+        display_path,
+        path: settings_path,
+        language_hint: diff_options.language_override,
+        content,
+    }
+}
@@ -0,0 +1,515 @@
+//! Comparing two saved `--display json` outputs, to check whether a
+//! change is the same after being rebased or cherry-picked.
+//!
+//! We compare the shape of each file's changes: its status, language,
+//! the size of every hunk and moved block, and the novel tokens in
+//! each hunk. Line numbers are ignored, as they usually shift when a
+//! change is rebased, so tokens are compared by their line within the
+//! hunk, their columns and their text.
+
+use std::{fmt, iter::Peekable, path::Path, str::Chars};
+
+use crate::files::{display_path, read_or_die};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// A minimal JSON parser, sufficient for reading our own output.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Self {
+            chars: s.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(c) if c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("expected '{}' but found end of line", expected)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("expected '{}'", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut res = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(res),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => res.push('\n'),
+                    Some('r') => res.push('\r'),
+                    Some('t') => res.push('\t'),
+                    Some('u') => {
+                        let hex: String = self.chars.by_ref().take(4).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape '\\u{}'", hex))?;
+                        res.push(c);
+                    }
+                    Some(c) => res.push(c),
+                    None => return Err("unterminated string".into()),
+                },
+                Some(c) => res.push(c),
+                None => return Err("unterminated string".into()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                s.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        s.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number '{}'", s))
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => {
+                self.chars.next();
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&'}') {
+                    self.chars.next();
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Json::Object(fields)),
+                        _ => return Err("expected ',' or '}'".into()),
+                    }
+                    self.skip_whitespace();
+                }
+            }
+            Some('[') => {
+                self.chars.next();
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&']') {
+                    self.chars.next();
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Json::Array(items)),
+                        _ => return Err("expected ',' or ']'".into()),
+                    }
+                }
+            }
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some(_) => self.number(),
+            None => Err("unexpected end of line".into()),
+        }
+    }
+}
+
+fn parse_json(s: &str) -> Result<Json, String> {
+    let mut parser = Parser::new(s);
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected '{}' after value", c)),
+    }
+}
+
+/// The number of lines in a `[first, last]` line range, or `None`
+/// for `null`.
+fn range_size(range: Option<&Json>) -> Option<usize> {
+    match range?.as_array() {
+        [Json::Number(first), Json::Number(last)] => Some((last - first) as usize + 1),
+        _ => None,
+    }
+}
+
+/// The first line of a `[first, last]` line range.
+fn range_first(range: Option<&Json>) -> Option<usize> {
+    match range?.as_array() {
+        [Json::Number(first), Json::Number(_)] => Some(*first as usize),
+        _ => None,
+    }
+}
+
+/// A `[line, column]` position.
+fn position(point: Option<&Json>) -> Option<(usize, usize)> {
+    match point?.as_array() {
+        [Json::Number(line), Json::Number(col)] => Some((*line as usize, *col as usize)),
+        _ => None,
+    }
+}
+
+/// The novel tokens in `changes`, with their lines relative to
+/// `first_line`.
+fn hunk_changes(changes: Option<&Json>, first_line: Option<usize>) -> Vec<Change> {
+    changes
+        .map_or(&[][..], Json::as_array)
+        .iter()
+        .filter_map(|change| {
+            let (line, start_col) = position(change.get("start"))?;
+            let (_, end_col) = position(change.get("end"))?;
+            Some((
+                line.saturating_sub(first_line.unwrap_or(0)),
+                start_col,
+                end_col,
+                change.get("text").and_then(Json::as_str)?.to_owned(),
+            ))
+        })
+        .collect()
+}
+
+fn span_size(span: Option<&Json>) -> Option<usize> {
+    let line = |point: Option<&Json>| match point?.as_array().first() {
+        Some(Json::Number(line)) => Some(*line as usize),
+        _ => None,
+    };
+    let span = span?;
+    Some(line(span.get("end"))?.saturating_sub(line(span.get("start"))?) + 1)
+}
+
+/// A novel token: its line relative to the start of the hunk, its
+/// start and end columns, and its text.
+type Change = (usize, usize, usize, String);
+
+/// A hunk, without line numbers.
+#[derive(Debug, PartialEq)]
+struct HunkShape {
+    /// The number of novel lines on each side.
+    sizes: (Option<usize>, Option<usize>),
+    /// The novel tokens on each side. These are empty for output
+    /// from versions that didn't include them.
+    changes: (Vec<Change>, Vec<Change>),
+}
+
+/// The changes to a single file, without line numbers.
+#[derive(Debug, PartialEq)]
+struct FileShape {
+    path: String,
+    status: String,
+    language: Option<String>,
+    hunks: Vec<HunkShape>,
+    /// The kind of each move, and the number of lines it spans on
+    /// each side.
+    moves: Vec<(String, Option<usize>, Option<usize>)>,
+}
+
+impl FileShape {
+    fn from_json(json: &Json) -> Result<Self, String> {
+        let path = json
+            .get("rhs_path")
+            .and_then(Json::as_str)
+            .ok_or("missing \"rhs_path\"")?;
+        Ok(Self {
+            path: path.to_owned(),
+            status: json
+                .get("status")
+                .and_then(Json::as_str)
+                .unwrap_or("changed")
+                .to_owned(),
+            language: json
+                .get("language")
+                .and_then(Json::as_str)
+                .map(|s| s.to_owned()),
+            hunks: json
+                .get("hunks")
+                .map_or(&[][..], Json::as_array)
+                .iter()
+                .map(|hunk| {
+                    let (lhs_lines, rhs_lines) = (hunk.get("lhs_lines"), hunk.get("rhs_lines"));
+                    HunkShape {
+                        sizes: (range_size(lhs_lines), range_size(rhs_lines)),
+                        changes: (
+                            hunk_changes(hunk.get("lhs_changes"), range_first(lhs_lines)),
+                            hunk_changes(hunk.get("rhs_changes"), range_first(rhs_lines)),
+                        ),
+                    }
+                })
+                .collect(),
+            moves: json
+                .get("moves")
+                .map_or(&[][..], Json::as_array)
+                .iter()
+                .map(|m| {
+                    (
+                        m.get("kind")
+                            .and_then(Json::as_str)
+                            .unwrap_or("")
+                            .to_owned(),
+                        span_size(m.get("lhs")),
+                        span_size(m.get("rhs")),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+/// How the changes to a file differ between the two outputs.
+#[derive(Debug, PartialEq, Eq)]
+enum Difference {
+    OnlyInOld,
+    OnlyInNew,
+    Status(String, String),
+    Language,
+    HunkCount(usize, usize),
+    HunkSize(usize),
+    HunkChanges(usize),
+    Moves,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::OnlyInOld => write!(f, "only changed in the old diff"),
+            Difference::OnlyInNew => write!(f, "only changed in the new diff"),
+            Difference::Status(old, new) => write!(f, "was {}, now {}", old, new),
+            Difference::Language => write!(f, "language differs"),
+            Difference::HunkCount(old, new) => write!(f, "had {} hunks, now {}", old, new),
+            Difference::HunkSize(i) => write!(f, "hunk {} has a different size", i + 1),
+            Difference::HunkChanges(i) => write!(f, "hunk {} changes different code", i + 1),
+            Difference::Moves => write!(f, "moved or copied code differs"),
+        }
+    }
+}
+
+fn compare_shapes(old: &FileShape, new: &FileShape) -> Option<Difference> {
+    if old.status != new.status {
+        return Some(Difference::Status(old.status.clone(), new.status.clone()));
+    }
+    if old.language != new.language {
+        return Some(Difference::Language);
+    }
+    if old.hunks.len() != new.hunks.len() {
+        return Some(Difference::HunkCount(old.hunks.len(), new.hunks.len()));
+    }
+    for (i, (old_hunk, new_hunk)) in old.hunks.iter().zip(&new.hunks).enumerate() {
+        if old_hunk.sizes != new_hunk.sizes {
+            return Some(Difference::HunkSize(i));
+        }
+        if old_hunk.changes != new_hunk.changes {
+            return Some(Difference::HunkChanges(i));
+        }
+    }
+    if old.moves != new.moves {
+        return Some(Difference::Moves);
+    }
+    None
+}
+
+fn read_shapes(path: &Path) -> Result<Vec<FileShape>, String> {
+    let bytes = read_or_die(path);
+    let src = String::from_utf8_lossy(&bytes);
+    src.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_json(line)
+                .and_then(|json| FileShape::from_json(&json))
+                .map_err(|e| format!("{}:{}: {}", display_path(path), i + 1, e))
+        })
+        .collect()
+}
+
+/// Each file in either output, with how its changes differ. Files are
+/// matched by their RHS path.
+fn compare(old: &[FileShape], new: &[FileShape]) -> Vec<(String, Option<Difference>)> {
+    let mut res = vec![];
+    for old_shape in old {
+        let difference = match new.iter().find(|n| n.path == old_shape.path) {
+            Some(new_shape) => compare_shapes(old_shape, new_shape),
+            None => Some(Difference::OnlyInOld),
+        };
+        res.push((old_shape.path.clone(), difference));
+    }
+    for new_shape in new {
+        if !old.iter().any(|o| o.path == new_shape.path) {
+            res.push((new_shape.path.clone(), Some(Difference::OnlyInNew)));
+        }
+    }
+    res
+}
+
+/// Compare the JSON diffs at `old_path` and `new_path`, printing a
+/// line for every file. Returns true if the diffs are equivalent.
+pub fn run(old_path: &Path, new_path: &Path) -> bool {
+    let (old, new) = match (read_shapes(old_path), read_shapes(new_path)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("error: {}", e);
+            return false;
+        }
+    };
+
+    let mut num_different = 0;
+    for (path, difference) in compare(&old, &new) {
+        match difference {
+            Some(difference) => {
+                num_different += 1;
+                println!("{}: {}", path, difference);
+            }
+            None => println!("{}: same changes", path),
+        }
+    }
+
+    if num_different == 0 {
+        println!("The diffs are equivalent.");
+    } else {
+        println!("{} file(s) have different changes.", num_different);
+    }
+    num_different == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn shape(line: &str) -> FileShape {
+        FileShape::from_json(&parse_json(line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse_json(r#"{"a":[1,null],"b":"x\"y","c":true}"#),
+            Ok(Json::Object(vec![
+                ("a".into(), Json::Array(vec![Json::Number(1.0), Json::Null])),
+                ("b".into(), Json::String("x\"y".into())),
+                ("c".into(), Json::Bool(true)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_json_trailing_content() {
+        assert!(parse_json("{} x").is_err());
+    }
+
+    #[test]
+    fn test_shifted_lines_are_equivalent() {
+        let old = shape(
+            r#"{"lhs_path":"a.rs","rhs_path":"a.rs","language":"Rust","status":"changed","hunks":[{"lhs_lines":[3,5],"rhs_lines":[3,6]}],"moves":[]}"#,
+        );
+        let new = shape(
+            r#"{"lhs_path":"a.rs","rhs_path":"a.rs","language":"Rust","status":"changed","hunks":[{"lhs_lines":[13,15],"rhs_lines":[23,26]}],"moves":[]}"#,
+        );
+        assert_eq!(compare_shapes(&old, &new), None);
+    }
+
+    #[test]
+    fn test_different_hunk_size() {
+        let old = shape(r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,6]}]}"#);
+        let new = shape(r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,7]}]}"#);
+        assert_eq!(compare_shapes(&old, &new), Some(Difference::HunkSize(0)));
+    }
+
+    #[test]
+    fn test_shifted_changes_are_equivalent() {
+        let old = shape(
+            r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,3],"lhs_changes":[],"rhs_changes":[{"start":[3,4],"end":[3,7],"text":"foo"}]}]}"#,
+        );
+        let new = shape(
+            r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[8,8],"lhs_changes":[],"rhs_changes":[{"start":[8,4],"end":[8,7],"text":"foo"}]}]}"#,
+        );
+        assert_eq!(compare_shapes(&old, &new), None);
+    }
+
+    #[test]
+    fn test_same_size_changes_at_different_offsets() {
+        let old = shape(
+            r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,3],"lhs_changes":[],"rhs_changes":[{"start":[3,4],"end":[3,7],"text":"foo"}]}]}"#,
+        );
+        let new = shape(
+            r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,3],"lhs_changes":[],"rhs_changes":[{"start":[3,8],"end":[3,11],"text":"foo"}]}]}"#,
+        );
+        assert_eq!(compare_shapes(&old, &new), Some(Difference::HunkChanges(0)));
+
+        let new = shape(
+            r#"{"rhs_path":"a.rs","hunks":[{"lhs_lines":null,"rhs_lines":[3,3],"lhs_changes":[],"rhs_changes":[{"start":[3,4],"end":[3,7],"text":"bar"}]}]}"#,
+        );
+        assert_eq!(compare_shapes(&old, &new), Some(Difference::HunkChanges(0)));
+    }
+
+    #[test]
+    fn test_compare_missing_files() {
+        let old = vec![shape(r#"{"rhs_path":"a.rs"}"#)];
+        let new = vec![shape(r#"{"rhs_path":"b.rs"}"#)];
+        assert_eq!(
+            compare(&old, &new),
+            vec![
+                ("a.rs".into(), Some(Difference::OnlyInOld)),
+                ("b.rs".into(), Some(Difference::OnlyInNew)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Pairing files across differently-rooted trees, for `difft
+//! compare-trees`.
+//!
+//! The mapping file is a TOML file with a `[[tree]]` table for each
+//! pair of directories or files:
+//!
+//! ```toml
+//! [[tree]]
+//! lhs = "vendor/foo"
+//! rhs = "../foo"
+//!
+//! # Headers moved when we vendored foo.
+//! [[tree]]
+//! lhs = "vendor/foo/include"
+//! rhs = "../foo/src/include"
+//! ```
+//!
+//! A mapping inside another mapping's directory takes precedence, so
+//! files below `vendor/foo/include` are only compared with files
+//! below `../foo/src/include`. The file uses the same TOML subset as
+//! `difft verify` manifests, and relative paths are relative to the
+//! directory containing it.
+
+use std::{
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    files::{dir_file_pairs, display_path, read_or_die, FilePair},
+    profiles::Profile,
+    verify::{is_line_end, parse_string},
+};
+
+/// A pair of directories or files to compare.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMapping {
+    pub lhs_path: PathBuf,
+    pub rhs_path: PathBuf,
+}
+
+/// `path` without `.` components, so paths can be compared with
+/// `Path::starts_with` however they were written.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// Paths to leave out of the comparison, from `--ignore-path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnorePath {
+    lhs_path: PathBuf,
+    rhs_path: PathBuf,
+}
+
+impl IgnorePath {
+    /// Parse `LHS=RHS`, which ignores LHS in old trees and RHS in new
+    /// trees, or a single path, which is ignored in both. Paths that
+    /// aren't valid UTF-8 can only be given as a single path.
+    pub fn parse(arg: &OsStr) -> Self {
+        match arg.to_str().and_then(|arg| arg.split_once('=')) {
+            Some((lhs, rhs)) => IgnorePath {
+                lhs_path: normalize(Path::new(lhs)),
+                rhs_path: normalize(Path::new(rhs)),
+            },
+            None => IgnorePath {
+                lhs_path: normalize(Path::new(arg)),
+                rhs_path: normalize(Path::new(arg)),
+            },
+        }
+    }
+
+    fn matches(&self, pair: &FilePair) -> bool {
+        pair.lhs_path.starts_with(&self.lhs_path) || pair.rhs_path.starts_with(&self.rhs_path)
+    }
+}
+
+/// A `[[tree]]` table that we're still reading.
+#[derive(Default)]
+struct PartialMapping {
+    line_num: usize,
+    lhs_path: Option<PathBuf>,
+    rhs_path: Option<PathBuf>,
+}
+
+impl PartialMapping {
+    fn finish(self) -> Result<TreeMapping, String> {
+        let line_num = self.line_num;
+        let missing = |key: &str| format!("line {}: [[tree]] has no {}", line_num, key);
+        Ok(TreeMapping {
+            lhs_path: self.lhs_path.ok_or_else(|| missing("lhs"))?,
+            rhs_path: self.rhs_path.ok_or_else(|| missing("rhs"))?,
+        })
+    }
+}
+
+/// Parse the mappings in a mapping file. Relative paths are joined to
+/// `base_dir`.
+fn parse_mappings(src: &str, base_dir: &Path) -> Result<Vec<TreeMapping>, String> {
+    let mut mappings = vec![];
+    let mut current: Option<PartialMapping> = None;
+
+    for (i, line) in src.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.strip_prefix("[[tree]]").map_or(false, is_line_end) {
+            if let Some(mapping) = current.take() {
+                mappings.push(mapping.finish()?);
+            }
+            current = Some(PartialMapping {
+                line_num,
+                ..PartialMapping::default()
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected [[tree]] or KEY = VALUE", line_num))?;
+        let mapping = current
+            .as_mut()
+            .ok_or_else(|| format!("line {}: {} is outside a [[tree]]", line_num, key.trim()))?;
+        let value = parse_string(value.trim()).map_err(|e| format!("line {}: {}", line_num, e))?;
+        let path = normalize(&base_dir.join(value));
+
+        match key.trim() {
+            "lhs" => mapping.lhs_path = Some(path),
+            "rhs" => mapping.rhs_path = Some(path),
+            key => return Err(format!("line {}: unknown key '{}'", line_num, key)),
+        }
+    }
+
+    if let Some(mapping) = current {
+        mappings.push(mapping.finish()?);
+    }
+    Ok(mappings)
+}
+
+/// Read the mappings in the file at `path`, exiting if it isn't valid
+/// or maps a path that doesn't exist.
+pub fn read_mappings_or_die(path: &Path) -> Vec<TreeMapping> {
+    let src = String::from_utf8_lossy(&read_or_die(path)).into_owned();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mappings = match parse_mappings(&src, base_dir) {
+        Ok(mappings) => mappings,
+        Err(e) => {
+            eprintln!("error: {}: {}", display_path(path), e);
+            std::process::exit(2);
+        }
+    };
+
+    for mapping in &mappings {
+        for path in [&mapping.lhs_path, &mapping.rhs_path] {
+            if !path.exists() {
+                eprintln!("error: {}: no such file or directory", display_path(path));
+                std::process::exit(2);
+            }
+        }
+        if mapping.lhs_path.is_dir() != mapping.rhs_path.is_dir() {
+            eprintln!(
+                "error: {} and {} must both be directories or both be files.",
+                display_path(&mapping.lhs_path),
+                display_path(&mapping.rhs_path)
+            );
+            std::process::exit(2);
+        }
+    }
+    mappings
+}
+
+/// Is `pair`, found under `mapping`, inside a more specific mapping?
+/// If so, that mapping compares it instead.
+fn is_remapped(pair: &FilePair, mapping: &TreeMapping, mappings: &[TreeMapping]) -> bool {
+    let nested = |path: &Path, root: &Path, other_root: &Path| {
+        other_root != root && other_root.starts_with(root) && path.starts_with(other_root)
+    };
+    mappings.iter().any(|other| {
+        nested(&pair.lhs_path, &mapping.lhs_path, &other.lhs_path)
+            || nested(&pair.rhs_path, &mapping.rhs_path, &other.rhs_path)
+    })
+}
+
+/// Every pair of files to compare, in the order of `mappings`.
+///
+/// Pairs of directories are compared file by file, using their
+/// `.difftignore`, and files under `ignore_paths` are dropped.
+pub fn file_pairs(mappings: &[TreeMapping], ignore_paths: &[IgnorePath]) -> Vec<FilePair> {
+    let mut res = vec![];
+    for mapping in mappings {
+        let pairs = if mapping.lhs_path.is_dir() {
+            dir_file_pairs(&mapping.lhs_path, &mapping.rhs_path)
+        } else {
+            vec![FilePair {
+                lhs_path: mapping.lhs_path.clone(),
+                rhs_path: mapping.rhs_path.clone(),
+                profile: Profile::default(),
+                in_dir_pair: false,
+            }]
+        };
+        res.extend(pairs.into_iter().filter(|pair| {
+            !is_remapped(pair, mapping, mappings)
+                && !ignore_paths.iter().any(|ignore| ignore.matches(pair))
+        }));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_mappings() {
+        let src = r#"
+# Vendored from upstream.
+[[tree]]
+lhs = "./vendor/foo"
+rhs = '../foo' # Literal string.
+"#;
+        assert_eq!(
+            parse_mappings(src, Path::new("sync")),
+            Ok(vec![TreeMapping {
+                lhs_path: PathBuf::from("sync/vendor/foo"),
+                rhs_path: PathBuf::from("sync/../foo"),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_mappings_missing_key() {
+        let src = "[[tree]]\nlhs = \"a\"\n";
+        assert_eq!(
+            parse_mappings(src, Path::new("")),
+            Err("line 1: [[tree]] has no rhs".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_ignore_path() {
+        assert_eq!(
+            IgnorePath::parse(OsStr::new("./vendor/foo=upstream/foo")),
+            IgnorePath {
+                lhs_path: PathBuf::from("vendor/foo"),
+                rhs_path: PathBuf::from("upstream/foo"),
+            }
+        );
+        assert_eq!(
+            IgnorePath::parse(OsStr::new("docs")),
+            IgnorePath {
+                lhs_path: PathBuf::from("docs"),
+                rhs_path: PathBuf::from("docs"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_file_pairs() {
+        let dir = std::env::temp_dir().join(format!("difft-test-trees-{}", std::process::id()));
+        for name in [
+            "vendor/foo/a.c",
+            "vendor/foo/include/a.h",
+            "vendor/foo/docs/README",
+            "upstream/a.c",
+            "upstream/b.c",
+            "upstream/src/include/a.h",
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let mappings = vec![
+            TreeMapping {
+                lhs_path: dir.join("vendor/foo"),
+                rhs_path: dir.join("upstream"),
+            },
+            TreeMapping {
+                lhs_path: dir.join("vendor/foo/include"),
+                rhs_path: dir.join("upstream/src/include"),
+            },
+        ];
+        let ignore_paths = vec![IgnorePath::parse(dir.join("vendor/foo/docs").as_os_str())];
+        let pairs = file_pairs(&mappings, &ignore_paths);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let paths: Vec<_> = pairs
+            .iter()
+            .map(|pair| (pair.lhs_path.clone(), pair.rhs_path.clone()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (dir.join("vendor/foo/a.c"), dir.join("upstream/a.c")),
+                (dir.join("vendor/foo/b.c"), dir.join("upstream/b.c")),
+                (
+                    dir.join("vendor/foo/include/a.h"),
+                    dir.join("upstream/src/include/a.h")
+                ),
+            ]
+        );
+        assert!(pairs.iter().all(|pair| pair.in_dir_pair));
+    }
+}
@@ -0,0 +1,549 @@
+//! Generating shell completions and a man page from the clap
+//! definitions in `options.rs`, for packagers.
+//!
+//! We only need a small subset of what clap supports (long flags,
+//! possible values and paths), so we generate these ourselves
+//! rather than depending on clap_complete and clap_mangen.
+
+use clap::{Arg, Command};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Fish,
+    Zsh,
+}
+
+/// The shells we can generate completions for, as passed to
+/// `difft completions`.
+pub const SHELL_NAMES: &[&str] = &["bash", "fish", "zsh"];
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "fish" => Some(Shell::Fish),
+            "zsh" => Some(Shell::Zsh),
+            _ => None,
+        }
+    }
+}
+
+/// A command line option, as shown in completions and the man page.
+struct Flag {
+    long: &'static str,
+    /// The first line of the help, or the whole help for the man
+    /// page.
+    help: String,
+    /// The name of the value, if this option takes one.
+    value_name: Option<&'static str>,
+    possible_values: Vec<&'static str>,
+    env: Option<String>,
+    heading: Option<&'static str>,
+    /// The subcommand that takes this option, if it isn't a top-level
+    /// option.
+    subcommand: Option<String>,
+}
+
+fn flag(arg: &Arg<'static>, long_help: bool) -> Option<Flag> {
+    if arg.is_hide_set() {
+        return None;
+    }
+    let long = arg.get_long()?;
+
+    let help = if long_help {
+        arg.get_long_help().or_else(|| arg.get_help())
+    } else {
+        arg.get_help().or_else(|| arg.get_long_help())
+    };
+    let help = help.unwrap_or_default();
+    let help = if long_help {
+        help.to_owned()
+    } else {
+        help.lines().next().unwrap_or_default().to_owned()
+    };
+
+    let possible_values: Vec<&'static str> = arg
+        .get_possible_values()
+        .map(|values| {
+            values
+                .iter()
+                .filter(|v| !v.is_hide_set())
+                .map(|v| v.get_name())
+                .collect()
+        })
+        .unwrap_or_default();
+    let takes_value = arg.is_takes_value_set() || !possible_values.is_empty();
+    let value_name = if takes_value {
+        Some(
+            arg.get_value_names()
+                .and_then(|names| names.first().copied())
+                .unwrap_or("VALUE"),
+        )
+    } else {
+        None
+    };
+
+    Some(Flag {
+        long,
+        help,
+        value_name,
+        possible_values,
+        env: arg.get_env().map(|e| e.to_string_lossy().into_owned()),
+        heading: arg.get_help_heading(),
+        subcommand: None,
+    })
+}
+
+/// The options of `cmd`'s subcommands, and of their subcommands,
+/// marked with the subcommand that takes them.
+fn subcommand_flags(cmd: &Command<'static>, long_help: bool, flags: &mut Vec<Flag>) {
+    for subcommand in cmd.get_subcommands() {
+        for arg in subcommand.get_arguments() {
+            if let Some(mut flag) = flag(arg, long_help) {
+                flag.subcommand = Some(subcommand.get_name().to_owned());
+                flags.push(flag);
+            }
+        }
+        subcommand_flags(subcommand, long_help, flags);
+    }
+}
+
+/// Every option of `cmd`, followed by the options of its
+/// subcommands.
+fn flags(cmd: &Command<'static>, long_help: bool) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = cmd
+        .get_arguments()
+        .filter_map(|arg| flag(arg, long_help))
+        .collect();
+
+    // clap only adds these when parsing.
+    for (long, help) in [
+        ("help", "Print help information."),
+        ("version", "Print version information."),
+    ] {
+        if !flags.iter().any(|f| f.long == long) {
+            flags.push(Flag {
+                long,
+                help: help.into(),
+                value_name: None,
+                possible_values: vec![],
+                env: None,
+                heading: None,
+                subcommand: None,
+            });
+        }
+    }
+
+    subcommand_flags(cmd, long_help, &mut flags);
+    flags
+}
+
+/// A subcommand, as shown in completions and the man page.
+struct Subcommand {
+    name: String,
+    /// The first line of the description, or the whole description
+    /// for the man page.
+    about: String,
+}
+
+fn subcommands(cmd: &Command<'static>, long_help: bool) -> Vec<Subcommand> {
+    cmd.get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| {
+            let about = subcommand.get_about().unwrap_or_default();
+            Subcommand {
+                name: subcommand.get_name().to_owned(),
+                about: if long_help {
+                    about.to_owned()
+                } else {
+                    about.lines().next().unwrap_or_default().to_owned()
+                },
+            }
+        })
+        .collect()
+}
+
+/// `flags` with only the first option of each name, for shells that
+/// complete the same options wherever they appear.
+fn unique_flags(flags: &[Flag]) -> Vec<&Flag> {
+    let mut res: Vec<&Flag> = vec![];
+    for flag in flags {
+        if !res.iter().any(|f| f.long == flag.long) {
+            res.push(flag);
+        }
+    }
+    res
+}
+
+fn bash(bin_name: &str, flags: &[Flag], subcommands: &[Subcommand]) -> String {
+    let function_name = format!("_{}", bin_name.replace('-', "_"));
+    let mut res = format!(
+        "{}() {{\n    local cur prev\n    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\n    case \"$prev\" in\n",
+        function_name
+    );
+
+    let flags = unique_flags(flags);
+    for flag in &flags {
+        if flag.value_name.is_none() {
+            continue;
+        }
+        let action = if flag.possible_values.is_empty() {
+            "compgen -f -- \"$cur\"".to_owned()
+        } else {
+            format!(
+                "compgen -W \"{}\" -- \"$cur\"",
+                flag.possible_values.join(" ")
+            )
+        };
+        res.push_str(&format!(
+            "        --{})\n            COMPREPLY=($({}))\n            return 0\n            ;;\n",
+            flag.long, action
+        ));
+    }
+
+    let all_flags: Vec<String> = flags.iter().map(|f| format!("--{}", f.long)).collect();
+    let names: Vec<&str> = subcommands.iter().map(|s| s.name.as_str()).collect();
+    res.push_str(&format!(
+        "    esac\n\n    if [[ \"$cur\" == -* ]]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n    else\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -f -- \"$cur\"))\n    fi\n}}\n\ncomplete -o filenames -F {} {}\n",
+        all_flags.join(" "),
+        names.join(" "),
+        function_name,
+        bin_name
+    ));
+    res
+}
+
+/// Escape `s` for use inside a single-quoted zsh `_arguments` spec.
+fn zsh_escape(s: &str) -> String {
+    s.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(bin_name: &str, flags: &[Flag], subcommands: &[Subcommand]) -> String {
+    // The first argument is either a subcommand or the old path.
+    let first_arg_function = format!("_{}_first_arg", bin_name.replace('-', "_"));
+    let names: Vec<&str> = subcommands.iter().map(|s| s.name.as_str()).collect();
+    let mut res = format!(
+        "#compdef {}\n\n{}() {{\n    _alternative 'subcommands:subcommand:({})' 'files:file:_files'\n}}\n\n_arguments -s \\\n",
+        bin_name,
+        first_arg_function,
+        names.join(" ")
+    );
+    for flag in unique_flags(flags) {
+        let help = zsh_escape(&flag.help);
+        match flag.value_name {
+            Some(value_name) => {
+                let action = if flag.possible_values.is_empty() {
+                    "_files".to_owned()
+                } else {
+                    format!("({})", flag.possible_values.join(" "))
+                };
+                res.push_str(&format!(
+                    "    '--{}=[{}]:{}:{}' \\\n",
+                    flag.long,
+                    help,
+                    zsh_escape(value_name),
+                    action
+                ));
+            }
+            None => {
+                res.push_str(&format!("    '--{}[{}]' \\\n", flag.long, help));
+            }
+        }
+    }
+    res.push_str(&format!("    '1: :{}' \\\n", first_arg_function));
+    res.push_str("    '*:file:_files'\n");
+    res
+}
+
+/// Escape `s` for use inside a single-quoted fish string.
+fn fish_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(bin_name: &str, flags: &[Flag], subcommands: &[Subcommand]) -> String {
+    let mut res = String::new();
+    for subcommand in subcommands {
+        res.push_str(&format!(
+            "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'\n",
+            bin_name,
+            subcommand.name,
+            fish_escape(&subcommand.about)
+        ));
+    }
+    for flag in flags {
+        res.push_str(&format!("complete -c {}", bin_name));
+        if let Some(subcommand) = &flag.subcommand {
+            res.push_str(&format!(" -n '__fish_seen_subcommand_from {}'", subcommand));
+        }
+        res.push_str(&format!(
+            " -l {} -d '{}'",
+            flag.long,
+            fish_escape(&flag.help)
+        ));
+        if flag.value_name.is_some() {
+            res.push_str(" -r");
+            if !flag.possible_values.is_empty() {
+                res.push_str(&format!(" -f -a '{}'", flag.possible_values.join(" ")));
+            }
+        }
+        res.push('\n');
+    }
+    res
+}
+
+/// Shell completions for `cmd`, installed as `bin_name`.
+pub fn completions(cmd: &Command<'static>, bin_name: &str, shell: Shell) -> String {
+    let flags = flags(cmd, false);
+    let subcommands = subcommands(cmd, false);
+    match shell {
+        Shell::Bash => bash(bin_name, &flags, &subcommands),
+        Shell::Fish => fish(bin_name, &flags, &subcommands),
+        Shell::Zsh => zsh(bin_name, &flags, &subcommands),
+    }
+}
+
+/// Escape `s` for roff, so it isn't interpreted as a request.
+fn roff_escape(s: &str) -> String {
+    let escaped = s.replace('\\', "\\e").replace('-', "\\-");
+    escaped
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Describe `flags` as roff paragraphs.
+fn man_flags(flags: &[&Flag]) -> String {
+    let mut res = String::new();
+    for flag in flags {
+        res.push_str(".TP\n");
+        res.push_str(&format!("\\fB\\-\\-{}\\fR", roff_escape(flag.long)));
+        if let Some(value_name) = flag.value_name {
+            res.push_str(&format!(" \\fI{}\\fR", roff_escape(value_name)));
+        }
+        res.push('\n');
+
+        // Blank lines in help text become paragraph breaks.
+        let help = roff_escape(&flag.help).replace("\n\n", "\n.sp\n");
+        res.push_str(&help);
+        res.push('\n');
+
+        if !flag.possible_values.is_empty() {
+            res.push_str(&format!(
+                ".sp\n[possible values: {}]\n",
+                roff_escape(&flag.possible_values.join(", "))
+            ));
+        }
+        if let Some(env) = &flag.env {
+            res.push_str(&format!(".sp\n[env: {}]\n", roff_escape(env)));
+        }
+    }
+    res
+}
+
+/// A man page for `cmd`, installed as `bin_name`.
+pub fn man_page(cmd: &Command<'static>, bin_name: &str) -> String {
+    let flags = flags(cmd, true);
+    let subcommands = subcommands(cmd, true);
+    let mut res = format!(
+        ".TH {} 1 \"\" \"{} {}\" \"User Commands\"\n",
+        roff_escape(&bin_name.to_uppercase()),
+        roff_escape(bin_name),
+        roff_escape(cmd.get_version().unwrap_or_default())
+    );
+
+    res.push_str(".SH NAME\n");
+    res.push_str(&format!(
+        "{} \\- {}\n",
+        roff_escape(bin_name),
+        roff_escape(cmd.get_about().unwrap_or_default())
+    ));
+
+    res.push_str(".SH SYNOPSIS\n");
+    res.push_str(&format!(
+        ".B {}\n[\\fIOPTIONS\\fR] \\fIOLD\\-PATH\\fR \\fINEW\\-PATH\\fR\n",
+        roff_escape(bin_name)
+    ));
+    if !subcommands.is_empty() {
+        res.push_str(&format!(
+            ".br\n.B {}\n[\\fIOPTIONS\\fR] \\fICOMMAND\\fR [\\fIARGS\\fR]\n",
+            roff_escape(bin_name)
+        ));
+    }
+
+    res.push_str(".SH DESCRIPTION\n");
+    res.push_str(&format!(
+        "{} compares files or directories, using the syntax of each file to show which parts changed.\n",
+        roff_escape(bin_name)
+    ));
+
+    let top_level: Vec<&Flag> = flags.iter().filter(|f| f.subcommand.is_none()).collect();
+    let options: Vec<&Flag> = top_level
+        .iter()
+        .copied()
+        .filter(|f| f.heading.is_none())
+        .collect();
+    res.push_str(".SH OPTIONS\n");
+    res.push_str(&man_flags(&options));
+
+    let mut headings: Vec<&str> = vec![];
+    for heading in top_level.iter().filter_map(|f| f.heading) {
+        if !headings.contains(&heading) {
+            headings.push(heading);
+        }
+    }
+    for heading in headings {
+        let heading_flags: Vec<&Flag> = top_level
+            .iter()
+            .copied()
+            .filter(|f| f.heading == Some(heading))
+            .collect();
+        res.push_str(&format!(".SH {}\n", roff_escape(heading)));
+        res.push_str(&man_flags(&heading_flags));
+    }
+
+    if !subcommands.is_empty() {
+        res.push_str(".SH COMMANDS\n");
+    }
+    for subcommand in &subcommands {
+        res.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            roff_escape(&subcommand.name),
+            roff_escape(&subcommand.about)
+        ));
+        let subcommand_flags: Vec<&Flag> = flags
+            .iter()
+            .filter(|f| f.subcommand.as_ref() == Some(&subcommand.name))
+            .collect();
+        if !subcommand_flags.is_empty() {
+            res.push_str(".RS\n");
+            res.push_str(&man_flags(&subcommand_flags));
+            res.push_str(".RE\n");
+        }
+    }
+
+    res.push_str(".SH SEE ALSO\n");
+    res.push_str("The full manual: https://difftastic.wilfred.me.uk/\n");
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn test_cmd() -> Command<'static> {
+        Command::new("test")
+            .version("1.0")
+            .about("A test command")
+            .arg(
+                Arg::new("display")
+                    .long("display")
+                    .possible_values(["inline", "json"])
+                    .help("Display mode."),
+            )
+            .arg(
+                Arg::new("no-pager")
+                    .long("no-pager")
+                    .help("Don't use a pager: [ever]."),
+            )
+            .arg(Arg::new("paths").multiple_values(true).hide(true))
+    }
+
+    #[test]
+    fn test_fish() {
+        assert_eq!(
+            completions(&test_cmd(), "difft", Shell::Fish),
+            concat!(
+                "complete -c difft -l display -d 'Display mode.' -r -f -a 'inline json'\n",
+                "complete -c difft -l no-pager -d 'Don\\'t use a pager: [ever].'\n",
+                "complete -c difft -l help -d 'Print help information.'\n",
+                "complete -c difft -l version -d 'Print version information.'\n",
+            )
+        );
+    }
+
+    fn test_cmd_with_subcommand() -> Command<'static> {
+        test_cmd().subcommand(
+            Command::new("compare-trees")
+                .about("Compare trees.")
+                .arg(
+                    Arg::new("ignore-path")
+                        .long("ignore-path")
+                        .takes_value(true)
+                        .help("Don't compare PATH."),
+                )
+                .arg(Arg::new("mapping").required(true)),
+        )
+    }
+
+    #[test]
+    fn test_flags_include_subcommands() {
+        let flags = flags(&test_cmd_with_subcommand(), false);
+        let ignore_path = flags.iter().find(|f| f.long == "ignore-path").unwrap();
+        assert_eq!(ignore_path.subcommand.as_deref(), Some("compare-trees"));
+        assert_eq!(ignore_path.value_name, Some("VALUE"));
+    }
+
+    #[test]
+    fn test_fish_subcommands() {
+        let fish = completions(&test_cmd_with_subcommand(), "difft", Shell::Fish);
+        assert!(fish.contains(
+            "complete -c difft -n '__fish_use_subcommand' -a compare-trees -d 'Compare trees.'\n"
+        ));
+        assert!(fish.contains(
+            "complete -c difft -n '__fish_seen_subcommand_from compare-trees' -l ignore-path -d 'Don\\'t compare PATH.' -r\n"
+        ));
+    }
+
+    #[test]
+    fn test_bash_subcommands() {
+        let bash = completions(&test_cmd_with_subcommand(), "difft", Shell::Bash);
+        assert!(bash.contains("        --ignore-path)\n"));
+        assert!(bash.contains(
+            "COMPREPLY=($(compgen -W \"compare-trees\" -- \"$cur\") $(compgen -f -- \"$cur\"))"
+        ));
+    }
+
+    #[test]
+    fn test_zsh_escapes_help() {
+        let zsh = completions(&test_cmd(), "difft", Shell::Zsh);
+        assert!(zsh.contains("'--no-pager[Don'\\''t use a pager\\: \\[ever\\].]' \\\n"));
+        assert!(zsh.contains("'--display=[Display mode.]:VALUE:(inline json)' \\\n"));
+    }
+
+    #[test]
+    fn test_roff_escape() {
+        assert_eq!(roff_escape("--foo\n.bar"), "\\-\\-foo\n\\&.bar");
+    }
+
+    #[test]
+    fn test_man_page() {
+        let man = man_page(&test_cmd(), "difft");
+        assert!(man.starts_with(".TH DIFFT 1 \"\" \"difft 1.0\" \"User Commands\"\n"));
+        assert!(man.contains(".TP\n\\fB\\-\\-display\\fR \\fIVALUE\\fR\nDisplay mode.\n"));
+    }
+
+    #[test]
+    fn test_man_page_subcommands() {
+        let man = man_page(&test_cmd_with_subcommand(), "difft");
+        assert!(man.contains(concat!(
+            ".SH COMMANDS\n",
+            ".TP\n\\fBcompare\\-trees\\fR\nCompare trees.\n",
+            ".RS\n.TP\n\\fB\\-\\-ignore\\-path\\fR \\fIVALUE\\fR\nDon't compare PATH.\n.RE\n"
+        )));
+        // Subcommand options aren't top-level options.
+        let options = &man[man.find(".SH OPTIONS").unwrap()..man.find(".SH COMMANDS").unwrap()];
+        assert!(!options.contains("ignore"));
+    }
+}
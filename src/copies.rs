@@ -0,0 +1,394 @@
+//! Detecting new files that are copies of existing files, similar to
+//! `git diff -C`.
+//!
+//! Rather than comparing lines, we compare the top-level syntax
+//! nodes of each file, so reformatting a copy doesn't stop us
+//! finding its original.
+//!
+//! Parsing every existing file would be slow in large trees, so we
+//! only parse files whose language and size could make them the
+//! source of an added file. Identical files don't need parsing at
+//! all.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    panic,
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use typed_arena::Arena;
+
+use crate::{
+    files::{decode, is_probably_binary, FileTree},
+    parse::{
+        guess_language::{guess, Language},
+        syntax::Syntax,
+        tree_sitter_parser as tsp,
+    },
+};
+
+/// The proportion of top-level items that a new file must share with
+/// an existing file to be considered a copy.
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// A copy shares at least `MIN_SIMILARITY` of its top-level items
+/// with its source, so neither file can be much larger than the
+/// other. This is deliberately loose, as items vary in size.
+const MAX_SIZE_RATIO: u64 = 4;
+
+/// What we know about a file before parsing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Summary {
+    len: u64,
+    content_hash: u64,
+    language: Option<Language>,
+}
+
+/// Could a file summarised by `candidate` be the source of one
+/// summarised by `added`?
+fn is_plausible_source(candidate: &Summary, added: &Summary) -> bool {
+    candidate.language == added.language
+        && candidate.len <= added.len * MAX_SIZE_RATIO
+        && added.len <= candidate.len * MAX_SIZE_RATIO
+}
+
+/// A summary of the content of a file, ignoring whitespace and
+/// positions.
+#[derive(Debug, PartialEq, Eq)]
+struct Fingerprint {
+    language: Option<Language>,
+    /// The hashes of each top-level item, sorted.
+    hashes: Vec<u64>,
+}
+
+fn hash_node(node: &Syntax, hasher: &mut DefaultHasher) {
+    match node {
+        Syntax::List {
+            open_content,
+            children,
+            close_content,
+            ..
+        } => {
+            open_content.hash(hasher);
+            children.len().hash(hasher);
+            for child in children {
+                hash_node(child, hasher);
+            }
+            close_content.hash(hasher);
+        }
+        Syntax::Atom { content, .. } => {
+            content.hash(hasher);
+        }
+    }
+}
+
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Find the top-level items in `nodes`. If the file is a single list
+/// (e.g. a JSON object), use the items inside it.
+fn top_level_items<'a, 'b>(mut nodes: &'b [&'a Syntax<'a>]) -> &'b [&'a Syntax<'a>] {
+    while let [Syntax::List { children, .. }] = nodes {
+        nodes = &children[..];
+    }
+    nodes
+}
+
+fn fingerprint(path: &Path, src: &str, language_override: Option<Language>) -> Fingerprint {
+    let language = language_override.or_else(|| guess(path, src));
+
+    let mut hashes: Vec<u64> = match language.and_then(tsp::from_language) {
+        Some(config) => {
+            let arena = Arena::new();
+            let nodes = tsp::parse(&arena, src, &config);
+            top_level_items(&nodes)
+                .iter()
+                .map(|node| {
+                    let mut hasher = DefaultHasher::new();
+                    hash_node(node, &mut hasher);
+                    hasher.finish()
+                })
+                .collect()
+        }
+        None => src
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(hash_str)
+            .collect(),
+    };
+    hashes.sort_unstable();
+
+    Fingerprint { language, hashes }
+}
+
+/// The proportion of items shared by two fingerprints, from 0.0 to
+/// 1.0.
+fn similarity(lhs: &Fingerprint, rhs: &Fingerprint) -> f64 {
+    if lhs.language != rhs.language || lhs.hashes.is_empty() || rhs.hashes.is_empty() {
+        return 0.0;
+    }
+
+    // Both lists are sorted, so count the common items with a merge.
+    let mut common = 0;
+    let mut i = 0;
+    let mut j = 0;
+    while i < lhs.hashes.len() && j < rhs.hashes.len() {
+        match lhs.hashes[i].cmp(&rhs.hashes[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    common as f64 / lhs.hashes.len().max(rhs.hashes.len()) as f64
+}
+
+/// Summarise the file at `rel_path` in `tree`. Binary and blank
+/// files can't be copies, so we return `None` for them.
+fn read_summary(
+    tree: &FileTree,
+    rel_path: &Path,
+    language_override: Option<Language>,
+) -> Option<Summary> {
+    let bytes = tree.read(rel_path).ok()?;
+    if is_probably_binary(&bytes, None) {
+        return None;
+    }
+
+    let (src, _) = decode(&bytes, None);
+    if src.trim().is_empty() {
+        return None;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Some(Summary {
+        len: bytes.len() as u64,
+        content_hash: hasher.finish(),
+        language: language_override.or_else(|| guess(rel_path, &src)),
+    })
+}
+
+fn read_fingerprint(
+    tree: &FileTree,
+    rel_path: &Path,
+    language_override: Option<Language>,
+) -> Option<Fingerprint> {
+    let bytes = tree.read(rel_path).ok()?;
+    if is_probably_binary(&bytes, None) {
+        return None;
+    }
+
+    let (src, _) = decode(&bytes, None);
+    // A parser bug on one file shouldn't stop us diffing the others.
+    panic::catch_unwind(|| fingerprint(rel_path, &src, language_override)).ok()
+}
+
+/// The candidate in `candidates` that is most similar to `added`, if
+/// any are similar enough.
+fn best_match<'a>(
+    added: &Fingerprint,
+    candidates: impl Iterator<Item = (&'a PathBuf, &'a Fingerprint)>,
+) -> Option<&'a PathBuf> {
+    candidates
+        .map(|(path, candidate)| (path, similarity(candidate, added)))
+        .filter(|(_, score)| *score >= MIN_SIMILARITY)
+        .max_by(|(_, x), (_, y)| x.partial_cmp(y).unwrap())
+        .map(|(path, _)| path)
+}
+
+/// For each path in `rel_paths` that only exists in `rhs_tree`, find
+/// the file in `lhs_tree` that it was copied from, if any.
+pub fn find_copy_sources(
+    lhs_tree: &FileTree,
+    rhs_tree: &FileTree,
+    rel_paths: &[PathBuf],
+    language_override: Option<Language>,
+) -> FxHashMap<PathBuf, PathBuf> {
+    let added: Vec<&PathBuf> = rel_paths
+        .iter()
+        .filter(|rel_path| !lhs_tree.contains(rel_path))
+        .collect();
+    if added.is_empty() {
+        return FxHashMap::default();
+    }
+
+    let candidates: Vec<(PathBuf, Summary)> = rel_paths
+        .par_iter()
+        .filter_map(|rel_path| {
+            read_summary(lhs_tree, rel_path, language_override)
+                .map(|summary| (rel_path.clone(), summary))
+        })
+        .collect();
+
+    let mut res = FxHashMap::default();
+    // The candidates that each remaining added file could have been
+    // copied from.
+    let mut plausible: Vec<(&PathBuf, Vec<usize>)> = vec![];
+    for rel_path in added {
+        let summary = match read_summary(rhs_tree, rel_path, language_override) {
+            Some(summary) => summary,
+            None => continue,
+        };
+
+        let indexes: Vec<usize> = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, candidate))| is_plausible_source(candidate, &summary))
+            .map(|(i, _)| i)
+            .collect();
+        match indexes
+            .iter()
+            .find(|i| candidates[**i].1.content_hash == summary.content_hash)
+        {
+            Some(i) => {
+                res.insert(rel_path.clone(), candidates[*i].0.clone());
+            }
+            None if !indexes.is_empty() => plausible.push((rel_path, indexes)),
+            None => {}
+        }
+    }
+
+    // Parse each candidate at most once, and only if it might be a
+    // source.
+    let mut needed: Vec<usize> = plausible
+        .iter()
+        .flat_map(|(_, indexes)| indexes.iter().copied())
+        .collect();
+    needed.sort_unstable();
+    needed.dedup();
+    let fingerprints: FxHashMap<usize, Fingerprint> = needed
+        .par_iter()
+        .filter_map(|i| {
+            read_fingerprint(lhs_tree, &candidates[*i].0, language_override).map(|fp| (*i, fp))
+        })
+        .collect();
+
+    res.par_extend(plausible.par_iter().filter_map(|(rel_path, indexes)| {
+        let added_fp = read_fingerprint(rhs_tree, rel_path, language_override)?;
+        let sources = indexes
+            .iter()
+            .filter_map(|i| Some((&candidates[*i].0, fingerprints.get(i)?)));
+        best_match(&added_fp, sources).map(|source| ((*rel_path).clone(), source.clone()))
+    }));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn text_fingerprint(src: &str) -> Fingerprint {
+        fingerprint(Path::new("foo.txt"), src, None)
+    }
+
+    #[test]
+    fn test_text_fingerprint_ignores_whitespace() {
+        assert_eq!(
+            text_fingerprint("foo\n\nbar\n"),
+            text_fingerprint("  bar\nfoo  \n")
+        );
+    }
+
+    #[test]
+    fn test_similarity() {
+        let lhs = text_fingerprint("a\nb\nc\nd\n");
+        let rhs = text_fingerprint("a\nb\nc\ne\n");
+        assert_eq!(similarity(&lhs, &rhs), 0.75);
+    }
+
+    #[test]
+    fn test_best_match() {
+        let added = text_fingerprint("a\nb\nc\n");
+        let candidates = vec![
+            (PathBuf::from("x.txt"), text_fingerprint("x\ny\nz\n")),
+            (PathBuf::from("y.txt"), text_fingerprint("a\nb\nc\nd\n")),
+        ];
+        assert_eq!(
+            best_match(&added, candidates.iter().map(|(p, fp)| (p, fp))),
+            Some(&PathBuf::from("y.txt"))
+        );
+    }
+
+    #[test]
+    fn test_best_match_dissimilar() {
+        let added = text_fingerprint("a\nb\nc\n");
+        let candidates = vec![(PathBuf::from("x.txt"), text_fingerprint("a\ny\nz\n"))];
+        assert_eq!(
+            best_match(&added, candidates.iter().map(|(p, fp)| (p, fp))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_plausible_source() {
+        let summary = |len, language| Summary {
+            len,
+            content_hash: 0,
+            language,
+        };
+        let added = summary(100, Some(Language::Rust));
+
+        assert!(is_plausible_source(
+            &summary(300, Some(Language::Rust)),
+            &added
+        ));
+        assert!(!is_plausible_source(
+            &summary(500, Some(Language::Rust)),
+            &added
+        ));
+        assert!(!is_plausible_source(
+            &summary(20, Some(Language::Rust)),
+            &added
+        ));
+        assert!(!is_plausible_source(&summary(100, None), &added));
+    }
+
+    #[test]
+    fn test_find_copy_sources() {
+        let dir = std::env::temp_dir().join(format!("difft-test-copies-{}", std::process::id()));
+        let (lhs_dir, rhs_dir) = (dir.join("old"), dir.join("new"));
+        fs::create_dir_all(&lhs_dir).unwrap();
+        fs::create_dir_all(&rhs_dir).unwrap();
+
+        let original = "a\nb\nc\nd\n";
+        let big = "x\n".repeat(100);
+        for (name, src) in [("orig.txt", original), ("big.txt", big.as_str())] {
+            fs::write(lhs_dir.join(name), src).unwrap();
+            fs::write(rhs_dir.join(name), src).unwrap();
+        }
+        fs::write(rhs_dir.join("same.txt"), original).unwrap();
+        fs::write(rhs_dir.join("edited.txt"), "a\nb\nc\ne\n").unwrap();
+        fs::write(rhs_dir.join("new.txt"), "p\nq\n").unwrap();
+
+        let rel_paths: Vec<PathBuf> = ["big.txt", "edited.txt", "new.txt", "orig.txt", "same.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let sources = find_copy_sources(
+            &FileTree::Dir(lhs_dir),
+            &FileTree::Dir(rhs_dir),
+            &rel_paths,
+            None,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[Path::new("same.txt")], PathBuf::from("orig.txt"));
+        assert_eq!(sources[Path::new("edited.txt")], PathBuf::from("orig.txt"));
+    }
+}
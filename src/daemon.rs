@@ -0,0 +1,488 @@
+//! A long-running difftastic process that other invocations can hand
+//! their work to, so they don't pay for startup and compiling
+//! highlight queries every time.
+//!
+//! `difft --daemon SOCKET` listens on a unix socket. When
+//! `DFT_DAEMON_SOCKET` is set, difft connects to it and sends its
+//! arguments, working directory, environment and standard file
+//! descriptors. The daemon forks a worker that runs the diff on
+//! those file descriptors, then sends back the worker's exit status.
+//!
+//! Only supported on Unix.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Read, Write},
+    mem,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::{
+    options,
+    parse::{guess_language::LANGUAGE_EXTENSIONS, tree_sitter_parser as tsp},
+    warnings,
+};
+
+/// The environment variable naming the socket of a running daemon.
+const SOCKET_VAR: &str = "DFT_DAEMON_SOCKET";
+
+/// What a client asks the daemon to run.
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    cwd: PathBuf,
+    args: Vec<OsString>,
+    vars: Vec<(OsString, OsString)>,
+}
+
+/// Serialize `request` as NUL-terminated records: the working
+/// directory, the number of arguments, the arguments, then each
+/// environment variable as `KEY=VALUE`.
+fn encode_request(request: &Request) -> Vec<u8> {
+    let mut records: Vec<Vec<u8>> = vec![
+        request.cwd.as_os_str().as_bytes().to_vec(),
+        request.args.len().to_string().into_bytes(),
+    ];
+    records.extend(request.args.iter().map(|arg| arg.as_bytes().to_vec()));
+    records.extend(request.vars.iter().map(|(key, value)| {
+        let mut record = key.as_bytes().to_vec();
+        record.push(b'=');
+        record.extend_from_slice(value.as_bytes());
+        record
+    }));
+
+    let mut bytes = vec![];
+    for record in records {
+        bytes.extend(record);
+        bytes.push(b'\0');
+    }
+    bytes
+}
+
+fn decode_request(bytes: &[u8]) -> Option<Request> {
+    let mut records = bytes
+        .strip_suffix(b"\0")?
+        .split(|b| *b == b'\0')
+        .map(|record| OsString::from_vec(record.to_vec()));
+
+    let cwd = PathBuf::from(records.next()?);
+    let num_args: usize = records.next()?.to_str()?.parse().ok()?;
+    let args = records.by_ref().take(num_args).collect::<Vec<_>>();
+    if args.len() != num_args {
+        return None;
+    }
+
+    let vars = records
+        .map(|record| {
+            let bytes = record.into_vec();
+            let eq_idx = bytes.iter().position(|b| *b == b'=')?;
+            Some((
+                OsString::from_vec(bytes[..eq_idx].to_vec()),
+                OsString::from_vec(bytes[eq_idx + 1..].to_vec()),
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Request { cwd, args, vars })
+}
+
+/// Send `fds` over `stream`, along with a single placeholder byte.
+fn send_fds(stream: &UnixStream, fds: &[RawFd]) -> io::Result<()> {
+    let byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let fds_len = (fds.len() * mem::size_of::<RawFd>()) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len) } as usize];
+
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+
+        if libc::sendmsg(stream.as_raw_fd(), &msg, 0) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Receive `num_fds` file descriptors sent with `send_fds`.
+fn recv_fds(stream: &UnixStream, num_fds: usize) -> io::Result<Vec<RawFd>> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let fds_len = (num_fds * mem::size_of::<RawFd>()) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len) } as usize];
+
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        match libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {}
+        }
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+            || (*cmsg).cmsg_len as usize != libc::CMSG_LEN(fds_len) as usize
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected file descriptors",
+            ));
+        }
+
+        let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+        Ok((0..num_fds)
+            .map(|i| ptr::read_unaligned(data.add(i)))
+            .collect())
+    }
+}
+
+/// Convert a status from `waitpid` to an exit code, using the shell
+/// convention of 128 plus the signal number for killed processes.
+fn exit_code(status: libc::c_int) -> i32 {
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+/// Run `request` in this process, on the client's file descriptors.
+/// Never returns.
+fn run_worker(request: Request, fds: &[RawFd]) -> ! {
+    unsafe {
+        for (target, fd) in fds.iter().enumerate() {
+            libc::dup2(*fd, target as RawFd);
+            libc::close(*fd);
+        }
+    }
+
+    if env::set_current_dir(&request.cwd).is_err() {
+        eprintln!(
+            "error: Could not change to directory {}",
+            request.cwd.display()
+        );
+        std::process::exit(1);
+    }
+    for (key, _) in env::vars_os() {
+        env::remove_var(key);
+    }
+    for (key, value) in request.vars {
+        env::set_var(key, value);
+    }
+
+    crate::run(options::parse_args(request.args));
+    // Exit rather than returning to the handler, flushing stdout.
+    std::process::exit(0);
+}
+
+/// The user ID of the process on the other end of `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// The user ID of the process on the other end of `stream`.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// We can't check who connected on this platform, so refuse every
+/// connection.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn peer_uid(_stream: &UnixStream) -> io::Result<libc::uid_t> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "can't check the user of a connection on this platform",
+    ))
+}
+
+/// Handle a single client connection. This runs in its own process,
+/// and forks a worker so it can report the worker's exit status.
+fn handle(mut stream: UnixStream) -> io::Result<()> {
+    // Workers run as us, so only serve our own user, even if the
+    // socket's permissions have been changed.
+    if peer_uid(&stream)? != unsafe { libc::geteuid() } {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "connection from another user",
+        ));
+    }
+
+    let fds = recv_fds(&stream, 3)?;
+    let mut bytes = vec![];
+    stream.read_to_end(&mut bytes)?;
+    let request = decode_request(&bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed request"))?;
+
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        drop(stream);
+        run_worker(request, &fds);
+    }
+
+    for fd in fds {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+    let mut status = 0;
+    if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    stream.write_all(&exit_code(status).to_be_bytes())
+}
+
+/// Listen on `socket_path`, which only our own user can connect to.
+///
+/// Clients can read any file we can. The socket accepts connections
+/// as soon as it's bound, so we create it with the right permissions
+/// rather than changing them afterwards.
+fn bind_private(socket_path: &Path) -> io::Result<UnixListener> {
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket_path);
+    unsafe {
+        libc::umask(old_umask);
+    }
+    listener
+}
+
+/// Listen on `socket_path` and diff on behalf of clients, forever.
+pub fn serve(socket_path: &Path) {
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).is_ok() {
+            eprintln!(
+                "error: A daemon is already listening on {}",
+                socket_path.display()
+            );
+            std::process::exit(1);
+        }
+        // The socket was left behind by a daemon that's no longer
+        // running.
+        let _ = fs::remove_file(socket_path);
+    }
+
+    let listener = match bind_private(socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!(
+                "error: Could not listen on {} ({})",
+                socket_path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    // Build every parser configuration up front, so workers inherit
+    // them rather than compiling their own.
+    for (language, _) in LANGUAGE_EXTENSIONS {
+        tsp::cached_from_language(*language);
+    }
+    info!("Listening on {}", socket_path.display());
+
+    // We don't need the exit status of connection handlers, so let
+    // the kernel reap them.
+    unsafe {
+        libc::signal(libc::SIGCHLD, libc::SIG_IGN);
+    }
+
+    // Stay single-threaded, as forking a multi-threaded process can
+    // leave locks held in the child.
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warnings::warn(format!("Could not accept connection: {}", e));
+                continue;
+            }
+        };
+
+        match unsafe { libc::fork() } {
+            -1 => warnings::warn(format!("Could not fork: {}", io::Error::last_os_error())),
+            0 => {
+                drop(listener);
+                unsafe {
+                    libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+                }
+                if let Err(e) = handle(stream) {
+                    warnings::warn(format!("Could not handle connection: {}", e));
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// If a daemon is listening on `DFT_DAEMON_SOCKET`, have it run this
+/// invocation and return its exit code. Returns `None` if we should
+/// diff in this process instead.
+pub fn forward(args: &[OsString]) -> Option<i32> {
+    let socket_path = env::var_os(SOCKET_VAR)?;
+    let is_daemon = args
+        .iter()
+        .any(|arg| arg == "--daemon" || arg.as_bytes().starts_with(b"--daemon="));
+    if is_daemon {
+        return None;
+    }
+    // A pager started by the daemon couldn't read from our terminal,
+    // so only forward when something else is paging our output.
+    if atty::is(atty::Stream::Stdout) {
+        return None;
+    }
+
+    let mut stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            info!(
+                "Could not connect to daemon at {:?}, diffing locally: {}",
+                socket_path, e
+            );
+            return None;
+        }
+    };
+
+    let request = Request {
+        cwd: env::current_dir().ok()?,
+        args: args.to_vec(),
+        vars: env::vars_os().collect(),
+    };
+    let sent = send_fds(
+        &stream,
+        &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO],
+    )
+    .and_then(|_| stream.write_all(&encode_request(&request)))
+    .and_then(|_| stream.shutdown(std::net::Shutdown::Write));
+    if let Err(e) = sent {
+        info!("Could not send request to daemon, diffing locally: {}", e);
+        return None;
+    }
+
+    // The daemon may have written some output by now, so we can't
+    // safely diff locally if it fails.
+    let mut code = [0; 4];
+    match stream.read_exact(&mut code) {
+        Ok(()) => Some(i32::from_be_bytes(code)),
+        Err(e) => {
+            eprintln!(
+                "error: Lost connection to daemon at {:?} ({})",
+                socket_path, e
+            );
+            Some(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = Request {
+            cwd: PathBuf::from("/home/user/project"),
+            args: vec!["difft".into(), "old.rs".into(), "new.rs".into()],
+            vars: vec![
+                ("DFT_DISPLAY".into(), "inline".into()),
+                ("LESS".into(), "-R --prompt=a=b".into()),
+            ],
+        };
+        assert_eq!(decode_request(&encode_request(&request)), Some(request));
+    }
+
+    #[test]
+    fn test_decode_request_truncated() {
+        assert_eq!(decode_request(b"/tmp\x003\x00difft\x00"), None);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_peer_uid() {
+        let (lhs, _rhs) = UnixStream::pair().unwrap();
+        assert_eq!(peer_uid(&lhs).unwrap(), unsafe { libc::geteuid() });
+    }
+
+    #[test]
+    fn test_socket_permissions() {
+        let socket_path =
+            std::env::temp_dir().join(format!("difft-test-daemon-{}.sock", std::process::id()));
+        let listener = bind_private(&socket_path).unwrap();
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        drop(listener);
+        fs::remove_file(&socket_path).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -4,21 +4,46 @@ use std::num::NonZeroU32;
 
 use rustc_hash::FxHashMap;
 
-use crate::parse::syntax::Syntax;
+use crate::{diff::graph::Edge, parse::syntax::Syntax};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum ChangeKind<'a> {
     Unchanged(&'a Syntax<'a>),
     ReplacedComment(&'a Syntax<'a>, &'a Syntax<'a>),
+    ReplacedString(&'a Syntax<'a>, &'a Syntax<'a>),
     Novel,
 }
 
+/// The graph edge that decided the change state of a syntax node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Provenance {
+    pub edge: Edge,
+    /// The cost of this edge.
+    pub cost: u64,
+    /// The total cost of the route, up to and including this edge.
+    pub path_cost: u64,
+}
+
 #[derive(Debug, Default)]
 pub struct ChangeMap<'a> {
     changes: FxHashMap<NonZeroU32, ChangeKind<'a>>,
+    /// Only recorded for `--explain`, as it's a record per edge.
+    provenance: Option<FxHashMap<NonZeroU32, Provenance>>,
 }
 
 impl<'a> ChangeMap<'a> {
+    /// A change map that also records the provenance of each change.
+    pub fn with_provenance() -> Self {
+        Self {
+            changes: FxHashMap::default(),
+            provenance: Some(FxHashMap::default()),
+        }
+    }
+
+    pub fn records_provenance(&self) -> bool {
+        self.provenance.is_some()
+    }
+
     pub fn insert(&mut self, node: &'a Syntax<'a>, ck: ChangeKind<'a>) {
         self.changes.insert(node.id(), ck);
     }
@@ -26,6 +51,19 @@ impl<'a> ChangeMap<'a> {
     pub fn get(&self, node: &Syntax<'a>) -> Option<ChangeKind<'a>> {
         self.changes.get(&node.id()).copied()
     }
+
+    /// Record the edge that marked `node`, if we're recording
+    /// provenance. Descendants of a node marked with `UnchangedNode`
+    /// don't have their own provenance.
+    pub fn insert_provenance(&mut self, node: &'a Syntax<'a>, provenance: Option<Provenance>) {
+        if let (Some(map), Some(provenance)) = (&mut self.provenance, provenance) {
+            map.insert(node.id(), provenance);
+        }
+    }
+
+    pub fn provenance(&self, node: &Syntax<'a>) -> Option<Provenance> {
+        self.provenance.as_ref()?.get(&node.id()).copied()
+    }
 }
 
 pub fn insert_deep_unchanged<'a>(
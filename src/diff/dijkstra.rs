@@ -1,11 +1,11 @@
 //! Implements Dijkstra's algorithm for shortest path, to find an
 //! optimal and readable diff between two ASTs.
 
-use std::{cmp::Reverse, env};
+use std::{cmp::Reverse, env, time::Instant};
 
 use crate::{
     diff::changes::ChangeMap,
-    diff::graph::{neighbours, populate_change_map, Edge, Vertex},
+    diff::graph::{id_after, neighbours, populate_change_map, CostModel, Edge, Vertex},
     parse::syntax::Syntax,
 };
 use bumpalo::Bump;
@@ -15,15 +15,47 @@ use rustc_hash::FxHashMap;
 
 type PredecessorInfo<'a, 'b> = (u64, &'b Vertex<'a>);
 
-/// Return the shortest route from `start` to the end vertex.
-fn shortest_vertex_path(start: Vertex, size_hint: usize) -> Vec<Vertex> {
+/// How many vertices to visit between checking the deadline. Reading
+/// the clock for every vertex would slow down the search. We always
+/// visit this many vertices, so even a search that starts after its
+/// deadline makes some progress.
+const DEADLINE_CHECK_INTERVAL: usize = 4096;
+
+/// The ID after the last node in `node` and its siblings, or 0 if
+/// there are no nodes.
+fn end_id(node: Option<&Syntax>) -> u32 {
+    let mut node = match node {
+        Some(node) => node,
+        None => return 0,
+    };
+    while let Some(next) = node.next_sibling() {
+        node = next;
+    }
+    id_after(node)
+}
+
+/// Return the shortest route from `start` to the end vertex, and
+/// true.
+///
+/// If we reach `deadline` first, return the shortest route to the
+/// vertex that's furthest through the syntax, and false.
+fn shortest_vertex_path<'a>(
+    start: Vertex<'a>,
+    size_hint: usize,
+    cost_model: &CostModel,
+    deadline: Option<Instant>,
+) -> (Vec<Vertex<'a>>, bool) {
+    let lhs_end_id = end_id(start.lhs_syntax);
+    let rhs_end_id = end_id(start.rhs_syntax);
+
     // We want to visit nodes with the shortest distance first, but
     // RadixHeapMap is a max-heap. Ensure nodes are wrapped with
     // Reverse to flip comparisons.
     let mut heap: RadixHeapMap<Reverse<_>, &Vertex> = RadixHeapMap::new();
 
     let vertex_arena = Bump::new();
-    heap.push(Reverse(0), vertex_arena.alloc(start.clone()));
+    let start: &Vertex = vertex_arena.alloc(start);
+    heap.push(Reverse(0), start);
 
     // TODO: this grows very big. Consider using IDA* to reduce memory
     // usage.
@@ -33,17 +65,34 @@ fn shortest_vertex_path(start: Vertex, size_hint: usize) -> Vec<Vertex> {
     let mut neighbour_buf = [
         None, None, None, None, None, None, None, None, None, None, None, None,
     ];
-    let end = loop {
+    let mut num_visited: usize = 0;
+    let mut furthest: (u64, &Vertex) = (0, start);
+    let (end, completed) = loop {
+        num_visited += 1;
+        if let Some(deadline) = deadline {
+            if num_visited % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                break (furthest.1, false);
+            }
+        }
+
         match heap.pop() {
             Some((Reverse(distance), current)) => {
                 if current.is_end() {
-                    break current;
+                    break (current, true);
+                }
+                if deadline.is_some() {
+                    // Vertices are popped in order of distance, so
+                    // this is the cheapest route to this progress.
+                    let progress = current.progress(lhs_end_id, rhs_end_id);
+                    if progress > furthest.0 {
+                        furthest = (progress, current);
+                    }
                 }
 
                 neighbours(current, &mut neighbour_buf, &vertex_arena);
                 for neighbour in &mut neighbour_buf {
                     if let Some((edge, next)) = neighbour.take() {
-                        let distance_to_next = distance + edge.cost();
+                        let distance_to_next = distance + edge.cost(cost_model);
                         let found_shorter_route = match predecessors.get(&next) {
                             Some((prev_shortest, _)) => distance_to_next < *prev_shortest,
                             _ => true,
@@ -77,10 +126,13 @@ fn shortest_vertex_path(start: Vertex, size_hint: usize) -> Vec<Vertex> {
     }
 
     vertex_route.reverse();
-    vertex_route
+    (vertex_route, completed)
 }
 
-fn shortest_path_with_edges<'a>(route: &[Vertex<'a>]) -> Vec<(Edge, Vertex<'a>)> {
+fn shortest_path_with_edges<'a>(
+    route: &[Vertex<'a>],
+    cost_model: &CostModel,
+) -> Vec<(Edge, Vertex<'a>)> {
     let mut prev = route.first().expect("Expected non-empty route");
 
     let mut cost = 0;
@@ -88,7 +140,7 @@ fn shortest_path_with_edges<'a>(route: &[Vertex<'a>]) -> Vec<(Edge, Vertex<'a>)>
     for vertex in route.iter().skip(1) {
         let edge = edge_between(prev, vertex);
         res.push((edge, prev.clone()));
-        cost += edge.cost();
+        cost += edge.cost(cost_model);
 
         prev = vertex;
     }
@@ -97,13 +149,23 @@ fn shortest_path_with_edges<'a>(route: &[Vertex<'a>]) -> Vec<(Edge, Vertex<'a>)>
     res
 }
 
-/// Return the shortest route from the `start` to the end vertex.
+/// Return the shortest route from the `start` to the end vertex, and
+/// whether we reached the end before `deadline`. Otherwise, the
+/// route only goes as far as the search got.
 ///
 /// The vec returned does not return the very last vertex. This is
 /// necessary because a route of N vertices only has N-1 edges.
-fn shortest_path(start: Vertex, size_hint: usize) -> Vec<(Edge, Vertex)> {
-    let vertex_path = shortest_vertex_path(start, size_hint);
-    shortest_path_with_edges(&vertex_path)
+fn shortest_path<'a>(
+    start: Vertex<'a>,
+    size_hint: usize,
+    cost_model: &CostModel,
+    deadline: Option<Instant>,
+) -> (Vec<(Edge, Vertex<'a>)>, bool) {
+    let (vertex_path, completed) = shortest_vertex_path(start, size_hint, cost_model, deadline);
+    (
+        shortest_path_with_edges(&vertex_path, cost_model),
+        completed,
+    )
 }
 
 fn edge_between<'a>(before: &Vertex<'a>, after: &Vertex<'a>) -> Edge {
@@ -158,11 +220,19 @@ fn tree_count(root: Option<&Syntax>) -> u32 {
     count
 }
 
+/// Find the changes between `lhs_syntax` and `rhs_syntax`, and their
+/// siblings, and record them in `change_map`.
+///
+/// Returns false if the search doesn't finish before `deadline`. We
+/// still record the changes up to the furthest point that the search
+/// reached, but later nodes have no change state.
 pub fn mark_syntax<'a>(
     lhs_syntax: Option<&'a Syntax<'a>>,
     rhs_syntax: Option<&'a Syntax<'a>>,
     change_map: &mut ChangeMap<'a>,
-) {
+    cost_model: &CostModel,
+    deadline: Option<Instant>,
+) -> bool {
     let lhs_node_count = node_count(lhs_syntax) as usize;
     let rhs_node_count = node_count(rhs_syntax) as usize;
     info!(
@@ -180,7 +250,10 @@ pub fn mark_syntax<'a>(
     let size_hint = lhs_node_count * rhs_node_count;
 
     let start = Vertex::new(lhs_syntax, rhs_syntax);
-    let route = shortest_path(start, size_hint);
+    let (route, completed) = shortest_path(start, size_hint, cost_model, deadline);
+    if !completed {
+        info!("Structural diff exceeded its time limit");
+    }
 
     let print_length = if env::var("DFT_VERBOSE").is_ok() {
         50
@@ -199,7 +272,7 @@ pub fn mark_syntax<'a>(
                         .map_or_else(|| "None".into(), Syntax::dbg_content),
                     x.1.rhs_syntax
                         .map_or_else(|| "None".into(), Syntax::dbg_content),
-                    x.0.cost(),
+                    x.0.cost(cost_model),
                     x.0,
                 )
             })
@@ -207,7 +280,8 @@ pub fn mark_syntax<'a>(
             .collect_vec()
     );
 
-    populate_change_map(&route, change_map);
+    populate_change_map(&route, change_map, cost_model);
+    completed
 }
 
 #[cfg(test)]
@@ -249,13 +323,15 @@ mod tests {
         init_all_info(&[lhs], &[rhs]);
 
         let start = Vertex::new(Some(lhs), Some(rhs));
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
             actions,
             vec![UnchangedNode {
-                depth_difference: 0
+                depth_difference: 0,
+                punctuation: false,
+                context_mismatches: 0,
             }]
         );
     }
@@ -289,7 +365,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -331,7 +407,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -377,7 +453,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -386,10 +462,14 @@ mod tests {
                 EnterNovelDelimiterRHS { contiguous: false },
                 EnterNovelDelimiterLHS { contiguous: false },
                 UnchangedNode {
-                    depth_difference: 0
+                    depth_difference: 0,
+                    punctuation: false,
+                    context_mismatches: 0,
                 },
                 UnchangedNode {
-                    depth_difference: 0
+                    depth_difference: 0,
+                    punctuation: false,
+                    context_mismatches: 0,
                 },
                 ExitDelimiterRHS,
                 ExitDelimiterLHS,
@@ -416,14 +496,16 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
             actions,
             vec![
                 UnchangedNode {
-                    depth_difference: 0
+                    depth_difference: 0,
+                    punctuation: false,
+                    context_mismatches: 0,
                 },
                 NovelAtomLHS { contiguous: false },
                 NovelAtomLHS { contiguous: true },
@@ -453,7 +535,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -491,7 +573,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -525,7 +607,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -536,6 +618,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn replace_similar_string() {
+        let arena = Arena::new();
+
+        let lhs = vec![Syntax::new_atom(
+            &arena,
+            pos_helper(1),
+            "\"the quick brown fox\"",
+            AtomKind::String,
+        )];
+
+        let rhs = vec![Syntax::new_atom(
+            &arena,
+            pos_helper(1),
+            "\"the quick brown cat\"",
+            AtomKind::String,
+        )];
+        init_all_info(&lhs, &rhs);
+
+        let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
+
+        let actions = route.iter().map(|(action, _)| *action).collect_vec();
+        assert!(matches!(actions.as_slice(), [ReplacedString { .. }]));
+    }
+
     #[test]
     fn replace_very_different_comment() {
         let arena = Arena::new();
@@ -556,7 +664,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -595,7 +703,7 @@ mod tests {
         init_all_info(&lhs, &rhs);
 
         let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
-        let route = shortest_path(start, 0);
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
 
         let actions = route.iter().map(|(action, _)| *action).collect_vec();
         assert_eq!(
@@ -617,12 +725,61 @@ mod tests {
         init_all_info(&[lhs], &[rhs]);
 
         let mut change_map = ChangeMap::default();
-        mark_syntax(Some(lhs), Some(rhs), &mut change_map);
+        mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
 
         assert_eq!(change_map.get(lhs), Some(ChangeKind::Unchanged(rhs)));
         assert_eq!(change_map.get(rhs), Some(ChangeKind::Unchanged(lhs)));
     }
 
+    #[test]
+    fn mark_syntax_records_provenance() {
+        let arena = Arena::new();
+        let lhs = Syntax::new_atom(&arena, pos_helper(1), "foo", AtomKind::Normal);
+        let rhs = Syntax::new_atom(&arena, pos_helper(1), "bar", AtomKind::Normal);
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::with_provenance();
+        mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
+
+        let lhs_provenance = change_map.provenance(lhs).unwrap();
+        let rhs_provenance = change_map.provenance(rhs).unwrap();
+        assert!(matches!(lhs_provenance.edge, NovelAtomLHS { .. }));
+        assert!(matches!(rhs_provenance.edge, NovelAtomRHS { .. }));
+        assert!(lhs_provenance.path_cost > 0);
+    }
+
+    #[test]
+    fn mark_syntax_skips_provenance_by_default() {
+        let arena = Arena::new();
+        let lhs = Syntax::new_atom(&arena, pos_helper(1), "foo", AtomKind::Normal);
+        let rhs = Syntax::new_atom(&arena, pos_helper(1), "bar", AtomKind::Normal);
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::default();
+        mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
+
+        assert_eq!(change_map.get(lhs), Some(ChangeKind::Novel));
+        assert_eq!(change_map.provenance(lhs), None);
+    }
+
     #[test]
     fn mark_syntax_different_atoms() {
         let arena = Arena::new();
@@ -631,8 +788,174 @@ mod tests {
         init_all_info(&[lhs], &[rhs]);
 
         let mut change_map = ChangeMap::default();
-        mark_syntax(Some(lhs), Some(rhs), &mut change_map);
+        mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
         assert_eq!(change_map.get(lhs), Some(ChangeKind::Novel));
         assert_eq!(change_map.get(rhs), Some(ChangeKind::Novel));
     }
+
+    #[test]
+    fn punctuation_cost() {
+        let arena = Arena::new();
+
+        let lhs = Syntax::new_atom(&arena, pos_helper(0), ";", AtomKind::Normal);
+        let rhs = Syntax::new_atom(&arena, pos_helper(0), ";", AtomKind::Normal);
+        init_all_info(&[lhs], &[rhs]);
+
+        let cost_model = CostModel {
+            unchanged_punctuation: 1000,
+            ..CostModel::default()
+        };
+        let start = Vertex::new(Some(lhs), Some(rhs));
+        let (route, _) = shortest_path(start, 0, &cost_model, None);
+
+        let actions = route.iter().map(|(action, _)| *action).collect_vec();
+        assert!(!actions
+            .iter()
+            .any(|action| matches!(action, UnchangedNode { .. })));
+    }
+
+    #[test]
+    fn unrelated_closing_delimiters() {
+        let arena = Arena::new();
+
+        let lhs = vec![Syntax::new_list(
+            &arena,
+            "",
+            vec![],
+            vec![
+                Syntax::new_atom(&arena, pos_helper(0), "function", AtomKind::Keyword),
+                Syntax::new_atom(&arena, pos_helper(1), "foo", AtomKind::Normal),
+                Syntax::new_atom(&arena, pos_helper(2), "end", AtomKind::Keyword),
+            ],
+            "",
+            vec![],
+        )];
+        let rhs = vec![Syntax::new_list(
+            &arena,
+            "",
+            vec![],
+            vec![
+                Syntax::new_atom(&arena, pos_helper(0), "if", AtomKind::Keyword),
+                Syntax::new_atom(&arena, pos_helper(1), "bar", AtomKind::Normal),
+                Syntax::new_atom(&arena, pos_helper(2), "end", AtomKind::Keyword),
+            ],
+            "",
+            vec![],
+        )];
+        init_all_info(&lhs, &rhs);
+
+        let start = Vertex::new(lhs.get(0).copied(), rhs.get(0).copied());
+        let (route, _) = shortest_path(start, 0, &CostModel::default(), None);
+
+        let actions = route.iter().map(|(action, _)| *action).collect_vec();
+        assert!(!actions
+            .iter()
+            .any(|action| matches!(action, UnchangedNode { .. })));
+    }
+
+    #[test]
+    fn prefer_duplicate_with_same_context() {
+        let arena = Arena::new();
+        let block = |line: usize| {
+            Syntax::new_list(
+                &arena,
+                "(",
+                pos_helper(line),
+                vec![Syntax::new_atom(
+                    &arena,
+                    col_helper(line, 1),
+                    "x",
+                    AtomKind::Normal,
+                )],
+                ")",
+                col_helper(line, 2),
+            )
+        };
+
+        let lhs = vec![
+            block(0),
+            Syntax::new_atom(&arena, pos_helper(1), "q", AtomKind::Normal),
+        ];
+        let rhs = vec![
+            Syntax::new_atom(&arena, pos_helper(0), "p", AtomKind::Normal),
+            block(1),
+            block(2),
+            Syntax::new_atom(&arena, pos_helper(3), "q", AtomKind::Normal),
+        ];
+        init_all_info(&lhs, &rhs);
+
+        let mut change_map = ChangeMap::default();
+        mark_syntax(
+            lhs.get(0).copied(),
+            rhs.get(0).copied(),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
+
+        // The second copy is followed by `q`, like the LHS block.
+        assert_eq!(change_map.get(rhs[1]), Some(ChangeKind::Novel));
+        assert_eq!(change_map.get(rhs[2]), Some(ChangeKind::Unchanged(lhs[0])));
+    }
+
+    #[test]
+    fn mark_syntax_past_deadline() {
+        let arena = Arena::new();
+        let lhs = Syntax::new_atom(&arena, pos_helper(1), "foo", AtomKind::Normal);
+        let rhs = Syntax::new_atom(&arena, pos_helper(1), "bar", AtomKind::Normal);
+        init_all_info(&[lhs], &[rhs]);
+
+        // Small searches finish before we first check the deadline.
+        let mut change_map = ChangeMap::default();
+        let completed = mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            Some(Instant::now()),
+        );
+        assert!(completed);
+        assert_eq!(change_map.get(lhs), Some(ChangeKind::Novel));
+    }
+
+    #[test]
+    fn mark_syntax_past_deadline_keeps_partial_result() {
+        let arena = Arena::new();
+        let atoms = |prefix: &str| {
+            (0..100)
+                .map(|i| {
+                    Syntax::new_atom(
+                        &arena,
+                        pos_helper(1),
+                        &format!("{}{}", prefix, i),
+                        AtomKind::Normal,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let lhs = atoms("a");
+        let rhs = atoms("b");
+        init_all_info(&lhs, &rhs);
+
+        let mut change_map = ChangeMap::default();
+        let completed = mark_syntax(
+            lhs.get(0).copied(),
+            rhs.get(0).copied(),
+            &mut change_map,
+            &CostModel::default(),
+            Some(Instant::now()),
+        );
+        assert!(!completed);
+
+        // We keep the start of the route, but not the end.
+        assert!(change_map.get(lhs[0]).is_some() || change_map.get(rhs[0]).is_some());
+        assert_eq!(change_map.get(lhs[99]), None);
+        assert_eq!(change_map.get(rhs[99]), None);
+    }
 }
@@ -0,0 +1,203 @@
+//! Describing why a syntax node was marked as unchanged or novel, for
+//! `--explain`.
+
+use crate::{
+    constants::Side,
+    diff::{
+        changes::{ChangeKind, ChangeMap},
+        graph::Edge,
+    },
+    lines::LineNumber,
+    parse::syntax::Syntax,
+    positions::SingleLineSpan,
+};
+
+fn contains(spans: &[SingleLineSpan], line: LineNumber, col: usize) -> bool {
+    spans
+        .iter()
+        .any(|span| span.line == line && span.start_col <= col && col < span.end_col)
+}
+
+/// The innermost node in `nodes` at `line` and `col`. For lists, only
+/// the delimiters count as part of the node.
+fn node_at<'a>(nodes: &[&'a Syntax<'a>], line: LineNumber, col: usize) -> Option<&'a Syntax<'a>> {
+    for node in nodes {
+        match node {
+            Syntax::List {
+                open_position,
+                close_position,
+                children,
+                ..
+            } => {
+                if contains(open_position, line, col) || contains(close_position, line, col) {
+                    return Some(node);
+                }
+                if let Some(child) = node_at(children, line, col) {
+                    return Some(child);
+                }
+            }
+            Syntax::Atom { position, .. } => {
+                if contains(position, line, col) {
+                    return Some(node);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Left => "LHS",
+        Side::Right => "RHS",
+    }
+}
+
+fn opposite(side: Side) -> Side {
+    match side {
+        Side::Left => Side::Right,
+        Side::Right => Side::Left,
+    }
+}
+
+/// Explain how the token at `line` and `col` in `roots` was matched,
+/// or `None` if there's no token at that position.
+pub fn explain<'a>(
+    roots: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+    side: Side,
+    line: LineNumber,
+    col: usize,
+) -> Option<String> {
+    let node = node_at(roots, line, col)?;
+    let mut lines = vec![format!(
+        "{} line {} column {}: {}",
+        side_name(side),
+        line.one_indexed(),
+        col + 1,
+        node.dbg_content()
+    )];
+
+    lines.push(match change_map.get(node) {
+        Some(ChangeKind::Unchanged(opposite_node)) => format!(
+            "  Unchanged, matched with {} {}.",
+            side_name(opposite(side)),
+            opposite_node.dbg_content()
+        ),
+        Some(ChangeKind::ReplacedComment(_, opposite_node)) => format!(
+            "  Replaced comment, similar to {} {}.",
+            side_name(opposite(side)),
+            opposite_node.dbg_content()
+        ),
+        Some(ChangeKind::ReplacedString(_, opposite_node)) => format!(
+            "  Replaced string, similar to {} {}.",
+            side_name(opposite(side)),
+            opposite_node.dbg_content()
+        ),
+        Some(ChangeKind::Novel) => "  Novel.".into(),
+        None => "  No change recorded.".into(),
+    });
+
+    // Descendants of an unchanged node are marked along with it, so
+    // look for the closest ancestor that was marked by the graph
+    // search.
+    let mut marked = None;
+    let mut current = Some(node);
+    while let Some(n) = current {
+        if let Some(provenance) = change_map.provenance(n) {
+            marked = Some((n, provenance));
+            break;
+        }
+        current = n.parent();
+    }
+
+    match marked {
+        Some((marked_node, provenance)) => {
+            if marked_node.id() != node.id() {
+                lines.push(format!(
+                    "  Marked along with its enclosing node {}.",
+                    marked_node.dbg_content()
+                ));
+            }
+            lines.push(format!(
+                "  Edge: {:?} (cost {}), total path cost {}.",
+                provenance.edge, provenance.cost, provenance.path_cost
+            ));
+
+            let edge_is_unchanged = matches!(
+                provenance.edge,
+                Edge::UnchangedNode { .. } | Edge::EnterUnchangedDelimiter { .. }
+            );
+            let is_unchanged = matches!(change_map.get(node), Some(ChangeKind::Unchanged(_)));
+            if edge_is_unchanged != is_unchanged
+                && !matches!(
+                    provenance.edge,
+                    Edge::ReplacedComment { .. } | Edge::ReplacedString { .. }
+                )
+            {
+                lines.push("  Slider correction changed this after the graph search.".into());
+            }
+        }
+        None => {
+            lines.push(
+                "  Not part of the graph search: marked while skipping regions that are identical on both sides."
+                    .into(),
+            );
+        }
+    }
+
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diff::{dijkstra::mark_syntax, graph::CostModel},
+        parse::syntax::{init_all_info, AtomKind},
+    };
+    use typed_arena::Arena;
+
+    fn pos(line: usize, start_col: usize, end_col: usize) -> Vec<SingleLineSpan> {
+        vec![SingleLineSpan {
+            line: line.into(),
+            start_col,
+            end_col,
+        }]
+    }
+
+    #[test]
+    fn test_explain_unchanged_atom() {
+        let arena = Arena::new();
+        let lhs = Syntax::new_atom(&arena, pos(0, 0, 3), "foo", AtomKind::Normal);
+        let rhs = Syntax::new_atom(&arena, pos(2, 4, 7), "foo", AtomKind::Normal);
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::with_provenance();
+        mark_syntax(
+            Some(lhs),
+            Some(rhs),
+            &mut change_map,
+            &CostModel::default(),
+            None,
+        );
+
+        let explanation = explain(&[rhs], &change_map, Side::Right, 2.into(), 5).unwrap();
+        assert!(explanation.starts_with("RHS line 3 column 6: "));
+        assert!(explanation.contains("Unchanged, matched with LHS"));
+        assert!(explanation.contains("Edge: UnchangedNode"));
+    }
+
+    #[test]
+    fn test_explain_no_token() {
+        let arena = Arena::new();
+        let atom = Syntax::new_atom(&arena, pos(0, 0, 3), "foo", AtomKind::Normal);
+        init_all_info(&[atom], &[]);
+
+        let change_map = ChangeMap::default();
+        assert_eq!(
+            explain(&[atom], &change_map, Side::Left, 0.into(), 10),
+            None
+        );
+    }
+}
@@ -10,7 +10,7 @@ use std::{
 use strsim::normalized_levenshtein;
 
 use crate::{
-    diff::changes::{insert_deep_unchanged, ChangeKind, ChangeMap},
+    diff::changes::{insert_deep_unchanged, ChangeKind, ChangeMap, Provenance},
     parse::syntax::{AtomKind, Syntax, SyntaxId},
 };
 use Edge::*;
@@ -245,11 +245,61 @@ fn push_rhs_delimiter<'a>(
     entered.push(EnteredDelimiter::PopEither((lhs_delims, rhs_delims)))
 }
 
+/// The ID of the first node after `node` and its descendants, in
+/// pre-order.
+pub fn id_after(node: &Syntax) -> u32 {
+    let num_descendants = match node {
+        Syntax::List {
+            num_descendants, ..
+        } => *num_descendants,
+        Syntax::Atom { .. } => 0,
+    };
+    node.id().get() + num_descendants + 1
+}
+
+/// The ID of the next node to match on one side: `syntax`, or the
+/// node after the innermost list we're in if we've reached its end,
+/// or `end_id` at the end of the section.
+fn next_id(syntax: Option<&Syntax>, innermost_delimiter: Option<&Syntax>, end_id: u32) -> u32 {
+    match (syntax, innermost_delimiter) {
+        (Some(node), _) => node.id().get(),
+        (None, Some(delimiter)) => id_after(delimiter),
+        (None, None) => end_id,
+    }
+}
+
 impl<'a> Vertex<'a> {
     pub fn is_end(&self) -> bool {
         self.lhs_syntax.is_none() && self.rhs_syntax.is_none() && self.parents.is_empty()
     }
 
+    /// How far this vertex is through the syntax. Syntax IDs are
+    /// assigned in pre-order, so this is the total of the next IDs to
+    /// match on each side. `lhs_end_id` and `rhs_end_id` are the IDs
+    /// after the last nodes being diffed.
+    pub fn progress(&self, lhs_end_id: u32, rhs_end_id: u32) -> u64 {
+        let mut lhs_delimiter = None;
+        let mut rhs_delimiter = None;
+        for entered in self.parents.iter() {
+            match entered {
+                EnteredDelimiter::PopBoth((lhs_delim, rhs_delim)) => {
+                    lhs_delimiter = lhs_delimiter.or(Some(*lhs_delim));
+                    rhs_delimiter = rhs_delimiter.or(Some(*rhs_delim));
+                }
+                EnteredDelimiter::PopEither((lhs_delims, rhs_delims)) => {
+                    lhs_delimiter = lhs_delimiter.or_else(|| lhs_delims.peek().copied());
+                    rhs_delimiter = rhs_delimiter.or_else(|| rhs_delims.peek().copied());
+                }
+            }
+            if lhs_delimiter.is_some() && rhs_delimiter.is_some() {
+                break;
+            }
+        }
+
+        u64::from(next_id(self.lhs_syntax, lhs_delimiter, lhs_end_id))
+            + u64::from(next_id(self.rhs_syntax, rhs_delimiter, rhs_end_id))
+    }
+
     pub fn new(lhs_syntax: Option<&'a Syntax<'a>>, rhs_syntax: Option<&'a Syntax<'a>>) -> Self {
         let parents = Stack::new();
         Vertex {
@@ -272,22 +322,67 @@ impl<'a> Vertex<'a> {
 /// See [`neighbours`] for all the edges available for a given `Vertex`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Edge {
-    UnchangedNode { depth_difference: u32 },
-    EnterUnchangedDelimiter { depth_difference: u32 },
-    ReplacedComment { levenshtein_pct: u8 },
-    NovelAtomLHS { contiguous: bool },
-    NovelAtomRHS { contiguous: bool },
+    UnchangedNode {
+        depth_difference: u32,
+        punctuation: bool,
+        /// How many of the siblings either side of a duplicated
+        /// list differ, from 0 to 2. This is always 0 for atoms and
+        /// lists that only occur once.
+        context_mismatches: u8,
+    },
+    EnterUnchangedDelimiter {
+        depth_difference: u32,
+    },
+    ReplacedComment {
+        levenshtein_pct: u8,
+    },
+    ReplacedString {
+        levenshtein_pct: u8,
+    },
+    NovelAtomLHS {
+        contiguous: bool,
+    },
+    NovelAtomRHS {
+        contiguous: bool,
+    },
     // TODO: An EnterNovelDelimiterBoth edge might help performance
     // rather doing LHS and RHS separately.
-    EnterNovelDelimiterLHS { contiguous: bool },
-    EnterNovelDelimiterRHS { contiguous: bool },
+    EnterNovelDelimiterLHS {
+        contiguous: bool,
+    },
+    EnterNovelDelimiterRHS {
+        contiguous: bool,
+    },
     ExitDelimiterLHS,
     ExitDelimiterRHS,
     ExitDelimiterBoth,
 }
 
+/// Adjustable weights for edges in the graph. Raising a cost makes
+/// the diff less likely to use that kind of edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    /// The base cost of matching a pair of delimiters, such as `{`
+    /// and `}`, on both sides. Higher values stop unrelated code
+    /// being matched just because it's inside the same kind of
+    /// brackets.
+    pub unchanged_delimiter: u64,
+    /// The extra cost of matching a punctuation atom, such as `,` or
+    /// `;`, on both sides.
+    pub unchanged_punctuation: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            unchanged_delimiter: 100,
+            unchanged_punctuation: 0,
+        }
+    }
+}
+
 impl Edge {
-    pub fn cost(self) -> u64 {
+    pub fn cost(self, cost_model: &CostModel) -> u64 {
         match self {
             // When we're at the end of a list, there's only one exit
             // delimiter possibility, so the cost doesn't matter. We
@@ -301,14 +396,33 @@ impl Edge {
             ExitDelimiterLHS | ExitDelimiterRHS => 2,
 
             // Matching nodes is always best.
-            UnchangedNode { depth_difference } => min(40, u64::from(depth_difference) + 1),
+            UnchangedNode {
+                depth_difference,
+                punctuation,
+                context_mismatches,
+            } => {
+                let punctuation_cost = if punctuation {
+                    cost_model.unchanged_punctuation
+                } else {
+                    0
+                };
+                // When the same block occurs more than once, prefer
+                // the copy with the same neighbours. This is small
+                // enough that it only breaks ties.
+                min(40, u64::from(depth_difference) + 1)
+                    + punctuation_cost
+                    + u64::from(context_mismatches)
+            }
             // Matching an outer delimiter is good.
             EnterUnchangedDelimiter { depth_difference } => {
-                100 + min(40, u64::from(depth_difference))
+                cost_model.unchanged_delimiter + min(40, u64::from(depth_difference))
             }
 
-            // Replacing a comment is better than treating it as novel.
-            ReplacedComment { levenshtein_pct } => 150 + u64::from(100 - levenshtein_pct),
+            // Replacing a comment or string is better than treating
+            // it as novel.
+            ReplacedComment { levenshtein_pct } | ReplacedString { levenshtein_pct } => {
+                150 + u64::from(100 - levenshtein_pct)
+            }
 
             // Otherwise, we've added/removed a node.
             NovelAtomLHS { contiguous }
@@ -331,6 +445,87 @@ impl Edge {
     }
 }
 
+/// How many of the siblings before and after two equal lists have
+/// different content. Blocks such as switch cases and test table rows
+/// are often duplicated, and the neighbours tell us which copy was
+/// changed.
+///
+/// This is always 0 unless the list occurs more than once among its
+/// siblings on either side, so other matches aren't penalised.
+fn context_mismatches(lhs: &Syntax, rhs: &Syntax) -> u8 {
+    if let Syntax::Atom { .. } = lhs {
+        return 0;
+    }
+    if !lhs.has_duplicate_sibling() && !rhs.has_duplicate_sibling() {
+        return 0;
+    }
+
+    let content_id = |node: Option<&Syntax>| node.map(Syntax::content_id);
+    let mut mismatches = 0;
+    if content_id(lhs.previous_sibling()) != content_id(rhs.previous_sibling()) {
+        mismatches += 1;
+    }
+    if content_id(lhs.next_sibling()) != content_id(rhs.next_sibling()) {
+        mismatches += 1;
+    }
+    mismatches
+}
+
+/// Is `node` an atom that only contains punctuation, such as `,`?
+fn is_punctuation(node: &Syntax) -> bool {
+    match node {
+        Syntax::Atom { content, .. } => {
+            !content.is_empty() && content.chars().all(|c| c.is_ascii_punctuation())
+        }
+        Syntax::List { .. } => false,
+    }
+}
+
+/// Tokens that close a block in grammars that don't give us
+/// delimiters, such as `end` in Lua or `fi` in Bash.
+const CLOSING_TOKENS: &[&str] = &["}", ")", "]", "end", "fi", "done", "esac"];
+
+/// Is `node` a closing token at the end of its parent, such as the
+/// `end` in `if x then y end`?
+fn is_lone_closing_delimiter(node: &Syntax) -> bool {
+    match node {
+        Syntax::Atom { content, .. } => {
+            node.parent().is_some()
+                && node.next_sibling().is_none()
+                && CLOSING_TOKENS.contains(&content.as_str())
+        }
+        Syntax::List { .. } => false,
+    }
+}
+
+/// Were the parents of the current nodes entered together, and do
+/// they start with the same node?
+///
+/// Lone closing delimiters like `end` are very common, so matching
+/// them is cheap. Without this check, we happily match the `end` of
+/// one block with the `end` of an unrelated block, which produces
+/// scrambled hunks.
+fn parents_match(v: &Vertex) -> bool {
+    match v.parents.peek() {
+        Some(EnteredDelimiter::PopBoth((lhs_parent, rhs_parent))) => {
+            match (lhs_parent, rhs_parent) {
+                (
+                    Syntax::List {
+                        children: lhs_children,
+                        ..
+                    },
+                    Syntax::List {
+                        children: rhs_children,
+                        ..
+                    },
+                ) => lhs_children.first() == rhs_children.first(),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
 /// Calculate all the neighbours from `v` and write them to `buf`.
 pub fn neighbours<'a, 'b>(
     v: &Vertex<'a>,
@@ -405,14 +600,19 @@ pub fn neighbours<'a, 'b>(
     }
 
     if let (Some(lhs_syntax), Some(rhs_syntax)) = (&v.lhs_syntax, &v.rhs_syntax) {
-        if lhs_syntax == rhs_syntax {
+        if lhs_syntax == rhs_syntax && (!is_lone_closing_delimiter(lhs_syntax) || parents_match(v))
+        {
             let depth_difference = (lhs_syntax.num_ancestors() as i32
                 - rhs_syntax.num_ancestors() as i32)
                 .abs() as u32;
 
             // Both nodes are equal, the happy case.
             buf[i] = Some((
-                UnchangedNode { depth_difference },
+                UnchangedNode {
+                    depth_difference,
+                    punctuation: is_punctuation(lhs_syntax),
+                    context_mismatches: context_mismatches(lhs_syntax, rhs_syntax),
+                },
                 alloc.alloc(Vertex {
                     lhs_syntax: lhs_syntax.next_sibling(),
                     rhs_syntax: rhs_syntax.next_sibling(),
@@ -470,23 +670,30 @@ pub fn neighbours<'a, 'b>(
         if let (
             Syntax::Atom {
                 content: lhs_content,
-                kind: AtomKind::Comment,
+                kind: lhs_kind,
                 ..
             },
             Syntax::Atom {
                 content: rhs_content,
-                kind: AtomKind::Comment,
+                kind: rhs_kind,
                 ..
             },
         ) = (lhs_syntax, rhs_syntax)
         {
-            // Both sides are comments and their content is reasonably
-            // similar.
-            if lhs_content != rhs_content {
+            // Both sides are comments, or both are strings, and their
+            // content has changed. We diff the words inside them, so
+            // only the edited words are highlighted.
+            let is_text = matches!(lhs_kind, AtomKind::Comment | AtomKind::String);
+            if is_text && lhs_kind == rhs_kind && lhs_content != rhs_content {
                 let levenshtein_pct =
                     (normalized_levenshtein(lhs_content, rhs_content) * 100.0).round() as u8;
+                let edge = if *lhs_kind == AtomKind::Comment {
+                    ReplacedComment { levenshtein_pct }
+                } else {
+                    ReplacedString { levenshtein_pct }
+                };
                 buf[i] = Some((
-                    ReplacedComment { levenshtein_pct },
+                    edge,
                     alloc.alloc(Vertex {
                         lhs_syntax: lhs_syntax.next_sibling(),
                         rhs_syntax: rhs_syntax.next_sibling(),
@@ -594,8 +801,26 @@ pub fn neighbours<'a, 'b>(
     );
 }
 
-pub fn populate_change_map<'a>(route: &[(Edge, Vertex<'a>)], change_map: &mut ChangeMap<'a>) {
+pub fn populate_change_map<'a>(
+    route: &[(Edge, Vertex<'a>)],
+    change_map: &mut ChangeMap<'a>,
+    cost_model: &CostModel,
+) {
+    let record_provenance = change_map.records_provenance();
+    let mut path_cost = 0;
     for (e, v) in route {
+        let provenance = if record_provenance {
+            let cost = e.cost(cost_model);
+            path_cost += cost;
+            Some(Provenance {
+                edge: *e,
+                cost,
+                path_cost,
+            })
+        } else {
+            None
+        };
+
         match e {
             ExitDelimiterBoth | ExitDelimiterLHS | ExitDelimiterRHS => {
                 // Nothing to do: we have already marked this node when we entered it.
@@ -607,6 +832,8 @@ pub fn populate_change_map<'a>(route: &[(Edge, Vertex<'a>)], change_map: &mut Ch
 
                 insert_deep_unchanged(lhs, rhs, change_map);
                 insert_deep_unchanged(rhs, lhs, change_map);
+                change_map.insert_provenance(lhs, provenance);
+                change_map.insert_provenance(rhs, provenance);
             }
             EnterUnchangedDelimiter { .. } => {
                 // No change on the outer delimiter, but children may
@@ -615,6 +842,8 @@ pub fn populate_change_map<'a>(route: &[(Edge, Vertex<'a>)], change_map: &mut Ch
                 let rhs = v.rhs_syntax.unwrap();
                 change_map.insert(lhs, ChangeKind::Unchanged(rhs));
                 change_map.insert(rhs, ChangeKind::Unchanged(lhs));
+                change_map.insert_provenance(lhs, provenance);
+                change_map.insert_provenance(rhs, provenance);
             }
             ReplacedComment { levenshtein_pct } => {
                 let lhs = v.lhs_syntax.unwrap();
@@ -627,14 +856,32 @@ pub fn populate_change_map<'a>(route: &[(Edge, Vertex<'a>)], change_map: &mut Ch
                     change_map.insert(lhs, ChangeKind::Novel);
                     change_map.insert(rhs, ChangeKind::Novel);
                 }
+                change_map.insert_provenance(lhs, provenance);
+                change_map.insert_provenance(rhs, provenance);
+            }
+            ReplacedString { levenshtein_pct } => {
+                let lhs = v.lhs_syntax.unwrap();
+                let rhs = v.rhs_syntax.unwrap();
+
+                if *levenshtein_pct > 40 {
+                    change_map.insert(lhs, ChangeKind::ReplacedString(lhs, rhs));
+                    change_map.insert(rhs, ChangeKind::ReplacedString(rhs, lhs));
+                } else {
+                    change_map.insert(lhs, ChangeKind::Novel);
+                    change_map.insert(rhs, ChangeKind::Novel);
+                }
+                change_map.insert_provenance(lhs, provenance);
+                change_map.insert_provenance(rhs, provenance);
             }
             NovelAtomLHS { .. } | EnterNovelDelimiterLHS { .. } => {
                 let lhs = v.lhs_syntax.unwrap();
                 change_map.insert(lhs, ChangeKind::Novel);
+                change_map.insert_provenance(lhs, provenance);
             }
             NovelAtomRHS { .. } | EnterNovelDelimiterRHS { .. } => {
                 let rhs = v.rhs_syntax.unwrap();
                 change_map.insert(rhs, ChangeKind::Novel);
+                change_map.insert_provenance(rhs, provenance);
             }
         }
     }
@@ -1,6 +1,9 @@
 pub mod changes;
 pub mod dijkstra;
-mod graph;
+pub mod explain;
+pub mod graph;
+pub mod moves;
 pub mod myers_diff;
+pub mod signatures;
 pub mod sliders;
 pub mod unchanged;
@@ -0,0 +1,189 @@
+//! Detecting code that has moved, or been copied, between the two
+//! sides of a diff.
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    diff::changes::{ChangeKind, ChangeMap},
+    parse::syntax::{outermost_novel, Syntax},
+    positions::SingleLineSpan,
+};
+
+/// Ignore small nodes, as short expressions like `x + 1` frequently
+/// occur in unrelated places.
+const MIN_DESCENDANTS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveKind {
+    /// The code was removed from the LHS and added elsewhere on the
+    /// RHS.
+    Moved,
+    /// The code is still present in the LHS, and has been duplicated
+    /// on the RHS.
+    Copied,
+}
+
+/// The position of a syntax node, from its first character to its
+/// last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: SingleLineSpan,
+    pub end: SingleLineSpan,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub kind: MoveKind,
+    pub lhs: Span,
+    pub rhs: Span,
+}
+
+fn node_span(node: &Syntax) -> Option<Span> {
+    let (first_pos, last_pos) = match node {
+        Syntax::List {
+            open_position,
+            close_position,
+            ..
+        } => (open_position.first(), close_position.last()),
+        Syntax::Atom { position, .. } => (position.first(), position.last()),
+    };
+
+    match (first_pos, last_pos) {
+        (Some(start), Some(end)) => Some(Span {
+            start: *start,
+            end: *end,
+        }),
+        _ => None,
+    }
+}
+
+fn is_large(node: &Syntax) -> bool {
+    match node {
+        Syntax::List {
+            num_descendants, ..
+        } => *num_descendants >= MIN_DESCENDANTS,
+        Syntax::Atom { .. } => false,
+    }
+}
+
+/// Add every large node in `nodes`, including nested nodes, to
+/// `by_content`.
+fn index_by_content<'a>(nodes: &[&'a Syntax<'a>], by_content: &mut FxHashMap<u32, &'a Syntax<'a>>) {
+    for node in nodes {
+        if is_large(node) {
+            by_content.entry(node.content_id()).or_insert(node);
+        }
+        if let Syntax::List { children, .. } = node {
+            index_by_content(children, by_content);
+        }
+    }
+}
+
+/// Find novel nodes on the RHS whose content also occurs on the
+/// LHS. If the LHS node is novel too, the code has moved, otherwise
+/// it has been copied.
+pub fn find_moves<'a>(
+    lhs_nodes: &[&'a Syntax<'a>],
+    rhs_nodes: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+) -> Vec<Move> {
+    let mut lhs_novel: FxHashMap<u32, Vec<&'a Syntax<'a>>> = FxHashMap::default();
+    for node in outermost_novel(lhs_nodes, change_map) {
+        if is_large(node) {
+            lhs_novel.entry(node.content_id()).or_default().push(node);
+        }
+    }
+
+    let mut lhs_by_content = FxHashMap::default();
+    index_by_content(lhs_nodes, &mut lhs_by_content);
+
+    let mut moves = vec![];
+    for rhs_node in outermost_novel(rhs_nodes, change_map) {
+        if !is_large(rhs_node) {
+            continue;
+        }
+
+        // Each removed LHS node can only be moved to one place.
+        let moved_from = lhs_novel
+            .get_mut(&rhs_node.content_id())
+            .and_then(|nodes| nodes.pop());
+
+        let (kind, lhs_node) = match moved_from {
+            Some(lhs_node) => (MoveKind::Moved, lhs_node),
+            None => match lhs_by_content.get(&rhs_node.content_id()) {
+                Some(lhs_node) if change_map.get(lhs_node) != Some(ChangeKind::Novel) => {
+                    (MoveKind::Copied, *lhs_node)
+                }
+                _ => continue,
+            },
+        };
+
+        if let (Some(lhs), Some(rhs)) = (node_span(lhs_node), node_span(rhs_node)) {
+            moves.push(Move { kind, lhs, rhs });
+        }
+    }
+
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use typed_arena::Arena;
+
+    use super::*;
+    use crate::{
+        diff::changes::{insert_deep_novel, insert_deep_unchanged},
+        parse::syntax::{init_all_info, AtomKind},
+    };
+
+    fn large_list<'a>(arena: &'a Arena<Syntax<'a>>, line: usize) -> &'a Syntax<'a> {
+        let pos = |start_col: usize| {
+            vec![SingleLineSpan {
+                line: line.into(),
+                start_col,
+                end_col: start_col + 1,
+            }]
+        };
+        let children = (0..MIN_DESCENDANTS as usize)
+            .map(|i| Syntax::new_atom(arena, pos(i + 1), "x", AtomKind::Normal))
+            .collect();
+        Syntax::new_list(arena, "(", pos(0), children, ")", pos(10))
+    }
+
+    #[test]
+    fn test_find_moves_moved() {
+        let arena = Arena::new();
+        let lhs = large_list(&arena, 0);
+        let rhs = large_list(&arena, 5);
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::default();
+        insert_deep_novel(lhs, &mut change_map);
+        insert_deep_novel(rhs, &mut change_map);
+
+        let moves = find_moves(&[lhs], &[rhs], &change_map);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].kind, MoveKind::Moved);
+        assert_eq!(moves[0].lhs.start.line, 0.into());
+        assert_eq!(moves[0].rhs.start.line, 5.into());
+    }
+
+    #[test]
+    fn test_find_moves_copied() {
+        let arena = Arena::new();
+        let lhs = large_list(&arena, 0);
+        let rhs = large_list(&arena, 0);
+        let rhs_copy = large_list(&arena, 5);
+        init_all_info(&[lhs], &[rhs, rhs_copy]);
+
+        let mut change_map = ChangeMap::default();
+        insert_deep_unchanged(lhs, rhs, &mut change_map);
+        insert_deep_unchanged(rhs, lhs, &mut change_map);
+        insert_deep_novel(rhs_copy, &mut change_map);
+
+        let moves = find_moves(&[lhs], &[rhs, rhs_copy], &change_map);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].kind, MoveKind::Copied);
+        assert_eq!(moves[0].rhs.start.line, 5.into());
+    }
+}
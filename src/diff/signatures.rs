@@ -0,0 +1,222 @@
+//! Noticing when a definition's signature changed but its body
+//! didn't, or the other way round. This helps reviewers tell interface
+//! changes from implementation changes.
+
+use crate::{
+    diff::changes::{ChangeKind, ChangeMap},
+    lines::LineNumber,
+    parse::syntax::Syntax,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureChangeKind {
+    /// The signature changed, but the body is the same.
+    SignatureOnly,
+    /// The body changed, but the signature is the same.
+    BodyOnly,
+}
+
+/// A top-level definition where only the signature or only the body
+/// changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureChange {
+    pub kind: SignatureChangeKind,
+    /// The first and last lines of the definition on each side.
+    pub lhs_lines: (LineNumber, LineNumber),
+    pub rhs_lines: (LineNumber, LineNumber),
+    /// The first and last lines of the body on each side.
+    pub lhs_body_lines: (LineNumber, LineNumber),
+    pub rhs_body_lines: (LineNumber, LineNumber),
+}
+
+/// Does `node`, or any of its descendants, have a change? Nodes
+/// without a change state weren't diffed, so they don't count.
+fn is_changed<'a>(node: &Syntax<'a>, change_map: &ChangeMap<'a>) -> bool {
+    match change_map.get(node) {
+        Some(ChangeKind::Unchanged(_)) | None => {}
+        Some(_) => return true,
+    }
+    match node {
+        Syntax::List { children, .. } => children.iter().any(|child| is_changed(child, change_map)),
+        Syntax::Atom { .. } => false,
+    }
+}
+
+/// Split a definition into its signature and its body.
+///
+/// This is a heuristic: we treat the last child as the body if it's
+/// a list, such as a `{ ... }` block in Rust or an indented block in
+/// Python. Lists with delimiters, such as arrays, aren't definitions.
+fn signature_and_body<'a>(node: &'a Syntax<'a>) -> Option<(&'a [&'a Syntax<'a>], &'a Syntax<'a>)> {
+    match node {
+        Syntax::List {
+            open_content,
+            children,
+            ..
+        } if open_content.is_empty() => {
+            let (body, signature) = children.split_last()?;
+            if signature.is_empty() {
+                return None;
+            }
+            match body {
+                Syntax::List { .. } => Some((signature, body)),
+                Syntax::Atom { .. } => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn line_range(node: &Syntax) -> Option<(LineNumber, LineNumber)> {
+    Some((node.first_line()?, node.last_line()?))
+}
+
+/// Find the top-level definitions in `rhs` where either the signature
+/// or the body changed, but not both.
+pub fn find_signature_changes<'a>(
+    rhs: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+) -> Vec<SignatureChange> {
+    rhs.iter()
+        .filter_map(|rhs_node| {
+            let lhs_node = match change_map.get(rhs_node) {
+                Some(ChangeKind::Unchanged(lhs_node)) => lhs_node,
+                _ => return None,
+            };
+            let (lhs_signature, lhs_body) = signature_and_body(lhs_node)?;
+            let (rhs_signature, rhs_body) = signature_and_body(rhs_node)?;
+
+            let signature_changed = lhs_signature
+                .iter()
+                .chain(rhs_signature)
+                .any(|node| is_changed(node, change_map));
+            let body_changed = is_changed(lhs_body, change_map) || is_changed(rhs_body, change_map);
+
+            let kind = match (signature_changed, body_changed) {
+                (true, false) => SignatureChangeKind::SignatureOnly,
+                (false, true) => SignatureChangeKind::BodyOnly,
+                _ => return None,
+            };
+            Some(SignatureChange {
+                kind,
+                lhs_lines: line_range(lhs_node)?,
+                rhs_lines: line_range(rhs_node)?,
+                lhs_body_lines: line_range(lhs_body)?,
+                rhs_body_lines: line_range(rhs_body)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use typed_arena::Arena;
+
+    use super::*;
+    use crate::{
+        diff::changes::{insert_deep_novel, insert_deep_unchanged},
+        parse::syntax::{init_all_info, AtomKind},
+        positions::SingleLineSpan,
+    };
+
+    fn pos(line: usize, start_col: usize) -> Vec<SingleLineSpan> {
+        vec![SingleLineSpan {
+            line: line.into(),
+            start_col,
+            end_col: start_col + 1,
+        }]
+    }
+
+    /// `fn NAME { BODY }`, on lines 0 to 2.
+    fn definition<'a>(arena: &'a Arena<Syntax<'a>>, name: &str, body: &str) -> &'a Syntax<'a> {
+        let signature = vec![
+            Syntax::new_atom(arena, pos(0, 0), "fn", AtomKind::Keyword),
+            Syntax::new_atom(arena, pos(0, 3), name, AtomKind::Normal),
+        ];
+        let body = Syntax::new_list(
+            arena,
+            "{",
+            pos(0, 5),
+            vec![Syntax::new_atom(arena, pos(1, 4), body, AtomKind::Normal)],
+            "}",
+            pos(2, 0),
+        );
+        Syntax::new_list(
+            arena,
+            "",
+            pos(0, 0),
+            signature.into_iter().chain(std::iter::once(body)).collect(),
+            "",
+            pos(2, 1),
+        )
+    }
+
+    #[test]
+    fn test_signature_only() {
+        let arena = Arena::new();
+        let lhs = definition(&arena, "foo", "x");
+        let rhs = definition(&arena, "bar", "x");
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::default();
+        insert_deep_unchanged(lhs, rhs, &mut change_map);
+        insert_deep_unchanged(rhs, lhs, &mut change_map);
+        let (lhs_name, rhs_name) = match (lhs, rhs) {
+            (Syntax::List { children: l, .. }, Syntax::List { children: r, .. }) => (l[1], r[1]),
+            _ => unreachable!(),
+        };
+        insert_deep_novel(lhs_name, &mut change_map);
+        insert_deep_novel(rhs_name, &mut change_map);
+
+        let changes = find_signature_changes(&[rhs], &change_map);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, SignatureChangeKind::SignatureOnly);
+        assert_eq!(changes[0].rhs_lines, (0.into(), 2.into()));
+    }
+
+    /// The body of `node`, which was built by `definition`.
+    fn body<'a>(node: &'a Syntax<'a>) -> &'a Syntax<'a> {
+        match node {
+            Syntax::List { children, .. } => children[2],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_body_only() {
+        let arena = Arena::new();
+        let lhs = definition(&arena, "foo", "x");
+        let rhs = definition(&arena, "foo", "y");
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::default();
+        insert_deep_unchanged(lhs, rhs, &mut change_map);
+        insert_deep_unchanged(rhs, lhs, &mut change_map);
+        let (lhs_body, rhs_body) = (body(lhs), body(rhs));
+        let (lhs_atom, rhs_atom) = match (lhs_body, rhs_body) {
+            (Syntax::List { children: l, .. }, Syntax::List { children: r, .. }) => (l[0], r[0]),
+            _ => unreachable!(),
+        };
+        insert_deep_novel(lhs_atom, &mut change_map);
+        insert_deep_novel(rhs_atom, &mut change_map);
+
+        let changes = find_signature_changes(&[rhs], &change_map);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, SignatureChangeKind::BodyOnly);
+        assert_eq!(changes[0].rhs_body_lines, (0.into(), 2.into()));
+    }
+
+    #[test]
+    fn test_unchanged_definition() {
+        let arena = Arena::new();
+        let lhs = definition(&arena, "foo", "x");
+        let rhs = definition(&arena, "foo", "x");
+        init_all_info(&[lhs], &[rhs]);
+
+        let mut change_map = ChangeMap::default();
+        insert_deep_unchanged(lhs, rhs, &mut change_map);
+        insert_deep_unchanged(rhs, lhs, &mut change_map);
+
+        assert!(find_signature_changes(&[rhs], &change_map).is_empty());
+    }
+}
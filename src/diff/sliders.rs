@@ -133,7 +133,7 @@ fn fix_nested_slider_prefer_outer<'a>(node: &'a Syntax<'a>, change_map: &mut Cha
                     }
                 }
             }
-            ReplacedComment(_, _) => {}
+            ReplacedComment(_, _) | ReplacedString(_, _) => {}
             Novel => {
                 for child in children {
                     fix_nested_slider_prefer_outer(child, change_map);
@@ -157,7 +157,7 @@ fn fix_nested_slider_prefer_inner<'a>(node: &'a Syntax<'a>, change_map: &mut Cha
                     fix_nested_slider_prefer_inner(child, change_map);
                 }
             }
-            ReplacedComment(_, _) => {}
+            ReplacedComment(_, _) | ReplacedString(_, _) => {}
             Novel => {
                 let mut found_unchanged = vec![];
                 unchanged_descendants(children, &mut found_unchanged, change_map);
@@ -187,7 +187,7 @@ fn unchanged_descendants<'a>(
             Unchanged(_) => {
                 found.push(node);
             }
-            Novel | ReplacedComment(_, _) => {
+            Novel | ReplacedComment(_, _) | ReplacedString(_, _) => {
                 if let List { children, .. } = node {
                     unchanged_descendants(children, found, change_map);
                 }
@@ -341,7 +341,7 @@ fn novel_regions_after_unchanged<'a>(
                     region = Some(r);
                 }
             }
-            ReplacedComment(_, _) => {
+            ReplacedComment(_, _) | ReplacedString(_, _) => {
                 // Could have just finished a novel region.
                 if let Some(region) = region {
                     regions.push(region);
@@ -387,7 +387,7 @@ fn novel_regions_before_unchanged<'a>(
                 r.push(i);
                 region = Some(r);
             }
-            ReplacedComment(_, _) => {
+            ReplacedComment(_, _) | ReplacedString(_, _) => {
                 region = None;
             }
         }
@@ -604,9 +604,10 @@ impl<'a> Syntax<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "lang-elisp")]
+    use crate::parse::tree_sitter_parser::{from_language, parse};
     use crate::{
         parse::guess_language,
-        parse::tree_sitter_parser::{from_language, parse},
         syntax::{init_all_info, AtomKind},
     };
     use pretty_assertions::assert_eq;
@@ -710,10 +711,11 @@ mod tests {
         assert_eq!(change_map.get(lhs[1]), Some(Novel));
         assert_eq!(change_map.get(lhs[2]), Some(Novel));
     }
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_slider_two_steps() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs = parse(&arena, "A B", &config);
         let rhs = parse(&arena, "A B X\n A B", &config);
@@ -1,10 +1,12 @@
 //! Find nodes that are obviously unchanged, so we can run the main
 //! diff on smaller inputs.
 
+use rustc_hash::FxHashMap;
+
 use crate::diff::changes::{insert_deep_unchanged, ChangeKind, ChangeMap};
 use crate::diff::myers_diff;
 
-use crate::parse::syntax::Syntax;
+use crate::parse::syntax::{AtomKind, Syntax};
 
 const TINY_TREE_THRESHOLD: u32 = 10;
 const MOSTLY_UNCHANGED_MIN_NODES: usize = 4;
@@ -23,7 +25,194 @@ pub fn mark_unchanged<'a>(
     for (lhs_nodes, rhs_nodes) in split_mostly_unchanged_toplevel(&lhs_nodes, &rhs_nodes) {
         let (_, lhs_nodes, rhs_nodes) =
             shrink_unchanged_at_ends(&lhs_nodes, &rhs_nodes, change_map);
-        res.extend(split_unchanged(&lhs_nodes, &rhs_nodes, change_map));
+        for (lhs_nodes, rhs_nodes) in split_unchanged(&lhs_nodes, &rhs_nodes, change_map) {
+            res.extend(split_on_anchors(&lhs_nodes, &rhs_nodes, change_map));
+        }
+    }
+
+    res
+}
+
+/// Count how often each content ID occurs in `nodes`, including
+/// their descendants.
+fn count_content_ids(nodes: &[&Syntax], counts: &mut FxHashMap<u32, usize>) {
+    for node in nodes {
+        *counts.entry(node.content_id()).or_insert(0) += 1;
+        if let Syntax::List { children, .. } = node {
+            count_content_ids(children, counts);
+        }
+    }
+}
+
+/// Is `node` an identifier, or similar atom, that we can use as an
+/// anchor? Punctuation and keywords are too common to be useful.
+fn is_anchor_candidate(node: &Syntax) -> bool {
+    match node {
+        Syntax::Atom {
+            kind: AtomKind::Normal,
+            content,
+            ..
+        } => content.chars().any(|c| c.is_alphanumeric()),
+        _ => false,
+    }
+}
+
+/// The indexes of a longest strictly increasing subsequence of
+/// `values`.
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<usize> {
+    // The index of the smallest tail value of an increasing
+    // subsequence of each length, and the predecessor of each value.
+    let mut tails: Vec<usize> = vec![];
+    let mut predecessors: Vec<Option<usize>> = vec![None; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let len = tails.partition_point(|tail| values[*tail] < *value);
+        if len > 0 {
+            predecessors[i] = Some(tails[len - 1]);
+        }
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+
+    let mut res = vec![];
+    let mut i = tails.last().copied();
+    while let Some(index) = i {
+        res.push(index);
+        i = predecessors[index];
+    }
+    res.reverse();
+    res
+}
+
+/// The content IDs of the nodes in `node`, including `node` itself,
+/// that satisfy `is_anchor`.
+fn anchor_ids(node: &Syntax, is_anchor: &impl Fn(&Syntax) -> bool, ids: &mut Vec<u32>) {
+    if is_anchor(node) {
+        ids.push(node.content_id());
+    }
+    if let Syntax::List { children, .. } = node {
+        for child in children {
+            anchor_ids(child, is_anchor, ids);
+        }
+    }
+}
+
+/// Can we match `lhs` with `rhs` because they contain the same
+/// anchor? Atoms must be the anchor itself, and lists must have the
+/// same delimiters, so we can descend into them.
+fn is_anchor_pair(lhs: &Syntax, rhs: &Syntax) -> bool {
+    match (lhs, rhs) {
+        (Syntax::Atom { .. }, Syntax::Atom { .. }) => lhs.content_id() == rhs.content_id(),
+        (
+            Syntax::List {
+                open_content: lhs_open_content,
+                close_content: lhs_close_content,
+                ..
+            },
+            Syntax::List {
+                open_content: rhs_open_content,
+                close_content: rhs_close_content,
+                ..
+            },
+        ) => lhs_open_content == rhs_open_content && lhs_close_content == rhs_close_content,
+        _ => false,
+    }
+}
+
+/// Split a section at identifiers that occur exactly once on each
+/// side, like patience diff but on atoms, and mark them as
+/// unchanged. These anchors shrink the graph search, and stop
+/// repeated short tokens from being matched to the wrong occurrence.
+///
+/// Anchors may be at any depth. If a pair of lists with the same
+/// delimiters contain the same anchor, we mark their delimiters as
+/// unchanged and split their children in the same way.
+fn split_on_anchors<'a>(
+    lhs_nodes: &[&'a Syntax<'a>],
+    rhs_nodes: &[&'a Syntax<'a>],
+    change_map: &mut ChangeMap<'a>,
+) -> Vec<(Vec<&'a Syntax<'a>>, Vec<&'a Syntax<'a>>)> {
+    let mut lhs_counts = FxHashMap::default();
+    count_content_ids(lhs_nodes, &mut lhs_counts);
+    let mut rhs_counts = FxHashMap::default();
+    count_content_ids(rhs_nodes, &mut rhs_counts);
+
+    let is_unique = |node: &Syntax| {
+        is_anchor_candidate(node)
+            && lhs_counts.get(&node.content_id()) == Some(&1)
+            && rhs_counts.get(&node.content_id()) == Some(&1)
+    };
+
+    // The RHS node containing each anchor.
+    let mut rhs_indexes: FxHashMap<u32, usize> = FxHashMap::default();
+    for (j, node) in rhs_nodes.iter().enumerate() {
+        let mut ids = vec![];
+        anchor_ids(node, &is_unique, &mut ids);
+        rhs_indexes.extend(ids.into_iter().map(|id| (id, j)));
+    }
+
+    let mut candidates: Vec<(usize, usize)> = vec![];
+    for (i, node) in lhs_nodes.iter().enumerate() {
+        let mut ids = vec![];
+        anchor_ids(node, &is_unique, &mut ids);
+        for id in ids {
+            if let Some(j) = rhs_indexes.get(&id) {
+                if is_anchor_pair(node, rhs_nodes[*j]) {
+                    candidates.push((i, *j));
+                }
+            }
+        }
+    }
+    // A pair of lists may share several anchors, and a list may share
+    // anchors with several lists on the other side. Putting the
+    // largest RHS index first for each LHS node ensures that the
+    // increasing subsequence below uses each node at most once.
+    candidates
+        .sort_unstable_by(|(lhs_i, lhs_j), (rhs_i, rhs_j)| lhs_i.cmp(rhs_i).then(rhs_j.cmp(lhs_j)));
+    candidates.dedup();
+
+    // Only keep anchors that are in the same order on both sides.
+    let rhs_order: Vec<usize> = candidates.iter().map(|(_, j)| *j).collect();
+    let anchors: Vec<(usize, usize)> = longest_increasing_subsequence(&rhs_order)
+        .into_iter()
+        .map(|k| candidates[k])
+        .collect();
+
+    let mut res = vec![];
+    let (mut lhs_start, mut rhs_start) = (0, 0);
+    for (i, j) in anchors {
+        if lhs_start < i || rhs_start < j {
+            res.push((
+                lhs_nodes[lhs_start..i].to_vec(),
+                rhs_nodes[rhs_start..j].to_vec(),
+            ));
+        }
+        change_map.insert(lhs_nodes[i], ChangeKind::Unchanged(rhs_nodes[j]));
+        change_map.insert(rhs_nodes[j], ChangeKind::Unchanged(lhs_nodes[i]));
+        if let (
+            Syntax::List {
+                children: lhs_children,
+                ..
+            },
+            Syntax::List {
+                children: rhs_children,
+                ..
+            },
+        ) = (lhs_nodes[i], rhs_nodes[j])
+        {
+            res.extend(split_on_anchors(lhs_children, rhs_children, change_map));
+        }
+        lhs_start = i + 1;
+        rhs_start = j + 1;
+    }
+    if lhs_start < lhs_nodes.len() || rhs_start < rhs_nodes.len() {
+        res.push((
+            lhs_nodes[lhs_start..].to_vec(),
+            rhs_nodes[rhs_start..].to_vec(),
+        ));
     }
 
     res
@@ -170,7 +359,7 @@ fn is_mostly_unchanged_list(lhs: &Syntax, rhs: &Syntax) -> bool {
 /// This is important in cases where we have two adjacent lists that
 /// have a small number of changes.
 ///
-/// ```
+/// ```text
 /// ; old
 /// (1 2 3 4) (a b c d)
 ///
@@ -431,17 +620,91 @@ fn shrink_unchanged_at_ends<'a>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(any(feature = "lang-elisp", feature = "lang-rust"))]
     use crate::{
         parse::guess_language,
         parse::tree_sitter_parser::{from_language, parse},
         syntax::init_all_info,
     };
+    #[cfg(any(feature = "lang-elisp", feature = "lang-rust"))]
     use typed_arena::Arena;
 
+    #[test]
+    fn test_longest_increasing_subsequence() {
+        assert_eq!(longest_increasing_subsequence(&[]), Vec::<usize>::new());
+        assert_eq!(
+            longest_increasing_subsequence(&[3, 0, 1, 4, 2]),
+            vec![1, 2, 4]
+        );
+    }
+
+    #[cfg(feature = "lang-elisp")]
+    #[test]
+    fn test_split_on_anchors() {
+        let arena = Arena::new();
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
+
+        let lhs_nodes = parse(&arena, "a a foo b a", &config);
+        let rhs_nodes = parse(&arena, "a foo b b", &config);
+        init_all_info(&lhs_nodes, &rhs_nodes);
+
+        let mut change_map = ChangeMap::default();
+        let res = split_on_anchors(&lhs_nodes, &rhs_nodes, &mut change_map);
+        assert_eq!(
+            res,
+            vec![
+                (lhs_nodes[..2].to_vec(), rhs_nodes[..1].to_vec()),
+                (lhs_nodes[3..].to_vec(), rhs_nodes[2..].to_vec()),
+            ]
+        );
+        assert!(matches!(
+            change_map.get(lhs_nodes[2]),
+            Some(ChangeKind::Unchanged(_))
+        ));
+    }
+
+    /// Find the atom with `content` in `nodes`, at any depth.
+    #[cfg(feature = "lang-rust")]
+    fn find_atom<'a>(nodes: &[&'a Syntax<'a>], content: &str) -> Option<&'a Syntax<'a>> {
+        nodes.iter().find_map(|node| match node {
+            Syntax::Atom {
+                content: atom_content,
+                ..
+            } if atom_content == content => Some(*node),
+            Syntax::List { children, .. } => find_atom(children, content),
+            _ => None,
+        })
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_split_on_anchors_in_lists() {
+        let arena = Arena::new();
+        let config = from_language(guess_language::Language::Rust).unwrap();
+
+        // Every top-level node is a list, so the anchor is nested.
+        let lhs_nodes = parse(&arena, "fn f() { a(); foo(); b(); }", &config);
+        let rhs_nodes = parse(&arena, "fn f() { a(); a(); foo(); b(); b(); }", &config);
+        init_all_info(&lhs_nodes, &rhs_nodes);
+
+        let mut change_map = ChangeMap::default();
+        let res = split_on_anchors(&lhs_nodes, &rhs_nodes, &mut change_map);
+
+        assert!(!res.is_empty());
+        assert!(res
+            .iter()
+            .all(|(lhs_section, _)| !lhs_section.contains(&lhs_nodes[0])));
+        assert!(matches!(
+            change_map.get(find_atom(&lhs_nodes, "foo").unwrap()),
+            Some(ChangeKind::Unchanged(_))
+        ));
+    }
+
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_shrink_unchanged_at_start() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(&arena, "unchanged A B", &config);
         let rhs_nodes = parse(&arena, "unchanged X", &config);
@@ -464,10 +727,11 @@ mod tests {
         assert_eq!(rhs_after_skip.len(), 1);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_shrink_unchanged_at_end() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(&arena, "A B unchanged", &config);
         let rhs_nodes = parse(&arena, "X unchanged", &config);
@@ -490,10 +754,11 @@ mod tests {
         assert_eq!(rhs_after_skip.len(), 1);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_shrink_unchanged_nested() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(&arena, "unchanged-before (more-unchanged (A))", &config);
         let rhs_nodes = parse(&arena, "unchanged-before (more-unchanged (B))", &config);
@@ -515,10 +780,11 @@ mod tests {
         assert_eq!(change_map.get(rhs_after_skip[0]), None);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_unchanged_toplevel_at_start() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         // Make sure that the initial unchanged node exceeds TINY_TREE_THRESHOLD.
         let lhs_nodes = parse(&arena, "(unchanged (1 2 3 4 5 6 7 8 9 10)) A B", &config);
@@ -544,10 +810,11 @@ mod tests {
         assert_eq!(rhs_after_skip.len(), 1);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_unchanged_toplevel_at_end() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(&arena, "A B (unchanged (1 2 3 4 5 6 7 8 9 10))", &config);
         let rhs_nodes = parse(&arena, "X (unchanged (1 2 3 4 5 6 7 8 9 10))", &config);
@@ -572,10 +839,11 @@ mod tests {
         assert_eq!(rhs_after_skip.len(), 1);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_preserves_outer_delimiters() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(&arena, "(A)", &config);
         let rhs_nodes = parse(&arena, "(B)", &config);
@@ -599,10 +867,11 @@ mod tests {
         assert_eq!(change_map.get(rhs_nodes[0]), None);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_unchanged_middle() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(
             &arena,
@@ -636,10 +905,11 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_unchanged_multiple() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(
             &arena,
@@ -664,10 +934,11 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_unchanged_outer_delimiter() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(
             &arena,
@@ -691,10 +962,11 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_split_mostly_unchanged_toplevel() {
         let arena = Arena::new();
-        let config = from_language(guess_language::Language::EmacsLisp);
+        let config = from_language(guess_language::Language::EmacsLisp).unwrap();
 
         let lhs_nodes = parse(
             &arena,
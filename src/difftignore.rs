@@ -0,0 +1,209 @@
+//! Reading `.difftignore` files, which exclude paths from directory
+//! and git diffs, or set options for them.
+//!
+//! The syntax is the same as `.gitignore`. A pattern may be followed
+//! by options, in which case matching paths are still diffed but
+//! with those options:
+//!
+//! ```text
+//! vendor/
+//! !vendor/patches/
+//! *.lock summary-only
+//! ```
+
+use std::{fs, path::Path};
+
+use regex::Regex;
+
+use crate::{
+    editorconfig::glob_regex,
+    files::{display_path, slash_path, FileTree},
+    profiles::Profile,
+    warnings,
+};
+
+/// The options that a `.difftignore` pattern can set. These are
+/// applied like a profile.
+const OPTION_NAMES: &[&str] = &["summary-only", "expand-definitions"];
+
+#[derive(Debug)]
+struct Rule {
+    regex: Regex,
+    /// The pattern started with `!`, so it re-includes paths.
+    negated: bool,
+    /// The pattern ended with `/`, so it only matches directories.
+    dir_only: bool,
+    options: Profile,
+    has_options: bool,
+}
+
+impl Rule {
+    /// Does this rule match `rel_path`, or a directory containing it?
+    fn is_match(&self, rel_path: &str) -> bool {
+        let is_dir_match = rel_path
+            .match_indices('/')
+            .any(|(i, _)| self.regex.is_match(&rel_path[..i]));
+        is_dir_match || (!self.dir_only && self.regex.is_match(rel_path))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DifftIgnore {
+    rules: Vec<Rule>,
+}
+
+fn parse_line(line: &str, source: &str) -> Option<Rule> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut parts = line.split_whitespace();
+    let pattern = parts.next()?;
+    let (pattern, negated) = match pattern.strip_prefix('!') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    // A pattern with options, even unknown ones, never excludes
+    // paths.
+    let option_names: Vec<&str> = parts.collect();
+    let mut options = Profile::default();
+    for option in &option_names {
+        match *option {
+            "summary-only" => options.summary_only = true,
+            "expand-definitions" => options.expand_definitions = true,
+            _ => warnings::warn(format!(
+                "{}: unknown option '{}', expected one of: {}",
+                source,
+                option,
+                OPTION_NAMES.join(", ")
+            )),
+        }
+    }
+
+    Some(Rule {
+        regex: glob_regex(pattern)?,
+        negated,
+        dir_only,
+        options,
+        has_options: !option_names.is_empty(),
+    })
+}
+
+impl DifftIgnore {
+    fn parse(contents: &str, source: &str) -> Self {
+        Self {
+            rules: contents
+                .lines()
+                .filter_map(|line| parse_line(line, source))
+                .collect(),
+        }
+    }
+
+    /// Read `.difftignore` in `dir`. If there isn't one, nothing is
+    /// ignored.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(".difftignore");
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents, &display_path(&path)),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The rules for diffing two trees: the new tree's `.difftignore`,
+    /// falling back to the old tree's.
+    pub fn for_trees(lhs_tree: &FileTree, rhs_tree: &FileTree) -> Self {
+        let rel_path = Path::new(".difftignore");
+        let tree = if rhs_tree.contains(rel_path) {
+            rhs_tree
+        } else {
+            lhs_tree
+        };
+        match tree.read(rel_path) {
+            Ok(bytes) => Self::parse(
+                &String::from_utf8_lossy(&bytes),
+                &display_path(&tree.path(rel_path)),
+            ),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Can we skip everything inside the directory `rel_dir` without
+    /// listing it? This is the case when the directory is excluded
+    /// and no later `!` pattern could include something inside it.
+    pub fn is_dir_excluded(&self, rel_dir: &Path) -> bool {
+        let rel_dir = format!("{}/", slash_path(rel_dir));
+        let rules: Vec<&Rule> = self.rules.iter().filter(|rule| !rule.has_options).collect();
+        match rules.iter().rposition(|rule| rule.is_match(&rel_dir)) {
+            Some(i) => !rules[i].negated && !rules[i + 1..].iter().any(|rule| rule.negated),
+            None => false,
+        }
+    }
+
+    /// Should we skip `rel_path`? As in `.gitignore`, the last
+    /// matching pattern wins.
+    pub fn is_excluded(&self, rel_path: &Path) -> bool {
+        let rel_path = slash_path(rel_path);
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| !rule.has_options)
+            .find(|rule| rule.is_match(&rel_path))
+            .map_or(false, |rule| !rule.negated)
+    }
+
+    /// Turn on the options from every pattern matching `rel_path`.
+    pub fn apply_options(&self, rel_path: &Path, profile: &mut Profile) {
+        let rel_path = slash_path(rel_path);
+        for rule in &self.rules {
+            if rule.has_options && !rule.negated && rule.is_match(&rel_path) {
+                profile.add(rule.options);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_excluded() {
+        let ignore = DifftIgnore::parse("# Vendored code\nvendor/\n!vendor/patches/\n", "test");
+        assert!(ignore.is_excluded(Path::new("vendor/foo/lib.rs")));
+        assert!(!ignore.is_excluded(Path::new("vendor/patches/fix.diff")));
+        assert!(!ignore.is_excluded(Path::new("src/vendor.rs")));
+    }
+
+    #[test]
+    fn test_apply_options() {
+        let ignore = DifftIgnore::parse("*.lock summary-only\n", "test");
+        assert!(!ignore.is_excluded(Path::new("Cargo.lock")));
+
+        let mut profile = Profile::default();
+        ignore.apply_options(Path::new("sub/yarn.lock"), &mut profile);
+        assert!(profile.summary_only);
+    }
+
+    #[test]
+    fn test_is_dir_excluded() {
+        let ignore = DifftIgnore::parse(
+            "node_modules/
+build/
+!build/keep.txt
+",
+            "test",
+        );
+        assert!(ignore.is_dir_excluded(Path::new("node_modules")));
+        assert!(ignore.is_dir_excluded(Path::new("web/node_modules")));
+        assert!(!ignore.is_dir_excluded(Path::new("src")));
+        // A later pattern may include files inside build/, so we
+        // still have to look inside it.
+        assert!(!ignore.is_dir_excluded(Path::new("build")));
+    }
+}
@@ -0,0 +1,273 @@
+//! Per-file change metrics as CSV, for churn analysis across a
+//! repository.
+//!
+//! ```text
+//! path,language,tokens_added,tokens_removed,moves,definitions_touched
+//! src/main.rs,Rust,10,3,1,2
+//! ```
+//!
+//! Counts are of tokens rather than lines, so reformatting doesn't
+//! count as churn.
+
+use std::sync::Once;
+
+use rustc_hash::FxHashSet;
+
+use crate::{
+    diff::moves::MoveKind,
+    display::stat::file_stats,
+    options::DisplayOptions,
+    parse::syntax::{Definition, MatchKind, MatchedPos},
+    summary::{DiffResult, FileContent},
+};
+
+const HEADER: &str = "path,language,tokens_added,tokens_removed,moves,definitions_touched";
+
+/// Quote `s` as a CSV field, if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// The indexes of the definitions in `definitions` that contain a
+/// novel token.
+fn touched_definitions(definitions: &[Definition], mps: &[MatchedPos]) -> FxHashSet<usize> {
+    let mut res = FxHashSet::default();
+    for mp in mps.iter().filter(|mp| mp.kind.is_novel()) {
+        for (i, definition) in definitions.iter().enumerate() {
+            if definition.start <= mp.pos.line && mp.pos.line <= definition.end {
+                res.insert(i);
+            }
+        }
+    }
+    res
+}
+
+/// The index of the RHS definition that `lhs_definition` became,
+/// found from the first unchanged token inside it.
+fn rhs_counterpart(
+    lhs_definition: &Definition,
+    lhs_mps: &[MatchedPos],
+    rhs_definitions: &[Definition],
+) -> Option<usize> {
+    let opposite_line = lhs_mps.iter().find_map(|mp| match &mp.kind {
+        MatchKind::UnchangedToken { opposite_pos, .. }
+            if lhs_definition.start <= mp.pos.line && mp.pos.line <= lhs_definition.end =>
+        {
+            opposite_pos.first().map(|pos| pos.line)
+        }
+        _ => None,
+    })?;
+
+    rhs_definitions
+        .iter()
+        .position(|definition| definition.start <= opposite_line && opposite_line <= definition.end)
+}
+
+/// The number of definitions that contain a novel token. A
+/// definition that exists on both sides is only counted once, even
+/// if it was renamed, and distinct definitions with the same name
+/// are counted separately.
+fn num_touched_definitions(summary: &DiffResult) -> usize {
+    let mut rhs_touched = touched_definitions(&summary.rhs_definitions, &summary.rhs_positions);
+    let mut num_lhs_only = 0;
+    for i in touched_definitions(&summary.lhs_definitions, &summary.lhs_positions) {
+        match rhs_counterpart(
+            &summary.lhs_definitions[i],
+            &summary.lhs_positions,
+            &summary.rhs_definitions,
+        ) {
+            Some(rhs_i) => {
+                rhs_touched.insert(rhs_i);
+            }
+            None => num_lhs_only += 1,
+        }
+    }
+    rhs_touched.len() + num_lhs_only
+}
+
+/// Describe `summary` as a CSV row. Returns `None` if there are no
+/// changes and `print_unchanged` is false.
+fn render_row(summary: &DiffResult, print_unchanged: bool) -> Option<String> {
+    let stats = file_stats(summary);
+    if stats.is_none() && !print_unchanged {
+        return None;
+    }
+    let (added, removed) = stats.map_or((0, 0), |stats| (stats.added, stats.removed));
+
+    let language = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(_), FileContent::Text(_)) => {
+            summary.language.as_deref().unwrap_or("Text")
+        }
+        _ => "binary",
+    };
+    let num_moves = summary
+        .moves
+        .iter()
+        .filter(|m| m.kind == MoveKind::Moved)
+        .count();
+
+    Some(format!(
+        "{},{},{},{},{},{}",
+        csv_field(&summary.rhs_display_path),
+        csv_field(language),
+        added,
+        removed,
+        num_moves,
+        num_touched_definitions(summary)
+    ))
+}
+
+pub fn print(display_options: &DisplayOptions, summary: &DiffResult) {
+    static HEADER_PRINTED: Once = Once::new();
+    HEADER_PRINTED.call_once(|| println!("{}", HEADER));
+
+    if let Some(row) = render_row(summary, display_options.print_unchanged) {
+        println!("{}", row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        display::indentation::IndentationChanges, offsets::OffsetMap, parse::syntax::TokenKind,
+        positions::SingleLineSpan, profiles::Profile,
+    };
+    use pretty_assertions::assert_eq;
+
+    const SRC: &str = "fn foo() {\n    1\n}\nfn foo() {\n    2\n}\n";
+
+    fn span(line: usize) -> SingleLineSpan {
+        SingleLineSpan {
+            line: line.into(),
+            start_col: 0,
+            end_col: 1,
+        }
+    }
+
+    fn novel(line: usize) -> MatchedPos {
+        MatchedPos {
+            kind: MatchKind::Novel {
+                highlight: TokenKind::Delimiter,
+            },
+            pos: span(line),
+        }
+    }
+
+    fn unchanged(line: usize, opposite_line: usize) -> MatchedPos {
+        MatchedPos {
+            kind: MatchKind::UnchangedToken {
+                highlight: TokenKind::Delimiter,
+                self_pos: vec![span(line)],
+                opposite_pos: vec![span(opposite_line)],
+            },
+            pos: span(line),
+        }
+    }
+
+    fn definition(name: &str, start: usize, end: usize) -> Definition {
+        Definition {
+            name: name.into(),
+            start: start.into(),
+            signature_end: start.into(),
+            end: end.into(),
+        }
+    }
+
+    fn text_result(
+        lhs_definitions: Vec<Definition>,
+        rhs_definitions: Vec<Definition>,
+        lhs_positions: Vec<MatchedPos>,
+        rhs_positions: Vec<MatchedPos>,
+    ) -> DiffResult {
+        DiffResult {
+            lhs_display_path: "src/main.rs".into(),
+            rhs_display_path: "src/main.rs".into(),
+            language: Some("Rust".into()),
+            text_fallback: None,
+            encoding: None,
+            lhs_src: FileContent::Text(SRC.into()),
+            rhs_src: FileContent::Text(SRC.into()),
+            lhs_positions,
+            rhs_positions,
+            lhs_parse_errors: None,
+            rhs_parse_errors: None,
+            lhs_novel_ranges: vec![],
+            rhs_novel_ranges: vec![],
+            timed_out_regions: vec![],
+            lhs_definitions,
+            rhs_definitions,
+            moves: vec![],
+            copied: false,
+            status: None,
+            profile: Profile::default(),
+            explanations: vec![],
+            lhs_offsets: OffsetMap::default(),
+            rhs_offsets: OffsetMap::default(),
+            signature_changes: vec![],
+            lhs_indentation: IndentationChanges::default(),
+            rhs_indentation: IndentationChanges::default(),
+        }
+    }
+
+    #[test]
+    fn test_render_row_same_name_definitions() {
+        // Two functions called foo, such as cfg variants, both
+        // changed.
+        let definitions = vec![definition("fn foo()", 0, 2), definition("fn foo()", 3, 5)];
+        let summary = text_result(
+            definitions.clone(),
+            definitions,
+            vec![unchanged(0, 0), novel(1), unchanged(3, 3), novel(4)],
+            vec![unchanged(0, 0), novel(1), unchanged(3, 3), novel(4)],
+        );
+        assert_eq!(
+            render_row(&summary, false),
+            Some("src/main.rs,Rust,2,2,0,2".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_row_renamed_definition() {
+        let summary = text_result(
+            vec![definition("fn foo()", 0, 2)],
+            vec![definition("fn bar()", 0, 2)],
+            vec![novel(0), unchanged(1, 1)],
+            vec![novel(0), unchanged(1, 1)],
+        );
+        assert_eq!(
+            render_row(&summary, false),
+            Some("src/main.rs,Rust,1,1,0,1".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_row_removed_definition() {
+        let summary = text_result(
+            vec![definition("fn foo()", 0, 2), definition("fn foo()", 3, 5)],
+            vec![definition("fn foo()", 3, 5)],
+            vec![novel(0), novel(1), novel(2), unchanged(3, 3)],
+            vec![unchanged(3, 3)],
+        );
+        assert_eq!(
+            render_row(&summary, false),
+            Some("src/main.rs,Rust,0,3,0,1".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_render_row_unchanged() {
+        let summary = text_result(vec![], vec![], vec![], vec![]);
+        assert_eq!(render_row(&summary, false), None);
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("src/main.rs"), "src/main.rs");
+        assert_eq!(csv_field("a,\"b\".rs"), "\"a,\"\"b\"\".rs\"");
+    }
+}
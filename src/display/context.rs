@@ -17,19 +17,76 @@ use crate::{
 /// or end of the file.
 pub const MAX_PADDING: usize = 3;
 
+/// How to align lines that only occur on one side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// Show unmatched lines from both sides on the same row where
+    /// possible, so hunks take up fewer rows.
+    Compact,
+    /// Show every unmatched line opposite a blank filler row, so each
+    /// row only pairs lines that correspond.
+    Filler,
+}
+
 pub fn all_matched_lines_filled(
     lhs_mps: &[MatchedPos],
     rhs_mps: &[MatchedPos],
     lhs_lines: &[&str],
     rhs_lines: &[&str],
 ) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
-    let matched_lines = add_ends(&all_matched_lines(lhs_mps, rhs_mps), lhs_lines, rhs_lines);
+    all_matched_lines_aligned(lhs_mps, rhs_mps, lhs_lines, rhs_lines, Alignment::Compact)
+}
 
-    compact_gaps(&ensure_contiguous(&match_preceding_blanks(
+pub fn all_matched_lines_aligned(
+    lhs_mps: &[MatchedPos],
+    rhs_mps: &[MatchedPos],
+    lhs_lines: &[&str],
+    rhs_lines: &[&str],
+    alignment: Alignment,
+) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
+    let matched_lines = add_ends(&all_matched_lines(lhs_mps, rhs_mps), lhs_lines, rhs_lines);
+    let matched_lines = ensure_contiguous(&match_preceding_blanks(
         &matched_lines,
         lhs_lines,
         rhs_lines,
-    )))
+    ));
+
+    match alignment {
+        Alignment::Compact => compact_gaps(
+            &matched_lines,
+            &lines_with_opposites(lhs_mps),
+            &lines_with_opposites(rhs_mps),
+            lhs_lines,
+            rhs_lines,
+        ),
+        Alignment::Filler => lhs_before_rhs(&matched_lines),
+    }
+}
+
+/// Within each run of lines that only occur on one side, show the
+/// LHS lines first, as in a unified diff.
+fn lhs_before_rhs(
+    items: &[(Option<LineNumber>, Option<LineNumber>)],
+) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
+    let mut res = vec![];
+    let mut rhs_only = vec![];
+    for &(lhs_line, rhs_line) in items {
+        match (lhs_line, rhs_line) {
+            (Some(_), None) => res.push((lhs_line, rhs_line)),
+            (None, Some(_)) => rhs_only.push((lhs_line, rhs_line)),
+            _ => {
+                res.append(&mut rhs_only);
+                res.push((lhs_line, rhs_line));
+            }
+        }
+    }
+    res.append(&mut rhs_only);
+    res
+}
+
+/// The lines in `mps` that contain tokens matched with the other side.
+fn lines_with_opposites(mps: &[MatchedPos]) -> FxHashSet<LineNumber> {
+    opposite_positions(mps).into_keys().collect()
 }
 
 /// Extend `matched_lines` to include the leading and trailing lines
@@ -663,6 +720,12 @@ fn ensure_contiguous(
     res
 }
 
+fn is_blank(lines: &[&str], line_num: LineNumber) -> bool {
+    lines
+        .get(line_num.0)
+        .map_or(false, |line| line.trim().is_empty())
+}
+
 /// Before:
 ///
 /// 10 --
@@ -676,9 +739,35 @@ fn ensure_contiguous(
 /// 11 --
 /// 12 21
 ///
+/// Single-sided lines that contain matched tokens are never
+/// compacted. These occur when one line corresponds to several lines
+/// on the other side, such as a call that a formatter has split
+/// across lines:
+///
+/// 10 20 foo(a, b) | foo(
+/// -- 21           |     a,
+/// -- 22           |     b,
+/// -- 23           | )
+/// 11 --
+///
+/// Pairing 11 with 21 would show unrelated content alongside the
+/// reflowed call, so the continuation lines keep their filler rows.
+///
+/// Blank lines are only compacted with other blank lines. A blank
+/// line that's been added inside a changed block is shown on its own
+/// row, rather than shifting the pairing of every line after it:
+///
+/// 10 20 foo();    | foo();
+/// -- 21           |
+/// 11 22 bar();    | baz();
+///
 /// The returned vec will contain no (None, None) pairs.
 fn compact_gaps(
     items: &[(Option<LineNumber>, Option<LineNumber>)],
+    lhs_with_opposites: &FxHashSet<LineNumber>,
+    rhs_with_opposites: &FxHashSet<LineNumber>,
+    lhs_lines: &[&str],
+    rhs_lines: &[&str],
 ) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
     let mut res: Vec<(Option<LineNumber>, Option<LineNumber>)> = vec![];
     // A vec of the most recent single-sided lines, e.g.
@@ -691,25 +780,69 @@ fn compact_gaps(
 
     for (lhs_line, rhs_line) in items {
         match (lhs_line, rhs_line) {
+            (Some(lhs_line), None) if lhs_with_opposites.contains(lhs_line) => {
+                // A continuation of a reflowed line. Keep the
+                // pending lines on the other side for later.
+                if let Some((Some(_), None)) = unpaired_lines.first() {
+                    res.append(&mut unpaired_lines);
+                }
+                res.push((Some(*lhs_line), None));
+            }
+            (None, Some(rhs_line)) if rhs_with_opposites.contains(rhs_line) => {
+                if let Some((None, Some(_))) = unpaired_lines.first() {
+                    res.append(&mut unpaired_lines);
+                }
+                res.push((None, Some(*rhs_line)));
+            }
             (Some(lhs_line), None) => {
+                let lhs_blank = is_blank(lhs_lines, *lhs_line);
+                if !lhs_blank {
+                    // Blank lines waiting on the other side can't be
+                    // paired with this line.
+                    while let Some((None, Some(rhs_line))) = unpaired_lines.first() {
+                        if !is_blank(rhs_lines, *rhs_line) {
+                            break;
+                        }
+                        res.push(unpaired_lines.remove(0));
+                    }
+                }
+
                 match unpaired_lines.first() {
-                    Some((None, Some(rhs_line))) => {
+                    Some((None, Some(rhs_line))) if is_blank(rhs_lines, *rhs_line) == lhs_blank => {
                         // We've found a line that can be compacted.
                         res.push((Some(*lhs_line), Some(*rhs_line)));
                         unpaired_lines.remove(0);
                     }
+                    Some((None, Some(_))) => {
+                        // A blank line opposite non-blank lines. Keep
+                        // them for the lines after this one.
+                        res.push((Some(*lhs_line), None));
+                    }
                     _ => {
                         unpaired_lines.push((Some(*lhs_line), None));
                     }
                 }
             }
             (None, Some(rhs_line)) => {
+                let rhs_blank = is_blank(rhs_lines, *rhs_line);
+                if !rhs_blank {
+                    while let Some((Some(lhs_line), None)) = unpaired_lines.first() {
+                        if !is_blank(lhs_lines, *lhs_line) {
+                            break;
+                        }
+                        res.push(unpaired_lines.remove(0));
+                    }
+                }
+
                 match unpaired_lines.first() {
-                    Some((Some(lhs_line), None)) => {
+                    Some((Some(lhs_line), None)) if is_blank(lhs_lines, *lhs_line) == rhs_blank => {
                         // We've found a line that can be compacted.
                         res.push((Some(*lhs_line), Some(*rhs_line)));
                         unpaired_lines.remove(0);
                     }
+                    Some((Some(_), None)) => {
+                        res.push((None, Some(*rhs_line)));
+                    }
                     _ => {
                         unpaired_lines.push((None, Some(*rhs_line)));
                     }
@@ -901,11 +1034,17 @@ mod tests {
 
     #[test]
     fn test_compact_gaps() {
-        let res = compact_gaps(&[
-            (Some(0.into()), None),
-            (None, Some(0.into())),
-            (Some(1.into()), Some(1.into())),
-        ]);
+        let res = compact_gaps(
+            &[
+                (Some(0.into()), None),
+                (None, Some(0.into())),
+                (Some(1.into()), Some(1.into())),
+            ],
+            &FxHashSet::default(),
+            &FxHashSet::default(),
+            &["a", "b"],
+            &["c", "d"],
+        );
         assert_eq!(
             res,
             vec![
@@ -917,12 +1056,18 @@ mod tests {
 
     #[test]
     fn test_compact_gaps_with_larger_gap() {
-        let res = compact_gaps(&[
-            (Some(0.into()), None),
-            (Some(1.into()), None),
-            (None, Some(0.into())),
-            (None, Some(1.into())),
-        ]);
+        let res = compact_gaps(
+            &[
+                (Some(0.into()), None),
+                (Some(1.into()), None),
+                (None, Some(0.into())),
+                (None, Some(1.into())),
+            ],
+            &FxHashSet::default(),
+            &FxHashSet::default(),
+            &["a", "b"],
+            &["c", "d"],
+        );
         assert_eq!(
             res,
             vec![
@@ -932,6 +1077,61 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_compact_gaps_keeps_reflowed_lines() {
+        // LHS line 0 was split across RHS lines 0 to 2, and LHS line
+        // 1 is novel.
+        let rhs_with_opposites = FxHashSet::from_iter(vec![0.into(), 1.into(), 2.into()]);
+        let res = compact_gaps(
+            &[
+                (Some(0.into()), Some(0.into())),
+                (None, Some(1.into())),
+                (None, Some(2.into())),
+                (Some(1.into()), None),
+                (Some(2.into()), Some(3.into())),
+            ],
+            &FxHashSet::default(),
+            &rhs_with_opposites,
+            &["a", "b", "c"],
+            &["d", "e", "f", "g"],
+        );
+        assert_eq!(
+            res,
+            vec![
+                (Some(0.into()), Some(0.into())),
+                (None, Some(1.into())),
+                (None, Some(2.into())),
+                (Some(1.into()), None),
+                (Some(2.into()), Some(3.into())),
+            ]
+        )
+    }
+
+    #[test]
+    fn test_compact_gaps_added_blank_line() {
+        let res = compact_gaps(
+            &[
+                (Some(0.into()), None),
+                (Some(1.into()), None),
+                (None, Some(0.into())),
+                (None, Some(1.into())),
+                (None, Some(2.into())),
+            ],
+            &FxHashSet::default(),
+            &FxHashSet::default(),
+            &["foo();", "bar();"],
+            &["foo();", "", "baz();"],
+        );
+        assert_eq!(
+            res,
+            vec![
+                (Some(0.into()), Some(0.into())),
+                (None, Some(1.into())),
+                (Some(1.into()), Some(2.into())),
+            ]
+        )
+    }
+
     #[test]
     fn test_match_preceding_blanks() {
         let lhs_lines = vec!["x", "", "", "y"];
@@ -1031,4 +1231,64 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_filler_alignment() {
+        let lhs_lines = vec!["foo", "old"];
+        let rhs_lines = vec!["foo", "new"];
+
+        let matched_pos = SingleLineSpan {
+            line: 0.into(),
+            start_col: 0,
+            end_col: 3,
+        };
+        let unchanged = MatchedPos {
+            kind: MatchKind::UnchangedToken {
+                highlight: TokenKind::Delimiter,
+                self_pos: vec![matched_pos],
+                opposite_pos: vec![matched_pos],
+            },
+            pos: matched_pos,
+        };
+        let novel = MatchedPos {
+            kind: MatchKind::Novel {
+                highlight: TokenKind::Delimiter,
+            },
+            pos: SingleLineSpan {
+                line: 1.into(),
+                start_col: 0,
+                end_col: 3,
+            },
+        };
+        let lhs_mps = [unchanged.clone(), novel.clone()];
+        let rhs_mps = [unchanged, novel];
+
+        assert_eq!(
+            all_matched_lines_aligned(
+                &lhs_mps,
+                &rhs_mps,
+                &lhs_lines,
+                &rhs_lines,
+                Alignment::Compact
+            ),
+            vec![
+                (Some(0.into()), Some(0.into())),
+                (Some(1.into()), Some(1.into())),
+            ]
+        );
+        assert_eq!(
+            all_matched_lines_aligned(
+                &lhs_mps,
+                &rhs_mps,
+                &lhs_lines,
+                &rhs_lines,
+                Alignment::Filler
+            ),
+            vec![
+                (Some(0.into()), Some(0.into())),
+                (Some(1.into()), None),
+                (None, Some(1.into())),
+            ]
+        );
+    }
 }
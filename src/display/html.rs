@@ -0,0 +1,231 @@
+//! HTML output, for showing diffs in web pages.
+//!
+//! Each file is a `<div class="difft-file">` containing a `<table>`
+//! per hunk. Removed content is wrapped in `<del>` and added content
+//! in `<ins>`, so pages can style the diff with CSS.
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    display::{
+        context::all_matched_lines_filled,
+        hunks::{self, file_hunks, matched_lines_for_hunk, Hunk},
+        side_by_side::split_on_newlines,
+        style::definition_header,
+    },
+    i18n::{tr, Message},
+    lines::LineNumber,
+    options::DisplayOptions,
+    parse::syntax::MatchedPos,
+    summary::{DiffResult, FileContent},
+};
+
+/// Escape `s` so it can be used as HTML text or an attribute value.
+pub fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// The byte ranges of novel content on each line, sorted by start.
+fn novel_ranges_by_line(mps: &[MatchedPos]) -> FxHashMap<LineNumber, Vec<(usize, usize)>> {
+    let mut ranges: FxHashMap<LineNumber, Vec<(usize, usize)>> = FxHashMap::default();
+    for mp in mps {
+        if mp.kind.is_novel() {
+            ranges
+                .entry(mp.pos.line)
+                .or_default()
+                .push((mp.pos.start_col, mp.pos.end_col));
+        }
+    }
+    // Each line is sorted independently, so hash order doesn't matter.
+    for line_ranges in ranges.values_mut() {
+        line_ranges.sort_unstable();
+    }
+    ranges
+}
+
+/// Escape `line`, wrapping the novel `ranges` in `tag`.
+///
+/// Tolerant against ranges that overlap, or that extend past the end
+/// of the line.
+fn highlight_line(line: &str, ranges: &[(usize, usize)], tag: &str) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut i = 0;
+    for &(start, end) in ranges {
+        let start = start.max(i).min(line.len());
+        let end = end.min(line.len());
+        if start >= end || !line.is_char_boundary(start) || !line.is_char_boundary(end) {
+            continue;
+        }
+
+        res.push_str(&escape(&line[i..start]));
+        res.push_str(&format!("<{}>{}</{}>", tag, escape(&line[start..end]), tag));
+        i = end;
+    }
+    res.push_str(&escape(&line[i..]));
+    res
+}
+
+/// The line number cell and the content cell for one side of a row.
+fn side_cells(
+    line_num: Option<LineNumber>,
+    lines: &[&str],
+    ranges: &FxHashMap<LineNumber, Vec<(usize, usize)>>,
+    tag: &str,
+) -> String {
+    match line_num {
+        Some(line_num) => {
+            let line = lines.get(line_num.0).copied().unwrap_or("");
+            let line_ranges = ranges.get(&line_num).map_or(&[][..], |r| &r[..]);
+            format!(
+                "<td class=\"difft-line-num\">{}</td><td class=\"difft-{}\">{}</td>",
+                line_num.one_indexed(),
+                tag,
+                highlight_line(line, line_ranges, tag)
+            )
+        }
+        None => "<td class=\"difft-line-num\"></td><td></td>".into(),
+    }
+}
+
+fn text_body(
+    lhs_src: &str,
+    rhs_src: &str,
+    summary: &DiffResult,
+    group_by_definition: bool,
+) -> Option<String> {
+    let hunks = file_hunks(
+        lhs_src,
+        rhs_src,
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+    );
+    if hunks.is_empty() {
+        return None;
+    }
+
+    let lhs_lines = split_on_newlines(lhs_src);
+    let rhs_lines = split_on_newlines(rhs_src);
+    let lhs_ranges = novel_ranges_by_line(&summary.lhs_positions);
+    let rhs_ranges = novel_ranges_by_line(&summary.rhs_positions);
+    let matched_lines = all_matched_lines_filled(
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+        &lhs_lines,
+        &rhs_lines,
+    );
+
+    let hunk_table = |hunk: &Hunk| {
+        let mut table = String::from("<table class=\"difft-hunk\">\n");
+        for (lhs_line_num, rhs_line_num) in matched_lines_for_hunk(&matched_lines, hunk) {
+            table.push_str(&format!(
+                "<tr>{}{}</tr>\n",
+                side_cells(lhs_line_num, &lhs_lines, &lhs_ranges, "del"),
+                side_cells(rhs_line_num, &rhs_lines, &rhs_ranges, "ins")
+            ));
+        }
+        table.push_str("</table>\n");
+        table
+    };
+
+    let mut body = String::new();
+    if group_by_definition {
+        // Each definition is a collapsible section.
+        for (definition, hunks) in
+            hunks::group_by_definition(&hunks, &summary.lhs_definitions, &summary.rhs_definitions)
+        {
+            body.push_str(&format!(
+                "<details open>\n<summary>{}</summary>\n",
+                escape(&definition_header(definition, hunks.len(), false))
+            ));
+            for hunk in &hunks {
+                body.push_str(&hunk_table(hunk));
+            }
+            body.push_str("</details>\n");
+        }
+    } else {
+        for hunk in &hunks {
+            body.push_str(&hunk_table(hunk));
+        }
+    }
+    Some(body)
+}
+
+/// Describe `summary` as an HTML fragment. Returns `None` if there
+/// are no changes and `print_unchanged` is false.
+pub fn render(
+    summary: &DiffResult,
+    print_unchanged: bool,
+    group_by_definition: bool,
+) -> Option<String> {
+    let body = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            text_body(lhs_src, rhs_src, summary, group_by_definition)
+        }
+        (lhs_src, rhs_src) if lhs_src != rhs_src => {
+            Some(format!("<p>{}</p>\n", tr(Message::BinaryContentsChanged)))
+        }
+        _ => None,
+    };
+    if body.is_none() && !print_unchanged {
+        return None;
+    }
+
+    let language = summary.language.as_deref().unwrap_or("Text");
+    Some(format!(
+        "<div class=\"difft-file\">\n<div class=\"difft-header\">{} --- {}</div>\n{}</div>",
+        escape(&summary.rhs_display_path),
+        escape(language),
+        body.unwrap_or_else(|| "<p>No changes.</p>\n".into())
+    ))
+}
+
+pub fn print(display_options: &DisplayOptions, summary: &DiffResult) {
+    if let Some(html) = render(
+        summary,
+        display_options.print_unchanged,
+        display_options.group_by_definition,
+    ) {
+        println!("{}", html);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        assert_eq!(
+            highlight_line("let x = a<b;", &[(4, 5), (8, 11)], "ins"),
+            "let <ins>x</ins> = <ins>a&lt;b</ins>;"
+        );
+    }
+
+    #[test]
+    fn test_highlight_line_out_of_bounds() {
+        assert_eq!(
+            highlight_line("foo", &[(0, 2), (1, 10)], "del"),
+            "<del>fo</del><del>o</del>"
+        );
+    }
+}
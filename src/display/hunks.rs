@@ -5,16 +5,21 @@
 /// If we exceed this, the lines are stored in separate hunks.
 const MAX_DISTANCE: usize = 4;
 
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt};
 
 use rustc_hash::FxHashMap;
 
 use crate::{
     constants::Side,
+    diff::{
+        moves::{Move, Span},
+        signatures::{SignatureChange, SignatureChangeKind},
+    },
     display::context::{add_context, opposite_positions, MAX_PADDING},
     display::side_by_side::lines_with_novel,
-    lines::LineNumber,
-    parse::syntax::{zip_pad_shorter, MatchedPos},
+    lines::{LineNumber, MaxLine},
+    parse::syntax::{zip_pad_shorter, AtomKind, Definition, MatchKind, MatchedPos, TokenKind},
+    summary::TimedOutRegion,
 };
 
 /// A hunk represents a series of modified lines that are displayed
@@ -30,6 +35,27 @@ pub struct Hunk {
     pub lines: Vec<(Option<LineNumber>, Option<LineNumber>)>,
 }
 
+/// Where a batch of hunks comes in its file, so headers are numbered
+/// consistently when a file's hunks are printed in several batches,
+/// such as one per definition.
+#[derive(Debug, Clone, Copy)]
+pub struct HunkNumbering {
+    /// The zero-indexed position of the first hunk in the batch.
+    pub first: usize,
+    /// The number of hunks in the file.
+    pub total: usize,
+}
+
+impl HunkNumbering {
+    /// Number `hunks` as every hunk in the file.
+    pub fn all(hunks: &[Hunk]) -> Self {
+        Self {
+            first: 0,
+            total: hunks.len(),
+        }
+    }
+}
+
 impl Hunk {
     pub fn merge(self, other: &Self) -> Self {
         let mut lines = self.lines;
@@ -72,6 +98,127 @@ impl Hunk {
             lines: deduped_lines,
         }
     }
+
+    /// Count the lines added and removed in this hunk, and the moves
+    /// that end in it. Lines that are part of a move aren't counted
+    /// as additions or removals.
+    pub fn counts(&self, moves: &[Move]) -> HunkCounts {
+        let in_span =
+            |line: &LineNumber, span: &Span| span.start.line <= *line && *line <= span.end.line;
+
+        let hunk_moves: Vec<&Move> = moves
+            .iter()
+            .filter(|m| self.novel_rhs.contains(&m.rhs.start.line))
+            .collect();
+
+        HunkCounts {
+            additions: self
+                .novel_rhs
+                .iter()
+                .filter(|line| !hunk_moves.iter().any(|m| in_span(line, &m.rhs)))
+                .count(),
+            removals: self
+                .novel_lhs
+                .iter()
+                .filter(|line| !hunk_moves.iter().any(|m| in_span(line, &m.lhs)))
+                .count(),
+            moves: hunk_moves.len(),
+        }
+    }
+}
+
+/// The signature change for the definition that `hunk` is inside,
+/// if there is one.
+///
+/// Every change in the hunk must be inside the definition, so the
+/// banner describes the whole hunk. For a body-only change, the hunk
+/// must also change the body rather than just the lines around it.
+pub fn hunk_signature_change<'a>(
+    hunk: &Hunk,
+    signature_changes: &'a [SignatureChange],
+) -> Option<&'a SignatureChange> {
+    if hunk.novel_lhs.is_empty() && hunk.novel_rhs.is_empty() {
+        return None;
+    }
+
+    let inside =
+        |line: &LineNumber, (start, end): (LineNumber, LineNumber)| start <= *line && *line <= end;
+    signature_changes.iter().find(|change| {
+        let all_inside = hunk
+            .novel_lhs
+            .iter()
+            .all(|line| inside(line, change.lhs_lines))
+            && hunk
+                .novel_rhs
+                .iter()
+                .all(|line| inside(line, change.rhs_lines));
+        let changes_body = hunk
+            .novel_lhs
+            .iter()
+            .any(|line| inside(line, change.lhs_body_lines))
+            || hunk
+                .novel_rhs
+                .iter()
+                .any(|line| inside(line, change.rhs_body_lines));
+
+        all_inside
+            && match change.kind {
+                SignatureChangeKind::BodyOnly => changes_body,
+                SignatureChangeKind::SignatureOnly => true,
+            }
+    })
+}
+
+/// The region that we diffed as text after exceeding `--time-limit`,
+/// if `hunk` changes any lines inside one.
+pub fn hunk_timed_out_region<'a>(
+    hunk: &Hunk,
+    timed_out_regions: &'a [TimedOutRegion],
+) -> Option<&'a TimedOutRegion> {
+    let inside = |line: &LineNumber, lines: Option<(LineNumber, LineNumber)>| {
+        lines.map_or(false, |(start, end)| start <= *line && *line <= end)
+    };
+    timed_out_regions.iter().find(|region| {
+        hunk.novel_lhs
+            .iter()
+            .any(|line| inside(line, region.lhs_lines))
+            || hunk
+                .novel_rhs
+                .iter()
+                .any(|line| inside(line, region.rhs_lines))
+    })
+}
+
+/// A summary of how much a hunk changes, shown in its header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HunkCounts {
+    pub additions: usize,
+    pub removals: usize,
+    pub moves: usize,
+}
+
+impl fmt::Display for HunkCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize, word: &str| {
+            if n == 1 {
+                format!("{} {}", n, word)
+            } else {
+                format!("{} {}s", n, word)
+            }
+        };
+
+        let mut parts = vec![];
+        if self.additions > 0 {
+            parts.push(plural(self.additions, "addition"));
+        }
+        if self.removals > 0 {
+            parts.push(plural(self.removals, "removal"));
+        }
+        if self.moves > 0 {
+            parts.push(plural(self.moves, "move"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
 }
 
 fn fill_between(
@@ -575,6 +722,80 @@ pub fn matched_pos_to_hunks(lhs_mps: &[MatchedPos], rhs_mps: &[MatchedPos]) -> V
     lines_to_hunks(&matched_novel_lines(lhs_mps, rhs_mps), lhs_mps, rhs_mps)
 }
 
+/// Find the hunks to display for a file, merging hunks that are close
+/// together.
+pub fn file_hunks(
+    lhs_src: &str,
+    rhs_src: &str,
+    lhs_mps: &[MatchedPos],
+    rhs_mps: &[MatchedPos],
+) -> Vec<Hunk> {
+    let opposite_to_lhs = opposite_positions(lhs_mps);
+    let opposite_to_rhs = opposite_positions(rhs_mps);
+
+    let hunks = matched_pos_to_hunks(lhs_mps, rhs_mps);
+    merge_adjacent(
+        &hunks,
+        &opposite_to_lhs,
+        &opposite_to_rhs,
+        lhs_src.max_line(),
+        rhs_src.max_line(),
+    )
+}
+
+/// The order to display hunks in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkOrder {
+    /// The order they occur in the file.
+    Position,
+    /// Hunks with the most changed lines first.
+    Size,
+    /// Hunks that change code first, then hunks that only change
+    /// comments.
+    Semantic,
+}
+
+pub fn is_novel_comment(kind: &MatchKind) -> bool {
+    match kind {
+        MatchKind::Novel { highlight }
+        | MatchKind::NovelWord { highlight }
+        | MatchKind::NovelLinePart { highlight, .. } => {
+            *highlight == TokenKind::Atom(AtomKind::Comment)
+        }
+        MatchKind::UnchangedToken { .. } => false,
+    }
+}
+
+/// Is every novel token on the novel lines of `hunk` a comment?
+fn only_changes_comments(hunk: &Hunk, lhs_mps: &[MatchedPos], rhs_mps: &[MatchedPos]) -> bool {
+    let novel_in_hunk = |mps: &[MatchedPos], lines: &HashSet<LineNumber>| {
+        mps.iter()
+            .filter(|mp| mp.kind.is_novel() && lines.contains(&mp.pos.line))
+            .all(|mp| is_novel_comment(&mp.kind))
+    };
+    novel_in_hunk(lhs_mps, &hunk.novel_lhs) && novel_in_hunk(rhs_mps, &hunk.novel_rhs)
+}
+
+/// Sort `hunks` into `order`. Hunks that are equally interesting
+/// stay in file order.
+pub fn sort_hunks(
+    hunks: &mut [Hunk],
+    order: HunkOrder,
+    lhs_mps: &[MatchedPos],
+    rhs_mps: &[MatchedPos],
+) {
+    match order {
+        HunkOrder::Position => {}
+        HunkOrder::Size => {
+            hunks
+                .sort_by_key(|hunk| std::cmp::Reverse(hunk.novel_lhs.len() + hunk.novel_rhs.len()));
+        }
+        HunkOrder::Semantic => {
+            hunks.sort_by_cached_key(|hunk| only_changes_comments(hunk, lhs_mps, rhs_mps));
+        }
+    }
+}
+
 fn either_side_equal(
     x: &(Option<LineNumber>, Option<LineNumber>),
     y: &(Option<LineNumber>, Option<LineNumber>),
@@ -595,6 +816,41 @@ fn either_side_equal(
     false
 }
 
+/// The name of the definition in `definitions` that contains the
+/// first of `lines`.
+fn containing_definition<'a>(
+    lines: &HashSet<LineNumber>,
+    definitions: &'a [Definition],
+) -> Option<&'a str> {
+    let first_line = lines.iter().min()?;
+    definitions
+        .iter()
+        .find(|d| d.start <= *first_line && *first_line <= d.end)
+        .map(|d| &d.name[..])
+}
+
+/// Group consecutive `hunks` by the top-level definition they start
+/// in. We use the RHS definition unless the hunk only removes
+/// lines. Hunks outside any definition have no name.
+pub fn group_by_definition<'a>(
+    hunks: &[Hunk],
+    lhs_definitions: &'a [Definition],
+    rhs_definitions: &'a [Definition],
+) -> Vec<(Option<&'a str>, Vec<Hunk>)> {
+    let mut groups: Vec<(Option<&str>, Vec<Hunk>)> = vec![];
+    for hunk in hunks {
+        let name = containing_definition(&hunk.novel_rhs, rhs_definitions)
+            .or_else(|| containing_definition(&hunk.novel_lhs, lhs_definitions));
+        match groups.last_mut() {
+            Some((group_name, group_hunks)) if *group_name == name => {
+                group_hunks.push(hunk.clone());
+            }
+            _ => groups.push((name, vec![hunk.clone()])),
+        }
+    }
+    groups
+}
+
 pub fn matched_lines_for_hunk(
     matched_lines: &[(Option<LineNumber>, Option<LineNumber>)],
     hunk: &Hunk,
@@ -648,6 +904,135 @@ pub fn matched_lines_for_hunk(
     matched_lines[start_i..end_i].to_vec()
 }
 
+/// Novel definitions, and unchanged definitions in
+/// side-by-side-show-both display, spanning at least this many lines
+/// are collapsed.
+const MIN_COLLAPSED_LINES: usize = 10;
+
+/// Is `line_num` strictly between the first and last line of a range
+/// that is long enough to collapse?
+fn inside_collapsed_range(line_num: LineNumber, ranges: &[(LineNumber, LineNumber)]) -> bool {
+    ranges.iter().any(|(first, last)| {
+        last.0 - first.0 + 1 >= MIN_COLLAPSED_LINES && *first < line_num && line_num < *last
+    })
+}
+
+/// The range in `ranges` that contains `line_num` and is long enough
+/// to collapse.
+fn collapsible_range(
+    line_num: LineNumber,
+    ranges: &[(LineNumber, LineNumber)],
+) -> Option<(LineNumber, LineNumber)> {
+    ranges.iter().copied().find(|(first, last)| {
+        last.0 - first.0 + 1 >= MIN_COLLAPSED_LINES && *first <= line_num && line_num <= *last
+    })
+}
+
+/// Definitions that are shown as a single row summarising them. Maps
+/// the first line of each definition to its number of lines.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CollapsedDefinitions {
+    pub lhs: FxHashMap<LineNumber, usize>,
+    pub rhs: FxHashMap<LineNumber, usize>,
+}
+
+/// Collapse large definitions that were entirely added or removed,
+/// such as new functions, to their first line, when there's nothing
+/// on the other side to show alongside them.
+///
+/// Returns the lines to display. The first line of each collapsed
+/// definition is added to `collapsed`, so it can be shown as a
+/// summary.
+pub fn collapse_novel_lines(
+    lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    lhs_novel_ranges: &[(LineNumber, LineNumber)],
+    rhs_novel_ranges: &[(LineNumber, LineNumber)],
+    collapsed: &mut CollapsedDefinitions,
+) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
+    let mut res = vec![];
+    for line in lines {
+        let (line_num, ranges, num_lines) = match *line {
+            (Some(lhs_line), None) => (lhs_line, lhs_novel_ranges, &mut collapsed.lhs),
+            (None, Some(rhs_line)) => (rhs_line, rhs_novel_ranges, &mut collapsed.rhs),
+            _ => {
+                res.push(*line);
+                continue;
+            }
+        };
+
+        if let Some((first, last)) = collapsible_range(line_num, ranges) {
+            if line_num == first {
+                num_lines.insert(first, last.0 - first.0 + 1);
+            } else if num_lines.contains_key(&first) {
+                // Only hide lines when we've shown the first line of
+                // the definition, so there's a summary.
+                continue;
+            }
+        }
+        res.push(*line);
+    }
+
+    res
+}
+
+/// The last signature line and the last RHS line of each definition
+/// in `rhs_definitions` whose lines are all unchanged, and aligned
+/// with unchanged LHS lines.
+pub fn unchanged_definition_ranges(
+    rhs_definitions: &[Definition],
+    lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    lhs_lines_with_novel: &HashSet<LineNumber>,
+    rhs_lines_with_novel: &HashSet<LineNumber>,
+) -> Vec<(LineNumber, LineNumber)> {
+    rhs_definitions
+        .iter()
+        .filter(|definition| {
+            lines
+                .iter()
+                .filter(|(_, rhs_line)| {
+                    rhs_line.map_or(false, |rhs_line| {
+                        definition.start <= rhs_line && rhs_line <= definition.end
+                    })
+                })
+                .all(|(lhs_line, rhs_line)| match (lhs_line, rhs_line) {
+                    (Some(lhs_line), Some(rhs_line)) => {
+                        !lhs_lines_with_novel.contains(lhs_line)
+                            && !rhs_lines_with_novel.contains(rhs_line)
+                    }
+                    _ => false,
+                })
+        })
+        .map(|definition| (definition.signature_end, definition.end))
+        .collect()
+}
+
+/// Hide the bodies of unchanged definitions, keeping every line of
+/// their signature and their last line.
+///
+/// Returns the lines to display, each with the number of lines hidden
+/// immediately after it.
+pub fn collapse_unchanged_definitions(
+    lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    unchanged_ranges: &[(LineNumber, LineNumber)],
+) -> Vec<((Option<LineNumber>, Option<LineNumber>), usize)> {
+    let mut res: Vec<((Option<LineNumber>, Option<LineNumber>), usize)> = vec![];
+    for line in lines {
+        if let (Some(_), Some(rhs_line)) = *line {
+            if inside_collapsed_range(rhs_line, unchanged_ranges) {
+                // Unchanged lines are on both sides, so the placeholder
+                // goes after the previous line shown on both sides.
+                if let Some(((Some(_), Some(_)), prev_num_hidden)) = res.last_mut() {
+                    *prev_num_hidden += 1;
+                    continue;
+                }
+            }
+        }
+        res.push((*line, 0));
+    }
+
+    res
+}
+
 #[cfg(test)]
 mod tests {
     use std::iter::FromIterator;
@@ -659,6 +1044,39 @@ mod tests {
     };
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn test_group_by_definition() {
+        let hunk = |rhs_line: usize| Hunk {
+            novel_lhs: HashSet::new(),
+            novel_rhs: HashSet::from_iter(vec![rhs_line.into()]),
+            lines: vec![(None, Some(rhs_line.into()))],
+        };
+        let hunks = vec![hunk(1), hunk(3), hunk(10), hunk(20)];
+        let rhs_definitions = vec![
+            Definition {
+                name: "fn foo()".into(),
+                start: 0.into(),
+                signature_end: 0.into(),
+                end: 5.into(),
+            },
+            Definition {
+                name: "fn bar()".into(),
+                start: 8.into(),
+                signature_end: 8.into(),
+                end: 12.into(),
+            },
+        ];
+
+        let groups: Vec<_> = group_by_definition(&hunks, &[], &rhs_definitions)
+            .into_iter()
+            .map(|(name, hunks)| (name, hunks.len()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![(Some("fn foo()"), 2), (Some("fn bar()"), 1), (None, 1)]
+        );
+    }
+
     #[test]
     fn test_sorted_novel_positions_simple() {
         let novel_mp = MatchedPos {
@@ -821,4 +1239,250 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_collapse_unchanged_definitions() {
+        let lines: Vec<(Option<LineNumber>, Option<LineNumber>)> = (0..12)
+            .map(|i: usize| (Some(i.into()), Some((i + 1).into())))
+            .collect();
+        let definitions = vec![Definition {
+            name: "fn foo()".into(),
+            start: 1.into(),
+            signature_end: 1.into(),
+            end: 12.into(),
+        }];
+        let ranges =
+            unchanged_definition_ranges(&definitions, &lines, &HashSet::new(), &HashSet::new());
+        assert_eq!(ranges, vec![(1.into(), 12.into())]);
+
+        let res = collapse_unchanged_definitions(&lines, &ranges);
+        assert_eq!(
+            res,
+            vec![
+                ((Some(0.into()), Some(1.into())), 10),
+                ((Some(11.into()), Some(12.into())), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_unchanged_definitions_multiline_signature() {
+        let lines: Vec<(Option<LineNumber>, Option<LineNumber>)> = (0..14)
+            .map(|i: usize| (Some(i.into()), Some(i.into())))
+            .collect();
+        let definitions = vec![Definition {
+            name: "fn foo(".into(),
+            start: 0.into(),
+            signature_end: 2.into(),
+            end: 13.into(),
+        }];
+        let ranges =
+            unchanged_definition_ranges(&definitions, &lines, &HashSet::new(), &HashSet::new());
+
+        let res = collapse_unchanged_definitions(&lines, &ranges);
+        assert_eq!(
+            res,
+            vec![
+                ((Some(0.into()), Some(0.into())), 0),
+                ((Some(1.into()), Some(1.into())), 0),
+                ((Some(2.into()), Some(2.into())), 10),
+                ((Some(13.into()), Some(13.into())), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unchanged_definition_ranges_with_novel_line() {
+        let lines: Vec<(Option<LineNumber>, Option<LineNumber>)> = (0..12)
+            .map(|i: usize| (Some(i.into()), Some(i.into())))
+            .collect();
+        let definitions = vec![Definition {
+            name: "fn foo()".into(),
+            start: 0.into(),
+            signature_end: 0.into(),
+            end: 11.into(),
+        }];
+        let rhs_lines_with_novel = HashSet::from_iter(vec![5.into()]);
+        assert!(unchanged_definition_ranges(
+            &definitions,
+            &lines,
+            &HashSet::new(),
+            &rhs_lines_with_novel
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_collapse_novel_lines() {
+        let lines: Vec<_> = (0..12_usize).map(|i| (None, Some(i.into()))).collect();
+
+        let mut collapsed = CollapsedDefinitions::default();
+        let res = collapse_novel_lines(&lines, &[], &[(0.into(), 11.into())], &mut collapsed);
+        assert_eq!(res, vec![(None, Some(0.into()))]);
+        assert_eq!(collapsed.rhs, FxHashMap::from_iter(vec![(0.into(), 12)]));
+        assert!(collapsed.lhs.is_empty());
+    }
+
+    #[test]
+    fn test_collapse_novel_lines_short_range() {
+        let lines: Vec<_> = (0..3_usize).map(|i| (None, Some(i.into()))).collect();
+
+        let mut collapsed = CollapsedDefinitions::default();
+        let res = collapse_novel_lines(&lines, &[], &[(0.into(), 2.into())], &mut collapsed);
+        assert_eq!(res.len(), 3);
+        assert_eq!(collapsed, CollapsedDefinitions::default());
+    }
+
+    #[test]
+    fn test_collapse_novel_lines_without_first_line() {
+        // The hunk starts partway through the definition, so there's
+        // no row to summarise it.
+        let lines: Vec<_> = (5..12_usize).map(|i| (None, Some(i.into()))).collect();
+
+        let mut collapsed = CollapsedDefinitions::default();
+        let res = collapse_novel_lines(&lines, &[], &[(0.into(), 11.into())], &mut collapsed);
+        assert_eq!(res.len(), 7);
+    }
+
+    #[test]
+    fn test_collapse_novel_lines_both_sides() {
+        let lines: Vec<_> = (0..12_usize)
+            .map(|i| (Some(i.into()), Some(i.into())))
+            .collect();
+
+        let mut collapsed = CollapsedDefinitions::default();
+        let res = collapse_novel_lines(&lines, &[(0.into(), 11.into())], &[], &mut collapsed);
+        assert_eq!(res.len(), 12);
+    }
+
+    fn body_only_change() -> SignatureChange {
+        SignatureChange {
+            kind: SignatureChangeKind::BodyOnly,
+            lhs_lines: (0.into(), 5.into()),
+            rhs_lines: (0.into(), 5.into()),
+            lhs_body_lines: (1.into(), 5.into()),
+            rhs_body_lines: (1.into(), 5.into()),
+        }
+    }
+
+    #[test]
+    fn test_hunk_signature_change_body() {
+        let hunk = Hunk {
+            novel_lhs: HashSet::from_iter(vec![2.into()]),
+            novel_rhs: HashSet::from_iter(vec![2.into(), 3.into()]),
+            lines: vec![],
+        };
+        let changes = vec![body_only_change()];
+        assert_eq!(hunk_signature_change(&hunk, &changes), Some(&changes[0]));
+    }
+
+    #[test]
+    fn test_hunk_signature_change_outside_definition() {
+        // The hunk also adds a line after the definition, so the
+        // banner wouldn't describe all of it.
+        let hunk = Hunk {
+            novel_lhs: HashSet::new(),
+            novel_rhs: HashSet::from_iter(vec![4.into(), 7.into()]),
+            lines: vec![],
+        };
+        assert_eq!(hunk_signature_change(&hunk, &[body_only_change()]), None);
+    }
+
+    #[test]
+    fn test_hunk_timed_out_region() {
+        let regions = vec![TimedOutRegion {
+            lhs_lines: None,
+            rhs_lines: Some((10.into(), 20.into())),
+        }];
+
+        let inside = Hunk {
+            novel_lhs: HashSet::new(),
+            novel_rhs: HashSet::from_iter(vec![12.into()]),
+            lines: vec![],
+        };
+        assert_eq!(hunk_timed_out_region(&inside, &regions), Some(&regions[0]));
+
+        // Only the RHS of this region timed out, so LHS line 12 isn't
+        // inside it.
+        let outside = Hunk {
+            novel_lhs: HashSet::from_iter(vec![12.into()]),
+            novel_rhs: HashSet::from_iter(vec![21.into()]),
+            lines: vec![],
+        };
+        assert_eq!(hunk_timed_out_region(&outside, &regions), None);
+    }
+
+    #[test]
+    fn test_hunk_signature_change_not_in_body() {
+        let hunk = Hunk {
+            novel_lhs: HashSet::new(),
+            novel_rhs: HashSet::from_iter(vec![0.into()]),
+            lines: vec![],
+        };
+        assert_eq!(hunk_signature_change(&hunk, &[body_only_change()]), None);
+    }
+
+    #[test]
+    fn test_hunk_counts() {
+        let hunk = Hunk {
+            novel_lhs: HashSet::from_iter(vec![1.into(), 2.into()]),
+            novel_rhs: HashSet::from_iter(vec![1.into(), 5.into(), 6.into()]),
+            lines: vec![],
+        };
+        let span = |start: usize, end: usize| Span {
+            start: SingleLineSpan {
+                line: start.into(),
+                start_col: 0,
+                end_col: 1,
+            },
+            end: SingleLineSpan {
+                line: end.into(),
+                start_col: 0,
+                end_col: 1,
+            },
+        };
+        let moves = vec![Move {
+            kind: crate::diff::moves::MoveKind::Moved,
+            lhs: span(2, 2),
+            rhs: span(5, 6),
+        }];
+
+        let counts = hunk.counts(&moves);
+        assert_eq!(
+            counts,
+            HunkCounts {
+                additions: 1,
+                removals: 1,
+                moves: 1
+            }
+        );
+        assert_eq!(counts.to_string(), "1 addition, 1 removal, 1 move");
+        assert_eq!(hunk.counts(&[]).to_string(), "3 additions, 2 removals");
+    }
+
+    #[test]
+    fn test_sort_hunks_semantic() {
+        let novel = |line: usize, highlight: TokenKind| MatchedPos {
+            kind: MatchKind::Novel { highlight },
+            pos: SingleLineSpan {
+                line: line.into(),
+                start_col: 0,
+                end_col: 1,
+            },
+        };
+        let hunk = |line: usize| Hunk {
+            novel_lhs: HashSet::new(),
+            novel_rhs: HashSet::from_iter(vec![line.into()]),
+            lines: vec![(None, Some(line.into()))],
+        };
+        let rhs_mps = vec![
+            novel(0, TokenKind::Atom(AtomKind::Comment)),
+            novel(10, TokenKind::Atom(AtomKind::Normal)),
+        ];
+
+        let mut hunks = vec![hunk(0), hunk(10)];
+        sort_hunks(&mut hunks, HunkOrder::Semantic, &[], &rhs_mps);
+        assert_eq!(hunks[0].lines, vec![(None, Some(10.into()))]);
+        assert_eq!(hunks[1].lines, vec![(None, Some(0.into()))]);
+    }
 }
@@ -0,0 +1,182 @@
+//! Showing indentation changes with `--show-indentation`.
+//!
+//! We replace tabs with spaces before diffing, and whitespace isn't a
+//! token, so a line whose only change is its indentation would
+//! otherwise look unchanged. Instead, we draw its indentation with
+//! `»` for tabs and `·` for spaces.
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    diff::myers_diff,
+    lines::LineNumber,
+    parse::syntax::{AtomKind, MatchKind, MatchedPos, TokenKind},
+    positions::SingleLineSpan,
+};
+
+/// Lines whose only change is their indentation, mapped to that
+/// indentation drawn with visible characters.
+pub type IndentationChanges = FxHashMap<LineNumber, String>;
+
+fn split_indentation(line: &str) -> (&str, &str) {
+    let rest = line.trim_start_matches(|c| c == ' ' || c == '\t');
+    (&line[..line.len() - rest.len()], rest)
+}
+
+/// Draw `indentation` with one visible character per column, after
+/// tabs have been replaced with `tab_width` spaces.
+fn visible_indentation(indentation: &str, tab_width: usize) -> String {
+    let mut res = String::new();
+    for c in indentation.chars() {
+        if c == '\t' {
+            if tab_width > 0 {
+                res.push('»');
+                res.push_str(&" ".repeat(tab_width - 1));
+            }
+        } else {
+            res.push('·');
+        }
+    }
+    res
+}
+
+/// Find the lines whose only change is their indentation. `lhs_src`
+/// and `rhs_src` must still contain their tabs.
+pub fn find_indentation_changes(
+    lhs_src: &str,
+    rhs_src: &str,
+    tab_width: usize,
+) -> (IndentationChanges, IndentationChanges) {
+    let lhs_lines: Vec<_> = lhs_src.lines().map(split_indentation).collect();
+    let rhs_lines: Vec<_> = rhs_src.lines().map(split_indentation).collect();
+    let lhs_rest: Vec<_> = lhs_lines.iter().map(|(_, rest)| *rest).collect();
+    let rhs_rest: Vec<_> = rhs_lines.iter().map(|(_, rest)| *rest).collect();
+
+    let mut lhs_changes = IndentationChanges::default();
+    let mut rhs_changes = IndentationChanges::default();
+    let mut lhs_i = 0;
+    let mut rhs_i = 0;
+    for diff_res in myers_diff::slice(&lhs_rest, &rhs_rest) {
+        match diff_res {
+            myers_diff::DiffResult::Left(_) => lhs_i += 1,
+            myers_diff::DiffResult::Right(_) => rhs_i += 1,
+            myers_diff::DiffResult::Both(rest, _) => {
+                let (lhs_indentation, _) = lhs_lines[lhs_i];
+                let (rhs_indentation, _) = rhs_lines[rhs_i];
+                // Ignore blank lines, where only trailing whitespace
+                // changed.
+                if lhs_indentation != rhs_indentation && !rest.is_empty() {
+                    lhs_changes.insert(
+                        lhs_i.into(),
+                        visible_indentation(lhs_indentation, tab_width),
+                    );
+                    rhs_changes.insert(
+                        rhs_i.into(),
+                        visible_indentation(rhs_indentation, tab_width),
+                    );
+                }
+                lhs_i += 1;
+                rhs_i += 1;
+            }
+        }
+    }
+    (lhs_changes, rhs_changes)
+}
+
+/// Mark the indentation of each line in `changes` as novel, unless
+/// the line already has novel positions. This makes sure the line is
+/// shown in a hunk.
+pub fn mark_indentation_changes(positions: &mut Vec<MatchedPos>, changes: &IndentationChanges) {
+    let novel_lines: FxHashSet<LineNumber> = positions
+        .iter()
+        .filter(|mp| mp.kind.is_novel())
+        .map(|mp| mp.pos.line)
+        .collect();
+
+    for (line, visible) in changes {
+        if novel_lines.contains(line) {
+            continue;
+        }
+        positions.push(MatchedPos {
+            kind: MatchKind::Novel {
+                highlight: TokenKind::Atom(AtomKind::Normal),
+            },
+            pos: SingleLineSpan {
+                line: *line,
+                start_col: 0,
+                end_col: visible.chars().count(),
+            },
+        });
+    }
+    positions.sort_by_key(|mp| mp.pos);
+}
+
+/// Replace the leading spaces of `line` with `visible`. `line` may
+/// contain ANSI escapes, which are kept. `visible` has one character
+/// per column, so the width of the line doesn't change.
+pub fn show_indentation(line: &str, visible: &str) -> String {
+    let mut res = String::with_capacity(line.len() + visible.len());
+    let mut visible = visible.chars();
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\x1b' {
+            res.push(c);
+            for (_, c) in chars.by_ref() {
+                res.push(c);
+                if c == 'm' {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        match visible.next() {
+            Some(shown) if c == ' ' => res.push(shown),
+            _ => {
+                res.push_str(&line[i..]);
+                break;
+            }
+        }
+    }
+    res
+}
+
+/// Apply `show_indentation` to every line of `src` in `changes`.
+pub fn show_indentation_in_src(src: String, changes: &IndentationChanges) -> String {
+    if changes.is_empty() {
+        return src;
+    }
+
+    src.split('\n')
+        .enumerate()
+        .map(|(i, line)| match changes.get(&LineNumber::from(i)) {
+            Some(visible) => show_indentation(line, visible),
+            None => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_find_indentation_changes() {
+        let (lhs_changes, rhs_changes) =
+            find_indentation_changes("foo\n\tbar\nbaz", "foo\n    bar\nquux", 4);
+
+        assert_eq!(lhs_changes.len(), 1);
+        assert_eq!(lhs_changes[&LineNumber::from(1)], "»   ");
+        assert_eq!(rhs_changes[&LineNumber::from(1)], "····");
+    }
+
+    #[test]
+    fn test_show_indentation_keeps_escapes() {
+        assert_eq!(
+            show_indentation("\x1b[31m  x\x1b[0m", "»·"),
+            "\x1b[31m»·x\x1b[0m"
+        );
+    }
+}
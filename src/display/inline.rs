@@ -1,12 +1,20 @@
 //! Inline, or "unified" diff display.
 
 use crate::{
+    diff::{moves::Move, signatures::SignatureChange},
     display::context::{calculate_after_context, calculate_before_context, opposite_positions},
-    display::hunks::Hunk,
-    display::style::{self, apply_colors},
-    lines::{format_line_num, MaxLine},
+    display::hunks::{
+        collapse_novel_lines, hunk_signature_change, hunk_timed_out_region, CollapsedDefinitions,
+        Hunk, HunkNumbering,
+    },
+    display::indentation::{show_indentation_in_src, IndentationChanges},
+    display::style::{
+        self, apply_colors, collapsed_definition, hunk_minimap, isolate_rtl, trim_trailing_spaces,
+    },
+    lines::{format_line_num, LineNumber, MaxLine},
     options::DisplayOptions,
     parse::syntax::MatchedPos,
+    summary::TimedOutRegion,
 };
 use owo_colors::colored::*;
 
@@ -17,9 +25,17 @@ pub fn print(
     lhs_positions: &[MatchedPos],
     rhs_positions: &[MatchedPos],
     hunks: &[Hunk],
+    numbering: HunkNumbering,
     lhs_display_path: &str,
     rhs_display_path: &str,
     lang_name: &str,
+    lhs_novel_ranges: &[(LineNumber, LineNumber)],
+    rhs_novel_ranges: &[(LineNumber, LineNumber)],
+    moves: &[Move],
+    signature_changes: &[SignatureChange],
+    timed_out_regions: &[TimedOutRegion],
+    lhs_indentation: &IndentationChanges,
+    rhs_indentation: &IndentationChanges,
 ) {
     let (lhs_colored, rhs_colored) = if display_options.use_color {
         (
@@ -28,6 +44,7 @@ pub fn print(
                 true,
                 display_options.syntax_highlight,
                 display_options.background_color,
+                display_options.color_depth,
                 lhs_positions,
             ),
             apply_colors(
@@ -35,31 +52,57 @@ pub fn print(
                 false,
                 display_options.syntax_highlight,
                 display_options.background_color,
+                display_options.color_depth,
                 rhs_positions,
             ),
         )
     } else {
-        (lhs_src.to_string(), rhs_src.to_string())
+        (
+            style::highlight_invisible_chars(lhs_src, false),
+            style::highlight_invisible_chars(rhs_src, false),
+        )
     };
 
+    let lhs_colored = show_indentation_in_src(lhs_colored, lhs_indentation);
+    let rhs_colored = show_indentation_in_src(rhs_colored, rhs_indentation);
     let lhs_lines: Vec<_> = lhs_colored.lines().collect();
     let rhs_lines: Vec<_> = rhs_colored.lines().collect();
+    let lhs_src_lines: Vec<_> = lhs_src.lines().collect();
+    let rhs_src_lines: Vec<_> = rhs_src.lines().collect();
 
     let opposite_to_lhs = opposite_positions(lhs_positions);
     let opposite_to_rhs = opposite_positions(rhs_positions);
 
     for (i, hunk) in hunks.iter().enumerate() {
-        println!(
-            "{}",
-            style::header(
-                lhs_display_path,
-                rhs_display_path,
-                i + 1,
-                hunks.len(),
-                lang_name,
-                display_options
-            )
+        let mut header = style::header(
+            lhs_display_path,
+            rhs_display_path,
+            numbering.first + i + 1,
+            numbering.total,
+            lang_name,
+            Some(hunk.counts(moves)),
+            display_options,
         );
+        if display_options.show_minimap {
+            header = format!(
+                "{} {}",
+                header,
+                hunk_minimap(hunk, lhs_src.max_line(), rhs_src.max_line())
+            );
+        }
+        println!("{}", header);
+        if let Some(change) = hunk_signature_change(hunk, signature_changes) {
+            println!(
+                "{}",
+                style::signature_banner(change.kind, display_options.use_color)
+            );
+        }
+        if let Some(region) = hunk_timed_out_region(hunk, timed_out_regions) {
+            println!(
+                "{}",
+                style::timed_out_banner(region, display_options.use_color)
+            );
+        }
 
         let hunk_lines = hunk.lines.clone();
 
@@ -76,34 +119,111 @@ pub fn print(
 
         for (lhs_line, _) in before_lines {
             if let Some(lhs_line) = lhs_line {
-                println!("{}   {}", format_line_num(lhs_line), lhs_lines[lhs_line.0]);
+                println!(
+                    "{}",
+                    trim_trailing_spaces(
+                        &format!(
+                            "{}   {}",
+                            format_line_num(lhs_line),
+                            isolate_rtl(&lhs_lines[lhs_line.0])
+                        ),
+                        display_options
+                    )
+                );
             }
         }
 
-        for (lhs_line, _) in &hunk_lines {
+        let mut collapsed = CollapsedDefinitions::default();
+        let visible_lines = if display_options.collapse_definitions {
+            collapse_novel_lines(
+                &hunk_lines,
+                lhs_novel_ranges,
+                rhs_novel_ranges,
+                &mut collapsed,
+            )
+        } else {
+            hunk_lines.clone()
+        };
+
+        for (lhs_line, _) in &visible_lines {
             if let Some(lhs_line) = lhs_line {
+                let content = match collapsed.lhs.get(lhs_line) {
+                    Some(num_lines) => {
+                        summary_line(lhs_src_lines[lhs_line.0], *num_lines, true, display_options)
+                    }
+                    None => lhs_lines[lhs_line.0].to_owned(),
+                };
                 println!(
-                    "{}   {}",
-                    format_line_num(*lhs_line).red().bold(),
-                    lhs_lines[lhs_line.0]
+                    "{}",
+                    trim_trailing_spaces(
+                        &format!(
+                            "{}   {}",
+                            format_line_num(*lhs_line).red().bold(),
+                            isolate_rtl(&content)
+                        ),
+                        display_options
+                    )
                 );
             }
         }
-        for (_, rhs_line) in &hunk_lines {
+        for (_, rhs_line) in &visible_lines {
             if let Some(rhs_line) = rhs_line {
+                let content = match collapsed.rhs.get(rhs_line) {
+                    Some(num_lines) => summary_line(
+                        rhs_src_lines[rhs_line.0],
+                        *num_lines,
+                        false,
+                        display_options,
+                    ),
+                    None => rhs_lines[rhs_line.0].to_owned(),
+                };
                 println!(
-                    "   {}{}",
-                    format_line_num(*rhs_line).green().bold(),
-                    rhs_lines[rhs_line.0]
+                    "{}",
+                    trim_trailing_spaces(
+                        &format!(
+                            "   {}{}",
+                            format_line_num(*rhs_line).green().bold(),
+                            isolate_rtl(&content)
+                        ),
+                        display_options
+                    )
                 );
             }
         }
 
         for (_, rhs_line) in &after_lines {
             if let Some(rhs_line) = rhs_line {
-                println!("   {}{}", format_line_num(*rhs_line), rhs_lines[rhs_line.0]);
+                println!(
+                    "{}",
+                    trim_trailing_spaces(
+                        &format!(
+                            "   {}{}",
+                            format_line_num(*rhs_line),
+                            isolate_rtl(&rhs_lines[rhs_line.0])
+                        ),
+                        display_options
+                    )
+                );
             }
         }
         println!();
     }
 }
+
+/// A single row summarising a collapsed definition that starts with
+/// `first_line`.
+fn summary_line(
+    first_line: &str,
+    num_lines: usize,
+    is_lhs: bool,
+    display_options: &DisplayOptions,
+) -> String {
+    let summary = collapsed_definition(first_line, num_lines);
+    if !display_options.use_color {
+        summary
+    } else if is_lhs {
+        summary.red().to_string()
+    } else {
+        summary.green().to_string()
+    }
+}
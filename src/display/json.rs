@@ -0,0 +1,763 @@
+//! Machine-readable output, as one JSON object per file.
+//!
+//! ```text
+//! {"lhs_path":"old.rs","rhs_path":"new.rs","language":"Rust","status":"changed",
+//!  "hunks":[{"lhs_lines":[3,5],"rhs_lines":[3,6],"lhs_scope":[1,8],"rhs_scope":[1,9],"independent":true}],
+//!  "moves":[{"kind":"moved","lhs":{"start":[10,4],"end":[20,5]},"rhs":{"start":[30,4],"end":[40,5]}}]}
+//! ```
+//!
+//! Line numbers are one-indexed, and columns are zero-indexed byte
+//! offsets. With `--show-offsets`, hunks and spans also have an
+//! `"offsets"` pair: the byte offsets from the start of the file on
+//! disk, before we expanded tabs or decoded it.
+//!
+//! `"lhs_parse_errors"` and `"rhs_parse_errors"` are the number of
+//! tree-sitter errors in each file, and the proportion of the file
+//! inside them. They're `null` if the file wasn't parsed with
+//! tree-sitter.
+//!
+//! Each hunk lists its novel tokens on each side (`"lhs_changes"` and
+//! `"rhs_changes"`), with their positions and text.
+//!
+//! Each hunk has the lines of the top-level definitions it touches
+//! on each side (`"lhs_scope"` and `"rhs_scope"`), and whether it's
+//! `"independent"`: no other hunk touches the same definitions. This
+//! is `null` if the hunk changes lines outside every definition, as
+//! we don't know what depends on them.
+//!
+//! With `--schema-version`, each object starts with a
+//! `"schema_version"` field and follows the JSON Schema for that
+//! version (see `--print-schema`). A version only gains optional
+//! fields; renaming or removing a field, or changing its meaning,
+//! needs a new version.
+
+use std::cmp::{max, min};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    constants::Side,
+    diff::moves::{Move, MoveKind, Span},
+    display::hunks::{file_hunks, Hunk},
+    lines::{LineNumber, NewlinePositions},
+    offsets::OffsetMap,
+    options::DisplayOptions,
+    parse::{
+        syntax::{Definition, MatchedPos},
+        tree_sitter_parser::ParseErrors,
+    },
+    positions::SingleLineSpan,
+    summary::{DiffResult, FileContent, FileStatus},
+};
+
+/// The JSON Schema for `--schema-version 1` output.
+pub const SCHEMA_V1: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "difftastic JSON output, one object per file",
+  "type": "object",
+  "required": ["schema_version", "lhs_path", "rhs_path", "language", "status", "hunks", "moves"],
+  "properties": {
+    "schema_version": { "const": 1 },
+    "lhs_path": { "type": "string" },
+    "rhs_path": { "type": "string" },
+    "language": { "type": ["string", "null"] },
+    "status": {
+      "enum": ["added", "deleted", "lhs_truncated", "rhs_truncated", "changed", "unchanged"]
+    },
+    "lhs_parse_errors": { "$ref": "#/$defs/parse_errors" },
+    "rhs_parse_errors": { "$ref": "#/$defs/parse_errors" },
+    "hunks": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["lhs_lines", "rhs_lines"],
+        "properties": {
+          "lhs_lines": { "$ref": "#/$defs/range" },
+          "rhs_lines": { "$ref": "#/$defs/range" },
+          "lhs_offsets": { "$ref": "#/$defs/range" },
+          "rhs_offsets": { "$ref": "#/$defs/range" },
+          "lhs_changes": { "type": "array", "items": { "$ref": "#/$defs/change" } },
+          "rhs_changes": { "type": "array", "items": { "$ref": "#/$defs/change" } },
+          "lhs_scope": { "$ref": "#/$defs/range" },
+          "rhs_scope": { "$ref": "#/$defs/range" },
+          "independent": {
+            "description": "No other hunk in this file touches the same top-level definition, so this hunk can be applied on its own. Null if the hunk changes lines outside every top-level definition, so its dependencies are unknown.",
+            "type": ["boolean", "null"]
+          }
+        }
+      }
+    },
+    "moves": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["kind", "lhs", "rhs"],
+        "properties": {
+          "kind": { "enum": ["moved", "copied"] },
+          "lhs": { "$ref": "#/$defs/span" },
+          "rhs": { "$ref": "#/$defs/span" }
+        }
+      }
+    }
+  },
+  "$defs": {
+    "range": {
+      "description": "An inclusive pair of one-indexed line numbers, or a pair of byte offsets. null if this side has no lines.",
+      "oneOf": [
+        { "type": "array", "items": { "type": "integer", "minimum": 0 }, "minItems": 2, "maxItems": 2 },
+        { "type": "null" }
+      ]
+    },
+    "position": {
+      "description": "A one-indexed line number and a zero-indexed byte column.",
+      "type": "array",
+      "items": { "type": "integer", "minimum": 0 },
+      "minItems": 2,
+      "maxItems": 2
+    },
+    "span": {
+      "type": "object",
+      "required": ["start", "end"],
+      "properties": {
+        "start": { "$ref": "#/$defs/position" },
+        "end": { "$ref": "#/$defs/position" },
+        "offsets": { "$ref": "#/$defs/range" }
+      }
+    },
+    "parse_errors": {
+      "description": "The number of tree-sitter ERROR and MISSING nodes, and the proportion of the file's bytes inside an ERROR node. null if the file wasn't parsed with tree-sitter.",
+      "oneOf": [
+        {
+          "type": "object",
+          "required": ["count", "ratio"],
+          "properties": {
+            "count": { "type": "integer", "minimum": 0 },
+            "ratio": { "type": "number", "minimum": 0, "maximum": 1 }
+          }
+        },
+        { "type": "null" }
+      ]
+    },
+    "change": {
+      "description": "A novel token, which is always on a single line.",
+      "type": "object",
+      "required": ["start", "end", "text"],
+      "properties": {
+        "start": { "$ref": "#/$defs/position" },
+        "end": { "$ref": "#/$defs/position" },
+        "text": { "type": "string" }
+      }
+    }
+  }
+}
+"##;
+
+/// Quote `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+pub fn line_range(lines: &[LineNumber]) -> String {
+    match (lines.iter().min(), lines.iter().max()) {
+        (Some(first), Some(last)) => {
+            format!("[{},{}]", first.one_indexed(), last.one_indexed())
+        }
+        _ => "null".into(),
+    }
+}
+
+/// Converts positions on one side to byte offsets in the original
+/// file.
+struct SideOffsets<'a> {
+    newline_positions: NewlinePositions,
+    offset_map: &'a OffsetMap,
+}
+
+impl<'a> SideOffsets<'a> {
+    fn new(src: &str, offset_map: &'a OffsetMap) -> Self {
+        Self {
+            newline_positions: src.into(),
+            offset_map,
+        }
+    }
+
+    fn line_start(&self, line: LineNumber) -> usize {
+        self.offset_map
+            .original(self.newline_positions.line_offsets(line).0)
+    }
+
+    fn line_end(&self, line: LineNumber) -> usize {
+        self.offset_map
+            .original(self.newline_positions.line_offsets(line).1)
+    }
+
+    fn offset(&self, line: LineNumber, col: usize) -> usize {
+        self.offset_map
+            .original(self.newline_positions.line_offsets(line).0 + col)
+    }
+}
+
+/// The byte offsets of the start of the first line and the end of
+/// the last line in `lines`.
+fn line_offsets(lines: &[LineNumber], side_offsets: &SideOffsets) -> String {
+    match (lines.iter().min(), lines.iter().max()) {
+        (Some(first), Some(last)) => format!(
+            "[{},{}]",
+            side_offsets.line_start(*first),
+            side_offsets.line_end(*last)
+        ),
+        _ => "null".into(),
+    }
+}
+
+/// The lines of the top-level definitions that contain any of
+/// `lines`, or `None` if they're all outside definitions.
+fn enclosing_scope(
+    lines: &[LineNumber],
+    definitions: &[Definition],
+) -> Option<(LineNumber, LineNumber)> {
+    definitions
+        .iter()
+        .filter(|d| lines.iter().any(|line| d.start <= *line && *line <= d.end))
+        .fold(None, |scope, d| match scope {
+            Some((start, end)) => Some((min(start, d.start), max(end, d.end))),
+            None => Some((d.start, d.end)),
+        })
+}
+
+/// Are any of `lines` outside every definition in `definitions`?
+fn outside_definitions(lines: &[LineNumber], definitions: &[Definition]) -> bool {
+    lines.iter().any(|line| {
+        !definitions
+            .iter()
+            .any(|d| d.start <= *line && *line <= d.end)
+    })
+}
+
+/// The enclosing scope of each hunk on each side.
+type HunkScope = (
+    Option<(LineNumber, LineNumber)>,
+    Option<(LineNumber, LineNumber)>,
+);
+
+fn scopes_overlap(
+    scope: Option<(LineNumber, LineNumber)>,
+    other: Option<(LineNumber, LineNumber)>,
+) -> bool {
+    match (scope, other) {
+        (Some((start, end)), Some((other_start, other_end))) => {
+            start <= other_end && other_start <= end
+        }
+        _ => false,
+    }
+}
+
+/// Is each hunk independent of the others? Hunks are independent if
+/// they don't touch the same definition on either side, so tools can
+/// apply a subset of them without breaking the code in between.
+///
+/// Returns `None` for hunks that are `unscoped`, because they change
+/// code outside any definition, so we can't tell what depends on
+/// them.
+fn independent_hunks(scopes: &[HunkScope], unscoped: &[bool]) -> Vec<Option<bool>> {
+    scopes
+        .iter()
+        .zip(unscoped)
+        .enumerate()
+        .map(|(i, ((lhs_scope, rhs_scope), unscoped))| {
+            if *unscoped {
+                return None;
+            }
+            Some(scopes.iter().enumerate().filter(|(j, _)| i != *j).all(
+                |(_, (other_lhs_scope, other_rhs_scope))| {
+                    !scopes_overlap(*lhs_scope, *other_lhs_scope)
+                        && !scopes_overlap(*rhs_scope, *other_rhs_scope)
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_errors_json(errors: Option<ParseErrors>) -> String {
+    match errors {
+        Some(errors) => format!("{{\"count\":{},\"ratio\":{}}}", errors.count, errors.ratio),
+        None => "null".into(),
+    }
+}
+
+fn scope_json(scope: Option<(LineNumber, LineNumber)>) -> String {
+    match scope {
+        Some((start, end)) => format!("[{},{}]", start.one_indexed(), end.one_indexed()),
+        None => "null".into(),
+    }
+}
+
+/// The novel tokens on each line of one side of a file, with the
+/// text of each line.
+struct NovelTokens<'a> {
+    by_line: FxHashMap<LineNumber, Vec<&'a SingleLineSpan>>,
+    lines: Vec<&'a str>,
+}
+
+impl<'a> NovelTokens<'a> {
+    fn new(positions: &'a [MatchedPos], src: &'a str) -> Self {
+        let mut by_line: FxHashMap<LineNumber, Vec<&SingleLineSpan>> = FxHashMap::default();
+        for mp in positions.iter().filter(|mp| mp.kind.is_novel()) {
+            by_line.entry(mp.pos.line).or_default().push(&mp.pos);
+        }
+        Self {
+            by_line,
+            lines: src.lines().collect(),
+        }
+    }
+
+    /// The novel tokens on `lines`, in order, as a JSON array.
+    fn changes_json(&self, lines: &[LineNumber]) -> String {
+        let mut lines = lines.to_vec();
+        lines.sort();
+
+        let mut changes = vec![];
+        for line in lines {
+            let mut spans = self.by_line.get(&line).cloned().unwrap_or_default();
+            spans.sort_by_key(|span| span.start_col);
+
+            let line_text = self.lines.get(line.0).copied().unwrap_or("");
+            for span in spans {
+                changes.push(format!(
+                    "{{\"start\":[{},{}],\"end\":[{},{}],\"text\":{}}}",
+                    line.one_indexed(),
+                    span.start_col,
+                    line.one_indexed(),
+                    span.end_col,
+                    json_string(line_text.get(span.start_col..span.end_col).unwrap_or(""))
+                ));
+            }
+        }
+        format!("[{}]", changes.join(","))
+    }
+}
+
+fn hunk_json(
+    hunk: &Hunk,
+    novel_tokens: Option<&(NovelTokens, NovelTokens)>,
+    offsets: Option<&(SideOffsets, SideOffsets)>,
+    scope: HunkScope,
+    independent: Option<bool>,
+) -> String {
+    let lhs_lines: Vec<_> = hunk.novel_lhs.iter().copied().collect();
+    let rhs_lines: Vec<_> = hunk.novel_rhs.iter().copied().collect();
+    let changes = match novel_tokens {
+        Some((lhs_tokens, rhs_tokens)) => format!(
+            ",\"lhs_changes\":{},\"rhs_changes\":{}",
+            lhs_tokens.changes_json(&lhs_lines),
+            rhs_tokens.changes_json(&rhs_lines)
+        ),
+        None => "".into(),
+    };
+    let offsets = match offsets {
+        Some((lhs_offsets, rhs_offsets)) => format!(
+            ",\"lhs_offsets\":{},\"rhs_offsets\":{}",
+            line_offsets(&lhs_lines, lhs_offsets),
+            line_offsets(&rhs_lines, rhs_offsets)
+        ),
+        None => "".into(),
+    };
+    format!(
+        "{{\"lhs_lines\":{},\"rhs_lines\":{}{}{},\"lhs_scope\":{},\"rhs_scope\":{},\"independent\":{}}}",
+        line_range(&lhs_lines),
+        line_range(&rhs_lines),
+        offsets,
+        changes,
+        scope_json(scope.0),
+        scope_json(scope.1),
+        match independent {
+            Some(independent) => independent.to_string(),
+            None => "null".into(),
+        }
+    )
+}
+
+fn span_json(span: &Span, side_offsets: Option<&SideOffsets>) -> String {
+    let offsets = match side_offsets {
+        Some(side_offsets) => format!(
+            ",\"offsets\":[{},{}]",
+            side_offsets.offset(span.start.line, span.start.start_col),
+            side_offsets.offset(span.end.line, span.end.end_col)
+        ),
+        None => "".into(),
+    };
+    format!(
+        "{{\"start\":[{},{}],\"end\":[{},{}]{}}}",
+        span.start.line.one_indexed(),
+        span.start.start_col,
+        span.end.line.one_indexed(),
+        span.end.end_col,
+        offsets
+    )
+}
+
+fn move_json(m: &Move, offsets: Option<&(SideOffsets, SideOffsets)>) -> String {
+    let kind = match m.kind {
+        MoveKind::Moved => "moved",
+        MoveKind::Copied => "copied",
+    };
+    format!(
+        "{{\"kind\":{},\"lhs\":{},\"rhs\":{}}}",
+        json_string(kind),
+        span_json(&m.lhs, offsets.map(|(lhs, _)| lhs)),
+        span_json(&m.rhs, offsets.map(|(_, rhs)| rhs))
+    )
+}
+
+/// Describe `summary` as a single line of JSON.
+///
+/// `offsets` converts positions on each side to byte offsets, if we
+/// should include them.
+fn to_json(
+    summary: &DiffResult,
+    changed: bool,
+    hunks: &[Hunk],
+    offsets: Option<&(SideOffsets, SideOffsets)>,
+    schema_version: Option<u32>,
+) -> String {
+    // Consumers rely on the fallback reason being part of the
+    // language, as it was before we printed warnings.
+    let language = match (&summary.language, summary.text_fallback) {
+        (Some(language), Some(reason)) => json_string(&format!("{} ({})", language, reason)),
+        (Some(language), None) => json_string(language),
+        (None, _) => "null".into(),
+    };
+
+    let status = match summary.status {
+        Some(FileStatus::Added) => "added",
+        Some(FileStatus::Deleted) => "deleted",
+        Some(FileStatus::Truncated(Side::Left)) => "lhs_truncated",
+        Some(FileStatus::Truncated(Side::Right)) => "rhs_truncated",
+        None if changed => "changed",
+        None => "unchanged",
+    };
+
+    let (scopes, unscoped): (Vec<HunkScope>, Vec<bool>) = hunks
+        .iter()
+        .map(|hunk| {
+            let lhs_lines: Vec<_> = hunk.novel_lhs.iter().copied().collect();
+            let rhs_lines: Vec<_> = hunk.novel_rhs.iter().copied().collect();
+            (
+                (
+                    enclosing_scope(&lhs_lines, &summary.lhs_definitions),
+                    enclosing_scope(&rhs_lines, &summary.rhs_definitions),
+                ),
+                outside_definitions(&lhs_lines, &summary.lhs_definitions)
+                    || outside_definitions(&rhs_lines, &summary.rhs_definitions),
+            )
+        })
+        .unzip();
+    let independent = independent_hunks(&scopes, &unscoped);
+    let novel_tokens = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => Some((
+            NovelTokens::new(&summary.lhs_positions, lhs_src),
+            NovelTokens::new(&summary.rhs_positions, rhs_src),
+        )),
+        _ => None,
+    };
+    let hunks: Vec<_> = hunks
+        .iter()
+        .zip(scopes.iter().zip(independent.iter()))
+        .map(|(hunk, (scope, independent))| {
+            hunk_json(hunk, novel_tokens.as_ref(), offsets, *scope, *independent)
+        })
+        .collect();
+    let moves: Vec<_> = summary
+        .moves
+        .iter()
+        .map(|m| move_json(m, offsets))
+        .collect();
+
+    let schema_version = match schema_version {
+        Some(version) => format!("\"schema_version\":{},", version),
+        None => "".into(),
+    };
+
+    format!(
+        "{{{}\"lhs_path\":{},\"rhs_path\":{},\"language\":{},\"status\":{},\"lhs_parse_errors\":{},\"rhs_parse_errors\":{},\"hunks\":[{}],\"moves\":[{}]}}",
+        schema_version,
+        json_string(&summary.lhs_display_path),
+        json_string(&summary.rhs_display_path),
+        language,
+        json_string(status),
+        parse_errors_json(summary.lhs_parse_errors),
+        parse_errors_json(summary.rhs_parse_errors),
+        hunks.join(","),
+        moves.join(",")
+    )
+}
+
+/// Describe `summary` as JSON. Returns `None` if there are no changes
+/// and `print_unchanged` is false.
+pub fn render(
+    summary: &DiffResult,
+    print_unchanged: bool,
+    show_offsets: bool,
+    schema_version: Option<u32>,
+) -> Option<String> {
+    let (changed, hunks, offsets) = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            let hunks = file_hunks(
+                lhs_src,
+                rhs_src,
+                &summary.lhs_positions,
+                &summary.rhs_positions,
+            );
+            let offsets = if show_offsets {
+                Some((
+                    SideOffsets::new(lhs_src, &summary.lhs_offsets),
+                    SideOffsets::new(rhs_src, &summary.rhs_offsets),
+                ))
+            } else {
+                None
+            };
+            (!hunks.is_empty(), hunks, offsets)
+        }
+        (lhs_src, rhs_src) => (lhs_src != rhs_src, vec![], None),
+    };
+
+    if changed || print_unchanged {
+        Some(to_json(
+            summary,
+            changed,
+            &hunks,
+            offsets.as_ref(),
+            schema_version,
+        ))
+    } else {
+        None
+    }
+}
+
+pub fn print(display_options: &DisplayOptions, summary: &DiffResult) {
+    if let Some(json) = render(
+        summary,
+        display_options.print_unchanged,
+        display_options.show_offsets,
+        display_options.json_schema_version,
+    ) {
+        println!("{}", json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        display::indentation::IndentationChanges,
+        offsets::{clean, OffsetMap},
+        parse::syntax::{AtomKind, MatchKind, TokenKind},
+        profiles::Profile,
+    };
+    use encoding_rs::WINDOWS_1252;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\nd"), "\"a\\\"b\\\\c\\nd\"");
+    }
+
+    #[test]
+    fn test_json_string_control_chars() {
+        assert_eq!(json_string("\u{1b}"), "\"\\u001b\"");
+    }
+
+    #[test]
+    fn test_to_json_unchanged() {
+        let summary = DiffResult {
+            lhs_display_path: "foo.el".into(),
+            rhs_display_path: "foo.el".into(),
+            language: None,
+            text_fallback: None,
+            encoding: None,
+            lhs_src: FileContent::Text("".into()),
+            rhs_src: FileContent::Text("".into()),
+            lhs_positions: vec![],
+            rhs_positions: vec![],
+            lhs_parse_errors: None,
+            rhs_parse_errors: None,
+            lhs_novel_ranges: vec![],
+            rhs_novel_ranges: vec![],
+            timed_out_regions: vec![],
+            lhs_definitions: vec![],
+            rhs_definitions: vec![],
+            moves: vec![],
+            copied: false,
+            status: None,
+            profile: Profile::default(),
+            explanations: vec![],
+            lhs_offsets: OffsetMap::default(),
+            rhs_offsets: OffsetMap::default(),
+            signature_changes: vec![],
+            lhs_indentation: IndentationChanges::default(),
+            rhs_indentation: IndentationChanges::default(),
+        };
+
+        assert_eq!(
+            to_json(&summary, false, &[], None, None),
+            "{\"lhs_path\":\"foo.el\",\"rhs_path\":\"foo.el\",\"language\":null,\"status\":\"unchanged\",\"lhs_parse_errors\":null,\"rhs_parse_errors\":null,\"hunks\":[],\"moves\":[]}"
+        );
+        assert!(to_json(&summary, false, &[], None, Some(1)).starts_with("{\"schema_version\":1,"));
+
+        let summary = DiffResult {
+            language: Some("Text".into()),
+            text_fallback: Some("exceeded DFT_NODE_LIMIT"),
+            ..summary
+        };
+        assert!(to_json(&summary, false, &[], None, None)
+            .contains("\"language\":\"Text (exceeded DFT_NODE_LIMIT)\""));
+
+        let summary = DiffResult {
+            status: Some(FileStatus::Added),
+            ..summary
+        };
+        assert!(to_json(&summary, true, &[], None, None).contains("\"status\":\"added\""));
+        let summary = DiffResult {
+            status: Some(FileStatus::Deleted),
+            ..summary
+        };
+        assert!(to_json(&summary, true, &[], None, None).contains("\"status\":\"deleted\""));
+    }
+
+    #[test]
+    fn test_independent_hunks() {
+        let foo = Some((LineNumber::from(0), LineNumber::from(5)));
+        let bar = Some((LineNumber::from(10), LineNumber::from(15)));
+        assert_eq!(
+            independent_hunks(
+                &[(foo, foo), (foo, foo), (bar, bar), (None, None)],
+                &[false, false, false, true]
+            ),
+            vec![Some(false), Some(false), Some(true), None]
+        );
+    }
+
+    #[test]
+    fn test_outside_definitions() {
+        let definitions = vec![Definition {
+            name: "fn foo()".into(),
+            start: 2.into(),
+            signature_end: 2.into(),
+            end: 5.into(),
+        }];
+        assert!(!outside_definitions(&[3.into(), 5.into()], &definitions));
+        assert!(outside_definitions(&[0.into(), 3.into()], &definitions));
+        assert!(outside_definitions(&[0.into()], &[]));
+    }
+
+    #[test]
+    fn test_span_json_offsets() {
+        let offset_map = OffsetMap::default();
+        let side_offsets = SideOffsets::new("foo\n  bar()\n", &offset_map);
+        let span = Span {
+            start: SingleLineSpan {
+                line: 1.into(),
+                start_col: 2,
+                end_col: 5,
+            },
+            end: SingleLineSpan {
+                line: 1.into(),
+                start_col: 5,
+                end_col: 7,
+            },
+        };
+        assert_eq!(
+            span_json(&span, Some(&side_offsets)),
+            "{\"start\":[2,2],\"end\":[2,7],\"offsets\":[6,11]}"
+        );
+        assert_eq!(span_json(&span, None), "{\"start\":[2,2],\"end\":[2,7]}");
+    }
+
+    fn single_line_span(line: usize, start_col: usize, end_col: usize) -> Span {
+        let pos = SingleLineSpan {
+            line: line.into(),
+            start_col,
+            end_col,
+        };
+        Span {
+            start: pos,
+            end: pos,
+        }
+    }
+
+    #[test]
+    fn test_offsets_tabs() {
+        let (src, _, offset_map) = clean(b"foo\n\tbar()\n", 4, false, None);
+        assert_eq!(src, "foo\n    bar()\n");
+        let side_offsets = SideOffsets::new(&src, &offset_map);
+
+        // "bar" is at columns 4 to 7 after expanding the tab, but
+        // bytes 5 to 8 in the file.
+        assert_eq!(
+            span_json(&single_line_span(1, 4, 7), Some(&side_offsets)),
+            "{\"start\":[2,4],\"end\":[2,7],\"offsets\":[5,8]}"
+        );
+        assert_eq!(line_offsets(&[1.into()], &side_offsets), "[4,10]");
+    }
+
+    #[test]
+    fn test_offsets_latin1() {
+        let bytes = b"caf\xe9\nx = \xe9t\xe9\n";
+        let (src, _, offset_map) = clean(bytes, 4, false, Some(WINDOWS_1252));
+        assert_eq!(src, "café\nx = été\n");
+        let side_offsets = SideOffsets::new(&src, &offset_map);
+
+        // Each é is two bytes in UTF-8 but one byte in Latin-1.
+        assert_eq!(
+            span_json(&single_line_span(1, 4, 9), Some(&side_offsets)),
+            "{\"start\":[2,4],\"end\":[2,9],\"offsets\":[9,12]}"
+        );
+        assert_eq!(line_offsets(&[0.into(), 1.into()], &side_offsets), "[0,12]");
+    }
+
+    #[test]
+    fn test_changes_json() {
+        let positions = vec![
+            MatchedPos {
+                kind: MatchKind::Novel {
+                    highlight: TokenKind::Atom(AtomKind::Normal),
+                },
+                pos: SingleLineSpan {
+                    line: 1.into(),
+                    start_col: 8,
+                    end_col: 11,
+                },
+            },
+            MatchedPos {
+                kind: MatchKind::Novel {
+                    highlight: TokenKind::Atom(AtomKind::Normal),
+                },
+                pos: SingleLineSpan {
+                    line: 1.into(),
+                    start_col: 0,
+                    end_col: 3,
+                },
+            },
+        ];
+        let novel_tokens = NovelTokens::new(&positions, "foo\nlet x = \"y\";\n");
+        assert_eq!(
+            novel_tokens.changes_json(&[1.into()]),
+            "[{\"start\":[2,0],\"end\":[2,3],\"text\":\"let\"},{\"start\":[2,8],\"end\":[2,11],\"text\":\"\\\"y\\\"\"}]"
+        );
+        assert_eq!(novel_tokens.changes_json(&[0.into()]), "[]");
+    }
+}
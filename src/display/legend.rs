@@ -0,0 +1,149 @@
+//! The `--legend` line, which explains the colors used in the diff.
+//!
+//! We also show the legend the first time difftastic runs on a
+//! machine, recording that we've done so with a marker file in the
+//! cache directory.
+
+use std::{env, fs, path::PathBuf};
+
+use owo_colors::{OwoColorize, Style};
+
+use crate::{
+    display::style::{novel_style, syntax_style, ColorDepth},
+    files::cache_dir,
+    i18n::{tr, Message},
+    options::{DisplayMode, DisplayOptions},
+    parse::syntax::AtomKind,
+};
+
+fn marker_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("legend-shown"))
+}
+
+/// Is this the first time we've shown a colored diff on this
+/// machine? If we can't write the marker file, we return false
+/// rather than showing the legend on every run.
+fn is_first_run() -> bool {
+    let path = match marker_path() {
+        Some(path) => path,
+        None => return false,
+    };
+    if path.exists() {
+        return false;
+    }
+
+    let created = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, ""));
+    created.is_ok()
+}
+
+/// The legend line, with each word in the color it describes.
+pub fn legend(display_options: &DisplayOptions) -> String {
+    let background = display_options.background_color;
+    let color_depth = display_options.color_depth;
+    let style = |message: Message, style: Style| {
+        if display_options.use_color {
+            tr(message).style(style).to_string()
+        } else {
+            tr(message).to_owned()
+        }
+    };
+
+    let syntax = if display_options.syntax_highlight {
+        format!(
+            " {} {} {}",
+            style(
+                Message::LegendKeyword,
+                syntax_style(AtomKind::Keyword, background, color_depth)
+            ),
+            style(
+                Message::LegendString,
+                syntax_style(AtomKind::String, background, color_depth)
+            ),
+            style(
+                Message::LegendComment,
+                syntax_style(AtomKind::Comment, background, color_depth)
+            ),
+        )
+    } else {
+        String::new()
+    };
+
+    format!(
+        "{} {} {}{} {}",
+        tr(Message::Legend),
+        style(
+            Message::LegendRemoved,
+            novel_style(Style::new(), true, background, color_depth)
+        ),
+        style(
+            Message::LegendAdded,
+            novel_style(Style::new(), false, background, color_depth)
+        ),
+        syntax,
+        tr(Message::LegendMoved),
+    )
+}
+
+/// Should we show the legend without being asked? Not in CI, where
+/// every run is a first run, or when stdout isn't a terminal, where
+/// the extra line would end up in files or logs.
+fn may_show_automatically(color_depth: ColorDepth, stdout_is_tty: bool, in_ci: bool) -> bool {
+    color_depth != ColorDepth::Ci && stdout_is_tty && !in_ci
+}
+
+/// Print the legend to stderr before the first diff, if the user
+/// asked for it or this is the first run. We only show it for colored
+/// terminal output. On the first run, we also require stdout to be a
+/// terminal and `CI` to be unset.
+pub fn print_if_needed(display_options: &DisplayOptions) {
+    let is_terminal_display = matches!(
+        display_options.display_mode,
+        DisplayMode::Inline
+            | DisplayMode::SideBySide
+            | DisplayMode::SideBySideShowBoth
+            | DisplayMode::LhsOnly
+            | DisplayMode::RhsOnly
+    );
+    if !is_terminal_display || !display_options.use_color {
+        return;
+    }
+
+    let show_automatically = || {
+        may_show_automatically(
+            display_options.color_depth,
+            atty::is(atty::Stream::Stdout),
+            env::var_os("CI").is_some(),
+        ) && is_first_run()
+    };
+    if display_options.show_legend || show_automatically() {
+        eprintln!("{}\n", legend(display_options));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legend_without_color() {
+        let display_options = DisplayOptions {
+            show_legend: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            legend(&display_options),
+            "Colors: removed added keyword string comment (moved code is counted in hunk headers)"
+        );
+    }
+
+    #[test]
+    fn test_may_show_automatically() {
+        assert!(may_show_automatically(ColorDepth::Extended, true, false));
+        assert!(!may_show_automatically(ColorDepth::Extended, false, false));
+        assert!(!may_show_automatically(ColorDepth::Extended, true, true));
+        assert!(!may_show_automatically(ColorDepth::Ci, true, false));
+    }
+}
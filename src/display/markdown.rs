@@ -0,0 +1,198 @@
+//! Markdown output, for posting diffs as pull request comments.
+//!
+//! Each file is a collapsed `<details>` section containing a `<pre>`
+//! block per hunk, laid out like `diff -u`. Markdown has no emphasis
+//! inside fenced code blocks, so we use HTML, which GitHub and GitLab
+//! render in comments, and show novel tokens in bold.
+
+use std::collections::HashSet;
+
+use crate::{
+    display::{
+        context::all_matched_lines_filled,
+        html::escape,
+        hunks::{file_hunks, matched_lines_for_hunk, Hunk},
+        raw_spans::marked_lines,
+        side_by_side::{lines_with_novel, split_on_newlines},
+    },
+    i18n::{tr, Message},
+    lines::LineNumber,
+    options::DisplayOptions,
+    summary::{DiffResult, FileContent},
+};
+
+/// The lines of a unified diff for `hunk`, without a header. Lines
+/// with novel tokens are prefixed with `-` or `+`, and other lines
+/// are context, even if they're opposite a blank row.
+pub fn hunk_diff_lines(
+    hunk: &Hunk,
+    matched_lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    lhs_lines: &[&str],
+    rhs_lines: &[&str],
+    lhs_lines_with_novel: &HashSet<LineNumber>,
+    rhs_lines_with_novel: &HashSet<LineNumber>,
+) -> Vec<String> {
+    let mut res = vec![];
+    // Show removed lines before added lines, as in `diff -u`.
+    let mut removed = vec![];
+    let mut added = vec![];
+
+    for (lhs_line_num, rhs_line_num) in matched_lines_for_hunk(matched_lines, hunk) {
+        let lhs_novel = lhs_line_num.map_or(false, |n| lhs_lines_with_novel.contains(&n));
+        let rhs_novel = rhs_line_num.map_or(false, |n| rhs_lines_with_novel.contains(&n));
+
+        if let (Some(lhs_line_num), true) = (lhs_line_num, lhs_novel) {
+            removed.push(format!("-{}", lhs_lines[lhs_line_num.0]));
+        }
+        if let (Some(rhs_line_num), true) = (rhs_line_num, rhs_novel) {
+            added.push(format!("+{}", rhs_lines[rhs_line_num.0]));
+        }
+
+        let context = match (lhs_line_num, rhs_line_num) {
+            (_, Some(rhs_line_num)) if !rhs_novel => Some(rhs_lines[rhs_line_num.0]),
+            (Some(lhs_line_num), _) if !lhs_novel => Some(lhs_lines[lhs_line_num.0]),
+            _ => None,
+        };
+        if let Some(context) = context {
+            res.append(&mut removed);
+            res.append(&mut added);
+            res.push(format!(" {}", context));
+        }
+    }
+    res.append(&mut removed);
+    res.append(&mut added);
+    res
+}
+
+fn text_body(lhs_src: &str, rhs_src: &str, summary: &DiffResult) -> Option<String> {
+    let hunks = file_hunks(
+        lhs_src,
+        rhs_src,
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+    );
+    if hunks.is_empty() {
+        return None;
+    }
+
+    let lhs_lines = split_on_newlines(lhs_src);
+    let rhs_lines = split_on_newlines(rhs_src);
+    let (lhs_lines_with_novel, rhs_lines_with_novel) =
+        lines_with_novel(&summary.lhs_positions, &summary.rhs_positions);
+    let matched_lines = all_matched_lines_filled(
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+        &lhs_lines,
+        &rhs_lines,
+    );
+
+    let lhs_marked = marked_lines(&lhs_lines, &summary.lhs_positions, "<b>", "</b>", escape);
+    let rhs_marked = marked_lines(&rhs_lines, &summary.rhs_positions, "<b>", "</b>", escape);
+    let lhs_marked: Vec<&str> = lhs_marked.iter().map(|l| l.as_str()).collect();
+    let rhs_marked: Vec<&str> = rhs_marked.iter().map(|l| l.as_str()).collect();
+
+    let mut body = String::new();
+    for hunk in &hunks {
+        let diff = hunk_diff_lines(
+            hunk,
+            &matched_lines,
+            &lhs_marked,
+            &rhs_marked,
+            &lhs_lines_with_novel,
+            &rhs_lines_with_novel,
+        )
+        .join("\n");
+        body.push_str(&format!("<pre>\n{}\n</pre>\n\n", diff));
+    }
+    Some(body)
+}
+
+/// Describe `summary` as a collapsed Markdown section. Returns `None`
+/// if there are no changes and `print_unchanged` is false.
+pub fn render(summary: &DiffResult, print_unchanged: bool) -> Option<String> {
+    let body = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            text_body(lhs_src, rhs_src, summary)
+        }
+        (lhs_src, rhs_src) if lhs_src != rhs_src => {
+            Some(format!("{}\n\n", tr(Message::BinaryContentsChanged)))
+        }
+        _ => None,
+    };
+    if body.is_none() && !print_unchanged {
+        return None;
+    }
+
+    // The summary is HTML, so Markdown isn't rendered inside it.
+    let language = summary.language.as_deref().unwrap_or("Text");
+    Some(format!(
+        "<details>\n<summary><code>{}</code> --- {}</summary>\n\n{}</details>\n",
+        escape(&summary.rhs_display_path),
+        escape(language),
+        body.unwrap_or_else(|| format!("{}\n\n", tr(Message::NoChanges)))
+    ))
+}
+
+pub fn print(display_options: &DisplayOptions, summary: &DiffResult) {
+    if let Some(markdown) = render(summary, display_options.print_unchanged) {
+        println!("{}", markdown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_hunk_diff_lines() {
+        let hunk = Hunk {
+            novel_lhs: vec![1.into()].into_iter().collect(),
+            novel_rhs: vec![1.into()].into_iter().collect(),
+            lines: vec![(Some(1.into()), Some(1.into()))],
+        };
+        let matched_lines = [
+            (Some(0.into()), Some(0.into())),
+            (Some(1.into()), Some(1.into())),
+            (Some(2.into()), Some(2.into())),
+        ];
+        let lhs_lines = ["fn foo() {", "    1", "}"];
+        let rhs_lines = ["fn foo() {", "    2", "}"];
+
+        assert_eq!(
+            hunk_diff_lines(
+                &hunk,
+                &matched_lines,
+                &lhs_lines,
+                &rhs_lines,
+                &hunk.novel_lhs,
+                &hunk.novel_rhs,
+            ),
+            vec![" fn foo() {", "-    1", "+    2", " }"]
+        );
+    }
+
+    #[test]
+    fn test_hunk_diff_lines_unchanged_one_sided() {
+        // An unchanged line opposite a blank row is still context.
+        let hunk = Hunk {
+            novel_lhs: vec![0.into()].into_iter().collect(),
+            novel_rhs: HashSet::new(),
+            lines: vec![(Some(0.into()), None), (Some(1.into()), None)],
+        };
+        let matched_lines = [(Some(0.into()), None), (Some(1.into()), None)];
+        let lhs_lines = ["foo();", ""];
+
+        assert_eq!(
+            hunk_diff_lines(
+                &hunk,
+                &matched_lines,
+                &lhs_lines,
+                &[],
+                &hunk.novel_lhs,
+                &hunk.novel_rhs,
+            ),
+            vec!["-foo();", " "]
+        );
+    }
+}
@@ -1,5 +1,14 @@
+pub mod churn;
 pub mod context;
+pub mod html;
 pub mod hunks;
+pub mod indentation;
 pub mod inline;
+pub mod json;
+pub mod legend;
+pub mod markdown;
+pub mod multi_pane;
+pub mod raw_spans;
 pub mod side_by_side;
+pub mod stat;
 pub mod style;
@@ -0,0 +1,461 @@
+//! Multi-pane display, showing three or more files as columns.
+
+use owo_colors::{OwoColorize, Style};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::{max, min};
+
+use crate::{
+    constants::Side,
+    display::{
+        context::{all_matched_lines_filled, MAX_PADDING},
+        side_by_side::split_on_newlines,
+        style::{
+            apply_header_color, color_positions, novel_style, split_and_apply,
+            trim_trailing_spaces, BackgroundColor, ColorDepth,
+        },
+    },
+    i18n::{tr, Message},
+    lines::{format_line_num, LineNumber},
+    options::DisplayOptions,
+    parse::syntax::MatchedPos,
+    positions::SingleLineSpan,
+};
+
+const SPACER: &str = " ";
+
+/// The line numbers, one per file, that are displayed on the same
+/// row. `None` means that file has no corresponding line.
+type Row = Vec<Option<LineNumber>>;
+
+/// Sizes used when displaying the panes.
+struct PaneDimensions {
+    content_widths: Vec<usize>,
+    line_nums_widths: Vec<usize>,
+}
+
+impl PaneDimensions {
+    fn new(terminal_width: usize, all_lines: &[Vec<&str>]) -> Self {
+        let num_panes = max(1, all_lines.len());
+        let pane_width = terminal_width.saturating_sub(SPACER.len() * (num_panes - 1)) / num_panes;
+
+        let line_nums_widths: Vec<usize> = all_lines
+            .iter()
+            .map(|lines| format_line_num(lines.len().saturating_sub(1).into()).len())
+            .collect();
+        let content_widths = line_nums_widths
+            .iter()
+            .map(|line_nums_width| max(1, pane_width.saturating_sub(*line_nums_width)))
+            .collect();
+
+        Self {
+            content_widths,
+            line_nums_widths,
+        }
+    }
+}
+
+/// Match up the lines of the first file and the `other` file.
+fn aligned_with_first(
+    all_lines: &[Vec<&str>],
+    pairwise_positions: &[Vec<Vec<MatchedPos>>],
+    other: usize,
+) -> Vec<(Option<LineNumber>, Option<LineNumber>)> {
+    let first_mps = &pairwise_positions[0][other];
+    let other_mps = &pairwise_positions[other][0];
+
+    if first_mps.is_empty() && other_mps.is_empty() {
+        // The files are identical, so every line matches.
+        let first_len = all_lines[0].len();
+        let other_len = all_lines[other].len();
+        return (0..max(first_len, other_len))
+            .map(|i| {
+                (
+                    if i < first_len { Some(i.into()) } else { None },
+                    if i < other_len { Some(i.into()) } else { None },
+                )
+            })
+            .collect();
+    }
+
+    all_matched_lines_filled(first_mps, other_mps, &all_lines[0], &all_lines[other])
+}
+
+/// Add a row for each line that only occurs in the other files,
+/// placing lines from different files alongside each other.
+fn push_unmatched(rows: &mut Vec<Row>, unmatched: &[Vec<LineNumber>]) {
+    let num_rows = unmatched.iter().map(|lines| lines.len()).max().unwrap_or(0);
+    for i in 0..num_rows {
+        let mut row = vec![None];
+        row.extend(unmatched.iter().map(|lines| lines.get(i).copied()));
+        rows.push(row);
+    }
+}
+
+/// Combine the alignments of every file with the first file into
+/// rows, so each line of the first file has a single row.
+fn merge_alignments(
+    alignments: &[Vec<(Option<LineNumber>, Option<LineNumber>)>],
+    num_first_lines: usize,
+) -> Vec<Row> {
+    let mut rows = vec![];
+    let mut indexes = vec![0; alignments.len()];
+
+    for first_line in 0..num_first_lines {
+        let first_line: LineNumber = first_line.into();
+
+        let mut unmatched = vec![vec![]; alignments.len()];
+        for (k, alignment) in alignments.iter().enumerate() {
+            while let Some((aligned_first_line, other_line)) = alignment.get(indexes[k]) {
+                match aligned_first_line {
+                    Some(aligned_first_line) if *aligned_first_line >= first_line => break,
+                    _ => {
+                        if let Some(other_line) = other_line {
+                            unmatched[k].push(*other_line);
+                        }
+                        indexes[k] += 1;
+                    }
+                }
+            }
+        }
+        push_unmatched(&mut rows, &unmatched);
+
+        let mut row = vec![Some(first_line)];
+        for (k, alignment) in alignments.iter().enumerate() {
+            match alignment.get(indexes[k]) {
+                Some((Some(aligned_first_line), other_line))
+                    if *aligned_first_line == first_line =>
+                {
+                    row.push(*other_line);
+                    indexes[k] += 1;
+                }
+                _ => row.push(None),
+            }
+        }
+        rows.push(row);
+    }
+
+    let unmatched: Vec<Vec<LineNumber>> = alignments
+        .iter()
+        .enumerate()
+        .map(|(k, alignment)| {
+            alignment[min(indexes[k], alignment.len())..]
+                .iter()
+                .filter_map(|(_, other_line)| *other_line)
+                .collect()
+        })
+        .collect();
+    push_unmatched(&mut rows, &unmatched);
+
+    rows
+}
+
+fn spans_overlap(x: &SingleLineSpan, y: &SingleLineSpan) -> bool {
+    x.line == y.line && ((x.start_col < y.end_col && y.start_col < x.end_col) || x == y)
+}
+
+fn partially_shared_style(background: BackgroundColor, color_depth: ColorDepth) -> Style {
+    if background.is_dark() && color_depth.has_bright() {
+        Style::new().bright_yellow().underline()
+    } else {
+        Style::new().yellow().underline()
+    }
+}
+
+/// Calculate the styles for every token in file `i`, based on how
+/// many of the other files contain that token. Also returns the lines
+/// that contain tokens not shared with every other file.
+fn file_styles(
+    i: usize,
+    pairwise_positions: &[Vec<Vec<MatchedPos>>],
+    display_options: &DisplayOptions,
+) -> (
+    FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>>,
+    FxHashSet<LineNumber>,
+) {
+    let others: Vec<usize> = (0..pairwise_positions.len()).filter(|j| *j != i).collect();
+
+    let mut styles: FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>> = FxHashMap::default();
+    let mut changed_lines = FxHashSet::default();
+
+    // Use the tokens from any file that isn't identical to this
+    // one. If there aren't any, every token is shared.
+    let base_mps = match others
+        .iter()
+        .map(|j| &pairwise_positions[i][*j])
+        .find(|mps| !mps.is_empty())
+    {
+        Some(mps) => mps,
+        None => return (styles, changed_lines),
+    };
+
+    let mut novel_spans: Vec<FxHashMap<LineNumber, Vec<SingleLineSpan>>> = vec![];
+    for j in &others {
+        let mut spans: FxHashMap<LineNumber, Vec<SingleLineSpan>> = FxHashMap::default();
+        for mp in &pairwise_positions[i][*j] {
+            if mp.kind.is_novel() {
+                spans
+                    .entry(mp.pos.line)
+                    .or_insert_with(Vec::new)
+                    .push(mp.pos);
+            }
+        }
+        novel_spans.push(spans);
+    }
+
+    let base_styles = color_positions(
+        true,
+        display_options.background_color,
+        display_options.color_depth,
+        display_options.syntax_highlight,
+        base_mps,
+    );
+    for (span, base_style) in base_styles {
+        let num_shared = novel_spans
+            .iter()
+            .filter(|spans| match spans.get(&span.line) {
+                Some(spans) => !spans.iter().any(|novel| spans_overlap(novel, &span)),
+                None => true,
+            })
+            .count();
+
+        let style = if num_shared == others.len() {
+            base_style
+        } else if num_shared == 0 {
+            changed_lines.insert(span.line);
+            novel_style(
+                Style::new(),
+                false,
+                display_options.background_color,
+                display_options.color_depth,
+            )
+        } else {
+            changed_lines.insert(span.line);
+            partially_shared_style(
+                display_options.background_color,
+                display_options.color_depth,
+            )
+        };
+
+        styles
+            .entry(span.line)
+            .or_insert_with(Vec::new)
+            .push((span, style));
+    }
+
+    // The sort is stable and per line, so neither the hash order of
+    // lines nor ties between spans make the output vary.
+    for line_styles in styles.values_mut() {
+        line_styles.sort_by_key(|(span, _)| span.start_col);
+    }
+
+    (styles, changed_lines)
+}
+
+/// Which rows should we print? We show every changed row, along with
+/// some context.
+fn visible_rows(rows: &[Row], changed_lines: &[FxHashSet<LineNumber>]) -> Vec<bool> {
+    let mut visible = vec![false; rows.len()];
+    for (r, row) in rows.iter().enumerate() {
+        let is_changed = row.iter().enumerate().any(|(i, line_num)| match line_num {
+            Some(line_num) => changed_lines[i].contains(line_num),
+            None => true,
+        });
+
+        if is_changed {
+            let end = min(rows.len(), r + MAX_PADDING + 1);
+            for is_visible in &mut visible[r.saturating_sub(MAX_PADDING)..end] {
+                *is_visible = true;
+            }
+        }
+    }
+
+    visible
+}
+
+fn print_row(
+    row: &Row,
+    all_lines: &[Vec<&str>],
+    all_styles: &[FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>>],
+    changed_lines: &[FxHashSet<LineNumber>],
+    dims: &PaneDimensions,
+    display_options: &DisplayOptions,
+) {
+    let mut panes: Vec<(String, Vec<String>)> = vec![];
+    for (i, line_num) in row.iter().enumerate() {
+        let content_width = dims.content_widths[i];
+        let line_nums_width = dims.line_nums_widths[i];
+
+        match line_num {
+            Some(line_num) => {
+                let line = all_lines[i].get(line_num.0).copied().unwrap_or("");
+                let styles: &[(SingleLineSpan, Style)] = match all_styles[i].get(line_num) {
+                    Some(styles) if display_options.use_color => &styles[..],
+                    _ => &[],
+                };
+                let parts =
+                    split_and_apply(line, content_width, !styles.is_empty(), styles, Side::Left);
+
+                let mut num = format!(
+                    "{:>width$} ",
+                    line_num.one_indexed(),
+                    width = line_nums_width - 1
+                );
+                if display_options.use_color && changed_lines[i].contains(line_num) {
+                    num = num.bold().to_string();
+                }
+                panes.push((num, parts));
+            }
+            None => {
+                panes.push((" ".repeat(line_nums_width), vec![" ".repeat(content_width)]));
+            }
+        }
+    }
+
+    let num_visual_lines = panes
+        .iter()
+        .map(|(_, parts)| parts.len())
+        .max()
+        .unwrap_or(0);
+    for visual_line in 0..num_visual_lines {
+        let columns: Vec<String> = panes
+            .iter()
+            .enumerate()
+            .map(|(i, (num, parts))| {
+                let num = if visual_line == 0 {
+                    num.clone()
+                } else {
+                    " ".repeat(dims.line_nums_widths[i])
+                };
+                let content = match parts.get(visual_line) {
+                    Some(part) => part.clone(),
+                    None => " ".repeat(dims.content_widths[i]),
+                };
+                format!("{}{}", num, content)
+            })
+            .collect();
+        println!(
+            "{}",
+            trim_trailing_spaces(&columns.join(SPACER), display_options)
+        );
+    }
+}
+
+/// Print every file in its own column. Tokens are highlighted
+/// according to whether they occur in all the other files, some of
+/// them, or none of them.
+pub fn print(
+    display_paths: &[String],
+    srcs: &[String],
+    pairwise_positions: &[Vec<Vec<MatchedPos>>],
+    lang_name: &str,
+    display_options: &DisplayOptions,
+) {
+    let all_lines: Vec<Vec<&str>> = srcs.iter().map(|src| split_on_newlines(src)).collect();
+
+    let alignments: Vec<_> = (1..srcs.len())
+        .map(|other| aligned_with_first(&all_lines, pairwise_positions, other))
+        .collect();
+    let rows = merge_alignments(&alignments, all_lines[0].len());
+
+    let mut all_styles = vec![];
+    let mut changed_lines = vec![];
+    for i in 0..srcs.len() {
+        let (styles, changed) = file_styles(i, pairwise_positions, display_options);
+        all_styles.push(styles);
+        changed_lines.push(changed);
+    }
+
+    let display_paths: Vec<String> = display_paths
+        .iter()
+        .map(|path| {
+            apply_header_color(
+                path,
+                display_options.use_color,
+                display_options.background_color,
+                display_options.color_depth,
+            )
+        })
+        .collect();
+    println!("{} --- {}", display_paths.join(" --- "), lang_name);
+
+    let visible = visible_rows(&rows, &changed_lines);
+    if !visible.iter().any(|is_visible| *is_visible) {
+        println!("{}\n", tr(Message::NoChanges));
+        return;
+    }
+
+    let dims = PaneDimensions::new(display_options.display_width, &all_lines);
+    let mut prev_row = None;
+    for (r, row) in rows.iter().enumerate() {
+        if !visible[r] {
+            continue;
+        }
+        if let Some(prev_row) = prev_row {
+            if prev_row + 1 != r {
+                println!();
+            }
+        }
+        prev_row = Some(r);
+
+        print_row(
+            row,
+            &all_lines,
+            &all_styles,
+            &changed_lines,
+            &dims,
+            display_options,
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_merge_alignments_identical() {
+        let alignment = vec![
+            (Some(0.into()), Some(0.into())),
+            (Some(1.into()), Some(1.into())),
+        ];
+        let rows = merge_alignments(&[alignment.clone(), alignment], 2);
+
+        assert_eq!(
+            rows,
+            vec![
+                vec![Some(0.into()), Some(0.into()), Some(0.into())],
+                vec![Some(1.into()), Some(1.into()), Some(1.into())],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_alignments_extra_lines() {
+        let rows = merge_alignments(
+            &[
+                vec![(None, Some(0.into())), (Some(0.into()), Some(1.into()))],
+                vec![(Some(0.into()), Some(0.into())), (None, Some(1.into()))],
+            ],
+            1,
+        );
+
+        assert_eq!(
+            rows,
+            vec![
+                vec![None, Some(0.into()), None],
+                vec![Some(0.into()), Some(1.into()), Some(0.into())],
+                vec![None, None, Some(1.into())],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pane_dimensions() {
+        let dims = PaneDimensions::new(80, &[vec!["a"], vec!["b"], vec!["c"]]);
+
+        assert_eq!(dims.line_nums_widths, vec![2, 2, 2]);
+        assert_eq!(dims.content_widths, vec![24, 24, 24]);
+    }
+}
@@ -0,0 +1,301 @@
+//! Plain text output with changed spans marked inline, for other
+//! tools to format.
+//!
+//! This is a unified diff, but novel tokens within a line are wrapped
+//! in `[-...-]` on removed lines and `{+...+}` on added lines, like
+//! `git diff --word-diff=plain`. There are no ANSI escapes, so tools
+//! such as delta or bat can apply their own styling to difftastic's
+//! matching.
+//!
+//! A backslash escapes the next character. Source text containing
+//! `\`, `[-`, `-]`, `{+` or `+}` has a backslash inserted before it,
+//! so these sequences are only unescaped when they're markers.
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    display::{
+        context::all_matched_lines_filled,
+        hunks::{file_hunks, matched_lines_for_hunk, Hunk},
+        markdown::hunk_diff_lines,
+        side_by_side::{lines_with_novel, split_on_newlines},
+    },
+    lines::LineNumber,
+    options::DisplayOptions,
+    parse::syntax::MatchedPos,
+    summary::{DiffResult, FileContent},
+};
+
+/// The byte ranges of novel tokens on each line, sorted and with
+/// adjacent ranges merged.
+fn novel_spans_by_line(mps: &[MatchedPos]) -> FxHashMap<LineNumber, Vec<(usize, usize)>> {
+    let mut spans: FxHashMap<LineNumber, Vec<(usize, usize)>> = FxHashMap::default();
+    for mp in mps.iter().filter(|mp| mp.kind.is_novel()) {
+        spans
+            .entry(mp.pos.line)
+            .or_default()
+            .push((mp.pos.start_col, mp.pos.end_col));
+    }
+
+    // Lines are visited in hash order, but each line is merged on its
+    // own, so the result doesn't depend on that order.
+    for line_spans in spans.values_mut() {
+        line_spans.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = vec![];
+        for (start, end) in line_spans.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *line_spans = merged;
+    }
+    spans
+}
+
+/// Wrap each of `spans` in `line` with `open` and `close`. The text
+/// of the line is passed through `escape`, but the markers aren't.
+fn mark_spans(
+    line: &str,
+    spans: &[(usize, usize)],
+    open: &str,
+    close: &str,
+    escape: fn(&str) -> String,
+) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut prev_end = 0;
+    for (start, end) in spans {
+        // Spans can extend past the line end, e.g. for a trailing
+        // newline inside a multiline string.
+        let start = (*start).max(prev_end).min(line.len());
+        let end = (*end).min(line.len());
+        match (line.get(prev_end..start), line.get(start..end)) {
+            (Some(before), Some(span)) if !span.is_empty() => {
+                res.push_str(&escape(before));
+                res.push_str(open);
+                res.push_str(&escape(span));
+                res.push_str(close);
+                prev_end = end;
+            }
+            _ => {}
+        }
+    }
+    res.push_str(&escape(&line[prev_end..]));
+    res
+}
+
+/// Mark the novel spans on every line of `lines`, passing the text of
+/// each line through `escape`.
+pub fn marked_lines(
+    lines: &[&str],
+    mps: &[MatchedPos],
+    open: &str,
+    close: &str,
+    escape: fn(&str) -> String,
+) -> Vec<String> {
+    let spans = novel_spans_by_line(mps);
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match spans.get(&LineNumber::from(i)) {
+            Some(line_spans) => mark_spans(line, line_spans, open, close, escape),
+            None => escape(line),
+        })
+        .collect()
+}
+
+/// Escape `s` so it can't be mistaken for a marker.
+fn escape_markers(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let is_marker = matches!(
+            (c, chars.peek().copied()),
+            ('[', Some('-')) | ('-', Some(']')) | ('{', Some('+')) | ('+', Some('}'))
+        );
+        if c == '\\' || is_marker {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// A unified diff hunk header, e.g. `@@ -3,2 +3,4 @@`. The counts
+/// are the context lines plus the removed or added lines in
+/// `diff_lines`.
+fn hunk_header(
+    hunk_lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    diff_lines: &[String],
+) -> String {
+    let range = |first: Option<LineNumber>, prefix: char| {
+        let count = diff_lines
+            .iter()
+            .filter(|line| line.starts_with(prefix) || line.starts_with(' '))
+            .count();
+        match first {
+            Some(first) => format!("{},{}", first.0 + 1, count),
+            None => "0,0".to_owned(),
+        }
+    };
+    let lhs_first = hunk_lines.iter().find_map(|(lhs, _)| *lhs);
+    let rhs_first = hunk_lines.iter().find_map(|(_, rhs)| *rhs);
+    format!(
+        "@@ -{} +{} @@",
+        range(lhs_first, '-'),
+        range(rhs_first, '+')
+    )
+}
+
+fn text_body(lhs_src: &str, rhs_src: &str, summary: &DiffResult, hunks: &[Hunk]) -> String {
+    let lhs_lines = split_on_newlines(lhs_src);
+    let rhs_lines = split_on_newlines(rhs_src);
+    let (lhs_lines_with_novel, rhs_lines_with_novel) =
+        lines_with_novel(&summary.lhs_positions, &summary.rhs_positions);
+    let matched_lines = all_matched_lines_filled(
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+        &lhs_lines,
+        &rhs_lines,
+    );
+
+    let lhs_marked = marked_lines(
+        &lhs_lines,
+        &summary.lhs_positions,
+        "[-",
+        "-]",
+        escape_markers,
+    );
+    let rhs_marked = marked_lines(
+        &rhs_lines,
+        &summary.rhs_positions,
+        "{+",
+        "+}",
+        escape_markers,
+    );
+    let lhs_marked: Vec<&str> = lhs_marked.iter().map(|l| l.as_str()).collect();
+    let rhs_marked: Vec<&str> = rhs_marked.iter().map(|l| l.as_str()).collect();
+
+    let mut body = String::new();
+    for hunk in hunks {
+        let diff_lines = hunk_diff_lines(
+            hunk,
+            &matched_lines,
+            &lhs_marked,
+            &rhs_marked,
+            &lhs_lines_with_novel,
+            &rhs_lines_with_novel,
+        );
+        body.push_str(&hunk_header(
+            &matched_lines_for_hunk(&matched_lines, hunk),
+            &diff_lines,
+        ));
+        body.push('\n');
+        for line in diff_lines {
+            body.push_str(&line);
+            body.push('\n');
+        }
+    }
+    body
+}
+
+/// Describe `summary` as a unified diff with inline span markers.
+/// Returns `None` if there are no changes and `print_unchanged` is
+/// false.
+pub fn render(summary: &DiffResult, print_unchanged: bool) -> Option<String> {
+    let header = format!(
+        "--- {}\n+++ {}\n",
+        summary.lhs_display_path, summary.rhs_display_path
+    );
+    let body = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            let hunks = file_hunks(
+                lhs_src,
+                rhs_src,
+                &summary.lhs_positions,
+                &summary.rhs_positions,
+            );
+            if hunks.is_empty() {
+                None
+            } else {
+                Some(text_body(lhs_src, rhs_src, summary, &hunks))
+            }
+        }
+        (lhs_src, rhs_src) if lhs_src != rhs_src => Some("Binary contents changed.\n".into()),
+        _ => None,
+    };
+
+    match body {
+        Some(body) => Some(header + &body),
+        None if print_unchanged => Some(header + "No changes.\n"),
+        None => None,
+    }
+}
+
+pub fn print(display_options: &DisplayOptions, summary: &DiffResult) {
+    if let Some(raw_spans) = render(summary, display_options.print_unchanged) {
+        print!("{}", raw_spans);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::html;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_mark_spans() {
+        let unescaped = |s: &str| s.to_owned();
+        assert_eq!(
+            mark_spans(
+                "let x = foo(1);",
+                &[(8, 11), (12, 13)],
+                "{+",
+                "+}",
+                unescaped
+            ),
+            "let x = {+foo+}({+1+});"
+        );
+        assert_eq!(mark_spans("x", &[(0, 5)], "[-", "-]", unescaped), "[-x-]");
+    }
+
+    #[test]
+    fn test_mark_spans_escaped() {
+        assert_eq!(
+            mark_spans("a < b", &[(4, 5)], "<b>", "</b>", html::escape),
+            "a &lt; <b>b</b>"
+        );
+    }
+
+    #[test]
+    fn test_hunk_header() {
+        let lines = [
+            (Some(2.into()), Some(2.into())),
+            (None, Some(3.into())),
+            (Some(3.into()), None),
+        ];
+        let diff_lines = [" a".to_owned(), "+b".to_owned(), "-c".to_owned()];
+        assert_eq!(hunk_header(&lines, &diff_lines), "@@ -3,2 +3,2 @@");
+        assert_eq!(
+            hunk_header(&[(None, Some(0.into()))], &["+x".to_owned()]),
+            "@@ -0,0 +1,1 @@"
+        );
+    }
+
+    #[test]
+    fn test_escape_markers() {
+        assert_eq!(escape_markers("a[-1] + {+x}"), "a\\[-1] + \\{+x}");
+        assert_eq!(escape_markers("x--]"), "x-\\-]");
+        assert_eq!(escape_markers("c:\\dir"), "c:\\\\dir");
+        assert_eq!(escape_markers("a[b]-c"), "a[b]-c");
+    }
+
+    #[test]
+    fn test_mark_spans_escapes_markers() {
+        assert_eq!(
+            mark_spans("x = a[-1]", &[(4, 9)], "{+", "+}", escape_markers),
+            "x = {+a\\[-1]+}"
+        );
+    }
+}
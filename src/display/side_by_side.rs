@@ -1,31 +1,54 @@
 //! Side-by-side (two column) display of diffs.
 
-use cansi::{self, categorise_text};
 use owo_colors::{OwoColorize, Style};
+use rayon::prelude::*;
 use rustc_hash::FxHashMap;
-use std::{cmp::max, collections::HashSet};
+use std::{
+    cmp::{max, min},
+    collections::HashSet,
+    fmt::Write,
+    sync::Once,
+};
 use yansi::{Color, Paint};
 
 use crate::{
     constants::Side,
-    display::context::all_matched_lines_filled,
-    display::hunks::{matched_lines_for_hunk, Hunk},
+    diff::{moves::Move, signatures::SignatureChange},
+    display::context::all_matched_lines_aligned,
+    display::hunks::{
+        collapse_novel_lines, collapse_unchanged_definitions, hunk_signature_change,
+        hunk_timed_out_region, matched_lines_for_hunk, unchanged_definition_ranges,
+        CollapsedDefinitions, Hunk, HunkNumbering,
+    },
+    display::indentation::{show_indentation, show_indentation_in_src, IndentationChanges},
     display::style::{
-        self, apply_colors, color_positions, novel_style, split_and_apply, BackgroundColor,
+        self, apply_line_styles, collapsed_definition, collapsed_lines, display_width,
+        hunk_minimap, isolate_rtl, line_styles, novel_style, split_and_apply, trim_trailing_spaces,
+        BackgroundColor, ColorDepth, LineStyles,
     },
-    lines::{codepoint_len, format_line_num, LineNumber},
+    lines::{format_line_num, LineNumber, MaxLine},
     options::{DisplayMode, DisplayOptions},
-    parse::syntax::{zip_pad_shorter, MatchedPos},
+    parse::syntax::{zip_pad_shorter, Definition, MatchedPos},
     positions::SingleLineSpan,
+    summary::TimedOutRegion,
+    warnings,
 };
 
 const SPACER: &str = " ";
 
+/// Separates the columns with `--copyable`, so either side can be
+/// cut out of the output.
+const COPYABLE_SPACER: &str = " │ ";
+
+/// The narrowest we make a column of content, even if the display is
+/// narrower. Narrower columns would wrap every few characters.
+const MIN_CONTENT_WIDTH: usize = 4;
+
 /// Split `s` on \n or \r\n. Always returns a non-empty vec.
 ///
 /// This differs from `str::lines`, which considers `""` to be zero
 /// lines and `"foo\n"` to be one line.
-fn split_on_newlines(s: &str) -> Vec<&str> {
+pub fn split_on_newlines(s: &str) -> Vec<&str> {
     s.split('\n')
         .map(|l| {
             if let Some(l) = l.strip_suffix('\r') {
@@ -38,6 +61,10 @@ fn split_on_newlines(s: &str) -> Vec<&str> {
 }
 
 fn format_line_num_padded(line_num: LineNumber, column_width: usize) -> String {
+    // There's no line number column with --copyable.
+    if column_width == 0 {
+        return String::new();
+    }
     format!(
         "{:width$} ",
         line_num.one_indexed(),
@@ -56,6 +83,9 @@ fn format_missing_line_num(
     } else {
         source_dims.rhs_line_nums_width
     };
+    if column_width == 0 {
+        return String::new();
+    }
 
     let after_end = if is_lhs {
         prev_num >= source_dims.lhs_max_line
@@ -87,7 +117,11 @@ fn display_single_column(
     is_lhs: bool,
     display_options: &DisplayOptions,
 ) -> String {
-    let column_width = format_line_num(src.lines().count().into()).len();
+    let column_width = if display_options.copyable {
+        0
+    } else {
+        format_line_num(src.lines().count().into()).len()
+    };
 
     let mut result = String::with_capacity(src.len());
     result.push_str(&style::header(
@@ -96,22 +130,51 @@ fn display_single_column(
         1,
         1,
         lang_name,
+        None,
         display_options,
     ));
     result.push('\n');
 
     let mut style = Style::new();
     if display_options.use_color {
-        style = novel_style(Style::new(), is_lhs, display_options.background_color);
+        style = novel_style(
+            Style::new(),
+            is_lhs,
+            display_options.background_color,
+            display_options.color_depth,
+        );
     }
 
-    for (i, line) in src.lines().enumerate() {
-        result.push_str(
-            &format_line_num_padded(i.into(), column_width)
-                .style(style)
-                .to_string(),
+    let num_lines = src.lines().count();
+    let num_shown = match display_options.preview_lines {
+        Some(preview_lines) => min(num_lines, preview_lines),
+        None => num_lines,
+    };
+
+    for (i, line) in src.lines().take(num_shown).enumerate() {
+        let line = format!(
+            "{}{}",
+            format_line_num_padded(i.into(), column_width).style(style),
+            line
         );
-        result.push_str(line);
+        result.push_str(trim_trailing_spaces(&line, display_options));
+        result.push('\n');
+    }
+
+    if num_shown < num_lines {
+        let num_hidden = num_lines - num_shown;
+        let summary = format!(
+            "{} more {}, {} in total",
+            num_hidden,
+            if num_hidden == 1 { "line" } else { "lines" },
+            num_lines
+        );
+        result.push_str(&" ".repeat(column_width));
+        if display_options.use_color {
+            result.push_str(&summary.dimmed().to_string());
+        } else {
+            result.push_str(&summary);
+        }
         result.push('\n');
     }
 
@@ -124,6 +187,7 @@ fn display_line_nums(
     source_dims: &SourceDimensions,
     use_color: bool,
     background: BackgroundColor,
+    color_depth: ColorDepth,
     lhs_has_novel: bool,
     rhs_has_novel: bool,
     prev_lhs_line_num: Option<LineNumber>,
@@ -134,7 +198,7 @@ fn display_line_nums(
             let s = format_line_num_padded(line_num, source_dims.lhs_line_nums_width);
             if lhs_has_novel && use_color {
                 // TODO: factor out applying colours to line numbers.
-                if background.is_dark() {
+                if background.is_dark() && color_depth.has_bright() {
                     s.bright_red().to_string()
                 } else {
                     s.red().to_string()
@@ -154,7 +218,7 @@ fn display_line_nums(
         Some(line_num) => {
             let s = format_line_num_padded(line_num, source_dims.rhs_line_nums_width);
             if rhs_has_novel && use_color {
-                if background.is_dark() {
+                if background.is_dark() && color_depth.has_bright() {
                     s.bright_green().to_string()
                 } else {
                     s.green().to_string()
@@ -182,6 +246,8 @@ struct SourceDimensions {
     rhs_line_nums_width: usize,
     lhs_max_line: LineNumber,
     rhs_max_line: LineNumber,
+    /// Printed between the LHS and RHS columns.
+    spacer: String,
 }
 
 impl SourceDimensions {
@@ -190,6 +256,9 @@ impl SourceDimensions {
         line_nums: &[(Option<LineNumber>, Option<LineNumber>)],
         lhs_lines: &[&str],
         rhs_lines: &[&str],
+        copyable: bool,
+        separator: Option<&str>,
+        fit_lhs: bool,
     ) -> Self {
         let mut lhs_max_line: LineNumber = 1.into();
         let mut rhs_max_line: LineNumber = 1.into();
@@ -199,21 +268,50 @@ impl SourceDimensions {
         for (lhs_line_num, rhs_line_num) in line_nums {
             if let Some(lhs_line_num) = lhs_line_num {
                 lhs_max_line = max(lhs_max_line, *lhs_line_num);
-                lhs_max_content = max(lhs_max_content, codepoint_len(lhs_lines[lhs_line_num.0]));
+                lhs_max_content = max(lhs_max_content, display_width(lhs_lines[lhs_line_num.0]));
             }
             if let Some(rhs_line_num) = rhs_line_num {
                 rhs_max_line = max(rhs_max_line, *rhs_line_num);
-                rhs_max_content = max(rhs_max_content, codepoint_len(rhs_lines[rhs_line_num.0]));
+                rhs_max_content = max(rhs_max_content, display_width(rhs_lines[rhs_line_num.0]));
             }
         }
 
-        let lhs_line_nums_width = format_line_num(lhs_max_line).len();
-        let rhs_line_nums_width = format_line_num(rhs_max_line).len();
+        let (lhs_line_nums_width, rhs_line_nums_width, default_spacer) = if copyable {
+            (0, 0, COPYABLE_SPACER)
+        } else {
+            (
+                format_line_num(lhs_max_line).len(),
+                format_line_num(rhs_max_line).len(),
+                SPACER,
+            )
+        };
+        let spacer = separator.unwrap_or(default_spacer).to_owned();
+        // Separators such as ' ｜ ' may contain wide characters.
+        let spacer_width = display_width(&spacer);
 
-        let lhs_total_width = (terminal_width - SPACER.len()) / 2;
-        let lhs_content_width = lhs_total_width - lhs_line_nums_width;
-        let rhs_content_width =
-            terminal_width - lhs_total_width - SPACER.len() - rhs_line_nums_width;
+        // Give each side half of the terminal. If `fit_lhs` is set,
+        // don't pad the LHS beyond its longest line, and give any
+        // space left over to the RHS.
+        //
+        // On tiny displays, there may not be room for the line
+        // numbers, so clamp the content to a minimum width. The
+        // output will then be wider than the display.
+        let half_width = terminal_width.saturating_sub(spacer_width) / 2;
+        let lhs_total_width = if fit_lhs {
+            min(half_width, lhs_line_nums_width + lhs_max_content)
+        } else {
+            half_width
+        };
+        let lhs_content_width = max(
+            min(MIN_CONTENT_WIDTH, lhs_max_content),
+            lhs_total_width.saturating_sub(lhs_line_nums_width),
+        );
+        let rhs_content_width = max(
+            MIN_CONTENT_WIDTH,
+            terminal_width
+                .saturating_sub(lhs_line_nums_width + lhs_content_width)
+                .saturating_sub(spacer_width + rhs_line_nums_width),
+        );
 
         Self {
             lhs_content_width,
@@ -222,8 +320,160 @@ impl SourceDimensions {
             rhs_line_nums_width,
             lhs_max_line,
             rhs_max_line,
+            spacer,
+        }
+    }
+
+    /// The width of a row showing both sides.
+    fn total_width(&self) -> usize {
+        self.lhs_line_nums_width
+            + self.lhs_content_width
+            + display_width(&self.spacer)
+            + self.rhs_line_nums_width
+            + self.rhs_content_width
+    }
+}
+
+/// Where a row is in a group of rows that show the same code, split
+/// over a different number of lines on each side. For example, a
+/// call whose arguments were moved onto separate lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reflow {
+    None,
+    Start,
+    Middle,
+    End,
+}
+
+/// The most rows that we consider for a single reflowed statement.
+const MAX_REFLOW_ROWS: usize = 20;
+
+fn without_whitespace(s: &str) -> String {
+    s.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Find groups of `rows` where the two sides have the same content,
+/// ignoring whitespace, but a different number of lines.
+///
+/// A group must start with a row that has a different non-blank line
+/// on each side, so added blank lines aren't mistaken for reflowing.
+fn reflow_groups(
+    rows: &[(Option<LineNumber>, Option<LineNumber>)],
+    lhs_lines: &[&str],
+    rhs_lines: &[&str],
+) -> Vec<Reflow> {
+    let mut res = vec![Reflow::None; rows.len()];
+
+    let mut start = 0;
+    while start < rows.len() {
+        let starts_group = match rows[start] {
+            (Some(lhs_num), Some(rhs_num)) => {
+                let lhs_text = without_whitespace(lhs_lines[lhs_num.0]);
+                let rhs_text = without_whitespace(rhs_lines[rhs_num.0]);
+                !lhs_text.is_empty() && !rhs_text.is_empty() && lhs_text != rhs_text
+            }
+            _ => false,
+        };
+        if !starts_group {
+            start += 1;
+            continue;
+        }
+
+        let mut lhs_text = String::new();
+        let mut rhs_text = String::new();
+        let mut num_lhs_lines = 0;
+        let mut num_rhs_lines = 0;
+        let mut end = None;
+        for (i, (lhs_num, rhs_num)) in rows.iter().enumerate().skip(start).take(MAX_REFLOW_ROWS) {
+            if let Some(lhs_num) = lhs_num {
+                lhs_text.push_str(&without_whitespace(lhs_lines[lhs_num.0]));
+                num_lhs_lines += 1;
+            }
+            if let Some(rhs_num) = rhs_num {
+                rhs_text.push_str(&without_whitespace(rhs_lines[rhs_num.0]));
+                num_rhs_lines += 1;
+            }
+
+            if lhs_text == rhs_text {
+                if num_lhs_lines != num_rhs_lines {
+                    end = Some(i);
+                }
+                break;
+            }
+            if !lhs_text.starts_with(&rhs_text) && !rhs_text.starts_with(&lhs_text) {
+                break;
+            }
+        }
+
+        match end {
+            Some(end) => {
+                res[start] = Reflow::Start;
+                for reflow in &mut res[start + 1..end] {
+                    *reflow = Reflow::Middle;
+                }
+                res[end] = Reflow::End;
+                start = end + 1;
+            }
+            None => start += 1,
         }
     }
+
+    res
+}
+
+/// How to style the separator between the columns, with
+/// `--separator-style`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeparatorStyle {
+    Plain,
+    Dim,
+    Bold,
+}
+
+impl SeparatorStyle {
+    fn apply(self, separator: &str, use_color: bool) -> String {
+        match self {
+            SeparatorStyle::Dim if use_color => separator.dimmed().to_string(),
+            SeparatorStyle::Bold if use_color => separator.bold().to_string(),
+            _ => separator.to_owned(),
+        }
+    }
+}
+
+/// The spacer between the columns of a row, replaced by a connector
+/// if the row is part of a reflowed group. `is_first` and `is_last`
+/// say whether this is the first or last display line of a wrapped
+/// row.
+fn reflow_spacer(
+    spacer: &str,
+    spacer_style: SeparatorStyle,
+    reflow: Reflow,
+    is_first: bool,
+    is_last: bool,
+    use_color: bool,
+) -> String {
+    let connector = match reflow {
+        Reflow::None => return spacer_style.apply(spacer, use_color),
+        Reflow::Start if is_first => "⎡",
+        Reflow::End if is_last => "⎣",
+        _ => "⎢",
+    };
+    if use_color {
+        connector.dimmed().to_string()
+    } else {
+        connector.to_owned()
+    }
+}
+
+/// Warn that rows are wider than the display, at most once.
+fn warn_too_narrow(display_width: usize, row_width: usize) {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        warnings::warn(format!(
+            "The display is {} columns wide, but side-by-side rows need at least {}. Lines will be wider than the display.",
+            display_width, row_width
+        ));
+    });
 }
 
 pub fn lines_with_novel(
@@ -244,35 +494,6 @@ pub fn lines_with_novel(
     (lhs_lines_with_novel, rhs_lines_with_novel)
 }
 
-/// Calculate positions of highlights on both sides. This includes
-/// both syntax highlighting and added/removed content highlighting.
-fn highlight_positions(
-    background: BackgroundColor,
-    syntax_highlight: bool,
-    lhs_mps: &[MatchedPos],
-    rhs_mps: &[MatchedPos],
-) -> (
-    FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>>,
-    FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>>,
-) {
-    let lhs_positions = color_positions(true, background, syntax_highlight, lhs_mps);
-    // Preallocate the hashmap assuming the average line will have 2 items on it.
-    let mut lhs_styles: FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>> = FxHashMap::default();
-    for (span, style) in lhs_positions {
-        let styles = lhs_styles.entry(span.line).or_insert_with(Vec::new);
-        styles.push((span, style));
-    }
-
-    let rhs_positions = color_positions(false, background, syntax_highlight, rhs_mps);
-    let mut rhs_styles: FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>> = FxHashMap::default();
-    for (span, style) in rhs_positions {
-        let styles = rhs_styles.entry(span.line).or_insert_with(Vec::new);
-        styles.push((span, style));
-    }
-
-    (lhs_styles, rhs_styles)
-}
-
 fn highlight_as_novel(
     line_num: Option<LineNumber>,
     lines: &[&str],
@@ -297,8 +518,41 @@ fn highlight_as_novel(
     false
 }
 
+/// The background color to fill a whole line with.
+fn novel_line_background(is_novel: bool, is_lhs: bool, display_options: &DisplayOptions) -> Color {
+    // Only the 256 color palette has colors subtle enough for a
+    // whole line.
+    if is_novel
+        && display_options.use_color
+        && display_options.line_background
+        && display_options.color_depth == ColorDepth::Extended
+    {
+        if is_lhs {
+            Color::Fixed(224)
+        } else {
+            Color::Fixed(194)
+        }
+    } else {
+        Color::Default
+    }
+}
+
+/// Pad `line`, which is `width` columns wide when printed, to the
+/// display width and fill its background with `line_bg`.
+fn fill_line_background(line: &str, width: usize, line_bg: Color, display_width: usize) -> String {
+    let padding_len = if line_bg == Color::Default {
+        0
+    } else {
+        display_width.saturating_sub(width)
+    };
+    Paint::wrapping(format!("{}{}", line, " ".repeat(padding_len)))
+        .bg(line_bg)
+        .to_string()
+}
+
 pub fn print(
     hunks: &[Hunk],
+    numbering: HunkNumbering,
     display_options: &DisplayOptions,
     lhs_display_path: &str,
     rhs_display_path: &str,
@@ -307,26 +561,48 @@ pub fn print(
     rhs_src: &str,
     lhs_mps: &[MatchedPos],
     rhs_mps: &[MatchedPos],
+    lhs_novel_ranges: &[(LineNumber, LineNumber)],
+    rhs_novel_ranges: &[(LineNumber, LineNumber)],
+    rhs_definitions: &[Definition],
+    moves: &[Move],
+    signature_changes: &[SignatureChange],
+    timed_out_regions: &[TimedOutRegion],
+    lhs_indentation: &IndentationChanges,
+    rhs_indentation: &IndentationChanges,
 ) {
-    let (lhs_colored_src, rhs_colored_src) = if display_options.use_color {
+    // Colour whole lines and split lines into columns with the same
+    // styles, so the two can't disagree.
+    let (mut lhs_highlights, mut rhs_highlights) = if display_options.use_color {
         (
-            apply_colors(
-                lhs_src,
+            line_styles(
                 true,
                 display_options.syntax_highlight,
                 display_options.background_color,
+                display_options.color_depth,
                 lhs_mps,
             ),
-            apply_colors(
-                rhs_src,
+            line_styles(
                 false,
                 display_options.syntax_highlight,
                 display_options.background_color,
+                display_options.color_depth,
                 rhs_mps,
             ),
         )
     } else {
-        (lhs_src.to_string(), rhs_src.to_string())
+        (LineStyles::default(), LineStyles::default())
+    };
+
+    let (lhs_colored_src, rhs_colored_src) = if display_options.use_color {
+        (
+            apply_line_styles(lhs_src, &lhs_highlights),
+            apply_line_styles(rhs_src, &rhs_highlights),
+        )
+    } else {
+        (
+            style::highlight_invisible_chars(lhs_src, false),
+            style::highlight_invisible_chars(rhs_src, false),
+        )
     };
 
     if lhs_src.is_empty() {
@@ -358,282 +634,710 @@ pub fn print(
         return;
     }
 
-    // TODO: this is largely duplicating the `apply_colors` logic.
-    let (lhs_highlights, rhs_highlights) = if display_options.use_color {
-        highlight_positions(
-            display_options.background_color,
-            display_options.syntax_highlight,
-            lhs_mps,
-            rhs_mps,
-        )
-    } else {
-        (FxHashMap::default(), FxHashMap::default())
-    };
-
     let lhs_lines = split_on_newlines(lhs_src);
     let rhs_lines = split_on_newlines(rhs_src);
+    let lhs_colored_src = show_indentation_in_src(lhs_colored_src, lhs_indentation);
+    let rhs_colored_src = show_indentation_in_src(rhs_colored_src, rhs_indentation);
     let lhs_colored_lines = split_on_newlines(&lhs_colored_src);
     let rhs_colored_lines = split_on_newlines(&rhs_colored_src);
 
     let (lhs_lines_with_novel, rhs_lines_with_novel) = lines_with_novel(lhs_mps, rhs_mps);
 
+    let matched_lines = all_matched_lines_aligned(
+        lhs_mps,
+        rhs_mps,
+        &lhs_lines,
+        &rhs_lines,
+        display_options.alignment,
+    );
+
+    let show_both = matches!(
+        display_options.display_mode,
+        DisplayMode::SideBySideShowBoth
+    );
+    let only_side = match display_options.display_mode {
+        DisplayMode::LhsOnly => Some(Side::Left),
+        DisplayMode::RhsOnly => Some(Side::Right),
+        _ => None,
+    };
+
+    // Showing both sides of every hunk is most useful for reviewing
+    // whole files, so hide the bodies of unchanged definitions.
+    let unchanged_ranges = if show_both && display_options.collapse_definitions {
+        unchanged_definition_ranges(
+            rhs_definitions,
+            &matched_lines,
+            &lhs_lines_with_novel,
+            &rhs_lines_with_novel,
+        )
+    } else {
+        vec![]
+    };
+
+    let mut collapsed = CollapsedDefinitions::default();
+    let hunk_lines: Vec<_> = hunks
+        .iter()
+        .map(|hunk| {
+            let aligned_lines = matched_lines_for_hunk(&matched_lines, hunk);
+            let visible_lines = if display_options.collapse_definitions {
+                collapse_unchanged_definitions(
+                    &collapse_novel_lines(
+                        &aligned_lines,
+                        lhs_novel_ranges,
+                        rhs_novel_ranges,
+                        &mut collapsed,
+                    ),
+                    &unchanged_ranges,
+                )
+            } else {
+                aligned_lines.iter().map(|line| (*line, 0)).collect()
+            };
+            (aligned_lines, visible_lines)
+        })
+        .collect();
+
+    // Show each collapsed definition as a single row summarising it,
+    // highlighted as novel.
+    let lhs_summaries = definition_summaries(&lhs_lines, &collapsed.lhs);
+    let rhs_summaries = definition_summaries(&rhs_lines, &collapsed.rhs);
+    let (lhs_colored_summaries, rhs_colored_summaries) = if display_options.use_color {
+        (
+            highlight_summaries(&lhs_summaries, true, display_options, &mut lhs_highlights),
+            highlight_summaries(&rhs_summaries, false, display_options, &mut rhs_highlights),
+        )
+    } else {
+        (lhs_summaries.clone(), rhs_summaries.clone())
+    };
+    let lhs_lines = replace_lines(&lhs_lines, &lhs_summaries);
+    let rhs_lines = replace_lines(&rhs_lines, &rhs_summaries);
+    let lhs_colored_lines = replace_lines(&lhs_colored_lines, &lhs_colored_summaries);
+    let rhs_colored_lines = replace_lines(&rhs_colored_lines, &rhs_colored_summaries);
+
+    // Colouring doesn't change the width of a line, so compute widths
+    // from the source rather than stripping ANSI escapes again.
+    let lhs_line_widths: Vec<usize> = lhs_lines.iter().map(|l| display_width(l)).collect();
+    let rhs_line_widths: Vec<usize> = rhs_lines.iter().map(|l| display_width(l)).collect();
+
+    // Each hunk shows missing line numbers relative to the last line
+    // number shown, which may be in an earlier hunk. Find these
+    // first, so we can render the hunks independently.
+    let mut prev_line_nums = Vec::with_capacity(hunks.len());
     let mut prev_lhs_line_num = None;
     let mut prev_rhs_line_num = None;
+    for (_, visible_lines) in &hunk_lines {
+        prev_line_nums.push((prev_lhs_line_num, prev_rhs_line_num));
+        if only_side.is_some() {
+            continue;
+        }
+        for ((lhs_line_num, rhs_line_num), _) in visible_lines {
+            if lhs_line_num.is_some() {
+                prev_lhs_line_num = *lhs_line_num;
+            }
+            if rhs_line_num.is_some() {
+                prev_rhs_line_num = *rhs_line_num;
+            }
+        }
+    }
+
+    let ctx = HunkContext {
+        display_options,
+        lhs_display_path,
+        rhs_display_path,
+        lang_name,
+        numbering,
+        moves,
+        signature_changes,
+        timed_out_regions,
+        lhs_max_line: lhs_src.max_line(),
+        rhs_max_line: rhs_src.max_line(),
+        lhs_lines: &lhs_lines,
+        rhs_lines: &rhs_lines,
+        lhs_colored_lines: &lhs_colored_lines,
+        rhs_colored_lines: &rhs_colored_lines,
+        lhs_line_widths: &lhs_line_widths,
+        rhs_line_widths: &rhs_line_widths,
+        lhs_highlights: &lhs_highlights,
+        rhs_highlights: &rhs_highlights,
+        lhs_lines_with_novel: &lhs_lines_with_novel,
+        rhs_lines_with_novel: &rhs_lines_with_novel,
+        lhs_indentation,
+        rhs_indentation,
+        show_both,
+        only_side,
+    };
 
-    let matched_lines = all_matched_lines_filled(lhs_mps, rhs_mps, &lhs_lines, &rhs_lines);
+    // Splitting, colouring and padding lines is slow for large
+    // diffs, so render hunks in parallel and print them in order.
+    let rendered: Vec<String> = (0..hunks.len())
+        .into_par_iter()
+        .map(|i| {
+            let (aligned_lines, visible_lines) = &hunk_lines[i];
+            let (prev_lhs_line_num, prev_rhs_line_num) = prev_line_nums[i];
+            render_hunk(
+                &ctx,
+                i,
+                &hunks[i],
+                aligned_lines,
+                visible_lines,
+                prev_lhs_line_num,
+                prev_rhs_line_num,
+            )
+        })
+        .collect();
+    for hunk in rendered {
+        print!("{}", hunk);
+    }
+}
 
-    for (i, hunk) in hunks.iter().enumerate() {
-        println!(
-            "{}",
-            style::header(
-                lhs_display_path,
-                rhs_display_path,
-                i + 1,
-                hunks.len(),
-                lang_name,
-                display_options
+/// The summary row for each collapsed definition in `lines`.
+fn definition_summaries(
+    lines: &[&str],
+    collapsed: &FxHashMap<LineNumber, usize>,
+) -> FxHashMap<LineNumber, String> {
+    collapsed
+        .iter()
+        .map(|(line_num, num_lines)| {
+            (
+                *line_num,
+                collapsed_definition(lines[line_num.0], *num_lines),
             )
+        })
+        .collect()
+}
+
+/// Highlight `summaries` as novel, replacing the styles of the lines
+/// they summarise. Returns the coloured summaries.
+fn highlight_summaries(
+    summaries: &FxHashMap<LineNumber, String>,
+    is_lhs: bool,
+    display_options: &DisplayOptions,
+    highlights: &mut LineStyles,
+) -> FxHashMap<LineNumber, String> {
+    let style = novel_style(
+        Style::new(),
+        is_lhs,
+        display_options.background_color,
+        display_options.color_depth,
+    );
+    summaries
+        .iter()
+        .map(|(line_num, summary)| {
+            let span = SingleLineSpan {
+                line: *line_num,
+                start_col: 0,
+                end_col: summary.len(),
+            };
+            highlights.insert(*line_num, vec![(span, style)]);
+            (*line_num, summary.style(style).to_string())
+        })
+        .collect()
+}
+
+/// `lines`, with the lines in `replacements` replaced.
+fn replace_lines<'a>(
+    lines: &[&'a str],
+    replacements: &'a FxHashMap<LineNumber, String>,
+) -> Vec<&'a str> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| replacements.get(&i.into()).map_or(*line, |s| s.as_str()))
+        .collect()
+}
+
+/// Everything needed to render a hunk that is shared between hunks.
+struct HunkContext<'a> {
+    display_options: &'a DisplayOptions,
+    lhs_display_path: &'a str,
+    rhs_display_path: &'a str,
+    lang_name: &'a str,
+    numbering: HunkNumbering,
+    moves: &'a [Move],
+    signature_changes: &'a [SignatureChange],
+    timed_out_regions: &'a [TimedOutRegion],
+    lhs_max_line: LineNumber,
+    rhs_max_line: LineNumber,
+    lhs_lines: &'a [&'a str],
+    rhs_lines: &'a [&'a str],
+    lhs_colored_lines: &'a [&'a str],
+    rhs_colored_lines: &'a [&'a str],
+    /// The width of each line, without colours.
+    lhs_line_widths: &'a [usize],
+    rhs_line_widths: &'a [usize],
+    lhs_highlights: &'a LineStyles,
+    rhs_highlights: &'a LineStyles,
+    lhs_lines_with_novel: &'a HashSet<LineNumber>,
+    rhs_lines_with_novel: &'a HashSet<LineNumber>,
+    lhs_indentation: &'a IndentationChanges,
+    rhs_indentation: &'a IndentationChanges,
+    show_both: bool,
+    only_side: Option<Side>,
+}
+
+/// Draw the indentation of the first row of a wrapped line, if its
+/// indentation changed.
+fn with_indentation(mut rows: Vec<String>, visible: Option<&String>) -> Vec<String> {
+    if let (Some(visible), Some(first_row)) = (visible, rows.first_mut()) {
+        *first_row = show_indentation(first_row, visible);
+    }
+    rows
+}
+
+/// Wrap a line for a single column display, with `line_num` on the
+/// first row and blank space under it on the rest. Returns each row
+/// with its printed width.
+fn wrap_single_column_line(
+    line_num: &str,
+    gutter_width: usize,
+    rows: Vec<String>,
+) -> Vec<(String, usize)> {
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let width = gutter_width + display_width(&row);
+            let gutter = if i == 0 {
+                line_num.to_owned()
+            } else {
+                " ".repeat(gutter_width)
+            };
+            (format!("{}{}", gutter, isolate_rtl(&row)), width)
+        })
+        .collect()
+}
+
+/// Render hunk `i` with its header, as it should be printed.
+fn render_hunk(
+    ctx: &HunkContext,
+    i: usize,
+    hunk: &Hunk,
+    aligned_lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    visible_lines: &[((Option<LineNumber>, Option<LineNumber>), usize)],
+    mut prev_lhs_line_num: Option<LineNumber>,
+    mut prev_rhs_line_num: Option<LineNumber>,
+) -> String {
+    let HunkContext {
+        display_options,
+        lhs_display_path,
+        rhs_display_path,
+        lang_name,
+        numbering,
+        moves,
+        signature_changes,
+        timed_out_regions,
+        lhs_max_line,
+        rhs_max_line,
+        lhs_lines,
+        rhs_lines,
+        lhs_colored_lines,
+        rhs_colored_lines,
+        lhs_line_widths,
+        rhs_line_widths,
+        lhs_highlights,
+        rhs_highlights,
+        lhs_lines_with_novel,
+        rhs_lines_with_novel,
+        lhs_indentation,
+        rhs_indentation,
+        show_both,
+        only_side,
+    } = *ctx;
+
+    let mut out = String::new();
+    let mut header = style::header(
+        lhs_display_path,
+        rhs_display_path,
+        numbering.first + i + 1,
+        numbering.total,
+        lang_name,
+        Some(hunk.counts(moves)),
+        display_options,
+    );
+    if display_options.show_minimap {
+        header = format!(
+            "{} {}",
+            header,
+            hunk_minimap(hunk, lhs_max_line, rhs_max_line)
         );
+    }
+    writeln!(out, "{}", header).unwrap();
+    if let Some(change) = hunk_signature_change(hunk, signature_changes) {
+        writeln!(
+            out,
+            "{}",
+            style::signature_banner(change.kind, display_options.use_color)
+        )
+        .unwrap();
+    }
+    if let Some(region) = hunk_timed_out_region(hunk, timed_out_regions) {
+        writeln!(
+            out,
+            "{}",
+            style::timed_out_banner(region, display_options.use_color)
+        )
+        .unwrap();
+    }
 
-        let aligned_lines = matched_lines_for_hunk(&matched_lines, hunk);
-        let no_lhs_changes = hunk.novel_lhs.is_empty();
-        let no_rhs_changes = hunk.novel_rhs.is_empty();
-        let same_lines = aligned_lines.iter().all(|(l, r)| l == r);
+    let no_lhs_changes = hunk.novel_lhs.is_empty();
+    let no_rhs_changes = hunk.novel_rhs.is_empty();
+    let same_lines = aligned_lines.iter().all(|(l, r)| l == r);
+
+    let source_dims = SourceDimensions::new(
+        display_options.display_width,
+        aligned_lines,
+        lhs_lines,
+        rhs_lines,
+        display_options.copyable,
+        display_options.separator.as_deref(),
+        // Both columns are always shown in this mode, so a short LHS
+        // would otherwise be padded with blank space.
+        show_both,
+    );
+
+    if only_side.is_none() && source_dims.total_width() > display_options.display_width {
+        warn_too_narrow(display_options.display_width, source_dims.total_width());
+    }
 
-        let source_dims = SourceDimensions::new(
-            display_options.display_width,
-            &aligned_lines,
-            &lhs_lines,
-            &rhs_lines,
+    // Connectors are the width of the default separator, so don't
+    // replace --copyable or custom separators.
+    let reflows = if display_options.copyable || display_options.separator.is_some() {
+        vec![Reflow::None; visible_lines.len()]
+    } else {
+        let rows: Vec<_> = visible_lines.iter().map(|(rows, _)| *rows).collect();
+        reflow_groups(&rows, lhs_lines, rhs_lines)
+    };
+
+    for (&((lhs_line_num, rhs_line_num), num_hidden), &reflow) in
+        visible_lines.iter().zip(reflows.iter())
+    {
+        let lhs_line_novel =
+            highlight_as_novel(lhs_line_num, lhs_lines, rhs_line_num, lhs_lines_with_novel);
+        let rhs_line_novel =
+            highlight_as_novel(rhs_line_num, rhs_lines, lhs_line_num, rhs_lines_with_novel);
+
+        let (display_lhs_line_num, display_rhs_line_num) = display_line_nums(
+            lhs_line_num,
+            rhs_line_num,
+            &source_dims,
+            display_options.use_color,
+            display_options.background_color,
+            display_options.color_depth,
+            lhs_line_novel,
+            rhs_line_novel,
+            prev_lhs_line_num,
+            prev_rhs_line_num,
         );
-        for (lhs_line_num, rhs_line_num) in aligned_lines {
-            let lhs_line_novel = highlight_as_novel(
-                lhs_line_num,
-                &lhs_lines,
-                rhs_line_num,
-                &lhs_lines_with_novel,
+
+        if let Some(side) = only_side {
+            // Only show one column, skipping the filler rows for
+            // lines that only exist on the other side.
+            let (line_num, display_line_num, lines, highlights, indentation, lines_with_novel) =
+                match side {
+                    Side::Left => (
+                        lhs_line_num,
+                        &display_lhs_line_num,
+                        lhs_lines,
+                        lhs_highlights,
+                        lhs_indentation,
+                        lhs_lines_with_novel,
+                    ),
+                    Side::Right => (
+                        rhs_line_num,
+                        &display_rhs_line_num,
+                        rhs_lines,
+                        rhs_highlights,
+                        rhs_indentation,
+                        rhs_lines_with_novel,
+                    ),
+                };
+            let is_lhs = side == Side::Left;
+            let line_num = match line_num {
+                Some(line_num) => line_num,
+                None => continue,
+            };
+
+            let gutter_width = if is_lhs {
+                source_dims.lhs_line_nums_width
+            } else {
+                source_dims.rhs_line_nums_width
+            };
+            // The column has the whole display, so wrap to that
+            // rather than the width of a side-by-side column.
+            let content_width = max(
+                MIN_CONTENT_WIDTH,
+                display_options.display_width.saturating_sub(gutter_width),
             );
-            let rhs_line_novel = highlight_as_novel(
-                rhs_line_num,
-                &rhs_lines,
-                lhs_line_num,
-                &rhs_lines_with_novel,
+            let rows = with_indentation(
+                split_and_apply(
+                    lines[line_num.0],
+                    content_width,
+                    display_options.use_color,
+                    highlights.get(&line_num).unwrap_or(&vec![]),
+                    side,
+                ),
+                indentation.get(&line_num),
             );
-
-            let (display_lhs_line_num, display_rhs_line_num) = display_line_nums(
-                lhs_line_num,
-                rhs_line_num,
-                &source_dims,
-                display_options.use_color,
-                display_options.background_color,
-                lhs_line_novel,
-                rhs_line_novel,
-                prev_lhs_line_num,
-                prev_rhs_line_num,
+            let line_bg = novel_line_background(
+                lines_with_novel.contains(&line_num),
+                is_lhs,
+                display_options,
             );
+            for (row, width) in wrap_single_column_line(display_line_num, gutter_width, rows) {
+                let row = fill_line_background(&row, width, line_bg, display_options.display_width);
+                writeln!(out, "{}", trim_trailing_spaces(&row, display_options)).unwrap();
+            }
 
-            let show_both = matches!(
-                display_options.display_mode,
-                DisplayMode::SideBySideShowBoth
-            );
-            if no_lhs_changes && !show_both {
-                match rhs_line_num {
-                    Some(rhs_line_num) => {
-                        let rhs_line = &rhs_colored_lines[rhs_line_num.0];
-                        let line_to_print = if same_lines {
-                            format!("{}{}", display_rhs_line_num, rhs_line)
-                        } else {
-                            format!(
-                                "{}{}{}",
-                                display_lhs_line_num, display_rhs_line_num, rhs_line
-                            )
-                        };
-                        let (line_bg, padding_len) = if rhs_lines_with_novel.contains(&rhs_line_num)
-                        {
-                            (
-                                Color::Fixed(194),
-                                display_options.display_width
-                                // we are using cansi::categorize_text to remove ANSI escapes
-                                // if we don't do this, we can't properly pad the line length
-                                // tried several other ANSI stripping libs, this one actually works
-                                    - categorise_text(&line_to_print)
-                                        .iter()
-                                        .map(|s| (s.end - s.start) as usize)
-                                        .sum::<usize>(),
-                            )
-                        } else {
-                            (Color::Default, 0)
-                        };
-                        println!(
-                            "{}",
-                            Paint::wrapping(format!(
-                                "{}{}",
-                                line_to_print,
-                                " ".repeat(padding_len)
-                            ))
-                            .bg(line_bg)
-                        );
-                    }
-                    None => {
-                        // We didn't have any changed RHS lines in the
-                        // hunk, but we had some contextual lines that
-                        // only occurred on the LHS (e.g. extra newlines).
-                        println!("{}{}", display_rhs_line_num, display_rhs_line_num);
-                    }
+            if num_hidden > 0 {
+                let indent = if is_lhs {
+                    source_dims.lhs_line_nums_width
+                } else {
+                    source_dims.rhs_line_nums_width
+                };
+                writeln!(
+                    out,
+                    "{}{}",
+                    " ".repeat(indent),
+                    collapsed_lines(num_hidden, display_options.use_color)
+                )
+                .unwrap();
+            }
+            continue;
+        } else if no_lhs_changes && !show_both {
+            match rhs_line_num {
+                Some(rhs_line_num) => {
+                    let rhs_line = isolate_rtl(&rhs_colored_lines[rhs_line_num.0]);
+                    let line_to_print = if same_lines {
+                        format!("{}{}", display_rhs_line_num, rhs_line)
+                    } else {
+                        format!(
+                            "{}{}{}",
+                            display_lhs_line_num, display_rhs_line_num, rhs_line
+                        )
+                    };
+                    let line_bg = novel_line_background(
+                        rhs_lines_with_novel.contains(&rhs_line_num),
+                        false,
+                        display_options,
+                    );
+                    let gutter_width = if same_lines {
+                        source_dims.rhs_line_nums_width
+                    } else {
+                        source_dims.lhs_line_nums_width + source_dims.rhs_line_nums_width
+                    };
+                    let line = fill_line_background(
+                        &line_to_print,
+                        gutter_width + rhs_line_widths[rhs_line_num.0],
+                        line_bg,
+                        display_options.display_width,
+                    );
+                    writeln!(out, "{}", trim_trailing_spaces(&line, display_options)).unwrap();
                 }
-            } else if no_rhs_changes && !show_both {
-                match lhs_line_num {
-                    Some(lhs_line_num) => {
-                        let lhs_line = &lhs_colored_lines[lhs_line_num.0];
-                        let line_to_print = if same_lines {
-                            format!("{}{}", display_lhs_line_num, lhs_line)
-                        } else {
-                            format!(
-                                "{}{}{}",
-                                display_lhs_line_num, display_lhs_line_num, lhs_line
-                            )
-                        };
-                        let (line_bg, padding_len) = if lhs_lines_with_novel.contains(&lhs_line_num)
-                        {
-                            (
-                                Color::Fixed(224),
-                                display_options.display_width
-                                // we are using cansi::categorize_text to remove ANSI escapes
-                                // if we don't do this, we can't properly pad the line length
-                                // tried several other ANSI stripping libs, this one actually works
-                                    - categorise_text(&line_to_print)
-                                        .iter()
-                                        .map(|s| (s.end - s.start) as usize)
-                                        .sum::<usize>(),
-                            )
-                        } else {
-                            (Color::Default, 0)
-                        };
-                        println!(
-                            "{}",
-                            Paint::wrapping(format!(
-                                "{}{}",
-                                line_to_print,
-                                " ".repeat(padding_len)
-                            ))
-                            .bg(line_bg)
-                        );
-                    }
-                    None => {
-                        println!("{}{}", display_lhs_line_num, display_lhs_line_num);
-                    }
+                None => {
+                    // We didn't have any changed RHS lines in the
+                    // hunk, but we had some contextual lines that
+                    // only occurred on the LHS (e.g. extra newlines).
+                    writeln!(
+                        out,
+                        "{}",
+                        trim_trailing_spaces(
+                            &format!("{}{}", display_rhs_line_num, display_rhs_line_num),
+                            display_options
+                        )
+                    )
+                    .unwrap();
                 }
-            } else {
-                let lhs_line = match lhs_line_num {
-                    Some(lhs_line_num) => split_and_apply(
+            }
+        } else if no_rhs_changes && !show_both {
+            match lhs_line_num {
+                Some(lhs_line_num) => {
+                    let lhs_line = isolate_rtl(&lhs_colored_lines[lhs_line_num.0]);
+                    let line_to_print = if same_lines {
+                        format!("{}{}", display_lhs_line_num, lhs_line)
+                    } else {
+                        format!(
+                            "{}{}{}",
+                            display_lhs_line_num, display_lhs_line_num, lhs_line
+                        )
+                    };
+                    let line_bg = novel_line_background(
+                        lhs_lines_with_novel.contains(&lhs_line_num),
+                        true,
+                        display_options,
+                    );
+                    let gutter_width = if same_lines {
+                        source_dims.lhs_line_nums_width
+                    } else {
+                        2 * source_dims.lhs_line_nums_width
+                    };
+                    let line = fill_line_background(
+                        &line_to_print,
+                        gutter_width + lhs_line_widths[lhs_line_num.0],
+                        line_bg,
+                        display_options.display_width,
+                    );
+                    writeln!(out, "{}", trim_trailing_spaces(&line, display_options)).unwrap();
+                }
+                None => {
+                    writeln!(
+                        out,
+                        "{}",
+                        trim_trailing_spaces(
+                            &format!("{}{}", display_lhs_line_num, display_lhs_line_num),
+                            display_options
+                        )
+                    )
+                    .unwrap();
+                }
+            }
+        } else {
+            let lhs_line = match lhs_line_num {
+                Some(lhs_line_num) => with_indentation(
+                    split_and_apply(
                         lhs_lines[lhs_line_num.0],
                         source_dims.lhs_content_width,
                         display_options.use_color,
                         lhs_highlights.get(&lhs_line_num).unwrap_or(&vec![]),
                         Side::Left,
                     ),
-                    None => vec![" ".repeat(source_dims.lhs_content_width)],
-                };
-                let rhs_line = match rhs_line_num {
-                    Some(rhs_line_num) => split_and_apply(
+                    lhs_indentation.get(&lhs_line_num),
+                ),
+                None => vec![" ".repeat(source_dims.lhs_content_width)],
+            };
+            let rhs_line = match rhs_line_num {
+                Some(rhs_line_num) => with_indentation(
+                    split_and_apply(
                         rhs_lines[rhs_line_num.0],
                         source_dims.rhs_content_width,
                         display_options.use_color,
                         rhs_highlights.get(&rhs_line_num).unwrap_or(&vec![]),
                         Side::Right,
                     ),
-                    None => vec!["".into()],
-                };
-
-                for (i, (lhs_line, rhs_line)) in zip_pad_shorter(&lhs_line, &rhs_line)
-                    .into_iter()
-                    .enumerate()
-                {
-                    let lhs_line =
-                        lhs_line.unwrap_or_else(|| " ".repeat(source_dims.lhs_content_width));
-                    let rhs_line = rhs_line.unwrap_or_else(|| "".into());
-                    let lhs_num: String = if i == 0 {
-                        display_lhs_line_num.clone()
-                    } else {
-                        let mut s = format_missing_line_num(
-                            lhs_line_num
-                                .unwrap_or_else(|| prev_lhs_line_num.unwrap_or_else(|| 10.into())),
-                            &source_dims,
-                            true,
-                            display_options.use_color,
-                        );
-                        if let Some(line_num) = lhs_line_num {
-                            if lhs_lines_with_novel.contains(&line_num) {
-                                s = if display_options.background_color.is_dark() {
-                                    s.bright_red().to_string()
-                                } else {
-                                    s.red().to_string()
-                                };
-                            }
-                        }
-                        s
-                    };
-                    let rhs_num: String = if i == 0 {
-                        display_rhs_line_num.clone()
-                    } else {
-                        let mut s = format_missing_line_num(
-                            rhs_line_num
-                                .unwrap_or_else(|| prev_rhs_line_num.unwrap_or_else(|| 10.into())),
-                            &source_dims,
-                            false,
-                            display_options.use_color,
-                        );
-                        if let Some(line_num) = rhs_line_num {
-                            if rhs_lines_with_novel.contains(&line_num) {
-                                s = if display_options.background_color.is_dark() {
-                                    s.bright_green().to_string()
-                                } else {
-                                    s.green().to_string()
-                                };
-                            }
-                        }
-                        s
-                    };
-
-                    println!(
-                        "{}{}{}",
-                        Paint::wrapping(format!("{}{}", lhs_num, lhs_line)).bg(
-                            if lhs_line_num.is_some()
-                                && lhs_lines_with_novel.contains(&lhs_line_num.unwrap())
+                    rhs_indentation.get(&rhs_line_num),
+                ),
+                None => vec!["".into()],
+            };
+
+            let display_lines = zip_pad_shorter(&lhs_line, &rhs_line);
+            let num_display_lines = display_lines.len();
+            for (i, (lhs_line, rhs_line)) in display_lines.into_iter().enumerate() {
+                let lhs_line =
+                    lhs_line.unwrap_or_else(|| " ".repeat(source_dims.lhs_content_width));
+                let rhs_line = rhs_line.unwrap_or_else(|| "".into());
+                let lhs_num: String = if i == 0 {
+                    display_lhs_line_num.clone()
+                } else {
+                    let mut s = format_missing_line_num(
+                        lhs_line_num
+                            .unwrap_or_else(|| prev_lhs_line_num.unwrap_or_else(|| 10.into())),
+                        &source_dims,
+                        true,
+                        display_options.use_color,
+                    );
+                    if let Some(line_num) = lhs_line_num {
+                        if lhs_lines_with_novel.contains(&line_num) {
+                            s = if display_options.background_color.is_dark()
+                                && display_options.color_depth.has_bright()
                             {
-                                Color::Fixed(224)
+                                s.bright_red().to_string()
                             } else {
-                                Color::Default
-                            }
-                        ),
-                        SPACER,
-                        Paint::wrapping(format!("{}{}", rhs_num, rhs_line)).bg(
-                            if rhs_line_num.is_some()
-                                && rhs_lines_with_novel.contains(&rhs_line_num.unwrap())
+                                s.red().to_string()
+                            };
+                        }
+                    }
+                    s
+                };
+                let rhs_num: String = if i == 0 {
+                    display_rhs_line_num.clone()
+                } else {
+                    let mut s = format_missing_line_num(
+                        rhs_line_num
+                            .unwrap_or_else(|| prev_rhs_line_num.unwrap_or_else(|| 10.into())),
+                        &source_dims,
+                        false,
+                        display_options.use_color,
+                    );
+                    if let Some(line_num) = rhs_line_num {
+                        if rhs_lines_with_novel.contains(&line_num) {
+                            s = if display_options.background_color.is_dark()
+                                && display_options.color_depth.has_bright()
                             {
-                                Color::Fixed(194)
+                                s.bright_green().to_string()
                             } else {
-                                Color::Default
-                            }
-                        ),
-                    );
-                }
-            }
+                                s.green().to_string()
+                            };
+                        }
+                    }
+                    s
+                };
 
-            if lhs_line_num.is_some() {
-                prev_lhs_line_num = lhs_line_num;
-            }
-            if rhs_line_num.is_some() {
-                prev_rhs_line_num = rhs_line_num;
+                let line = format!(
+                    "{}{}{}",
+                    Paint::wrapping(format!("{}{}", lhs_num, isolate_rtl(&lhs_line))).bg(
+                        novel_line_background(
+                            lhs_line_num.map_or(false, |n| lhs_lines_with_novel.contains(&n)),
+                            true,
+                            display_options,
+                        )
+                    ),
+                    reflow_spacer(
+                        &source_dims.spacer,
+                        display_options.separator_style,
+                        reflow,
+                        i == 0,
+                        i + 1 == num_display_lines,
+                        display_options.use_color,
+                    ),
+                    Paint::wrapping(format!("{}{}", rhs_num, isolate_rtl(&rhs_line))).bg(
+                        novel_line_background(
+                            rhs_line_num.map_or(false, |n| rhs_lines_with_novel.contains(&n)),
+                            false,
+                            display_options,
+                        )
+                    ),
+                );
+                writeln!(out, "{}", trim_trailing_spaces(&line, display_options)).unwrap();
             }
         }
-        println!();
+
+        if lhs_line_num.is_some() {
+            prev_lhs_line_num = lhs_line_num;
+        }
+        if rhs_line_num.is_some() {
+            prev_rhs_line_num = rhs_line_num;
+        }
+
+        if num_hidden > 0 {
+            // Hidden lines are only on one side, so single column
+            // hunks always show both line numbers.
+            let indent = if (no_lhs_changes || no_rhs_changes) && !show_both {
+                source_dims.lhs_line_nums_width + source_dims.rhs_line_nums_width
+            } else if rhs_line_num.is_none() {
+                source_dims.lhs_line_nums_width
+            } else {
+                source_dims.lhs_line_nums_width
+                    + source_dims.lhs_content_width
+                    + display_width(&source_dims.spacer)
+                    + source_dims.rhs_line_nums_width
+            };
+            writeln!(
+                out,
+                "{}{}",
+                " ".repeat(indent),
+                collapsed_lines(num_hidden, display_options.use_color)
+            )
+            .unwrap();
+        }
     }
+    writeln!(out).unwrap();
+    out
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::syntax::{AtomKind, MatchKind, TokenKind};
+    use crate::{
+        lines::codepoint_len,
+        positions::SingleLineSpan,
+        syntax::{AtomKind, MatchKind, TokenKind},
+    };
 
     use super::*;
     use pretty_assertions::assert_eq;
@@ -646,12 +1350,138 @@ mod tests {
             &line_nums,
             &split_on_newlines("foo\nbar\n"),
             &split_on_newlines("x\nx\nx\nx\nx\nx\nx\nx\nx\nx\nx\n"),
+            false,
+            None,
+            false,
         );
 
         assert_eq!(source_dims.lhs_line_nums_width, 2);
         assert_eq!(source_dims.rhs_line_nums_width, 3);
     }
 
+    #[test]
+    fn test_width_lhs_not_padded() {
+        let line_nums = [(Some(0.into()), Some(0.into()))];
+        let source_dims = SourceDimensions::new(
+            80,
+            &line_nums,
+            &split_on_newlines("foo"),
+            &split_on_newlines("bar"),
+            false,
+            None,
+            true,
+        );
+
+        assert_eq!(source_dims.lhs_content_width, 3);
+        assert_eq!(source_dims.rhs_content_width, 80 - 2 - 3 - 1 - 2);
+    }
+
+    #[test]
+    fn test_width_lhs_half_by_default() {
+        let line_nums = [(Some(0.into()), Some(0.into()))];
+        let source_dims = SourceDimensions::new(
+            80,
+            &line_nums,
+            &split_on_newlines("foo"),
+            &split_on_newlines("bar"),
+            false,
+            None,
+            false,
+        );
+
+        assert_eq!(source_dims.lhs_content_width, (80 - 1) / 2 - 2);
+        assert_eq!(source_dims.total_width(), 80);
+    }
+
+    #[test]
+    fn test_width_wide_separator() {
+        let line_nums = [(Some(0.into()), Some(0.into()))];
+        let source_dims = SourceDimensions::new(
+            80,
+            &line_nums,
+            &split_on_newlines("foo"),
+            &split_on_newlines("bar"),
+            false,
+            Some(" ｜ "),
+            false,
+        );
+
+        // The fullwidth bar takes two columns.
+        assert_eq!(source_dims.total_width(), 80);
+        assert_eq!(source_dims.lhs_content_width, (80 - 4) / 2 - 2);
+    }
+
+    #[test]
+    fn test_width_tiny_terminal() {
+        let line_nums = [(Some(0.into()), Some(0.into()))];
+        let source_dims = SourceDimensions::new(
+            1,
+            &line_nums,
+            &split_on_newlines("foo"),
+            &split_on_newlines("bar"),
+            false,
+            None,
+            false,
+        );
+
+        assert_eq!(source_dims.lhs_content_width, 3);
+        assert_eq!(source_dims.rhs_content_width, MIN_CONTENT_WIDTH);
+    }
+
+    #[test]
+    fn test_width_small_terminals() {
+        let line_nums: Vec<(Option<LineNumber>, Option<LineNumber>)> = (0..100_usize)
+            .map(|i| (Some(i.into()), Some(i.into())))
+            .collect();
+        let lines = vec!["a fairly long line of content"; 100];
+
+        for terminal_width in 1..=40 {
+            let source_dims = SourceDimensions::new(
+                terminal_width,
+                &line_nums,
+                &lines,
+                &lines,
+                false,
+                None,
+                false,
+            );
+
+            assert!(source_dims.lhs_content_width >= MIN_CONTENT_WIDTH);
+            assert!(source_dims.rhs_content_width >= MIN_CONTENT_WIDTH);
+
+            // Once there's room for the line numbers and the minimum
+            // content, we should fit in the terminal.
+            let min_row_width = 2 * (source_dims.lhs_line_nums_width + MIN_CONTENT_WIDTH) + 1;
+            if terminal_width >= min_row_width {
+                assert!(
+                    source_dims.total_width() <= terminal_width,
+                    "Row too wide for terminal width {}",
+                    terminal_width
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_width_copyable() {
+        let line_nums = [(Some(0.into()), Some(0.into()))];
+        let source_dims = SourceDimensions::new(
+            20,
+            &line_nums,
+            &split_on_newlines("foo"),
+            &split_on_newlines("bar"),
+            true,
+            None,
+            false,
+        );
+
+        assert_eq!(source_dims.lhs_line_nums_width, 0);
+        assert_eq!(source_dims.lhs_content_width, 8);
+        assert_eq!(source_dims.rhs_content_width, 9);
+        assert_eq!(source_dims.total_width(), 20);
+        assert_eq!(format_line_num_padded(0.into(), 0), "");
+    }
+
     #[test]
     fn test_format_missing_line_num() {
         let source_dims = SourceDimensions::new(
@@ -662,6 +1492,9 @@ mod tests {
             ],
             &split_on_newlines("foo\nbar\n"),
             &split_on_newlines("fox\nbax\n"),
+            false,
+            None,
+            false,
         );
 
         assert_eq!(
@@ -684,6 +1517,9 @@ mod tests {
             ],
             &split_on_newlines("foo\nbar\n"),
             &split_on_newlines("fox\nbax\n"),
+            false,
+            None,
+            false,
         );
 
         assert_eq!(
@@ -698,16 +1534,7 @@ mod tests {
 
     #[test]
     fn test_display_single_column() {
-        let display_options = DisplayOptions {
-            background_color: BackgroundColor::Dark,
-            use_color: false,
-            display_mode: DisplayMode::SideBySide,
-            print_unchanged: true,
-            tab_width: 8,
-            display_width: 80,
-            in_vcs: false,
-            syntax_highlight: true,
-        };
+        let display_options = DisplayOptions::default();
 
         // Basic smoke test.
         let res = display_single_column(
@@ -721,6 +1548,83 @@ mod tests {
         assert!(res.len() > 10);
     }
 
+    #[test]
+    fn test_display_single_column_preview() {
+        let display_options = DisplayOptions {
+            preview_lines: Some(20),
+            ..DisplayOptions::default()
+        };
+
+        let src = "x\n".repeat(25);
+        let res =
+            display_single_column("foo.py", "foo.py", "Python", &src, false, &display_options);
+
+        assert!(res.contains("5 more lines, 25 in total"));
+    }
+
+    #[test]
+    fn test_display_single_column_full_by_default() {
+        let src = "x\n".repeat(25);
+        let res = display_single_column(
+            "foo.py",
+            "foo.py",
+            "Python",
+            &src,
+            false,
+            &DisplayOptions::default(),
+        );
+
+        assert!(!res.contains("more lines"));
+    }
+
+    #[test]
+    fn test_novel_line_background_disabled() {
+        let display_options = DisplayOptions {
+            use_color: true,
+            line_background: false,
+            ..DisplayOptions::default()
+        };
+
+        assert_eq!(
+            novel_line_background(true, true, &display_options),
+            Color::Default
+        );
+    }
+
+    #[test]
+    fn test_reflow_groups() {
+        let lhs_lines = ["foo(a, b);", "bar();", ""];
+        let rhs_lines = ["foo(", "    a,", "    b);", "bar();", ""];
+        let rows = [
+            (Some(0.into()), Some(0.into())),
+            (None, Some(1.into())),
+            (None, Some(2.into())),
+            (Some(1.into()), Some(3.into())),
+            (None, Some(4.into())),
+        ];
+        assert_eq!(
+            reflow_groups(&rows, &lhs_lines, &rhs_lines),
+            vec![
+                Reflow::Start,
+                Reflow::Middle,
+                Reflow::End,
+                Reflow::None,
+                Reflow::None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reflow_groups_changed() {
+        let lhs_lines = ["foo(a, b);"];
+        let rhs_lines = ["foo(", "    a, c);"];
+        let rows = [(Some(0.into()), Some(0.into())), (None, Some(1.into()))];
+        assert_eq!(
+            reflow_groups(&rows, &lhs_lines, &rhs_lines),
+            vec![Reflow::None, Reflow::None]
+        );
+    }
+
     #[test]
     fn test_split_line_empty() {
         assert_eq!(split_on_newlines(""), vec![""]);
@@ -746,6 +1650,19 @@ mod tests {
         assert_eq!(split_on_newlines("foo\nbar\n"), vec!["foo", "bar", ""]);
     }
 
+    #[test]
+    fn test_fill_line_background() {
+        let line = "1 \x1b[31mfoo\x1b[0m";
+        assert_eq!(fill_line_background(line, 5, Color::Default, 8), line);
+
+        let filled = fill_line_background(line, 5, Color::Fixed(224), 8);
+        let text: String = cansi::categorise_text(&filled)
+            .iter()
+            .map(|s| s.text)
+            .collect();
+        assert_eq!(text, "1 foo   ");
+    }
+
     #[test]
     fn test_display_hunks() {
         // Simulate diffing:
@@ -789,19 +1706,15 @@ mod tests {
         }];
 
         let display_options = DisplayOptions {
-            background_color: BackgroundColor::Dark,
             use_color: true,
-            display_mode: DisplayMode::SideBySide,
-            print_unchanged: true,
-            tab_width: 8,
-            display_width: 80,
-            syntax_highlight: true,
             in_vcs: true,
+            ..DisplayOptions::default()
         };
 
         // Simple smoke test.
         print(
             &hunks,
+            HunkNumbering::all(&hunks),
             &display_options,
             "foo-old.el",
             "foo-new.el",
@@ -810,6 +1723,102 @@ mod tests {
             "bar",
             &lhs_mps,
             &rhs_mps,
+            &[],
+            &[],
+            &[],
+            &[],
+            &[],
+            &IndentationChanges::default(),
+            &IndentationChanges::default(),
+        );
+    }
+
+    /// Render a hunk where the single line of each file is changed.
+    fn render_only_side(lhs_src: &str, rhs_src: &str, only_side: Side) -> String {
+        let display_options = DisplayOptions {
+            display_width: 20,
+            ..DisplayOptions::default()
+        };
+        let lhs_lines = split_on_newlines(lhs_src);
+        let rhs_lines = split_on_newlines(rhs_src);
+        let lhs_line_widths: Vec<usize> = lhs_lines.iter().map(|l| codepoint_len(l)).collect();
+        let rhs_line_widths: Vec<usize> = rhs_lines.iter().map(|l| codepoint_len(l)).collect();
+        let mut novel_lines = HashSet::new();
+        novel_lines.insert(0.into());
+
+        let hunk = Hunk {
+            novel_lhs: novel_lines.clone(),
+            novel_rhs: novel_lines.clone(),
+            lines: vec![(Some(0.into()), Some(0.into()))],
+        };
+        let aligned_lines = [(Some(0.into()), Some(0.into()))];
+        let visible_lines = [((Some(0.into()), Some(0.into())), 0)];
+
+        let ctx = HunkContext {
+            display_options: &display_options,
+            lhs_display_path: "file.txt",
+            rhs_display_path: "file.txt",
+            lang_name: "Text",
+            numbering: HunkNumbering::all(std::slice::from_ref(&hunk)),
+            moves: &[],
+            signature_changes: &[],
+            timed_out_regions: &[],
+            lhs_max_line: lhs_src.max_line(),
+            rhs_max_line: rhs_src.max_line(),
+            lhs_lines: &lhs_lines,
+            rhs_lines: &rhs_lines,
+            lhs_colored_lines: &lhs_lines,
+            rhs_colored_lines: &rhs_lines,
+            lhs_line_widths: &lhs_line_widths,
+            rhs_line_widths: &rhs_line_widths,
+            lhs_highlights: &LineStyles::default(),
+            rhs_highlights: &LineStyles::default(),
+            lhs_lines_with_novel: &novel_lines,
+            rhs_lines_with_novel: &novel_lines,
+            lhs_indentation: &IndentationChanges::default(),
+            rhs_indentation: &IndentationChanges::default(),
+            show_both: false,
+            only_side: Some(only_side),
+        };
+        render_hunk(&ctx, 0, &hunk, &aligned_lines, &visible_lines, None, None)
+    }
+
+    #[test]
+    fn test_lhs_only_wraps_long_lines() {
+        let rendered = render_only_side(
+            "the old line is much wider than the display",
+            "new",
+            Side::Left,
+        );
+        // Skip the header.
+        let rows: Vec<&str> = rendered.lines().skip(1).filter(|l| !l.is_empty()).collect();
+
+        assert!(rows.len() > 1);
+        assert!(rows.iter().all(|row| codepoint_len(row) <= 20));
+        assert!(rows[0].starts_with("1 the old"));
+        // Continuation rows line up with the content, not the line
+        // number.
+        assert!(rows[1..].iter().all(|row| row.starts_with("  ")));
+    }
+
+    #[test]
+    fn test_rhs_only_wraps_long_lines() {
+        let rendered = render_only_side(
+            "old",
+            "the new line is much wider than the display",
+            Side::Right,
         );
+        let rows: Vec<&str> = rendered.lines().skip(1).filter(|l| !l.is_empty()).collect();
+
+        assert!(rows.len() > 1);
+        assert!(rows.iter().all(|row| codepoint_len(row) <= 20));
+        assert!(rows[0].starts_with("1 the new"));
+        assert!(rows.iter().all(|row| !row.contains("old")));
+    }
+
+    #[test]
+    fn test_wrap_single_column_line() {
+        let rows = wrap_single_column_line("1 ", 2, vec!["foo".to_owned(), "bar".to_owned()]);
+        assert_eq!(rows, vec![("1 foo".to_owned(), 5), ("  bar".to_owned(), 5)]);
     }
 }
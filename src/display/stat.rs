@@ -0,0 +1,144 @@
+//! Short, one line summaries of changes to a file.
+
+use crate::{
+    display::hunks::file_hunks,
+    parse::syntax::MatchedPos,
+    summary::{DiffResult, FileContent},
+};
+
+/// How much a file has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStats {
+    pub num_hunks: usize,
+    /// The number of novel tokens in the old file.
+    pub removed: usize,
+    /// The number of novel tokens in the new file.
+    pub added: usize,
+}
+
+fn num_novel(mps: &[MatchedPos]) -> usize {
+    mps.iter().filter(|mp| mp.kind.is_novel()).count()
+}
+
+/// Count the changes in `summary`. Returns `None` if the file is
+/// unchanged.
+pub fn file_stats(summary: &DiffResult) -> Option<FileStats> {
+    match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            let hunks = file_hunks(
+                lhs_src,
+                rhs_src,
+                &summary.lhs_positions,
+                &summary.rhs_positions,
+            );
+            if hunks.is_empty() {
+                return None;
+            }
+
+            Some(FileStats {
+                num_hunks: hunks.len(),
+                removed: num_novel(&summary.lhs_positions),
+                added: num_novel(&summary.rhs_positions),
+            })
+        }
+        (lhs_src, rhs_src) => {
+            if lhs_src == rhs_src {
+                None
+            } else {
+                Some(FileStats {
+                    num_hunks: 1,
+                    removed: 0,
+                    added: 0,
+                })
+            }
+        }
+    }
+}
+
+/// Describe `stats` for `summary` in a single line, e.g.
+///
+/// ```text
+/// src/main.rs Rust 2 hunks +10 -3
+/// ```
+fn summary_line(summary: &DiffResult, stats: &FileStats) -> String {
+    let language = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(_), FileContent::Text(_)) => {
+            summary.language.as_deref().unwrap_or("Text")
+        }
+        _ => "binary",
+    };
+
+    format!(
+        "{} {} {} {} +{} -{}",
+        summary.rhs_display_path,
+        language,
+        stats.num_hunks,
+        if stats.num_hunks == 1 {
+            "hunk"
+        } else {
+            "hunks"
+        },
+        stats.added,
+        stats.removed
+    )
+}
+
+/// Print one line for `summary` if the file has changed.
+pub fn print_summary(summary: &DiffResult) {
+    if let Some(stats) = file_stats(summary) {
+        println!("{}", summary_line(summary, &stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{display::indentation::IndentationChanges, offsets::OffsetMap, profiles::Profile};
+    use pretty_assertions::assert_eq;
+
+    fn binary_result(lhs: &[u8], rhs: &[u8]) -> DiffResult {
+        DiffResult {
+            lhs_display_path: "foo.png".into(),
+            rhs_display_path: "foo.png".into(),
+            language: None,
+            text_fallback: None,
+            encoding: None,
+            lhs_src: FileContent::Binary(lhs.to_vec()),
+            rhs_src: FileContent::Binary(rhs.to_vec()),
+            lhs_positions: vec![],
+            rhs_positions: vec![],
+            lhs_parse_errors: None,
+            rhs_parse_errors: None,
+            lhs_novel_ranges: vec![],
+            rhs_novel_ranges: vec![],
+            timed_out_regions: vec![],
+            lhs_definitions: vec![],
+            rhs_definitions: vec![],
+            moves: vec![],
+            copied: false,
+            status: None,
+            profile: Profile::default(),
+            explanations: vec![],
+            lhs_offsets: OffsetMap::default(),
+            rhs_offsets: OffsetMap::default(),
+            signature_changes: vec![],
+            lhs_indentation: IndentationChanges::default(),
+            rhs_indentation: IndentationChanges::default(),
+        }
+    }
+
+    #[test]
+    fn test_file_stats_unchanged() {
+        assert_eq!(file_stats(&binary_result(b"a", b"a")), None);
+    }
+
+    #[test]
+    fn test_summary_line_binary() {
+        let summary = binary_result(b"a", b"b");
+        let stats = file_stats(&summary).unwrap();
+        assert_eq!(
+            summary_line(&summary, &stats),
+            "foo.png binary 1 hunk +0 -0"
+        );
+    }
+}
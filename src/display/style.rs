@@ -2,14 +2,23 @@
 
 use crate::{
     constants::Side,
+    diff::signatures::SignatureChangeKind,
+    display::hunks::{Hunk, HunkCounts},
+    i18n::{tr, tr_path, Message},
     lines::{byte_len, codepoint_len, LineNumber},
     options::DisplayOptions,
-    parse::syntax::{AtomKind, MatchKind, MatchedPos, TokenKind},
+    parse::syntax::{definition_name, AtomKind, MatchKind, MatchedPos, TokenKind},
     positions::SingleLineSpan,
+    summary::{FileStatus, TimedOutRegion},
 };
 use owo_colors::{OwoColorize, Style};
 use rustc_hash::FxHashMap;
-use std::cmp::{max, min};
+use std::{
+    borrow::Cow,
+    cmp::{max, min},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Clone, Copy, Debug)]
 pub enum BackgroundColor {
@@ -23,24 +32,27 @@ impl BackgroundColor {
     }
 }
 
-/// Slice `s` from `start` inclusive to `end` exclusive by codepoint. This is safer than
-/// slicing by bytes, which panics if the byte isn't on a codepoint
-/// boundary.
-fn substring_by_codepoint(s: &str, start: usize, end: usize) -> &str {
-    if start == end {
-        return &s[0..0];
-    }
+/// The number of colors the terminal can display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// Only the 8 basic ANSI colors.
+    Basic,
+    /// The basic colors and their bright variants.
+    Bright,
+    /// The 256 color xterm palette.
+    Extended,
+    /// The basic colors and their bright variants, without any
+    /// background colors. CI log viewers often mangle backgrounds.
+    Ci,
+}
 
-    assert!(end > start);
+impl ColorDepth {
+    pub fn has_bright(self) -> bool {
+        !matches!(self, ColorDepth::Basic)
+    }
 
-    let mut char_idx_iter = s.char_indices();
-    let byte_start = char_idx_iter
-        .nth(start)
-        .expect("Expected a codepoint index inside `s`.")
-        .0;
-    match char_idx_iter.nth(end - start - 1) {
-        Some(byte_end) => &s[byte_start..byte_end.0],
-        None => &s[byte_start..],
+    pub fn has_backgrounds(self) -> bool {
+        !matches!(self, ColorDepth::Ci)
     }
 }
 
@@ -48,39 +60,274 @@ fn substring_by_byte(s: &str, start: usize, end: usize) -> &str {
     &s[start..end]
 }
 
-/// Split a string into equal length parts, padding the last part if
-/// necessary.
+/// A piece of a line that we never split when wrapping: a grapheme
+/// cluster, or an ANSI escape sequence.
+#[derive(Debug, PartialEq)]
+struct Cluster {
+    /// The byte offset of the cluster in the line.
+    start: usize,
+    /// The number of terminal columns the cluster takes up, once
+    /// invisible characters are replaced.
+    width: usize,
+}
+
+/// The width of `cluster` as we print it.
+fn cluster_width(cluster: &str) -> usize {
+    if cluster.chars().any(|c| visible_substitute(c).is_some()) {
+        UnicodeWidthStr::width(highlight_invisible_chars(cluster, false).as_str())
+    } else {
+        UnicodeWidthStr::width(cluster)
+    }
+}
+
+/// Split `s` into the clusters that we can wrap between.
+fn clusters(s: &str) -> Vec<Cluster> {
+    let mut res = vec![];
+    let mut start = 0;
+    while start < s.len() {
+        let rest = &s[start..];
+        let (len, width) = if let Some(params) = rest.strip_prefix("\x1b[") {
+            // A CSI sequence ends with a byte in the range @ to ~.
+            let len = params
+                .find(|c| ('@'..='~').contains(&c))
+                .map_or(rest.len(), |end| end + 3);
+            (len, 0)
+        } else {
+            let cluster = rest.graphemes(true).next().unwrap_or(rest);
+            (cluster.len(), cluster_width(cluster))
+        };
+        res.push(Cluster { start, width });
+        start += len;
+    }
+    res
+}
+
+/// The number of terminal columns that `s` takes up when printed,
+/// ignoring ANSI escape sequences.
+pub fn display_width(s: &str) -> usize {
+    clusters(s).iter().map(|cluster| cluster.width).sum()
+}
+
+/// Pad `s` with spaces to `width` columns.
+fn pad_to_width(s: &str, width: usize) -> String {
+    format!(
+        "{}{}",
+        s,
+        " ".repeat(width.saturating_sub(display_width(s)))
+    )
+}
+
+/// Split a string into parts of at most `max_len` columns, padding
+/// parts if necessary. Parts only end early to avoid splitting a
+/// grapheme cluster.
 ///
+/// ```text
+/// split_string_by_width("fooba", 3) // vec!["foo", "ba "]
 /// ```
-/// split_string_by_codepoint("fooba", 3) // vec!["foo", "ba "]
-/// ```
-fn split_string_by_codepoint(s: &str, max_len: usize, pad_last: bool) -> Vec<String> {
-    let mut res = vec![];
-    let mut s = s;
+fn split_string_by_width(s: &str, max_len: usize, pad_last: bool) -> Vec<String> {
+    wrap_rows(s, max_len, &[])
+        .into_iter()
+        .map(|row| {
+            let part = &s[row.start..row.end];
+            if pad_last {
+                pad_to_width(part, max_len)
+            } else {
+                part.to_owned()
+            }
+        })
+        .collect()
+}
+
+fn highlight_missing_style_bug(s: &str) -> String {
+    s.on_purple().to_string()
+}
 
-    while codepoint_len(s) > max_len {
-        res.push(substring_by_codepoint(s, 0, max_len).into());
-        s = substring_by_codepoint(s, max_len, codepoint_len(s));
+/// A visible character to show instead of `c`, if `c` is a control
+/// character or an invisible Unicode character (e.g. zero-width
+/// spaces and bidirectional overrides).
+///
+/// These are a security concern when reviewing changes, and they
+/// also break our column calculations, which assume that every
+/// character takes up one column.
+fn visible_substitute(c: char) -> Option<char> {
+    match c {
+        // Line endings are handled when splitting lines, and we
+        // use escape for our own styling.
+        '\n' | '\r' | '\x1b' => None,
+        // Use the control pictures block, e.g. U+2400 SYMBOL FOR NULL.
+        '\u{0}'..='\u{1f}' => std::char::from_u32(0x2400 + c as u32),
+        '\u{7f}' => Some('\u{2421}'),
+        '\u{200b}'..='\u{200f}'
+        | '\u{202a}'..='\u{202e}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{feff}' => Some('\u{25af}'),
+        _ => None,
     }
+}
 
-    if res.is_empty() || !s.is_empty() {
-        if pad_last {
-            res.push(format!("{:width$}", s, width = max_len));
+/// Replace control characters and invisible Unicode characters with
+/// visible substitutes. When `use_color` is set, show these
+/// substitutes and U+FFFD, which we use in place of bytes that
+/// aren't valid UTF-8, in reverse video so they stand out.
+///
+/// We only toggle reverse video, rather than resetting all styles
+/// afterwards, so we don't lose the styling of the surrounding token.
+pub fn highlight_invisible_chars(s: &str, use_color: bool) -> String {
+    let highlighted = |c: char| {
+        if c == std::char::REPLACEMENT_CHARACTER && use_color {
+            Some(c)
         } else {
-            res.push(s.to_string());
+            visible_substitute(c)
         }
+    };
+    if !s.chars().any(|c| highlighted(c).is_some()) {
+        return s.to_string();
     }
 
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match highlighted(c) {
+            Some(shown) if use_color => {
+                res.push_str("\x1b[7m");
+                res.push(shown);
+                res.push_str("\x1b[27m");
+            }
+            Some(shown) => res.push(shown),
+            None => res.push(c),
+        }
+    }
     res
 }
 
-fn highlight_missing_style_bug(s: &str) -> String {
-    s.on_purple().to_string()
+/// Is `c` from a right-to-left script, such as Hebrew or Arabic?
+fn is_rtl(c: char) -> bool {
+    matches!(c,
+        '\u{0590}'..='\u{08ff}'
+        | '\u{fb1d}'..='\u{fdff}'
+        | '\u{fe70}'..='\u{fefc}'
+        | '\u{10800}'..='\u{10fff}'
+        | '\u{1e800}'..='\u{1efff}')
+}
+
+/// Wrap `line` in a first strong isolate if it contains
+/// right-to-left text.
+///
+/// Terminals that implement the Unicode bidirectional algorithm
+/// reorder a whole row of output, which can move right-to-left text
+/// into the other column or past the line numbers. The isolate keeps
+/// the reordering within this line's content.
+pub fn isolate_rtl(line: &str) -> Cow<str> {
+    if line.chars().any(is_rtl) {
+        Cow::Owned(format!("\u{2068}{}\u{2069}", line))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Shown at the end of a wrapped row when a token continues on the
+/// next row.
+const WRAP_MARKER: char = '↩';
+
+/// Shown at the start of a wrapped row that continues a token from
+/// the previous row.
+const CONTINUATION_MARKER: char = '…';
+
+/// On narrower columns, the markers would leave too little room for
+/// the content.
+const MIN_WIDTH_FOR_WRAP_MARKERS: usize = 3;
+
+/// A row of a wrapped line: the byte range of the line it shows, and
+/// whether a token continues from the previous row or onto the next
+/// row.
+#[derive(Debug, PartialEq)]
+struct WrappedRow {
+    start: usize,
+    end: usize,
+    continues_from_prev: bool,
+    continues_to_next: bool,
+}
+
+/// Split `line` into rows of at most `max_len` columns, including
+/// markers. When a row boundary falls inside one of `spans`, the row
+/// is shortened to make room for a wrap marker. Rows never split a
+/// grapheme cluster, so a row may be shorter than `max_len`, or
+/// longer if a single cluster is wider than the row.
+fn wrap_rows(line: &str, max_len: usize, spans: &[SingleLineSpan]) -> Vec<WrappedRow> {
+    let clusters = clusters(line);
+    let offset = |i: usize| clusters.get(i).map_or(line.len(), |cluster| cluster.start);
+
+    let use_markers = max_len >= MIN_WIDTH_FOR_WRAP_MARKERS;
+    let inside_token = |byte_idx: usize| {
+        spans
+            .iter()
+            .any(|span| span.start_col < byte_idx && byte_idx < span.end_col)
+    };
+
+    let mut rows = vec![];
+    let mut start = 0;
+    let mut continues_from_prev = false;
+    loop {
+        let width = if continues_from_prev {
+            max_len - 1
+        } else {
+            max_len
+        };
+
+        // Take as many clusters as fit, and always at least one so
+        // we make progress.
+        let mut end = start;
+        let mut used = 0;
+        while end < clusters.len() && (end == start || used + clusters[end].width <= width) {
+            used += clusters[end].width;
+            end += 1;
+        }
+        if end == clusters.len() {
+            rows.push(WrappedRow {
+                start: offset(start),
+                end: line.len(),
+                continues_from_prev,
+                continues_to_next: false,
+            });
+            return rows;
+        }
+
+        if use_markers && inside_token(offset(end)) {
+            // Make room for the marker.
+            while end > start + 1 && used + 1 > width {
+                end -= 1;
+                used -= clusters[end].width;
+            }
+        }
+        // If we've moved the boundary out of the token, we don't
+        // need a marker after all.
+        let continues_to_next = use_markers && inside_token(offset(end));
+
+        rows.push(WrappedRow {
+            start: offset(start),
+            end: offset(end),
+            continues_from_prev,
+            continues_to_next,
+        });
+        start = end;
+        continues_from_prev = continues_to_next;
+    }
+}
+
+fn wrap_marker(marker: char, use_color: bool) -> String {
+    if use_color {
+        marker.dimmed().to_string()
+    } else {
+        marker.to_string()
+    }
 }
 
 /// Split `line` (from the source code) into multiple lines of
 /// `max_len` (i.e. word wrapping), and apply `styles` to each part
 /// according to its original position in `line`.
+///
+/// Tokens that are split across rows get wrap markers, so they
+/// aren't mistaken for separate tokens.
 pub fn split_and_apply(
     line: &str,
     max_len: usize,
@@ -90,7 +337,7 @@ pub fn split_and_apply(
 ) -> Vec<String> {
     if styles.is_empty() && !line.trim().is_empty() {
         // Missing styles is a bug, so highlight in purple to make this obvious.
-        return split_string_by_codepoint(line, max_len, true)
+        return split_string_by_width(line, max_len, true)
             .into_iter()
             .map(|part| {
                 if use_color {
@@ -102,15 +349,18 @@ pub fn split_and_apply(
             .collect();
     }
 
+    let spans: Vec<SingleLineSpan> = styles.iter().map(|(span, _)| *span).collect();
     let mut styled_parts = vec![];
-    let mut part_start = 0;
 
-    for part in split_string_by_codepoint(line, max_len, true) {
+    for row in wrap_rows(line, max_len, &spans) {
+        let part = &line[row.start..row.end];
+        let part_start = row.start;
+
         let mut res = String::with_capacity(part.len());
         let mut prev_style_end = 0;
         for (span, style) in styles {
             // The remaining spans are beyond the end of this part.
-            if span.start_col >= part_start + byte_len(&part) {
+            if span.start_col >= part_start + byte_len(part) {
                 break;
             }
 
@@ -119,7 +369,7 @@ pub fn split_and_apply(
                 // Then append that text without styling.
                 let unstyled_start = max(prev_style_end, part_start);
                 res.push_str(substring_by_byte(
-                    &part,
+                    part,
                     unstyled_start - part_start,
                     span.start_col - part_start,
                 ));
@@ -128,9 +378,9 @@ pub fn split_and_apply(
             // Apply style to the substring in this span.
             if span.end_col > part_start {
                 let span_s = substring_by_byte(
-                    &part,
+                    part,
                     max(0, span.start_col as isize - part_start as isize) as usize,
-                    min(byte_len(&part), span.end_col - part_start),
+                    min(byte_len(part), span.end_col - part_start),
                 );
                 res.push_str(&span_s.style(*style).to_string());
             }
@@ -144,13 +394,27 @@ pub fn split_and_apply(
         }
 
         // Unstyled text after the last span.
-        if prev_style_end < part_start + codepoint_len(&part) {
-            let span_s = substring_by_byte(&part, prev_style_end - part_start, byte_len(&part));
+        if prev_style_end < part_start + codepoint_len(part) {
+            let span_s = substring_by_byte(part, prev_style_end - part_start, byte_len(part));
             res.push_str(span_s);
         }
 
-        styled_parts.push(res);
-        part_start += byte_len(&part);
+        let mut styled = String::with_capacity(res.len() + max_len);
+        if row.continues_from_prev {
+            styled.push_str(&wrap_marker(CONTINUATION_MARKER, use_color));
+        }
+        styled.push_str(&highlight_invisible_chars(&res, use_color));
+        if row.continues_to_next {
+            styled.push_str(&wrap_marker(WRAP_MARKER, use_color));
+        }
+
+        let used_width =
+            display_width(part) + row.continues_from_prev as usize + row.continues_to_next as usize;
+        if used_width < max_len {
+            styled.push_str(&" ".repeat(max_len - used_width));
+        }
+
+        styled_parts.push(styled);
     }
 
     styled_parts
@@ -192,9 +456,12 @@ fn apply_line(line: &str, styles: &[(SingleLineSpan, Style)]) -> String {
     res
 }
 
-fn group_by_line(
-    ranges: &[(SingleLineSpan, Style)],
-) -> FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>> {
+/// The styles of each line, sorted by position within the line.
+pub type LineStyles = FxHashMap<LineNumber, Vec<(SingleLineSpan, Style)>>;
+
+/// Group `ranges` by line. The ranges on each line are sorted by
+/// column, so the output doesn't depend on the order we found them.
+pub fn group_by_line(ranges: &[(SingleLineSpan, Style)]) -> LineStyles {
     let mut ranges_by_line: FxHashMap<_, Vec<_>> = FxHashMap::default();
     for range in ranges {
         if let Some(matching_ranges) = ranges_by_line.get_mut(&range.0.line) {
@@ -204,26 +471,41 @@ fn group_by_line(
         }
     }
 
+    // The order we visit lines here doesn't matter: we only sort
+    // within each line.
+    for line_ranges in ranges_by_line.values_mut() {
+        line_ranges.sort_by_key(|(span, _)| (span.start_col, span.end_col));
+    }
+
     ranges_by_line
 }
 
-/// Apply the `Style`s to the spans specified.
+/// Apply the styles of each line in `s`.
 ///
 /// Tolerant against lines in `s` being shorter than the spans.
-fn apply(s: &str, styles: &[(SingleLineSpan, Style)]) -> String {
-    let mut ranges_by_line = group_by_line(styles);
-
+fn apply(s: &str, styles: &LineStyles) -> String {
     let mut res = String::with_capacity(s.len());
     for (i, line) in s.lines().enumerate() {
-        let ranges = ranges_by_line.remove(&i.into()).unwrap_or_default();
-        res.push_str(&apply_line(line, &ranges));
+        let ranges = styles.get(&i.into()).map(|r| &r[..]).unwrap_or(&[]);
+        res.push_str(&apply_line(line, ranges));
         res.push('\n');
     }
     res
 }
 
-pub fn novel_style(style: Style, is_lhs: bool, background: BackgroundColor) -> Style {
-    if background.is_dark() {
+pub fn novel_style(
+    style: Style,
+    is_lhs: bool,
+    background: BackgroundColor,
+    color_depth: ColorDepth,
+) -> Style {
+    if !color_depth.has_backgrounds() {
+        if is_lhs {
+            style.red()
+        } else {
+            style.green()
+        }
+    } else if background.is_dark() || !color_depth.has_bright() {
         if is_lhs {
             style.on_red()
         } else {
@@ -236,9 +518,29 @@ pub fn novel_style(style: Style, is_lhs: bool, background: BackgroundColor) -> S
     }
 }
 
+/// The style for unchanged atoms of `atom_kind`, when syntax
+/// highlighting is enabled.
+pub fn syntax_style(
+    atom_kind: AtomKind,
+    background: BackgroundColor,
+    color_depth: ColorDepth,
+) -> Style {
+    let style = Style::new();
+    let use_bright = background.is_dark() && color_depth.has_bright();
+    match atom_kind {
+        AtomKind::String if use_bright => style.bright_magenta(),
+        AtomKind::String => style.magenta(),
+        AtomKind::Comment if use_bright => style.italic().bright_blue(),
+        AtomKind::Comment => style.italic().blue(),
+        AtomKind::Keyword | AtomKind::Type => style.bold(),
+        AtomKind::Normal => style,
+    }
+}
+
 pub fn color_positions(
     is_lhs: bool,
     background: BackgroundColor,
+    color_depth: ColorDepth,
     syntax_highlight: bool,
     positions: &[MatchedPos],
 ) -> Vec<(SingleLineSpan, Style)> {
@@ -249,32 +551,12 @@ pub fn color_positions(
             MatchKind::UnchangedToken { highlight, .. } => {
                 if syntax_highlight {
                     if let TokenKind::Atom(atom_kind) = highlight {
-                        match atom_kind {
-                            AtomKind::String => {
-                                style = if background.is_dark() {
-                                    style.bright_magenta()
-                                } else {
-                                    style.magenta()
-                                };
-                            }
-                            AtomKind::Comment => {
-                                style = style.italic();
-                                style = if background.is_dark() {
-                                    style.bright_blue()
-                                } else {
-                                    style.blue()
-                                };
-                            }
-                            AtomKind::Keyword | AtomKind::Type => {
-                                style = style.bold();
-                            }
-                            AtomKind::Normal => {}
-                        }
+                        style = syntax_style(atom_kind, background, color_depth);
                     }
                 }
             }
             MatchKind::Novel { highlight, .. } => {
-                style = novel_style(style, is_lhs, background);
+                style = novel_style(style, is_lhs, background, color_depth);
                 if syntax_highlight {
                     if matches!(
                         highlight,
@@ -290,7 +572,7 @@ pub fn color_positions(
                 }
             }
             MatchKind::NovelWord { highlight } => {
-                style = novel_style(style, is_lhs, background).bold();
+                style = novel_style(style, is_lhs, background, color_depth).bold();
                 if syntax_highlight {
                     if matches!(highlight, TokenKind::Atom(AtomKind::Comment)) {
                         style = style.italic();
@@ -298,7 +580,7 @@ pub fn color_positions(
                 }
             }
             MatchKind::NovelLinePart { highlight, .. } => {
-                style = novel_style(style, is_lhs, background);
+                style = novel_style(style, is_lhs, background, color_depth);
                 if syntax_highlight {
                     if matches!(highlight, TokenKind::Atom(AtomKind::Comment)) {
                         style = style.italic();
@@ -311,20 +593,50 @@ pub fn color_positions(
     styles
 }
 
+/// The styles for syntax highlighting and novel content on each
+/// line. This is the single source of colours for a file, whether we
+/// colour whole lines or split them into columns.
+pub fn line_styles(
+    is_lhs: bool,
+    syntax_highlight: bool,
+    background: BackgroundColor,
+    color_depth: ColorDepth,
+    positions: &[MatchedPos],
+) -> LineStyles {
+    group_by_line(&color_positions(
+        is_lhs,
+        background,
+        color_depth,
+        syntax_highlight,
+        positions,
+    ))
+}
+
+/// Colour every line of `s` with `styles`.
+pub fn apply_line_styles(s: &str, styles: &LineStyles) -> String {
+    highlight_invisible_chars(&apply(s, styles), true)
+}
+
 pub fn apply_colors(
     s: &str,
     is_lhs: bool,
     syntax_highlight: bool,
     background: BackgroundColor,
+    color_depth: ColorDepth,
     positions: &[MatchedPos],
 ) -> String {
-    let styles = color_positions(is_lhs, background, syntax_highlight, positions);
-    apply(s, &styles)
+    let styles = line_styles(is_lhs, syntax_highlight, background, color_depth, positions);
+    apply_line_styles(s, &styles)
 }
 
-fn apply_header_color(s: &str, use_color: bool, background: BackgroundColor) -> String {
+pub fn apply_header_color(
+    s: &str,
+    use_color: bool,
+    background: BackgroundColor,
+    color_depth: ColorDepth,
+) -> String {
     if use_color {
-        if background.is_dark() {
+        if background.is_dark() && color_depth.has_bright() {
             s.bright_yellow().to_string()
         } else {
             s.yellow().to_string()
@@ -336,32 +648,205 @@ fn apply_header_color(s: &str, use_color: bool, background: BackgroundColor) ->
     }
 }
 
+/// Remove spaces at the end of a line of output, if the user has
+/// asked for this with `--trim-trailing-spaces`. Padding is only
+/// visible when it's styled (e.g. a background color), which ends
+/// the line with an escape code, so this leaves styled padding
+/// alone.
+pub fn trim_trailing_spaces<'a>(line: &'a str, display_options: &DisplayOptions) -> &'a str {
+    if display_options.trim_trailing_spaces {
+        line.trim_end_matches(' ')
+    } else {
+        line
+    }
+}
+
+/// Describe a new file that was copied from an existing file.
+pub fn copied_from(
+    lhs_display_path: &str,
+    rhs_display_path: &str,
+    display_options: &DisplayOptions,
+) -> String {
+    tr_path(
+        Message::Copied,
+        &apply_header_color(
+            rhs_display_path,
+            display_options.use_color,
+            display_options.background_color,
+            display_options.color_depth,
+        ),
+        &apply_header_color(
+            lhs_display_path,
+            display_options.use_color,
+            display_options.background_color,
+            display_options.color_depth,
+        ),
+    )
+}
+
+/// A banner for a file that was added or deleted.
+pub fn file_status(
+    status: FileStatus,
+    display_path: &str,
+    display_options: &DisplayOptions,
+) -> String {
+    let path = apply_header_color(
+        display_path,
+        display_options.use_color,
+        display_options.background_color,
+        display_options.color_depth,
+    );
+    let message = match status {
+        FileStatus::Added => Message::FileAdded,
+        FileStatus::Deleted => Message::FileDeleted,
+        FileStatus::Truncated(Side::Left) => Message::OldFileTruncated,
+        FileStatus::Truncated(Side::Right) => Message::NewFileTruncated,
+    };
+    tr_path(message, &path, "")
+}
+
+/// A one-line notice for a generated file that we don't show the
+/// changes for.
+pub fn generated_file_changed(display_path: &str, display_options: &DisplayOptions) -> String {
+    tr_path(
+        Message::GeneratedFileChanged,
+        &apply_header_color(
+            display_path,
+            display_options.use_color,
+            display_options.background_color,
+            display_options.color_depth,
+        ),
+        "",
+    )
+}
+
+/// The placeholders supported by `--header-format`.
+pub const HEADER_PLACEHOLDERS: &[&str] = &[
+    "path", "old_path", "lang", "n", "total", "changes", "old_hash", "new_hash", "old_mode",
+    "new_mode", "old_size", "new_size",
+];
+
+/// Replace every `{name}` in `template` with `lookup(name)`. If
+/// `lookup` returns `None`, the placeholder is left as-is.
+pub fn expand_placeholders(
+    template: &str,
+    mut lookup: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        match after
+            .find('}')
+            .and_then(|end| lookup(&after[..end]).map(|v| (end, v)))
+        {
+            Some((end, value)) => {
+                res.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                res.push('{');
+                rest = after;
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Render the header for a hunk using the user's `--header-format`.
+fn custom_header(
+    format: &str,
+    lhs_display_path: &str,
+    rhs_display_path: &str,
+    hunk_num: usize,
+    hunk_total: usize,
+    language_name: &str,
+    counts: Option<HunkCounts>,
+    display_options: &DisplayOptions,
+) -> String {
+    let metadata = display_options.vcs_metadata.as_ref();
+    let path = |p: &str| {
+        apply_header_color(
+            p,
+            display_options.use_color,
+            display_options.background_color,
+            display_options.color_depth,
+        )
+    };
+
+    expand_placeholders(format, |name| {
+        let value = match name {
+            "path" => path(rhs_display_path),
+            "old_path" => path(lhs_display_path),
+            "lang" => language_name.to_string(),
+            "n" => hunk_num.to_string(),
+            "total" => hunk_total.to_string(),
+            "changes" => counts.map(|c| c.to_string()).unwrap_or_default(),
+            "old_hash" => metadata.map(|m| m.lhs_hash.clone()).unwrap_or_default(),
+            "new_hash" => metadata.map(|m| m.rhs_hash.clone()).unwrap_or_default(),
+            "old_mode" => metadata.map(|m| m.lhs_mode.clone()).unwrap_or_default(),
+            "new_mode" => metadata.map(|m| m.rhs_mode.clone()).unwrap_or_default(),
+            "old_size" => metadata.map(|m| m.lhs_size.to_string()).unwrap_or_default(),
+            "new_size" => metadata.map(|m| m.rhs_size.to_string()).unwrap_or_default(),
+            _ => return None,
+        };
+        Some(value)
+    })
+}
+
+/// The header shown before each hunk. `counts` summarises the
+/// changes in the hunk, if we know them.
 pub fn header(
     lhs_display_path: &str,
     rhs_display_path: &str,
     hunk_num: usize,
     hunk_total: usize,
     language_name: &str,
+    counts: Option<HunkCounts>,
     display_options: &DisplayOptions,
 ) -> String {
+    if let Some(format) = &display_options.header_format {
+        return custom_header(
+            format,
+            lhs_display_path,
+            rhs_display_path,
+            hunk_num,
+            hunk_total,
+            language_name,
+            counts,
+            display_options,
+        );
+    }
+
     let divider = if hunk_total == 1 {
         "".to_owned()
     } else {
         format!("{}/{} --- ", hunk_num, hunk_total)
     };
+    let language_name = match counts {
+        Some(counts) if counts != HunkCounts::default() => {
+            format!("{} --- {}", language_name, counts)
+        }
+        _ => language_name.to_owned(),
+    };
 
     let rhs_path_pretty = apply_header_color(
         rhs_display_path,
         display_options.use_color,
         display_options.background_color,
+        display_options.color_depth,
     );
     if hunk_num == 1 && lhs_display_path != rhs_display_path && display_options.in_vcs {
         let lhs_path_pretty = apply_header_color(
             lhs_display_path,
             display_options.use_color,
             display_options.background_color,
+            display_options.color_depth,
         );
-        let renamed = format!("Renamed {} to {}", lhs_path_pretty, rhs_path_pretty,);
+        let renamed = tr_path(Message::Renamed, &rhs_path_pretty, &lhs_path_pretty);
         format!(
             "{}\n{} --- {}{}",
             renamed, rhs_path_pretty, divider, language_name
@@ -371,42 +856,260 @@ pub fn header(
     }
 }
 
+/// The number of characters used to draw a minimap.
+const MINIMAP_WIDTH: usize = 20;
+
+/// Draw a bar showing where the lines `start` to `end` (inclusive)
+/// occur in a file whose last line is `max_line`.
+///
+/// ```text
+/// minimap(0.into(), 9.into(), 19.into()) // "██████████──────────"
+/// ```
+fn minimap(start: LineNumber, end: LineNumber, max_line: LineNumber) -> String {
+    let num_lines = max_line.0 + 1;
+
+    (0..MINIMAP_WIDTH)
+        .map(|i| {
+            let cell_start = i * num_lines / MINIMAP_WIDTH;
+            let cell_end = max(cell_start + 1, (i + 1) * num_lines / MINIMAP_WIDTH);
+            if cell_start <= end.0 && start.0 < cell_end {
+                '█'
+            } else {
+                '─'
+            }
+        })
+        .collect()
+}
+
+/// Draw a minimap for `hunk`. We show where the hunk occurs in the
+/// RHS file, unless the hunk only contains removals.
+pub fn hunk_minimap(hunk: &Hunk, lhs_max_line: LineNumber, rhs_max_line: LineNumber) -> String {
+    let (novel_lines, max_line) = if hunk.novel_rhs.is_empty() {
+        (&hunk.novel_lhs, lhs_max_line)
+    } else {
+        (&hunk.novel_rhs, rhs_max_line)
+    };
+
+    match (novel_lines.iter().min(), novel_lines.iter().max()) {
+        (Some(start), Some(end)) => minimap(*start, *end, max_line),
+        _ => String::new(),
+    }
+}
+
+/// A heading for the hunks inside a top-level definition, such as
+/// `fn parse_args — 3 changes`.
+pub fn definition_header(definition: Option<&str>, num_hunks: usize, use_color: bool) -> String {
+    let s = format!(
+        "{} — {} {}",
+        definition.unwrap_or("(top level)"),
+        num_hunks,
+        if num_hunks == 1 { "change" } else { "changes" }
+    );
+    if use_color {
+        s.bold().to_string()
+    } else {
+        s
+    }
+}
+
+/// A note shown above a hunk when only the signature, or only the
+/// body, of the enclosing definition changed.
+pub fn signature_banner(kind: SignatureChangeKind, use_color: bool) -> String {
+    let s = match kind {
+        SignatureChangeKind::SignatureOnly => tr(Message::SignatureOnly),
+        SignatureChangeKind::BodyOnly => tr(Message::BodyOnly),
+    };
+    if use_color {
+        s.dimmed().to_string()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// A note shown above a hunk that we diffed as text, because the
+/// structural diff ran out of time.
+pub fn timed_out_banner(region: &TimedOutRegion, use_color: bool) -> String {
+    let s = format!("Text diff (exceeded --time-limit): {}", region);
+    if use_color {
+        s.dimmed().to_string()
+    } else {
+        s
+    }
+}
+
+/// A placeholder for lines hidden by collapsing the body of an
+/// unchanged definition.
+pub fn collapsed_lines(num_lines: usize, use_color: bool) -> String {
+    let s = format!(
+        "… {} {} hidden",
+        num_lines,
+        if num_lines == 1 { "line" } else { "lines" }
+    );
+    if use_color {
+        s.dimmed().to_string()
+    } else {
+        s
+    }
+}
+
+/// A single line summarising a collapsed definition of `num_lines`
+/// lines that starts with `first_line`, such as `fn foo(...) { … 84
+/// lines }`.
+pub fn collapsed_definition(first_line: &str, num_lines: usize) -> String {
+    let indent = &first_line[..first_line.len() - first_line.trim_start().len()];
+    let name = definition_name(first_line);
+    // Parameter lists can be long, so elide them.
+    let name = match (name.find('('), name.rfind(')')) {
+        (Some(open), Some(close)) if open < close => {
+            format!("{}(...){}", &name[..open], &name[close + 1..])
+        }
+        _ => name,
+    };
+    format!("{}{} {{ … {} lines }}", indent, name, num_lines)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cansi::categorise_text;
     use pretty_assertions::assert_eq;
 
+    fn strip_ansi_escapes(s: &str) -> String {
+        categorise_text(s).iter().map(|slice| slice.text).collect()
+    }
+
     #[test]
-    fn test_substring_by_codepoint() {
-        assert_eq!(substring_by_codepoint("abcd", 0, 2), "ab");
+    fn test_collapsed_definition() {
+        assert_eq!(
+            collapsed_definition("pub fn handle_request(req: Request) -> Response {", 84),
+            "pub fn handle_request(...) -> Response { … 84 lines }"
+        );
+        assert_eq!(
+            collapsed_definition("    def foo(x, y):", 12),
+            "    def foo(...) { … 12 lines }"
+        );
     }
 
     #[test]
-    fn test_substring_by_codepoint_empty() {
-        assert_eq!(substring_by_codepoint("abcd", 0, 0), "");
+    fn test_novel_style_ci_has_no_background() {
+        let style = novel_style(Style::new(), true, BackgroundColor::Dark, ColorDepth::Ci);
+        assert_eq!(style, Style::new().red());
     }
 
     #[test]
-    fn split_string_simple() {
+    fn test_highlight_replacement_chars() {
         assert_eq!(
-            split_string_by_codepoint("fooba", 3, true),
-            vec!["foo", "ba "]
+            highlight_invisible_chars("a\u{FFFD}b", true),
+            "a\x1b[7m\u{FFFD}\x1b[27mb"
         );
     }
 
     #[test]
-    fn split_string_simple_no_pad() {
+    fn test_highlight_invisible_chars() {
+        assert_eq!(
+            highlight_invisible_chars("a\u{200B}b\u{0}", true),
+            "a\x1b[7m\u{25AF}\x1b[27mb\x1b[7m\u{2400}\x1b[27m"
+        );
+    }
+
+    #[test]
+    fn test_highlight_invisible_chars_no_color() {
+        assert_eq!(
+            highlight_invisible_chars("\u{202E}x\u{FFFD}", false),
+            "\u{25AF}x\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn test_isolate_rtl() {
+        assert_eq!(isolate_rtl("foo = 1"), "foo = 1");
+        assert_eq!(
+            isolate_rtl("x = \"\u{5e9}\u{5dc}\u{5d5}\u{5dd}\""),
+            "\u{2068}x = \"\u{5e9}\u{5dc}\u{5d5}\u{5dd}\"\u{2069}"
+        );
+    }
+
+    #[test]
+    fn test_trim_trailing_spaces() {
+        let display_options = DisplayOptions {
+            trim_trailing_spaces: true,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(trim_trailing_spaces("1 foo   ", &display_options), "1 foo");
+        assert_eq!(
+            trim_trailing_spaces("\x1b[41mfoo  \x1b[0m", &display_options),
+            "\x1b[41mfoo  \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_trailing_spaces_kept_by_default() {
+        assert_eq!(
+            trim_trailing_spaces("1 foo   ", &DisplayOptions::default()),
+            "1 foo   "
+        );
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
         assert_eq!(
-            split_string_by_codepoint("fooba", 3, false),
-            vec!["foo", "ba"]
+            expand_placeholders("{path} ({lang}) {unknown} {", |name| match name {
+                "path" => Some("foo.rs".into()),
+                "lang" => Some("Rust".into()),
+                _ => None,
+            }),
+            "foo.rs (Rust) {unknown} {"
         );
     }
 
+    #[test]
+    fn test_group_by_line_sorts_columns() {
+        let span = |start_col, end_col| SingleLineSpan {
+            line: 0.into(),
+            start_col,
+            end_col,
+        };
+        let ranges = vec![(span(4, 6), Style::new()), (span(0, 2), Style::new())];
+
+        let by_line = group_by_line(&ranges);
+        let cols: Vec<_> = by_line[&0.into()]
+            .iter()
+            .map(|(span, _)| span.start_col)
+            .collect();
+        assert_eq!(cols, vec![0, 4]);
+    }
+
+    #[test]
+    fn split_string_simple() {
+        assert_eq!(split_string_by_width("fooba", 3, true), vec!["foo", "ba "]);
+    }
+
+    #[test]
+    fn split_string_simple_no_pad() {
+        assert_eq!(split_string_by_width("fooba", 3, false), vec!["foo", "ba"]);
+    }
+
     #[test]
     fn split_string_unicode() {
         assert_eq!(
-            split_string_by_codepoint("ab📦def", 3, true),
-            vec!["ab📦", "def"]
+            split_string_by_width("ab📦def", 3, true),
+            vec!["ab ", "📦 ", "def"]
+        );
+    }
+
+    #[test]
+    fn test_minimap() {
+        assert_eq!(
+            minimap(0.into(), 9.into(), 19.into()),
+            "██████████──────────"
+        );
+    }
+
+    #[test]
+    fn test_minimap_short_file() {
+        assert_eq!(
+            minimap(1.into(), 1.into(), 1.into()),
+            "──────────██████████"
         );
     }
 
@@ -483,6 +1186,188 @@ mod tests {
         assert_eq!(res, vec!["foo", "bar"])
     }
 
+    #[test]
+    fn test_split_string_keeps_clusters() {
+        // e followed by U+0301 COMBINING ACUTE ACCENT takes up one
+        // column.
+        assert_eq!(
+            split_string_by_width("abe\u{301}f", 3, true),
+            vec!["abe\u{301}", "f  "]
+        );
+        // Regional indicator pairs are a single flag.
+        assert_eq!(split_string_by_width("a🇩🇪🇫🇷", 3, true), vec!["a🇩🇪", "🇫🇷 "]);
+    }
+
+    #[test]
+    fn test_split_string_wide_chars() {
+        assert_eq!(
+            split_string_by_width("ab中文", 3, true),
+            vec!["ab ", "中 ", "文 "]
+        );
+    }
+
+    #[test]
+    fn test_clusters_escape() {
+        assert_eq!(
+            clusters("a\x1b[1mb"),
+            vec![
+                Cluster { start: 0, width: 1 },
+                Cluster { start: 1, width: 0 },
+                Cluster { start: 5, width: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("中文"), 4);
+        assert_eq!(display_width("\x1b[31ma\x1b[0m"), 1);
+        // Control characters are shown as a control picture.
+        assert_eq!(display_width("a\u{0}"), 2);
+    }
+
+    /// Render lines of random Unicode at random widths, checking that
+    /// rows fit, that rows only end on cluster boundaries, and that
+    /// no visible content is lost.
+    #[test]
+    fn test_split_and_apply_random_unicode() {
+        // Each entry is a complete grapheme cluster, or an escape.
+        let palette = [
+            "a",
+            "Z",
+            "e\u{301}",
+            "\u{5d0}\u{5b7}",
+            "中",
+            "文",
+            "📦",
+            "👩\u{200d}💻",
+            "👍\u{1f3fd}",
+            "🇩🇪",
+            "\x1b[31m",
+        ];
+
+        // A fixed xorshift generator, so failures are reproducible.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+
+        for _ in 0..500 {
+            // Style each cluster as a separate token.
+            let mut line = String::new();
+            let mut styles = vec![];
+            for _ in 0..next(40) {
+                let cluster = palette[next(palette.len())];
+                styles.push((
+                    SingleLineSpan {
+                        line: 0.into(),
+                        start_col: line.len(),
+                        end_col: line.len() + cluster.len(),
+                    },
+                    Style::new(),
+                ));
+                line.push_str(cluster);
+            }
+            let max_len = 8 + next(20);
+            let spans: Vec<_> = styles.iter().map(|(span, _)| *span).collect();
+
+            let boundary_offsets: Vec<usize> = clusters(&line)
+                .iter()
+                .map(|cluster| cluster.start)
+                .chain(std::iter::once(line.len()))
+                .collect();
+            for row in wrap_rows(&line, max_len, &spans) {
+                assert!(boundary_offsets.contains(&row.end), "{:?}", line);
+            }
+
+            let rows = split_and_apply(&line, max_len, false, &styles, Side::Left);
+            let mut visible = String::new();
+            for row in &rows {
+                let row = strip_ansi_escapes(row);
+                assert!(
+                    UnicodeWidthStr::width(row.as_str()) <= max_len,
+                    "{:?} {:?}",
+                    line,
+                    row
+                );
+                visible.push_str(
+                    row.trim_end_matches(' ')
+                        .trim_start_matches(CONTINUATION_MARKER)
+                        .trim_end_matches(WRAP_MARKER),
+                );
+            }
+            assert_eq!(
+                visible,
+                strip_ansi_escapes(&highlight_invisible_chars(&line, false))
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrap_rows_inside_token() {
+        let span = SingleLineSpan {
+            line: 0.into(),
+            start_col: 0,
+            end_col: 8,
+        };
+        assert_eq!(
+            wrap_rows("abcdefgh", 4, &[span]),
+            vec![
+                WrappedRow {
+                    start: 0,
+                    end: 3,
+                    continues_from_prev: false,
+                    continues_to_next: true,
+                },
+                WrappedRow {
+                    start: 3,
+                    end: 5,
+                    continues_from_prev: true,
+                    continues_to_next: true,
+                },
+                WrappedRow {
+                    start: 5,
+                    end: 8,
+                    continues_from_prev: true,
+                    continues_to_next: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_and_apply_wrap_markers() {
+        let res = split_and_apply(
+            "foobar x",
+            5,
+            false,
+            &[
+                (
+                    SingleLineSpan {
+                        line: 0.into(),
+                        start_col: 0,
+                        end_col: 6,
+                    },
+                    Style::new(),
+                ),
+                (
+                    SingleLineSpan {
+                        line: 0.into(),
+                        start_col: 7,
+                        end_col: 8,
+                    },
+                    Style::new(),
+                ),
+            ],
+            Side::Left,
+        );
+        assert_eq!(res, vec!["foob↩", "…ar x"])
+    }
+
     #[test]
     fn test_split_and_apply_trailing_text_newline() {
         let res = split_and_apply(
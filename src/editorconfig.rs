@@ -0,0 +1,269 @@
+//! Reading `.editorconfig` files, so tabs are expanded and files are
+//! decoded the same way as in the author's editor.
+//!
+//! We only support the `tab_width`, `indent_size` and `charset`
+//! properties. See https://editorconfig.org/ for the format.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::Regex;
+
+use crate::files::slash_path;
+
+/// The properties that apply to a file.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Properties {
+    pub tab_width: Option<usize>,
+    /// The charset, if it isn't UTF-8.
+    pub charset: Option<&'static Encoding>,
+}
+
+/// Which `.editorconfig` properties we should use. Properties that
+/// the user has set explicitly on the command line are ignored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EditorConfigUse {
+    pub tab_width: bool,
+    pub charset: bool,
+}
+
+/// The raw values set so far, so later sections can override or
+/// `unset` them.
+#[derive(Debug, Default)]
+struct Values {
+    tab_width: Option<String>,
+    indent_size: Option<String>,
+    charset: Option<String>,
+}
+
+impl Values {
+    fn set(&mut self, key: &str, value: &str) {
+        let value = if value == "unset" {
+            None
+        } else {
+            Some(value.to_owned())
+        };
+        match key {
+            "tab_width" => self.tab_width = value,
+            "indent_size" => self.indent_size = value,
+            "charset" => self.charset = value,
+            _ => {}
+        }
+    }
+
+    fn properties(&self) -> Properties {
+        // tab_width defaults to indent_size, unless indent_size is
+        // `tab`.
+        let tab_width = self
+            .tab_width
+            .as_ref()
+            .or(self.indent_size.as_ref())
+            .and_then(|s| s.parse::<usize>().ok())
+            .filter(|n| *n > 0);
+
+        // We assume UTF-8 anyway, so only other charsets override how
+        // we decode and detect binary files.
+        let charset = match self.charset.as_deref() {
+            Some("utf-8") | Some("utf-8-bom") => None,
+            Some("utf-16be") => Some(UTF_16BE),
+            Some("utf-16le") => Some(UTF_16LE),
+            Some(label) => Encoding::for_label(label.as_bytes()),
+            None => None,
+        }
+        .filter(|encoding| *encoding != UTF_8);
+
+        Properties { tab_width, charset }
+    }
+}
+
+/// Convert an editorconfig glob to a regex that matches the path
+/// relative to the directory containing the `.editorconfig`.
+pub fn glob_regex(glob: &str) -> Option<Regex> {
+    // Globs without a slash match files in any subdirectory.
+    let glob = match glob.strip_prefix('/') {
+        Some(glob) => glob.to_owned(),
+        None if !glob.contains('/') => format!("**/{}", glob),
+        None => glob.to_owned(),
+    };
+
+    let mut res = String::from("^");
+    let mut chars = glob.chars().peekable();
+    let mut brace_depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // `**/` also matches no directories at all.
+                    chars.next();
+                    res.push_str("(?:.*/)?");
+                } else {
+                    res.push_str(".*");
+                }
+            }
+            '*' => res.push_str("[^/]*"),
+            '?' => res.push_str("[^/]"),
+            '[' => {
+                res.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    res.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        res.push('\\');
+                    }
+                    res.push(c);
+                }
+                res.push(']');
+            }
+            '{' => {
+                brace_depth += 1;
+                res.push_str("(?:");
+            }
+            ',' if brace_depth > 0 => res.push('|'),
+            '}' if brace_depth > 0 => {
+                brace_depth -= 1;
+                res.push(')');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    res.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => res.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    res.push('$');
+
+    Regex::new(&res).ok()
+}
+
+/// Apply the sections in `contents` that match `rel_path` to
+/// `values`. Returns true if this is a root `.editorconfig`, so we
+/// shouldn't look in parent directories.
+fn apply(contents: &str, rel_path: &str, values: &mut Values) -> bool {
+    let mut is_root = false;
+    // None before the first section, when we're in the preamble.
+    let mut section_matches: Option<bool> = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section_matches = Some(glob_regex(glob).map_or(false, |re| re.is_match(rel_path)));
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_lowercase();
+            match section_matches {
+                None => {
+                    if key == "root" && value == "true" {
+                        is_root = true;
+                    }
+                }
+                Some(true) => values.set(&key, &value),
+                Some(false) => {}
+            }
+        }
+    }
+
+    is_root
+}
+
+/// Find the `.editorconfig` properties that apply to `path`.
+pub fn properties(path: &Path) -> Properties {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        // The file may not exist, e.g. if it was deleted, but its
+        // .editorconfig still applies.
+        Err(_) => match std::env::current_dir() {
+            Ok(cwd) => cwd.join(path),
+            Err(_) => return Properties::default(),
+        },
+    };
+
+    // Find every .editorconfig from the file's directory up to the
+    // root, then apply them outermost first so nearer files win.
+    let mut configs: Vec<(PathBuf, String)> = vec![];
+    for dir in path.ancestors().skip(1) {
+        if let Ok(contents) = fs::read_to_string(dir.join(".editorconfig")) {
+            let is_root = apply(&contents, "", &mut Values::default());
+            configs.push((dir.to_owned(), contents));
+            if is_root {
+                break;
+            }
+        }
+    }
+
+    let mut values = Values::default();
+    for (dir, contents) in configs.iter().rev() {
+        if let Ok(rel_path) = path.strip_prefix(dir) {
+            apply(contents, &slash_path(rel_path), &mut values);
+        }
+    }
+    values.properties()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_regex() {
+        let re = glob_regex("*.{js,ts}").unwrap();
+        assert!(re.is_match("main.js"));
+        assert!(re.is_match("src/lib/main.ts"));
+        assert!(!re.is_match("main.rs"));
+
+        let re = glob_regex("/src/**/[!_]*.c").unwrap();
+        assert!(re.is_match("src/main.c"));
+        assert!(re.is_match("src/a/b/main.c"));
+        assert!(!re.is_match("src/_private.c"));
+        assert!(!re.is_match("lib/main.c"));
+    }
+
+    #[test]
+    fn test_apply() {
+        let contents = "root = true\n\n[*]\nindent_size = 4\ncharset = latin1\n\n[Makefile]\ntab_width = 8\ncharset = unset\n";
+
+        let mut values = Values::default();
+        assert!(apply(contents, "src/main.c", &mut values));
+        assert_eq!(
+            values.properties(),
+            Properties {
+                tab_width: Some(4),
+                charset: Encoding::for_label(b"latin1"),
+            }
+        );
+
+        let mut values = Values::default();
+        apply(contents, "Makefile", &mut values);
+        assert_eq!(
+            values.properties(),
+            Properties {
+                tab_width: Some(8),
+                charset: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_utf8_charset_is_not_an_override() {
+        let contents = "[*]\ncharset = utf-8\n";
+        let mut values = Values::default();
+        apply(contents, "main.rs", &mut values);
+        assert_eq!(values.properties(), Properties::default());
+    }
+}
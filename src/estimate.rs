@@ -0,0 +1,209 @@
+//! Predicting how expensive a structural diff will be, without
+//! running the graph search.
+//!
+//! This is intended for wrappers that want to decide, per file,
+//! whether to call difftastic or a plain line-oriented diff.
+
+use std::fmt;
+
+use typed_arena::Arena;
+
+use crate::{
+    buffer::Buffer,
+    diff::{changes::ChangeMap, unchanged},
+    file_settings, max_num_nodes, num_nodes,
+    options::DiffOptions,
+    parse::tree_sitter_parser as tsp,
+    parse_srcs, prepare_srcs, Prepared, Strategy,
+};
+
+/// Below this proportion of the node limit, the graph search is
+/// usually too quick to notice.
+const FAST_NODE_LIMIT_DIVISOR: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prediction {
+    Identical,
+    Fast,
+    Slow,
+    /// Slow, and parts of the diff may exceed `--time-limit` and be
+    /// shown as text.
+    SlowWithTimeLimit,
+    /// We'll use a line-oriented diff, for the reason given.
+    TextFallback(&'static str),
+}
+
+impl fmt::Display for Prediction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prediction::Identical => write!(f, "identical"),
+            Prediction::Fast => write!(f, "fast"),
+            Prediction::Slow => write!(f, "slow"),
+            Prediction::SlowWithTimeLimit => write!(f, "slow (may exceed --time-limit)"),
+            Prediction::TextFallback(reason) => write!(f, "text fallback ({})", reason),
+        }
+    }
+}
+
+/// How long do we expect the graph search to take, given the size of
+/// the largest region that may have changed?
+fn predict(possibly_changed_nodes: u32, node_limit: u32, has_time_limit: bool) -> Prediction {
+    if possibly_changed_nodes == 0 {
+        Prediction::Identical
+    } else if possibly_changed_nodes > node_limit {
+        Prediction::TextFallback("exceeds node limit")
+    } else if possibly_changed_nodes <= node_limit / FAST_NODE_LIMIT_DIVISOR {
+        Prediction::Fast
+    } else if has_time_limit {
+        Prediction::SlowWithTimeLimit
+    } else {
+        Prediction::Slow
+    }
+}
+
+/// The sizes of the two syntax trees, and what we expect to happen
+/// when diffing them.
+pub struct Estimate {
+    pub language: Option<String>,
+    pub lhs_nodes: u32,
+    pub rhs_nodes: u32,
+    pub possibly_changed_nodes: u32,
+    pub prediction: Prediction,
+}
+
+impl Estimate {
+    fn fallback(language: Option<String>, reason: &'static str) -> Self {
+        Self {
+            language,
+            lhs_nodes: 0,
+            rhs_nodes: 0,
+            possibly_changed_nodes: 0,
+            prediction: Prediction::TextFallback(reason),
+        }
+    }
+}
+
+/// Parse `lhs` and `rhs`, and estimate how expensive a structural
+/// diff would be. This makes the same decisions as diffing, so the
+/// settings from `.editorconfig` and mode lines apply.
+pub fn estimate(
+    lhs: &Buffer,
+    rhs: &Buffer,
+    tab_width: usize,
+    diff_options: &DiffOptions,
+) -> Estimate {
+    let settings = file_settings(lhs, rhs, tab_width, diff_options);
+    let prepared = match prepare_srcs(lhs, rhs, &settings, diff_options) {
+        Prepared::Binary => return Estimate::fallback(None, "binary"),
+        Prepared::Identical { language_name, .. } => {
+            return Estimate {
+                language: language_name,
+                lhs_nodes: 0,
+                rhs_nodes: 0,
+                possibly_changed_nodes: 0,
+                prediction: Prediction::Identical,
+            }
+        }
+        Prepared::Text(prepared) => prepared,
+    };
+
+    let language_name = prepared
+        .language
+        .and_then(tsp::cached_from_language)
+        .map(|ts_lang| ts_lang.name.to_owned());
+    let ts_lang = match prepared.strategy {
+        Strategy::Syntax(ts_lang) => ts_lang,
+        Strategy::ByteLimit { .. } => {
+            return Estimate::fallback(language_name, "exceeds byte limit")
+        }
+        Strategy::Prose => return Estimate::fallback(None, "prose"),
+        Strategy::Truncated => return Estimate::fallback(language_name, "truncated file"),
+        Strategy::Text if prepared.api_lang_name.is_some() => {
+            return Estimate::fallback(prepared.api_lang_name, "API only")
+        }
+        Strategy::Text => return Estimate::fallback(None, "no parser"),
+    };
+
+    let arena = Arena::new();
+    let mut collapsed = vec![];
+    let parsed = parse_srcs(
+        &prepared,
+        ts_lang,
+        &arena,
+        diff_options,
+        &mut collapsed,
+        rhs.display_path,
+    );
+    let (lhs, rhs) = match parsed.roots {
+        Some(roots) => roots,
+        None => return Estimate::fallback(language_name, "exceeds parse error limit"),
+    };
+
+    // Skipping unchanged nodes is cheap, and is what decides how
+    // large the graph search is.
+    let mut change_map = ChangeMap::default();
+    let possibly_changed = unchanged::mark_unchanged(&lhs, &rhs, &mut change_map);
+    let possibly_changed_nodes = max_num_nodes(&possibly_changed);
+
+    Estimate {
+        language: language_name,
+        lhs_nodes: num_nodes(&lhs),
+        rhs_nodes: num_nodes(&rhs),
+        possibly_changed_nodes,
+        prediction: predict(
+            possibly_changed_nodes,
+            diff_options.node_limit,
+            diff_options.time_limit.is_some(),
+        ),
+    }
+}
+
+/// Describe `estimate` for `display_path` on a single line.
+pub fn describe(display_path: &str, estimate: &Estimate) -> String {
+    format!(
+        "{}: {} (language: {}, nodes: {} before, {} after, {} possibly changed)",
+        display_path,
+        estimate.prediction,
+        estimate.language.as_deref().unwrap_or("none"),
+        estimate.lhs_nodes,
+        estimate.rhs_nodes,
+        estimate.possibly_changed_nodes
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::DEFAULT_TAB_WIDTH;
+
+    #[test]
+    fn test_predict() {
+        assert_eq!(predict(0, 30_000, false), Prediction::Identical);
+        assert_eq!(predict(100, 30_000, false), Prediction::Fast);
+        assert_eq!(predict(10_000, 30_000, false), Prediction::Slow);
+        assert_eq!(predict(10_000, 30_000, true), Prediction::SlowWithTimeLimit);
+        assert_eq!(
+            predict(50_000, 30_000, false),
+            Prediction::TextFallback("exceeds node limit")
+        );
+    }
+
+    #[cfg(feature = "lang-rust")]
+    #[test]
+    fn test_estimate_uses_modeline() {
+        let buffer = |content: &'static [u8]| Buffer {
+            display_path: "build.txt",
+            path: None,
+            language_hint: None,
+            content,
+        };
+        let estimate = estimate(
+            &buffer(b"// difft: language=rust\nfn foo() {}\n"),
+            &buffer(b"// difft: language=rust\nfn bar() {}\n"),
+            DEFAULT_TAB_WIDTH,
+            &DiffOptions::default(),
+        );
+        assert_eq!(estimate.language.as_deref(), Some("Rust"));
+        assert_eq!(estimate.prediction, Prediction::Fast);
+    }
+}
@@ -1,21 +1,64 @@
 //! File reading utilities.
 
 use std::{
-    fs,
-    io::ErrorKind::*,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    io::{self, ErrorKind::*, Read},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
 };
 
-use rustc_hash::FxHashSet;
+use encoding_rs::{Encoding, UTF_8};
+#[cfg(feature = "archives")]
+use flate2::read::GzDecoder;
 use walkdir::WalkDir;
 
+use crate::{constants::Side, difftignore::DifftIgnore, profiles::Profile};
+
+/// Git passes `/dev/null` as the old file when a file is added, and
+/// as the new file when a file is deleted.
+pub fn is_dev_null(path: &Path) -> bool {
+    path == Path::new("/dev/null")
+}
+
+/// The fraction of the longer file that the shorter file must match
+/// before we consider it truncated.
+const TRUNCATION_THRESHOLD: f64 = 0.5;
+
+/// If one file is the start of the other, and stops partway through a
+/// line, it was probably truncated or corrupted. Returns the side that
+/// was truncated.
+///
+/// Files that are simply appended to aren't considered truncated, as
+/// the shorter file stops at the end of a line of the longer file,
+/// whether or not it has a trailing newline.
+pub fn truncated_side(lhs_src: &str, rhs_src: &str) -> Option<Side> {
+    let (shorter, longer, side) = if lhs_src.len() < rhs_src.len() {
+        (lhs_src, rhs_src, Side::Left)
+    } else {
+        (rhs_src, lhs_src, Side::Right)
+    };
+
+    if shorter.is_empty() || !longer.starts_with(shorter) {
+        return None;
+    }
+    let at_line_end = shorter.ends_with('\n') || longer[shorter.len()..].starts_with('\n');
+    if at_line_end {
+        return None;
+    }
+    if (shorter.len() as f64) < longer.len() as f64 * TRUNCATION_THRESHOLD {
+        return None;
+    }
+    Some(side)
+}
+
 pub fn read_files_or_die(
     lhs_path: &Path,
     rhs_path: &Path,
     missing_as_empty: bool,
 ) -> (Vec<u8>, Vec<u8>) {
-    let lhs_res = fs::read(lhs_path);
-    let rhs_res = fs::read(rhs_path);
+    let lhs_res = read(lhs_path);
+    let rhs_res = read(rhs_path);
 
     match (lhs_res, rhs_res) {
         // Both files exist, the happy case.
@@ -42,23 +85,81 @@ pub fn read_files_or_die(
 fn eprint_read_error(path: &Path, e: &std::io::Error) {
     match e.kind() {
         std::io::ErrorKind::NotFound => {
-            eprintln!("No such file: {}", path.display());
+            eprintln!("No such file: {}", display_path(path));
         }
         std::io::ErrorKind::PermissionDenied => {
-            eprintln!("Permission denied when reading file: {}", path.display());
+            eprintln!(
+                "Permission denied when reading file: {}",
+                display_path(path)
+            );
+        }
+        std::io::ErrorKind::Other => {
+            eprintln!("Could not read file: {} ({})", display_path(path), e);
         }
         _ => {
             eprintln!(
                 "Could not read file: {} (error {:?})",
-                path.display(),
+                display_path(path),
                 e.kind()
             );
         }
     };
 }
 
+/// Is `path` an http:// or https:// URL?
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn fetch_url(url: &str) -> io::Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    let mut bytes = vec![];
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_url(_url: &str) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "difftastic was built without the http feature",
+    ))
+}
+
+/// Read the file at `path`, or fetch it if `path` is a URL.
+///
+/// We always read until EOF rather than trusting the file length, as
+/// FIFOs (e.g. from process substitution with `<(git show
+/// HEAD:foo.rs)`) report a length of zero. FIFOs can only be read
+/// once, so callers must not read the same path twice.
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    match path.to_str() {
+        Some(url) if is_url(url) => fetch_url(url),
+        _ => {
+            let mut bytes = vec![];
+            fs::File::open(path)?.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// The path to use when guessing the language of `display_path`. For
+/// URLs, this ignores the query string and fragment.
+pub fn language_detection_path(display_path: &str) -> &Path {
+    if is_url(display_path) {
+        let end = display_path.find(|c| c == '?' || c == '#');
+        Path::new(&display_path[..end.unwrap_or(display_path.len())])
+    } else {
+        Path::new(display_path)
+    }
+}
+
 pub fn read_or_die(path: &Path) -> Vec<u8> {
-    match fs::read(path) {
+    match read(path) {
         Ok(src) => src,
         Err(e) => {
             eprint_read_error(path, &e);
@@ -67,8 +168,202 @@ pub fn read_or_die(path: &Path) -> Vec<u8> {
     }
 }
 
+/// Read the path list for `--files-from`, from stdin if `path` is
+/// `-`.
+pub fn read_path_list_or_die(path: &Path) -> Vec<u8> {
+    if path == Path::new("-") {
+        let mut bytes = vec![];
+        if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+            eprintln!("Could not read paths from stdin ({})", e);
+            std::process::exit(1);
+        }
+        bytes
+    } else {
+        read_or_die(path)
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Split a `--files-from` list into pairs of old and new paths.
+///
+/// Paths are separated by NUL bytes if there are any, as with `git
+/// diff -z`, and by newlines otherwise. Each pair is two consecutive
+/// paths. Returns the number of paths if it's odd.
+pub fn path_pairs(bytes: &[u8]) -> Result<Vec<(PathBuf, PathBuf)>, usize> {
+    let separator = if bytes.contains(&b'\0') { b'\0' } else { b'\n' };
+    let paths: Vec<PathBuf> = bytes
+        .split(|b| *b == separator)
+        .map(|record| {
+            if separator == b'\n' {
+                record.strip_suffix(b"\r").unwrap_or(record)
+            } else {
+                record
+            }
+        })
+        .filter(|record| !record.is_empty())
+        .map(path_from_bytes)
+        .collect();
+
+    if paths.len() % 2 != 0 {
+        return Err(paths.len());
+    }
+    Ok(paths
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+/// A pair of files to diff, from `--files-from` or `difft
+/// compare-trees`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePair {
+    pub lhs_path: PathBuf,
+    pub rhs_path: PathBuf,
+    /// The options that `.difftignore` sets for this pair.
+    pub profile: Profile,
+    /// Did we find this pair by walking two directories? The file may
+    /// then only exist on one side, so a missing file is empty.
+    pub in_dir_pair: bool,
+}
+
+/// The pair for every file in either `lhs_dir` or `rhs_dir`, with the
+/// options that their `.difftignore` sets, as when diffing two
+/// directories.
+pub fn dir_file_pairs(lhs_dir: &Path, rhs_dir: &Path) -> Vec<FilePair> {
+    let lhs_tree = FileTree::Dir(lhs_dir.to_owned());
+    let rhs_tree = FileTree::Dir(rhs_dir.to_owned());
+    let ignore = DifftIgnore::for_trees(&lhs_tree, &rhs_tree);
+    relative_paths_in_either(&lhs_tree, &rhs_tree, &ignore)
+        .into_iter()
+        .map(|rel_path| {
+            let mut profile = Profile::default();
+            ignore.apply_options(&rel_path, &mut profile);
+            FilePair {
+                lhs_path: lhs_dir.join(&rel_path),
+                rhs_path: rhs_dir.join(&rel_path),
+                profile,
+                in_dir_pair: true,
+            }
+        })
+        .collect()
+}
+
+/// Replace each pair of directories with a pair for every file in
+/// either directory, so differently-rooted trees (e.g. a vendored
+/// copy and its upstream checkout) can be compared file by file.
+///
+/// Paths excluded by `.difftignore` are dropped, and each pair has
+/// the options that `.difftignore` sets for it. Pairs of directories
+/// use their own `.difftignore`, as when diffing two directories,
+/// and pairs of files use `ignore`.
+pub fn expand_dir_pairs(pairs: Vec<(PathBuf, PathBuf)>, ignore: &DifftIgnore) -> Vec<FilePair> {
+    let mut res = vec![];
+    for (lhs_path, rhs_path) in pairs {
+        if lhs_path.is_dir() && rhs_path.is_dir() {
+            res.extend(dir_file_pairs(&lhs_path, &rhs_path));
+        } else if !ignore.is_excluded(&rhs_path) {
+            let mut profile = Profile::default();
+            ignore.apply_options(&rhs_path, &mut profile);
+            res.push(FilePair {
+                lhs_path,
+                rhs_path,
+                profile,
+                in_dir_pair: false,
+            });
+        }
+    }
+    res
+}
+
+/// Format `path` for display. Bytes that aren't valid UTF-8 are shown
+/// as octal escapes, like git does, rather than being replaced.
+#[cfg(unix)]
+pub fn display_path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut bytes = path.as_os_str().as_bytes();
+    let mut res = String::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                res.push_str(s);
+                break;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                res.push_str(std::str::from_utf8(valid).unwrap());
+
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                for byte in &rest[..invalid_len] {
+                    res.push_str(&format!("\\{:03o}", byte));
+                }
+                bytes = &rest[invalid_len..];
+            }
+        }
+    }
+
+    res
+}
+
+/// Format `path` for display.
+#[cfg(not(unix))]
+pub fn display_path(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// `rel_path` with `/` between its components, for matching against
+/// `.gitignore`-style globs. Bytes that aren't valid UTF-8 are escaped
+/// as in `display_path`, rather than being replaced with U+FFFD.
+pub fn slash_path(rel_path: &Path) -> String {
+    rel_path
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| display_path(Path::new(component.as_os_str())))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decode `bytes` as text. A byte order mark takes precedence over
+/// `encoding_override`, and we assume UTF-8 if neither is
+/// present. Invalid sequences are replaced with U+FFFD.
+///
+/// Returns the text and the encoding that we used.
+pub fn decode(
+    bytes: &[u8],
+    encoding_override: Option<&'static Encoding>,
+) -> (String, &'static Encoding) {
+    let (encoding, bytes) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, &bytes[bom_len..]),
+        None => (encoding_override.unwrap_or(UTF_8), bytes),
+    };
+
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), encoding)
+}
+
 /// Do these bytes look like a binary (non-textual) format?
-pub fn is_probably_binary(bytes: &[u8]) -> bool {
+///
+/// `encoding_override` is the encoding the user asked for, if any. We
+/// decode with it rather than assuming UTF-8, so text in that
+/// encoding isn't mistaken for binary, but binary files still are.
+pub fn is_probably_binary(bytes: &[u8], encoding_override: Option<&'static Encoding>) -> bool {
+    // Text with a byte order mark may still contain many null bytes,
+    // e.g. UTF-16.
+    if Encoding::for_bom(bytes).is_some() {
+        return false;
+    }
+
     let mime = tree_magic_mini::from_u8(bytes);
     match mime {
         // Treat pdf as binary.
@@ -85,9 +380,9 @@ pub fn is_probably_binary(bytes: &[u8]) -> bool {
     }
 
     // If more than 20 of the first 1,000 characters are null bytes or
-    // invalid UTF-8, we assume it's binary.
-    let num_replaced = String::from_utf8_lossy(bytes)
-        .to_string()
+    // invalid in the encoding, we assume it's binary.
+    let (text, _) = decode(bytes, encoding_override);
+    let num_replaced = text
         .chars()
         .take(1000)
         .filter(|c| *c == std::char::REPLACEMENT_CHARACTER || *c == '\0')
@@ -96,82 +391,626 @@ pub fn is_probably_binary(bytes: &[u8]) -> bool {
     num_replaced > 20
 }
 
-/// All the files in `dir`, including subdirectories.
-fn relative_file_paths_in_dir(dir: &Path) -> Vec<PathBuf> {
-    WalkDir::new(dir)
-        .into_iter()
-        .filter_map(Result::ok)
-        .map(|entry| entry.into_path())
-        .filter(|path| !path.is_dir())
-        .map(|path| path.strip_prefix(dir).unwrap().to_path_buf())
-        .collect()
+/// Something we can diff file by file, like a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeKind {
+    Dir,
+    Tar,
+    /// A gzip-compressed tarball.
+    TarGz,
+    Zip,
+    /// A git tree-ish, such as `HEAD~2` or `v1.0:src`.
+    GitTree,
+}
+
+/// Guess whether `name` is an archive from its extension.
+fn archive_kind(name: &str) -> Option<TreeKind> {
+    let name = name.to_lowercase();
+    if name.ends_with(".tar") {
+        Some(TreeKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(TreeKind::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(TreeKind::Zip)
+    } else {
+        None
+    }
+}
+
+fn is_git_tree(treeish: &Path) -> bool {
+    let treeish = match treeish.to_str() {
+        Some(treeish) => treeish,
+        None => return false,
+    };
+    Command::new("git")
+        .args(&["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{}^{{tree}}", treeish))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
 }
 
-/// Walk `lhs_dir` and `rhs_dir`, and return relative paths of files
-/// that occur in at least one directory.
+/// What kind of tree `path` is, if it isn't a single file.
 ///
-/// Attempts to preserve the ordering of files in both directories.
-pub fn relative_paths_in_either(lhs_dir: &Path, rhs_dir: &Path) -> Vec<PathBuf> {
-    let lhs_paths = relative_file_paths_in_dir(lhs_dir);
-    let rhs_paths = relative_file_paths_in_dir(rhs_dir);
+/// Archives and git trees are only trees with `--snapshots`, so we
+/// don't look inside a changed zip file or ask git about every path
+/// unless the user asked us to.
+pub fn tree_kind(path: &Path, snapshots: bool) -> Option<TreeKind> {
+    if path.is_dir() {
+        Some(TreeKind::Dir)
+    } else if !snapshots {
+        None
+    } else if path.is_file() {
+        archive_kind(&path.file_name()?.to_string_lossy())
+    } else if is_git_tree(path) {
+        Some(TreeKind::GitTree)
+    } else {
+        None
+    }
+}
 
-    let mut seen = FxHashSet::default();
-    let mut res: Vec<PathBuf> = vec![];
+#[cfg(feature = "archives")]
+fn check_status(program: &str, status: std::process::ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            Other,
+            format!("{} failed with {}", program, status),
+        ))
+    }
+}
 
-    let mut i = 0;
-    let mut j = 0;
+/// Read every regular file in a tarball. As in `read_zip`, entries
+/// with absolute paths or `..` are skipped, because we join relative
+/// paths to the directory on the other side.
+#[cfg(feature = "archives")]
+fn read_tar(reader: impl Read) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let components: Vec<Component> = entry
+            .path()?
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        if components.is_empty()
+            || !components
+                .iter()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            continue;
+        }
+        let rel_path: PathBuf = components.into_iter().collect();
 
-    loop {
-        match (lhs_paths.get(i), rhs_paths.get(j)) {
-            (Some(lhs_path), Some(rhs_path)) if lhs_path == rhs_path => {
-                if !seen.contains(lhs_path) {
-                    // It should be impossible to get duplicates, but
-                    // be defensive.
-                    res.push(lhs_path.clone());
-                    seen.insert(lhs_path);
-                }
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+        files.insert(rel_path, bytes);
+    }
+    Ok(files)
+}
+
+/// Read every file in a zip file. Entries with absolute paths or `..`
+/// are skipped.
+#[cfg(feature = "archives")]
+fn read_zip(file: fs::File) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut files = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let rel_path = match entry.enclosed_name() {
+            Some(rel_path) => rel_path.to_owned(),
+            None => continue,
+        };
 
-                i += 1;
-                j += 1;
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+        files.insert(rel_path, bytes);
+    }
+    Ok(files)
+}
+
+/// Read the files in the archive or git tree at `path`.
+#[cfg(feature = "archives")]
+fn read_snapshot(path: &Path, kind: TreeKind) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    match kind {
+        TreeKind::Dir => unreachable!("Directories aren't snapshots"),
+        TreeKind::Tar => read_tar(fs::File::open(path)?),
+        TreeKind::TarGz => read_tar(GzDecoder::new(fs::File::open(path)?)),
+        TreeKind::Zip => read_zip(fs::File::open(path)?),
+        TreeKind::GitTree => {
+            let output = Command::new("git")
+                .args(&["archive", "--format=tar"])
+                .arg(path)
+                .stderr(Stdio::inherit())
+                .output()?;
+            check_status("git archive", output.status)?;
+            read_tar(&output.stdout[..])
+        }
+    }
+}
+
+#[cfg(not(feature = "archives"))]
+fn read_snapshot(_path: &Path, _kind: TreeKind) -> io::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    Err(io::Error::new(
+        Other,
+        "difftastic was built without the archives feature, so it can't read archives or git trees",
+    ))
+}
+
+/// A tree of files that we diff file by file.
+///
+/// Directories are read from disk as we diff them. Archives and git
+/// trees are read into memory when they're opened, so nothing is
+/// extracted to disk and there's nothing to clean up, however we
+/// exit.
+pub enum FileTree {
+    Dir(PathBuf),
+    /// The files in an archive or git tree, by relative path.
+    Snapshot(BTreeMap<PathBuf, Vec<u8>>),
+}
+
+impl FileTree {
+    pub fn open(path: &Path, kind: TreeKind) -> io::Result<Self> {
+        match kind {
+            TreeKind::Dir => Ok(FileTree::Dir(path.to_owned())),
+            _ => Ok(FileTree::Snapshot(read_snapshot(path, kind)?)),
+        }
+    }
+
+    pub fn open_or_die(path: &Path, kind: TreeKind) -> Self {
+        match Self::open(path, kind) {
+            Ok(tree) => tree,
+            Err(e) => {
+                eprintln!("error: Could not read {}: {}", display_path(path), e);
+                std::process::exit(1);
             }
-            (Some(lhs_path), Some(rhs_path)) => {
-                if seen.contains(lhs_path) {
-                    i += 1;
-                } else if seen.contains(rhs_path) {
-                    j += 1;
-                } else {
-                    res.push(lhs_path.clone());
-                    res.push(rhs_path.clone());
-
-                    seen.insert(lhs_path);
-                    seen.insert(rhs_path);
-
-                    i += 1;
-                    j += 1;
-                }
+        }
+    }
+
+    /// The path of `rel_path` for language detection and error
+    /// messages. Files in snapshots only have their relative path.
+    pub fn path(&self, rel_path: &Path) -> PathBuf {
+        match self {
+            FileTree::Dir(dir) => dir.join(rel_path),
+            FileTree::Snapshot(_) => rel_path.to_owned(),
+        }
+    }
+
+    pub fn contains(&self, rel_path: &Path) -> bool {
+        match self {
+            FileTree::Dir(dir) => dir.join(rel_path).is_file(),
+            FileTree::Snapshot(files) => files.contains_key(rel_path),
+        }
+    }
+
+    pub fn read(&self, rel_path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            FileTree::Dir(dir) => fs::read(dir.join(rel_path)),
+            FileTree::Snapshot(files) => files
+                .get(rel_path)
+                .cloned()
+                .ok_or_else(|| io::Error::from(NotFound)),
+        }
+    }
+
+    /// Read `rel_path`, treating a file that isn't in the tree as
+    /// empty, so it's shown as added or removed. Exits on other
+    /// errors.
+    pub fn read_or_die(&self, rel_path: &Path) -> Vec<u8> {
+        match self.read(rel_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == NotFound => vec![],
+            Err(e) => {
+                eprint_read_error(&self.path(rel_path), &e);
+                std::process::exit(1);
             }
-            _ => break,
         }
     }
 
-    res.extend(lhs_paths.into_iter().skip(i));
-    res.extend(rhs_paths.into_iter().skip(j));
+    /// The relative paths of the files in this tree that `ignore`
+    /// doesn't exclude, sorted by path.
+    fn relative_file_paths(&self, ignore: &DifftIgnore) -> Vec<PathBuf> {
+        match self {
+            FileTree::Dir(dir) => relative_file_paths_in_dir(dir, ignore),
+            FileTree::Snapshot(files) => files
+                .keys()
+                .filter(|rel_path| !ignore.is_excluded(rel_path))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The directory for difftastic's cache files, following the
+/// conventions of the current platform.
+pub fn cache_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    let base = if cfg!(windows) {
+        non_empty("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        non_empty("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        non_empty("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|base| base.join("difftastic"))
+}
 
-    res
+/// All the files in `dir`, including subdirectories, that `ignore`
+/// doesn't exclude. Paths are sorted by name within each directory,
+/// so the order doesn't depend on the filesystem.
+///
+/// Excluded directories (e.g. `node_modules`) aren't walked at all.
+fn relative_file_paths_in_dir(dir: &Path, ignore: &DifftIgnore) -> Vec<PathBuf> {
+    let rel_path = |path: &Path| path.strip_prefix(dir).unwrap().to_path_buf();
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !entry.file_type().is_dir()
+                || !ignore.is_dir_excluded(&rel_path(entry.path()))
+        })
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| !path.is_dir())
+        .map(|path| rel_path(&path))
+        .filter(|rel_path| !ignore.is_excluded(rel_path))
+        .collect()
+}
+
+/// List `lhs_tree` and `rhs_tree`, and return relative paths of files
+/// that occur in at least one tree and that `ignore` doesn't exclude.
+///
+/// Paths are sorted, so the order doesn't depend on which tree a
+/// file is in.
+pub fn relative_paths_in_either(
+    lhs_tree: &FileTree,
+    rhs_tree: &FileTree,
+    ignore: &DifftIgnore,
+) -> Vec<PathBuf> {
+    let mut paths: BTreeSet<PathBuf> = lhs_tree.relative_file_paths(ignore).into_iter().collect();
+    paths.extend(rhs_tree.relative_file_paths(ignore));
+    paths.into_iter().collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(archive_kind("src.tar"), Some(TreeKind::Tar));
+        assert_eq!(archive_kind("src.tar.gz"), Some(TreeKind::TarGz));
+        assert_eq!(archive_kind("SRC.ZIP"), Some(TreeKind::Zip));
+        assert_eq!(archive_kind("main.rs"), None);
+    }
+
+    #[test]
+    fn test_tree_kind_needs_snapshots() {
+        let path = std::env::temp_dir().join(format!("difft-test-kind-{}.zip", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let without = tree_kind(&path, false);
+        let with = tree_kind(&path, true);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(without, None);
+        assert_eq!(with, Some(TreeKind::Zip));
+    }
+
+    #[test]
+    #[cfg(feature = "archives")]
+    fn test_snapshot_tar() {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, src) in [("./src/b.rs", "fn b() {}\n"), ("a.rs", "fn a() {}\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(src.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, src.as_bytes())
+                .unwrap();
+        }
+        let bytes = builder.into_inner().unwrap();
+
+        let tree = FileTree::Snapshot(read_tar(&bytes[..]).unwrap());
+        assert_eq!(
+            tree.relative_file_paths(&DifftIgnore::default()),
+            vec![PathBuf::from("a.rs"), PathBuf::from("src/b.rs")]
+        );
+        assert_eq!(tree.read(Path::new("src/b.rs")).unwrap(), b"fn b() {}\n");
+        assert!(!tree.contains(Path::new("c.rs")));
+        assert_eq!(tree.read_or_die(Path::new("c.rs")), Vec::<u8>::new());
+    }
+
+    #[test]
+    #[cfg(feature = "archives")]
+    fn test_snapshot_tar_outside_tree() {
+        let mut builder = tar::Builder::new(vec![]);
+        for name in ["../../etc/passwd", "/etc/shadow", "src/../../x.rs", "ok.rs"] {
+            // Builder::append_data refuses these paths, so set the
+            // name directly.
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &[][..]).unwrap();
+        }
+        let bytes = builder.into_inner().unwrap();
+
+        let files = read_tar(&bytes[..]).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec![&PathBuf::from("ok.rs")]
+        );
+    }
+
+    #[test]
+    fn test_relative_paths_in_dir_and_snapshot() {
+        let dir = std::env::temp_dir().join(format!("difft-test-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a.rs", "c.rs"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let lhs_tree = FileTree::Dir(dir.clone());
+
+        let mut files = BTreeMap::new();
+        for name in ["a.rs", "b.rs", "target/out.rs"] {
+            files.insert(PathBuf::from(name), vec![]);
+        }
+        files.insert(PathBuf::from(".difftignore"), b"target/\n".to_vec());
+        let rhs_tree = FileTree::Snapshot(files);
+
+        // The snapshot's .difftignore applies to both trees.
+        let ignore = DifftIgnore::for_trees(&lhs_tree, &rhs_tree);
+        let paths = relative_paths_in_either(&lhs_tree, &rhs_tree, &ignore);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from(".difftignore"),
+                PathBuf::from("a.rs"),
+                PathBuf::from("b.rs"),
+                PathBuf::from("c.rs"),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_fifo() {
+        let path = std::env::temp_dir().join(format!("difft-test-fifo-{}", std::process::id()));
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || fs::write(writer_path, "hello fifo").unwrap());
+
+        let bytes = read_or_die(&path);
+        writer.join().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes, b"hello fifo");
+    }
+
+    #[test]
+    fn test_relative_file_paths_in_dir_sorted() {
+        let dir = std::env::temp_dir().join(format!("difft-test-walk-{}", std::process::id()));
+        fs::create_dir_all(dir.join("c")).unwrap();
+        for name in ["b.rs", "c/d.rs", "a.rs"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let paths = relative_file_paths_in_dir(&dir, &DifftIgnore::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("a.rs"),
+                PathBuf::from("b.rs"),
+                PathBuf::from("c/d.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_dir_pairs_difftignore() {
+        let dir = std::env::temp_dir().join(format!("difft-test-prune-{}", std::process::id()));
+        fs::create_dir_all(dir.join("node_modules/pkg")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join(".difftignore"),
+            "node_modules/\n*.lock summary-only\n",
+        )
+        .unwrap();
+        for name in ["node_modules/pkg/index.js", "src/a.rs", "Cargo.lock"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let pairs = expand_dir_pairs(vec![(dir.clone(), dir.clone())], &DifftIgnore::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let summary_only = Profile {
+            summary_only: true,
+            ..Profile::default()
+        };
+        let pair = |name: &str, profile: Profile| FilePair {
+            lhs_path: dir.join(name),
+            rhs_path: dir.join(name),
+            profile,
+            in_dir_pair: true,
+        };
+        assert_eq!(
+            pairs,
+            vec![
+                pair(".difftignore", Profile::default()),
+                pair("Cargo.lock", summary_only),
+                pair("src/a.rs", Profile::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slash_path() {
+        assert_eq!(slash_path(&Path::new("src").join("main.rs")), "src/main.rs");
+        assert_eq!(slash_path(&Path::new(".").join("main.rs")), "main.rs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_slash_path_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.rs");
+        assert_eq!(slash_path(&Path::new("src").join(name)), "src/caf\\351.rs");
+    }
+
+    #[test]
+    fn test_path_pairs() {
+        let expected = vec![
+            (PathBuf::from("a.rs"), PathBuf::from("b.rs")),
+            (PathBuf::from("old dir/c.rs"), PathBuf::from("/dev/null")),
+        ];
+        assert_eq!(
+            path_pairs(b"a.rs\nb.rs\r\nold dir/c.rs\n/dev/null\n"),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            path_pairs(b"a.rs\0b.rs\0old dir/c.rs\0/dev/null\0"),
+            Ok(expected)
+        );
+        assert_eq!(path_pairs(b"a.rs\nb.rs\nc.rs\n"), Err(3));
+    }
+
+    #[test]
+    fn test_language_detection_path_url() {
+        assert_eq!(
+            language_detection_path("https://example.com/src/main.rs?raw=true"),
+            Path::new("https://example.com/src/main.rs")
+        );
+    }
+
+    #[test]
+    fn test_language_detection_path_local() {
+        assert_eq!(
+            language_detection_path("src/main.rs?"),
+            Path::new("src/main.rs?")
+        );
+    }
+
+    #[test]
+    fn test_truncated_side() {
+        assert_eq!(
+            truncated_side("fn foo() {\n    bar();\n}\n", "fn foo() {\n    ba"),
+            Some(Side::Right)
+        );
+    }
+
+    #[test]
+    fn test_appended_is_not_truncated() {
+        assert_eq!(truncated_side("foo\n", "foo\nbar\n"), None);
+    }
+
+    #[test]
+    fn test_missing_trailing_newline_is_not_truncated() {
+        assert_eq!(truncated_side("foo\nbar", "foo\nbar\n"), None);
+        assert_eq!(truncated_side("foo\nbar", "foo\nbar\nbaz"), None);
+    }
+
+    #[test]
+    fn test_short_prefix_is_not_truncated() {
+        assert_eq!(truncated_side("f", "foo\nbar\nbaz\n"), None);
+    }
+
     #[test]
     fn test_text_is_not_binary() {
         let s = "hello world";
-        assert!(!is_probably_binary(s.as_bytes()));
+        assert!(!is_probably_binary(s.as_bytes(), None));
     }
     #[test]
     fn test_null_bytes_are_binary() {
         let s = "\0".repeat(1000);
-        assert!(is_probably_binary(s.as_bytes()));
+        assert!(is_probably_binary(s.as_bytes(), None));
+    }
+
+    #[test]
+    fn test_null_bytes_are_binary_with_encoding() {
+        let s = "\0".repeat(1000);
+        assert!(is_probably_binary(
+            s.as_bytes(),
+            Some(encoding_rs::WINDOWS_1252)
+        ));
+    }
+
+    #[test]
+    fn test_utf16_is_not_binary() {
+        let bytes = b"\xff\xfeh\0e\0l\0l\0o\0";
+        assert!(!is_probably_binary(bytes, None));
+    }
+
+    #[test]
+    fn test_utf16_without_bom_is_not_binary_with_encoding() {
+        let bytes: Vec<u8> = "hello world\n"
+            .repeat(10)
+            .encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        assert!(is_probably_binary(&bytes, None));
+        assert!(!is_probably_binary(&bytes, Some(encoding_rs::UTF_16LE)));
+    }
+
+    #[test]
+    fn test_latin1_is_not_binary_with_encoding() {
+        let bytes = b"caf\xe9 ".repeat(50);
+        assert!(is_probably_binary(&bytes, None));
+        assert!(!is_probably_binary(&bytes, Some(encoding_rs::WINDOWS_1252)));
+    }
+
+    #[test]
+    fn test_decode_utf8() {
+        let (text, encoding) = decode("héllo".as_bytes(), None);
+        assert_eq!(text, "héllo");
+        assert_eq!(encoding, UTF_8);
+    }
+
+    #[test]
+    fn test_decode_bom() {
+        let (text, encoding) = decode(b"\xff\xfeh\0i\0", None);
+        assert_eq!(text, "hi");
+        assert_eq!(encoding.name(), "UTF-16LE");
+    }
+
+    #[test]
+    fn test_decode_override() {
+        let latin1 = Encoding::for_label(b"latin1");
+        let (text, _) = decode(b"caf\xe9", latin1);
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn test_display_path_utf8() {
+        assert_eq!(display_path(Path::new("foo/bär.rs")), "foo/bär.rs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_display_path_invalid_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new(OsStr::from_bytes(b"foo\xffbar.rs"));
+        assert_eq!(display_path(path), "foo\\377bar.rs");
     }
 }
@@ -0,0 +1,279 @@
+//! Translations of user-facing messages, such as "File added".
+//!
+//! The locale comes from `--lang-ui` or `DFT_LANG_UI`, or otherwise
+//! from the user's `LC_ALL`, `LC_MESSAGES` or `LANG`. We only
+//! translate complete messages. Everything else, such as warnings and
+//! hunk headers, is always in English, and so is output meant for
+//! other programs, such as JSON.
+
+use std::{
+    env,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::display::style::expand_placeholders;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    English,
+    German,
+    Spanish,
+    French,
+}
+
+/// The language codes accepted by `--lang-ui`.
+pub const LOCALE_NAMES: &[&str] = &["en", "de", "es", "fr"];
+
+impl Locale {
+    /// Parse one of `LOCALE_NAMES`. Anything else is English.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "de" => Locale::German,
+            "es" => Locale::Spanish,
+            "fr" => Locale::French,
+            _ => Locale::English,
+        }
+    }
+
+    /// Parse a POSIX locale such as `de_DE.UTF-8`. Languages we
+    /// don't have translations for, and `C`, are English.
+    fn from_posix(value: &str) -> Self {
+        let language = value
+            .split(|c| c == '_' || c == '.' || c == '@')
+            .next()
+            .unwrap_or_default();
+        Self::from_name(language)
+    }
+
+    /// The locale for messages from the environment. As with other
+    /// programs, `LC_ALL` takes precedence over `LC_MESSAGES`, which
+    /// takes precedence over `LANG`.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Locale::English, |value| Self::from_posix(&value))
+    }
+}
+
+static LOCALE: AtomicU8 = AtomicU8::new(Locale::English as u8);
+
+/// Set the locale used by `tr`. This is `--lang-ui`.
+pub fn set_locale(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        x if x == Locale::German as u8 => Locale::German,
+        x if x == Locale::Spanish as u8 => Locale::Spanish,
+        x if x == Locale::French as u8 => Locale::French,
+        _ => Locale::English,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    FileAdded,
+    FileDeleted,
+    /// The old file stops partway through the new file.
+    OldFileTruncated,
+    /// The new file stops partway through the old file.
+    NewFileTruncated,
+    GeneratedFileChanged,
+    Copied,
+    Renamed,
+    NoChanges,
+    NoSyntacticChanges,
+    BinaryContentsChanged,
+    SignatureOnly,
+    BodyOnly,
+    /// The heading of the `--legend` line.
+    Legend,
+    LegendRemoved,
+    LegendAdded,
+    LegendKeyword,
+    LegendString,
+    LegendComment,
+    LegendMoved,
+}
+
+/// The template for `message`. Templates may use the placeholders
+/// `{path}` and `{old_path}`.
+fn template(locale: Locale, message: Message) -> &'static str {
+    use Locale::*;
+    use Message::*;
+
+    match (message, locale) {
+        (FileAdded, English) => "File added: {path}",
+        (FileAdded, German) => "Datei hinzugefügt: {path}",
+        (FileAdded, Spanish) => "Archivo añadido: {path}",
+        (FileAdded, French) => "Fichier ajouté : {path}",
+
+        (FileDeleted, English) => "File deleted: {path}",
+        (FileDeleted, German) => "Datei gelöscht: {path}",
+        (FileDeleted, Spanish) => "Archivo eliminado: {path}",
+        (FileDeleted, French) => "Fichier supprimé : {path}",
+
+        (OldFileTruncated, English) => {
+            "File truncated: {path} (the old file stops partway through the new file)"
+        }
+        (OldFileTruncated, German) => {
+            "Datei abgeschnitten: {path} (die alte Datei endet mitten in der neuen Datei)"
+        }
+        (OldFileTruncated, Spanish) => {
+            "Archivo truncado: {path} (el archivo antiguo termina a mitad del archivo nuevo)"
+        }
+        (OldFileTruncated, French) => {
+            "Fichier tronqué : {path} (l'ancien fichier s'arrête au milieu du nouveau fichier)"
+        }
+
+        (NewFileTruncated, English) => {
+            "File truncated: {path} (the new file stops partway through the old file)"
+        }
+        (NewFileTruncated, German) => {
+            "Datei abgeschnitten: {path} (die neue Datei endet mitten in der alten Datei)"
+        }
+        (NewFileTruncated, Spanish) => {
+            "Archivo truncado: {path} (el archivo nuevo termina a mitad del archivo antiguo)"
+        }
+        (NewFileTruncated, French) => {
+            "Fichier tronqué : {path} (le nouveau fichier s'arrête au milieu de l'ancien fichier)"
+        }
+
+        (GeneratedFileChanged, English) => {
+            "Generated file changed: {path} (use --show-generated to see the changes)"
+        }
+        (GeneratedFileChanged, German) => {
+            "Generierte Datei geändert: {path} (--show-generated zeigt die Änderungen)"
+        }
+        (GeneratedFileChanged, Spanish) => {
+            "Archivo generado modificado: {path} (use --show-generated para ver los cambios)"
+        }
+        (GeneratedFileChanged, French) => {
+            "Fichier généré modifié : {path} (utilisez --show-generated pour voir les modifications)"
+        }
+
+        (Copied, English) => "Copied {old_path} to {path}",
+        (Copied, German) => "{old_path} nach {path} kopiert",
+        (Copied, Spanish) => "{old_path} copiado a {path}",
+        (Copied, French) => "{old_path} copié vers {path}",
+
+        (Renamed, English) => "Renamed {old_path} to {path}",
+        (Renamed, German) => "{old_path} in {path} umbenannt",
+        (Renamed, Spanish) => "{old_path} renombrado a {path}",
+        (Renamed, French) => "{old_path} renommé en {path}",
+
+        (NoChanges, English) => "No changes.",
+        (NoChanges, German) => "Keine Änderungen.",
+        (NoChanges, Spanish) => "Sin cambios.",
+        (NoChanges, French) => "Aucune modification.",
+
+        (NoSyntacticChanges, English) => "No syntactic changes.",
+        (NoSyntacticChanges, German) => "Keine syntaktischen Änderungen.",
+        (NoSyntacticChanges, Spanish) => "Sin cambios sintácticos.",
+        (NoSyntacticChanges, French) => "Aucune modification syntaxique.",
+
+        (BinaryContentsChanged, English) => "Binary contents changed.",
+        (BinaryContentsChanged, German) => "Binärinhalt geändert.",
+        (BinaryContentsChanged, Spanish) => "Contenido binario modificado.",
+        (BinaryContentsChanged, French) => "Contenu binaire modifié.",
+
+        (SignatureOnly, English) => "Signature changed, body unchanged",
+        (SignatureOnly, German) => "Signatur geändert, Rumpf unverändert",
+        (SignatureOnly, Spanish) => "Firma modificada, cuerpo sin cambios",
+        (SignatureOnly, French) => "Signature modifiée, corps inchangé",
+
+        (BodyOnly, English) => "Body changed, signature unchanged",
+        (BodyOnly, German) => "Rumpf geändert, Signatur unverändert",
+        (BodyOnly, Spanish) => "Cuerpo modificado, firma sin cambios",
+        (BodyOnly, French) => "Corps modifié, signature inchangée",
+
+        (Legend, English) => "Colors:",
+        (Legend, German) => "Farben:",
+        (Legend, Spanish) => "Colores:",
+        (Legend, French) => "Couleurs :",
+
+        (LegendRemoved, English) => "removed",
+        (LegendRemoved, German) => "entfernt",
+        (LegendRemoved, Spanish) => "eliminado",
+        (LegendRemoved, French) => "supprimé",
+
+        (LegendAdded, English) => "added",
+        (LegendAdded, German) => "hinzugefügt",
+        (LegendAdded, Spanish) => "añadido",
+        (LegendAdded, French) => "ajouté",
+
+        (LegendKeyword, English) => "keyword",
+        (LegendKeyword, German) => "Schlüsselwort",
+        (LegendKeyword, Spanish) => "palabra clave",
+        (LegendKeyword, French) => "mot-clé",
+
+        (LegendString, English) => "string",
+        (LegendString, German) => "Zeichenkette",
+        (LegendString, Spanish) => "cadena",
+        (LegendString, French) => "chaîne",
+
+        (LegendComment, English) => "comment",
+        (LegendComment, German) => "Kommentar",
+        (LegendComment, Spanish) => "comentario",
+        (LegendComment, French) => "commentaire",
+
+        (LegendMoved, English) => "(moved code is counted in hunk headers)",
+        (LegendMoved, German) => "(verschobener Code wird in den Abschnittsköpfen gezählt)",
+        (LegendMoved, Spanish) => "(el código movido se cuenta en las cabeceras de los bloques)",
+        (LegendMoved, French) => "(le code déplacé est compté dans les en-têtes des blocs)",
+    }
+}
+
+/// `message` in the current locale.
+pub fn tr(message: Message) -> &'static str {
+    template(locale(), message)
+}
+
+/// `message` in the current locale, with `{path}` and `{old_path}`
+/// replaced.
+pub fn tr_path(message: Message, path: &str, old_path: &str) -> String {
+    expand_placeholders(tr(message), |name| match name {
+        "path" => Some(path.to_owned()),
+        "old_path" => Some(old_path.to_owned()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Locale::from_name("de"), Locale::German);
+        assert_eq!(Locale::from_name("fr"), Locale::French);
+        assert_eq!(Locale::from_name("en"), Locale::English);
+    }
+
+    #[test]
+    fn test_from_posix() {
+        assert_eq!(Locale::from_posix("de_DE.UTF-8"), Locale::German);
+        assert_eq!(Locale::from_posix("es_ES@euro"), Locale::Spanish);
+        assert_eq!(Locale::from_posix("fr"), Locale::French);
+        assert_eq!(Locale::from_posix("pt_BR.UTF-8"), Locale::English);
+        assert_eq!(Locale::from_posix("C"), Locale::English);
+    }
+
+    #[test]
+    fn test_locale_names() {
+        for name in LOCALE_NAMES {
+            assert!(*name == "en" || Locale::from_name(name) != Locale::English);
+        }
+    }
+
+    #[test]
+    fn test_template_placeholders() {
+        assert_eq!(
+            template(Locale::German, Message::Copied),
+            "{old_path} nach {path} kopiert"
+        );
+    }
+}
@@ -0,0 +1,2310 @@
+//! Difftastic is a syntactic diff tool.
+//!
+//! For usage instructions and advice on contributing, see [the
+//! manual](http://difftastic.wilfred.me.uk/).
+//!
+//! The `difft` binary is a thin wrapper around [`main`]. The library
+//! target also builds the WebAssembly module, see `src/wasm.rs`.
+//!
+//! To diff content that isn't on disk, such as an editor buffer, pass
+//! a [`buffer::Buffer`] for each side to [`diff_file_content`]. No
+//! APIs are considered stable for external usage.
+//!
+
+// This tends to trigger on larger tuples of simple types, and naming
+// them would probably be worse for readability.
+#![allow(clippy::type_complexity)]
+// == "" is often clearer when dealing with strings.
+#![allow(clippy::comparison_to_empty)]
+// It's common to have pairs foo_lhs and foo_rhs, leading to double
+// the number of arguments and triggering this lint.
+#![allow(clippy::too_many_arguments)]
+
+mod api;
+mod assert_equal;
+mod bench;
+pub mod buffer;
+mod compare_diffs;
+mod compare_trees;
+mod completions;
+mod constants;
+mod copies;
+#[cfg(unix)]
+mod daemon;
+mod diff;
+mod difftignore;
+mod display;
+mod editorconfig;
+mod estimate;
+mod files;
+mod i18n;
+mod line_parser;
+mod lines;
+mod list_languages;
+mod modeline;
+mod offsets;
+mod options;
+#[cfg(unix)]
+mod pager;
+mod parse;
+mod positions;
+mod profiles;
+mod prose_parser;
+mod review;
+mod self_test;
+mod session;
+mod stage;
+mod summary;
+mod suppress;
+mod verify;
+mod warnings;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+#[macro_use]
+extern crate log;
+
+pub use options::{DiffOptions, DEFAULT_TAB_WIDTH};
+pub use summary::DiffResult;
+
+use crate::buffer::{file_buffer, Buffer};
+use crate::diff::{dijkstra, unchanged};
+use crate::display::hunks::{file_hunks, group_by_definition, sort_hunks, HunkNumbering};
+use crate::display::indentation::{
+    find_indentation_changes, mark_indentation_changes, IndentationChanges,
+};
+use crate::display::legend;
+use crate::parse::syntax::{self, MatchedPos};
+use diff::changes::ChangeMap;
+use difftignore::DifftIgnore;
+use encoding_rs::{Encoding, UTF_8};
+use files::{
+    decode, display_path, expand_dir_pairs, is_dev_null, is_probably_binary,
+    language_detection_path, path_pairs, read_files_or_die, read_or_die, read_path_list_or_die,
+    relative_paths_in_either, tree_kind, truncated_side, FilePair, FileTree,
+};
+use i18n::{tr, Message};
+use log::info;
+use offsets::OffsetMap;
+use parse::guess_language::{guess, Language};
+use parse::preprocessor::mask_inactive_branches;
+
+/// The global allocator used by difftastic.
+///
+/// Diffing allocates a large amount of memory, and `MiMalloc` performs
+/// better. MiMalloc doesn't support WebAssembly, so we use the default
+/// allocator there.
+#[cfg(not(target_arch = "wasm32"))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+use diff::sliders::fix_all_sliders;
+use options::{DiffMode, DisplayMode, DisplayOptions, Mode, PairSource};
+use profiles::Profile;
+use rayon::prelude::*;
+use review::Review;
+use std::{
+    borrow::Cow,
+    env,
+    ffi::OsString,
+    mem, panic,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Instant,
+};
+use summary::{DiffResult, FileContent, FileStatus, TimedOutRegion};
+use syntax::init_next_prev;
+use typed_arena::Arena;
+
+use crate::{
+    constants::Side,
+    diff::changes::insert_deep_novel,
+    dijkstra::mark_syntax,
+    lines::LineNumber,
+    parse::syntax::{init_all_info, init_all_info_with_case, Syntax},
+    parse::tree_sitter_parser as tsp,
+};
+
+extern crate pretty_env_logger;
+
+/// Terminate the process if we get SIGPIPE.
+#[cfg(unix)]
+fn reset_sigpipe() {
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+fn reset_sigpipe() {
+    // Do nothing.
+}
+
+/// Send our output through a pager, if appropriate.
+#[cfg(unix)]
+fn start_pager(use_pager: bool, in_vcs: bool) {
+    pager::start(use_pager, in_vcs);
+}
+
+#[cfg(not(unix))]
+fn start_pager(_use_pager: bool, _in_vcs: bool) {
+    // Do nothing.
+}
+
+/// Log to stderr, filtered by `DFT_LOG` (or `RUST_LOG`), e.g.
+/// `DFT_LOG=debug` to see phase timings.
+fn init_logging() {
+    let mut builder = pretty_env_logger::formatted_timed_builder();
+    if let Ok(filters) = env::var("DFT_LOG").or_else(|_| env::var("RUST_LOG")) {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+/// Start timing a phase, if debug logging is enabled.
+///
+/// `Instant::now` isn't available on wasm32, where we never log, so
+/// this only reads the clock when the timing would be logged.
+fn phase_start() -> Option<Instant> {
+    if log_enabled!(log::Level::Debug) {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Log how long `phase` took for `path`.
+fn log_phase(phase: &str, path: &str, start: Option<Instant>) {
+    if let Some(start) = start {
+        debug!(
+            "phase={} path={} elapsed={:?}",
+            phase,
+            path,
+            start.elapsed()
+        );
+    }
+}
+
+/// The most syntax nodes allocated for a single file. Each file has
+/// its own arena, which is freed once the file has been diffed.
+static PEAK_ARENA_NODES: AtomicUsize = AtomicUsize::new(0);
+
+/// Print resource usage for `--metrics`.
+///
+/// The byte count only covers the `Syntax` values in the arena. Each
+/// node's children, positions and text are still separate heap
+/// allocations, so they aren't included.
+fn print_metrics() {
+    let nodes = PEAK_ARENA_NODES.load(Ordering::SeqCst);
+    eprintln!(
+        "Peak syntax arena: {} nodes, {} bytes (excluding child lists, positions and text)",
+        nodes,
+        nodes * mem::size_of::<Syntax>()
+    );
+}
+
+/// Have a running daemon diff for us, if there is one, and exit with
+/// its exit code.
+#[cfg(unix)]
+fn forward_to_daemon(args: &[OsString]) {
+    if let Some(code) = daemon::forward(args) {
+        std::process::exit(code);
+    }
+}
+
+#[cfg(not(unix))]
+fn forward_to_daemon(_args: &[OsString]) {
+    // Do nothing.
+}
+
+#[cfg(unix)]
+fn serve_daemon(socket_path: &Path) {
+    daemon::serve(socket_path);
+}
+
+#[cfg(not(unix))]
+fn serve_daemon(_socket_path: &Path) {
+    eprintln!("error: --daemon is only supported on Unix.");
+    std::process::exit(1);
+}
+
+/// Run difftastic with the process's command line arguments. This is
+/// the entry point of the `difft` binary.
+pub fn main() {
+    init_logging();
+    reset_sigpipe();
+
+    let args: Vec<OsString> = env::args_os().collect();
+    forward_to_daemon(&args);
+    run(options::parse_args(args));
+}
+
+fn run(mode: Mode) {
+    match mode {
+        Mode::DumpTreeSitter {
+            path,
+            language_override,
+        } => {
+            let path = Path::new(&path);
+            let bytes = read_or_die(path);
+            let src = String::from_utf8_lossy(&bytes).to_string();
+
+            let language = language_override.or_else(|| guess(path, &src));
+            match language.and_then(tsp::from_language) {
+                Some(ts_lang) => {
+                    let tree = tsp::parse_to_tree(&src, &ts_lang);
+                    tsp::print_tree(&src, &tree);
+                }
+                None => {
+                    eprintln!("No tree-sitter parser for file: {:?}", path);
+                }
+            }
+        }
+        Mode::DumpSyntax {
+            path,
+            language_override,
+            as_dot,
+        } => {
+            let path = Path::new(&path);
+            let bytes = read_or_die(path);
+            let src = String::from_utf8_lossy(&bytes).to_string();
+
+            let language = language_override.or_else(|| guess(path, &src));
+            match language.and_then(tsp::from_language) {
+                Some(ts_lang) => {
+                    let arena = Arena::new();
+                    let ast = tsp::parse(&arena, &src, &ts_lang);
+                    init_all_info(&ast, &[]);
+                    if as_dot {
+                        print!("{}", syntax::to_dot(&ast));
+                    } else {
+                        println!("{:#?}", ast);
+                    }
+                }
+                None => {
+                    eprintln!("No tree-sitter parser for file: {:?}", path);
+                }
+            }
+        }
+        Mode::Completions { shell } => {
+            print!(
+                "{}",
+                completions::completions(&options::app(), env!("CARGO_BIN_NAME"), shell)
+            );
+        }
+        Mode::ManPage => {
+            print!(
+                "{}",
+                completions::man_page(&options::app(), env!("CARGO_BIN_NAME"))
+            );
+        }
+        Mode::ListLanguages => {
+            print!("{}", list_languages::render());
+        }
+        Mode::PrintSchema => {
+            print!("{}", display::json::SCHEMA_V1);
+        }
+        Mode::Estimate {
+            diff_options,
+            display_options,
+            lhs_path,
+            rhs_path,
+            lhs_display_path,
+            rhs_display_path,
+        } => {
+            let (lhs_path, rhs_path) = (Path::new(&lhs_path), Path::new(&rhs_path));
+            let (lhs_bytes, rhs_bytes) = read_files_or_die(lhs_path, rhs_path, false);
+            let in_vcs = display_options.in_vcs;
+            let estimate = estimate::estimate(
+                &file_buffer(
+                    &lhs_display_path,
+                    lhs_path,
+                    &lhs_bytes,
+                    in_vcs,
+                    &diff_options,
+                ),
+                &file_buffer(
+                    &rhs_display_path,
+                    rhs_path,
+                    &rhs_bytes,
+                    in_vcs,
+                    &diff_options,
+                ),
+                display_options.tab_width,
+                &diff_options,
+            );
+            println!("{}", estimate::describe(&rhs_display_path, &estimate));
+        }
+        Mode::PrintConfig { config } => {
+            print!("{}", config);
+        }
+        Mode::Replay { session_path } => {
+            session::replay(Path::new(&session_path));
+        }
+        Mode::Daemon { socket_path } => {
+            serve_daemon(Path::new(&socket_path));
+        }
+        Mode::CompareDiffs { old_path, new_path } => {
+            if !compare_diffs::run(Path::new(&old_path), Path::new(&new_path)) {
+                std::process::exit(1);
+            }
+        }
+        Mode::SelfTest => {
+            if !self_test::run() {
+                std::process::exit(1);
+            }
+        }
+        Mode::Bench {
+            path,
+            language_override,
+        } => {
+            bench::run(Path::new(&path), language_override);
+        }
+        Mode::DiffFilesFrom {
+            diff_options,
+            display_options,
+            missing_as_empty,
+            source,
+            use_pager,
+            assert_equal,
+            ignore_token_regexes,
+            metrics,
+            review_path,
+        } => {
+            let pairs = match source {
+                PairSource::List(list_path) => {
+                    let list_path = Path::new(&list_path);
+                    match path_pairs(&read_path_list_or_die(list_path)) {
+                        // Paths in the list are relative to the current
+                        // directory, so that's where its .difftignore is.
+                        Ok(pairs) => expand_dir_pairs(pairs, &DifftIgnore::load(Path::new("."))),
+                        Err(num_paths) => {
+                            eprintln!(
+                                "error: {} contains {} paths, but --files-from needs pairs of paths.",
+                                display_path(list_path),
+                                num_paths
+                            );
+                            std::process::exit(2);
+                        }
+                    }
+                }
+                PairSource::Trees {
+                    mapping_path,
+                    ignore_paths,
+                } => {
+                    let mappings = compare_trees::read_mappings_or_die(Path::new(&mapping_path));
+                    compare_trees::file_pairs(&mappings, &ignore_paths)
+                }
+            };
+
+            start_pager(use_pager, display_options.in_vcs);
+            legend::print_if_needed(&display_options);
+            let found_differences = AtomicBool::new(false);
+            let mut review = review_path.map(Review::new);
+            diff_file_pairs(pairs, &display_options, missing_as_empty, &diff_options).for_each(
+                |diff_result| {
+                    if assert_equal {
+                        if assert_equal::is_equal(&diff_result, &ignore_token_regexes) {
+                            return;
+                        }
+                        found_differences.store(true, Ordering::SeqCst);
+                    }
+                    if let Some(review) = &mut review {
+                        review.review_file(&display_options, &diff_result, |diff_result| {
+                            print_diff_result(&display_options, diff_result)
+                        });
+                        return;
+                    }
+                    recover_panic(&diff_result.rhs_display_path, || {
+                        print_diff_result(&display_options, &diff_result)
+                    });
+                },
+            );
+
+            if let Some(review) = &review {
+                review.finish();
+            }
+            if metrics {
+                print_metrics();
+            }
+            if found_differences.load(Ordering::SeqCst) {
+                std::process::exit(1);
+            }
+        }
+        Mode::Verify {
+            diff_options,
+            display_options,
+            manifest_path,
+        } => {
+            let checks = verify::read_manifest_or_die(Path::new(&manifest_path));
+            let pairs = checks
+                .iter()
+                .map(|check| FilePair {
+                    lhs_path: check.lhs_path.clone(),
+                    rhs_path: check.rhs_path.clone(),
+                    profile: Profile::default(),
+                    in_dir_pair: false,
+                })
+                .collect();
+
+            // Results are in manifest order, but a pair is missing if
+            // diffing it panicked. These count as failures.
+            let mut remaining = checks.iter();
+            let mut num_passed = 0;
+            // The manifest has already been checked for missing files.
+            diff_file_pairs(pairs, &display_options, false, &diff_options).for_each(
+                |diff_result| {
+                    let check = match remaining.by_ref().find(|check| {
+                        display_path(&check.lhs_path) == diff_result.lhs_display_path
+                            && display_path(&check.rhs_path) == diff_result.rhs_display_path
+                    }) {
+                        Some(check) => check,
+                        None => return,
+                    };
+
+                    let actual = verify::Outcome::of(&diff_result);
+                    if actual.satisfies(check.expected) {
+                        num_passed += 1;
+                        println!(
+                            "ok     {} {}",
+                            diff_result.lhs_display_path, diff_result.rhs_display_path
+                        );
+                        return;
+                    }
+
+                    println!(
+                        "FAILED {} {}: expected {}, but the result was {}",
+                        diff_result.lhs_display_path,
+                        diff_result.rhs_display_path,
+                        check.expected,
+                        actual
+                    );
+                    if actual != verify::Outcome::Equal {
+                        recover_panic(&diff_result.rhs_display_path, || {
+                            print_diff_result(&display_options, &diff_result)
+                        });
+                    }
+                },
+            );
+
+            println!();
+            let num_failed = checks.len() - num_passed;
+            if num_failed == 0 {
+                println!("All {} checks passed.", checks.len());
+            } else {
+                println!("{} of {} checks failed.", num_failed, checks.len());
+                std::process::exit(1);
+            }
+        }
+        Mode::DiffMany {
+            diff_options,
+            display_options,
+            paths,
+            use_pager,
+        } => {
+            start_pager(use_pager, display_options.in_vcs);
+            legend::print_if_needed(&display_options);
+            let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+            diff_many(&paths, &display_options, &diff_options);
+        }
+        Mode::DiffVcsBatch {
+            diff_options,
+            display_options,
+            files,
+            use_pager,
+            assert_equal,
+            ignore_token_regexes,
+        } => {
+            start_pager(use_pager, display_options.in_vcs);
+            legend::print_if_needed(&display_options);
+            let ignore = DifftIgnore::load(Path::new("."));
+            let mut found_differences = false;
+            for file in files {
+                let rhs_rel_path = Path::new(&file.rhs_display_path);
+                if ignore.is_excluded(rhs_rel_path) {
+                    continue;
+                }
+                let mut display_options = display_options.clone();
+                display_options.vcs_metadata = Some(file.vcs_metadata);
+
+                let mut diff_result = diff_file(
+                    &file.lhs_display_path,
+                    &file.rhs_display_path,
+                    Path::new(&file.lhs_path),
+                    Path::new(&file.rhs_path),
+                    &display_options,
+                    false,
+                    &diff_options,
+                );
+                ignore.apply_options(rhs_rel_path, &mut diff_result.profile);
+                if assert_equal {
+                    if assert_equal::is_equal(&diff_result, &ignore_token_regexes) {
+                        continue;
+                    }
+                    found_differences = true;
+                }
+                recover_panic(&diff_result.rhs_display_path, || {
+                    print_diff_result(&display_options, &diff_result)
+                });
+            }
+
+            if found_differences {
+                std::process::exit(1);
+            }
+        }
+        Mode::Diff {
+            diff_options,
+            mut display_options,
+            missing_as_empty,
+            snapshots,
+            lhs_path,
+            rhs_path,
+            lhs_display_path,
+            rhs_display_path,
+            lhs_label,
+            rhs_label,
+            record,
+            use_pager,
+            assert_equal,
+            ignore_token_regexes,
+            metrics,
+            review_path,
+        } => {
+            // Diff the recorded copies, so inputs that can only be
+            // read once (e.g. from process substitution) still work.
+            if let Some(record) = record {
+                session::record_or_die(&record, Path::new(&lhs_path), Path::new(&rhs_path));
+                session::replay(Path::new(&record.path));
+                return;
+            }
+
+            start_pager(use_pager, display_options.in_vcs);
+            legend::print_if_needed(&display_options);
+            let lhs_path = Path::new(&lhs_path);
+            let rhs_path = Path::new(&rhs_path);
+            let found_differences = AtomicBool::new(false);
+            let mut review = review_path.map(Review::new);
+
+            if lhs_path == rhs_path {
+                warnings::warn(format!(
+                    "You've specified the same {} twice.",
+                    if lhs_path.is_dir() {
+                        "directory"
+                    } else {
+                        "file"
+                    }
+                ));
+            }
+
+            // Git runs external diff tools at the top of the work tree,
+            // so that's where its .difftignore is.
+            let vcs_ignore = if display_options.in_vcs {
+                DifftIgnore::load(Path::new("."))
+            } else {
+                DifftIgnore::default()
+            };
+
+            // Directories, and archives and git trees with
+            // --snapshots, are all diffed file by file.
+            let trees = tree_kind(lhs_path, snapshots)
+                .and_then(|lhs_kind| Some((lhs_kind, tree_kind(rhs_path, snapshots)?)))
+                .map(|(lhs_kind, rhs_kind)| {
+                    (
+                        FileTree::open_or_die(lhs_path, lhs_kind),
+                        FileTree::open_or_die(rhs_path, rhs_kind),
+                    )
+                });
+
+            if let Some((lhs_tree, rhs_tree)) = &trees {
+                if lhs_label.is_some() || rhs_label.is_some() {
+                    warnings::warn(
+                        "--lhs-label and --rhs-label are ignored when diffing directories.",
+                    );
+                }
+
+                diff_directories(lhs_tree, rhs_tree, &display_options, &diff_options).for_each(
+                    |diff_result| {
+                        if assert_equal {
+                            if assert_equal::is_equal(&diff_result, &ignore_token_regexes) {
+                                return;
+                            }
+                            found_differences.store(true, Ordering::SeqCst);
+                        }
+                        if let Some(review) = &mut review {
+                            review.review_file(&display_options, &diff_result, |diff_result| {
+                                print_diff_result(&display_options, diff_result)
+                            });
+                            return;
+                        }
+                        recover_panic(&diff_result.rhs_display_path, || {
+                            print_diff_result(&display_options, &diff_result)
+                        });
+                    },
+                );
+            } else if vcs_ignore.is_excluded(Path::new(&rhs_display_path)) {
+                info!("Skipping {} because of .difftignore", rhs_display_path);
+            } else {
+                // Generated files are only collapsed when there may be
+                // many other files to read, not when the user has asked
+                // for this file specifically.
+                if !display_options.in_vcs {
+                    display_options.collapse_generated = false;
+                }
+
+                let mut diff_result = diff_file(
+                    &lhs_display_path,
+                    &rhs_display_path,
+                    lhs_path,
+                    rhs_path,
+                    &display_options,
+                    missing_as_empty,
+                    &diff_options,
+                );
+                vcs_ignore.apply_options(Path::new(&rhs_display_path), &mut diff_result.profile);
+                if let Some(lhs_label) = lhs_label {
+                    diff_result.lhs_display_path = lhs_label;
+                }
+                if let Some(rhs_label) = rhs_label {
+                    diff_result.rhs_display_path = rhs_label;
+                }
+
+                if assert_equal {
+                    if !assert_equal::is_equal(&diff_result, &ignore_token_regexes) {
+                        found_differences.store(true, Ordering::SeqCst);
+                        print_diff_result(&display_options, &diff_result);
+                    }
+                } else if let Some(review) = &mut review {
+                    review.review_file(&display_options, &diff_result, |diff_result| {
+                        print_diff_result(&display_options, diff_result)
+                    });
+                } else {
+                    print_diff_result(&display_options, &diff_result);
+                }
+            }
+
+            if let Some(review) = &review {
+                review.finish();
+            }
+            if metrics {
+                print_metrics();
+            }
+            if found_differences.load(Ordering::SeqCst) {
+                std::process::exit(1);
+            }
+        }
+    };
+}
+
+/// Print a diff between two files.
+fn diff_file<'a>(
+    lhs_display_path: &'a str,
+    rhs_display_path: &'a str,
+    lhs_path: &'a Path,
+    rhs_path: &'a Path,
+    display_options: &DisplayOptions,
+    missing_as_empty: bool,
+    diff_options: &DiffOptions,
+) -> DiffResult {
+    let (lhs_bytes, rhs_bytes) = read_files_or_die(lhs_path, rhs_path, missing_as_empty);
+    diff_file_bytes(
+        lhs_display_path,
+        rhs_display_path,
+        lhs_path,
+        rhs_path,
+        &lhs_bytes,
+        &rhs_bytes,
+        display_options,
+        diff_options,
+    )
+}
+
+/// Diff two files that we've already read. `lhs_path` and `rhs_path`
+/// are used for language detection and `.editorconfig`.
+fn diff_file_bytes(
+    lhs_display_path: &str,
+    rhs_display_path: &str,
+    lhs_path: &Path,
+    rhs_path: &Path,
+    lhs_bytes: &[u8],
+    rhs_bytes: &[u8],
+    display_options: &DisplayOptions,
+    diff_options: &DiffOptions,
+) -> DiffResult {
+    // Show the real path on both sides of an added or deleted file,
+    // and use it for language detection.
+    let (status, lhs_display_path, rhs_display_path) = if is_dev_null(lhs_path) {
+        (Some(FileStatus::Added), rhs_display_path, rhs_display_path)
+    } else if is_dev_null(rhs_path) {
+        (
+            Some(FileStatus::Deleted),
+            lhs_display_path,
+            lhs_display_path,
+        )
+    } else {
+        (None, lhs_display_path, rhs_display_path)
+    };
+
+    let lhs = file_buffer(
+        lhs_display_path,
+        lhs_path,
+        lhs_bytes,
+        display_options.in_vcs,
+        diff_options,
+    );
+    let rhs = file_buffer(
+        rhs_display_path,
+        rhs_path,
+        rhs_bytes,
+        display_options.in_vcs,
+        diff_options,
+    );
+    let settings = file_settings(&lhs, &rhs, display_options.tab_width, diff_options);
+
+    let mut diff_result = diff_buffers(&lhs, &rhs, &settings, diff_options);
+    if status.is_some() {
+        diff_result.status = status;
+    }
+
+    if display_options.show_indentation {
+        if let (FileContent::Text(_), FileContent::Text(_)) =
+            (&diff_result.lhs_src, &diff_result.rhs_src)
+        {
+            // We need the tabs, which have been replaced in the
+            // diffed source.
+            let (lhs_src, _) = decode(lhs_bytes, settings.encoding_override);
+            let (rhs_src, _) = decode(rhs_bytes, settings.encoding_override);
+            let (lhs_indentation, rhs_indentation) =
+                find_indentation_changes(&lhs_src, &rhs_src, settings.tab_width);
+            mark_indentation_changes(&mut diff_result.lhs_positions, &lhs_indentation);
+            mark_indentation_changes(&mut diff_result.rhs_positions, &rhs_indentation);
+            diff_result.lhs_indentation = lhs_indentation;
+            diff_result.rhs_indentation = rhs_indentation;
+        }
+    }
+
+    // Patches need the original bytes, which we don't keep in the
+    // result.
+    if let (DisplayMode::StageScript, Some(stage_dir)) =
+        (display_options.display_mode, &display_options.stage_dir)
+    {
+        if let Err(e) = stage::write_patches(stage_dir, &diff_result, lhs_bytes, rhs_bytes) {
+            eprintln!(
+                "error: Could not write patches to {}: {}",
+                display_path(stage_dir),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+    diff_result
+}
+
+/// Convert the bytes of a file to the text that we diff. Returns the
+/// text, the encoding that we used to decode it, and the map from
+/// offsets in the text back to `bytes`.
+fn clean_src(
+    bytes: &[u8],
+    tab_width: usize,
+    strip_input_ansi: bool,
+    encoding_override: Option<&'static Encoding>,
+) -> (String, &'static Encoding, OffsetMap) {
+    // TODO: don't replace tab characters inside string literals.
+    let (mut src, encoding, offset_map) =
+        offsets::clean(bytes, tab_width, strip_input_ansi, encoding_override);
+
+    // Ignore the trailing newline, if present.
+    // TODO: highlight if this has changes (#144).
+    if src.ends_with('\n') {
+        src.pop();
+    }
+
+    (src, encoding, offset_map)
+}
+
+/// Describe the encodings of the two files, unless they're both UTF-8.
+fn describe_encodings(lhs: &'static Encoding, rhs: &'static Encoding) -> Option<String> {
+    if lhs == UTF_8 && rhs == UTF_8 {
+        None
+    } else if lhs == rhs {
+        Some(lhs.name().into())
+    } else {
+        Some(format!("{} → {}", lhs.name(), rhs.name()))
+    }
+}
+
+/// Settings for diffing a pair of buffers, after applying any mode
+/// line and `.editorconfig`.
+#[derive(Clone, Copy)]
+struct FileSettings {
+    tab_width: usize,
+    encoding_override: Option<&'static Encoding>,
+    language_hint: Option<parse::guess_language::Language>,
+}
+
+/// Find the settings for diffing `lhs` and `rhs`. We use the settings
+/// for the new file, falling back to the old file if it's been
+/// deleted. A mode line in the file itself takes precedence over
+/// .editorconfig, but not over the command line.
+fn file_settings(
+    lhs: &Buffer,
+    rhs: &Buffer,
+    tab_width: usize,
+    diff_options: &DiffOptions,
+) -> FileSettings {
+    let editorconfig = diff_options.editorconfig;
+    let properties = match rhs.path.or(lhs.path) {
+        Some(path) if editorconfig.tab_width || editorconfig.charset => {
+            editorconfig::properties(path)
+        }
+        _ => editorconfig::Properties::default(),
+    };
+
+    let modeline_buffer = if rhs.content.is_empty() { lhs } else { rhs };
+    let modeline = match modeline::find(modeline_buffer.content) {
+        Ok(modeline) => modeline.unwrap_or_default(),
+        Err(e) => {
+            warnings::warn(format!("{}: {}", modeline_buffer.display_path, e));
+            modeline::Modeline::default()
+        }
+    };
+
+    let tab_width = match (modeline.tab_width, properties.tab_width) {
+        (Some(tab_width), _) if diff_options.modeline_tab_width => tab_width,
+        (_, Some(tab_width)) if editorconfig.tab_width => tab_width,
+        _ => tab_width,
+    };
+    let encoding_override = match properties.charset {
+        Some(charset) if editorconfig.charset => Some(charset),
+        _ => diff_options.encoding_override,
+    };
+    let language_hint = rhs
+        .language_hint
+        .or(lhs.language_hint)
+        .or(modeline.language);
+
+    FileSettings {
+        tab_width,
+        encoding_override,
+        language_hint,
+    }
+}
+
+/// Diff the contents of two buffers.
+pub fn diff_file_content(
+    lhs: &Buffer,
+    rhs: &Buffer,
+    tab_width: usize,
+    diff_options: &DiffOptions,
+) -> DiffResult {
+    let settings = file_settings(lhs, rhs, tab_width, diff_options);
+    diff_buffers(lhs, rhs, &settings, diff_options)
+}
+
+/// How we'll diff a pair of text files.
+#[derive(Clone, Copy)]
+enum Strategy {
+    /// A text diff, because a file exceeds DFT_BYTE_LIMIT.
+    ByteLimit {
+        has_parser: bool,
+    },
+    Prose,
+    /// A text diff, because a file looks truncated.
+    Truncated,
+    Syntax(&'static tsp::TreeSitterConfig),
+    /// A text diff, because we don't have a parser.
+    Text,
+}
+
+/// A pair of text files, decoded and ready to diff.
+struct PreparedSrcs {
+    lhs_src: String,
+    rhs_src: String,
+    /// The sources before blanking out ignored regions, if there were
+    /// any.
+    unmasked_srcs: Option<(String, String)>,
+    /// Maps from offsets in the sources to offsets in the original
+    /// bytes.
+    offset_maps: (OffsetMap, OffsetMap),
+    lhs_ignored: Vec<(LineNumber, LineNumber)>,
+    rhs_ignored: Vec<(LineNumber, LineNumber)>,
+    encoding: Option<String>,
+    language: Option<Language>,
+    /// The language name to show when diffing with `--api-only`.
+    api_lang_name: Option<String>,
+    profile: Profile,
+    truncated: Option<Side>,
+    strategy: Strategy,
+}
+
+enum Prepared {
+    Binary,
+    /// The two files have identical bytes.
+    Identical {
+        language_name: Option<String>,
+        encoding: Option<String>,
+        profile: Profile,
+    },
+    Text(PreparedSrcs),
+}
+
+/// Decode `lhs` and `rhs`, and decide how to diff them. Both diffing
+/// and `--estimate` start here, so they make the same decisions.
+fn prepare_srcs(
+    lhs: &Buffer,
+    rhs: &Buffer,
+    settings: &FileSettings,
+    diff_options: &DiffOptions,
+) -> Prepared {
+    let FileSettings {
+        tab_width,
+        encoding_override,
+        language_hint,
+    } = *settings;
+    let DiffOptions {
+        byte_limit,
+        strip_input_ansi,
+        api_only,
+        diff_mode,
+        ..
+    } = *diff_options;
+    let (lhs_bytes, rhs_bytes) = (lhs.content, rhs.content);
+
+    // TODO: take a Path directly instead.
+    let guess_path = language_detection_path(rhs.display_path);
+
+    let is_binary = |bytes: &[u8]| is_probably_binary(bytes, encoding_override);
+    if is_binary(lhs_bytes) || is_binary(rhs_bytes) {
+        return Prepared::Binary;
+    }
+
+    let (lhs_src, lhs_encoding, lhs_offsets) =
+        clean_src(lhs_bytes, tab_width, strip_input_ansi, encoding_override);
+    let (rhs_src, rhs_encoding, rhs_offsets) =
+        clean_src(rhs_bytes, tab_width, strip_input_ansi, encoding_override);
+    let encoding = describe_encodings(lhs_encoding, rhs_encoding);
+
+    // Take the larger of the two files when guessing the
+    // language. This is useful when we've added or removed a whole
+    // file.
+    let guess_src = if lhs_src.len() > rhs_src.len() {
+        &lhs_src
+    } else {
+        &rhs_src
+    };
+    let language = language_hint.or_else(|| guess(guess_path, guess_src));
+    let lang_config = language.and_then(tsp::cached_from_language);
+    let profile = profiles::for_file(guess_path, language, guess_src);
+
+    if lhs_bytes == rhs_bytes {
+        return Prepared::Identical {
+            language_name: lang_config.map(|l| l.name.into()),
+            encoding,
+            profile,
+        };
+    }
+
+    // A truncated file will usually have parse errors at the end, and
+    // structural diffing would report spurious changes. Diff it as
+    // text, so we only show the missing tail.
+    let truncated = truncated_side(&lhs_src, &rhs_src);
+    if let Some(side) = truncated {
+        info!("{:?} file looks truncated", side);
+    }
+
+    // With --api-only, diff the public declarations of each file as
+    // text, rather than the whole file.
+    let api_srcs = match (api_only, language, &lang_config) {
+        (true, Some(language), Some(ts_lang)) => api::declarations(language, ts_lang, &lhs_src)
+            .zip(api::declarations(language, ts_lang, &rhs_src)),
+        _ => None,
+    };
+    let (lhs_src, rhs_src, lang_config, api_lang_name, offset_maps) = match api_srcs {
+        // The declarations aren't in the file in this form, so
+        // offsets are relative to them.
+        Some((lhs_api, rhs_api)) => {
+            let api_lang_name = lang_config.map(|l| format!("{} API", l.name));
+            (lhs_api, rhs_api, None, api_lang_name, Default::default())
+        }
+        None => (
+            lhs_src,
+            rhs_src,
+            lang_config,
+            None,
+            (lhs_offsets, rhs_offsets),
+        ),
+    };
+
+    // Blank out regions the user has marked with difft:ignore-start
+    // and difft:ignore-end, so we don't spend time diffing them.
+    // Masking preserves positions, so we still display the original.
+    let lhs_ignored = suppress::ignored_lines(&lhs_src);
+    let rhs_ignored = suppress::ignored_lines(&rhs_src);
+    let unmasked_srcs = if lhs_ignored.is_empty() && rhs_ignored.is_empty() {
+        None
+    } else {
+        Some((lhs_src.clone(), rhs_src.clone()))
+    };
+    let lhs_src = suppress::mask_ignored(lhs_src, &lhs_ignored);
+    let rhs_src = suppress::mask_ignored(rhs_src, &rhs_ignored);
+
+    let use_prose = api_lang_name.is_none()
+        && match diff_mode {
+            DiffMode::Prose => true,
+            DiffMode::Auto => lang_config.is_none() && prose_parser::is_prose_path(guess_path),
+            DiffMode::Syntax => false,
+        };
+
+    let strategy = match lang_config {
+        _ if lhs_bytes.len() > byte_limit || rhs_bytes.len() > byte_limit => Strategy::ByteLimit {
+            has_parser: lang_config.is_some(),
+        },
+        _ if use_prose => Strategy::Prose,
+        Some(_) if truncated.is_some() => Strategy::Truncated,
+        Some(ts_lang) => Strategy::Syntax(ts_lang),
+        None => Strategy::Text,
+    };
+
+    Prepared::Text(PreparedSrcs {
+        lhs_src,
+        rhs_src,
+        unmasked_srcs,
+        offset_maps,
+        lhs_ignored,
+        rhs_ignored,
+        encoding,
+        language,
+        api_lang_name,
+        profile,
+        truncated,
+        strategy,
+    })
+}
+
+/// The syntax trees of a pair of files. There are no trees if parse
+/// errors exceed DFT_PARSE_ERROR_LIMIT.
+struct ParsedSrcs<'a> {
+    lhs_errors: tsp::ParseErrors,
+    rhs_errors: tsp::ParseErrors,
+    roots: Option<(Vec<&'a Syntax<'a>>, Vec<&'a Syntax<'a>>)>,
+}
+
+/// Parse both sides of `prepared` with `ts_lang`, honouring
+/// `--c-preprocessor`, `--max-depth` and `--ignore-case`. Lists that
+/// were cut off at `--max-depth` are added to `collapsed`.
+fn parse_srcs<'a>(
+    prepared: &PreparedSrcs,
+    ts_lang: &tsp::TreeSitterConfig,
+    arena: &'a Arena<Syntax<'a>>,
+    diff_options: &DiffOptions,
+    collapsed: &mut Vec<syntax::CollapsedList<'a>>,
+    rhs_display_path: &str,
+) -> ParsedSrcs<'a> {
+    let DiffOptions {
+        parse_error_limit,
+        c_preprocessor,
+        ignore_case,
+        max_depth,
+        ..
+    } = *diff_options;
+    let (lhs_src, rhs_src) = (&prepared.lhs_src, &prepared.rhs_src);
+
+    // Masking preserves positions, so we can parse the masked source
+    // but still display the original.
+    let (lhs_parse_src, rhs_parse_src) = if c_preprocessor
+        && matches!(
+            prepared.language,
+            Some(Language::C) | Some(Language::CPlusPlus)
+        ) {
+        (
+            Cow::Owned(mask_inactive_branches(lhs_src)),
+            Cow::Owned(mask_inactive_branches(rhs_src)),
+        )
+    } else {
+        (Cow::Borrowed(&lhs_src[..]), Cow::Borrowed(&rhs_src[..]))
+    };
+
+    let parse_start = phase_start();
+    let lhs_tree = tsp::parse_to_tree(&lhs_parse_src, ts_lang);
+    let rhs_tree = tsp::parse_to_tree(&rhs_parse_src, ts_lang);
+    log_phase("parse", rhs_display_path, parse_start);
+
+    let lhs_errors = tsp::parse_errors(&lhs_parse_src, &lhs_tree);
+    let rhs_errors = tsp::parse_errors(&rhs_parse_src, &rhs_tree);
+    if lhs_errors.ratio > parse_error_limit || rhs_errors.ratio > parse_error_limit {
+        info!(
+            "Parse errors cover {:.3} and {:.3} of the inputs, exceeding the limit {}",
+            lhs_errors.ratio, rhs_errors.ratio, parse_error_limit
+        );
+        return ParsedSrcs {
+            lhs_errors,
+            rhs_errors,
+            roots: None,
+        };
+    }
+
+    let lhs = tsp::to_syntax(&lhs_tree, &lhs_parse_src, arena, ts_lang);
+    let rhs = tsp::to_syntax(&rhs_tree, &rhs_parse_src, arena, ts_lang);
+    let (lhs, rhs) = match max_depth {
+        Some(max_depth) => (
+            syntax::collapse_deep_lists(arena, &lhs, max_depth, collapsed),
+            syntax::collapse_deep_lists(arena, &rhs, max_depth, collapsed),
+        ),
+        None => (lhs, rhs),
+    };
+    init_all_info_with_case(&lhs, &rhs, ignore_case);
+
+    ParsedSrcs {
+        lhs_errors,
+        rhs_errors,
+        roots: Some((lhs, rhs)),
+    }
+}
+
+fn diff_buffers(
+    lhs: &Buffer,
+    rhs: &Buffer,
+    settings: &FileSettings,
+    diff_options: &DiffOptions,
+) -> DiffResult {
+    let DiffOptions {
+        node_limit,
+        cost_model,
+        ignore_case,
+        time_limit,
+        word_split,
+        explain,
+        ..
+    } = *diff_options;
+    let (lhs_display_path, rhs_display_path) = (lhs.display_path, rhs.display_path);
+
+    let prepared = match prepare_srcs(lhs, rhs, settings, diff_options) {
+        Prepared::Binary => {
+            let profile = profiles::for_file(language_detection_path(rhs_display_path), None, "");
+            return DiffResult {
+                lhs_display_path: lhs_display_path.into(),
+                rhs_display_path: rhs_display_path.into(),
+                language: None,
+                text_fallback: None,
+                encoding: None,
+                lhs_src: FileContent::Binary(lhs.content.to_vec()),
+                rhs_src: FileContent::Binary(rhs.content.to_vec()),
+                lhs_positions: vec![],
+                rhs_positions: vec![],
+                lhs_parse_errors: None,
+                rhs_parse_errors: None,
+                lhs_novel_ranges: vec![],
+                rhs_novel_ranges: vec![],
+                timed_out_regions: vec![],
+                lhs_definitions: vec![],
+                rhs_definitions: vec![],
+                moves: vec![],
+                copied: false,
+                status: None,
+                profile,
+                explanations: vec![],
+                signature_changes: vec![],
+                lhs_indentation: IndentationChanges::default(),
+                rhs_indentation: IndentationChanges::default(),
+                lhs_offsets: OffsetMap::default(),
+                rhs_offsets: OffsetMap::default(),
+            };
+        }
+        Prepared::Identical {
+            language_name,
+            encoding,
+            profile,
+        } => {
+            // If the two files are completely identical, return early
+            // rather than doing any more work.
+            return DiffResult {
+                lhs_display_path: lhs_display_path.into(),
+                rhs_display_path: rhs_display_path.into(),
+                language: language_name,
+                text_fallback: None,
+                encoding,
+                lhs_src: FileContent::Text("".into()),
+                rhs_src: FileContent::Text("".into()),
+                lhs_positions: vec![],
+                rhs_positions: vec![],
+                lhs_parse_errors: None,
+                rhs_parse_errors: None,
+                lhs_novel_ranges: vec![],
+                rhs_novel_ranges: vec![],
+                timed_out_regions: vec![],
+                lhs_definitions: vec![],
+                rhs_definitions: vec![],
+                moves: vec![],
+                copied: false,
+                status: None,
+                profile,
+                explanations: vec![],
+                signature_changes: vec![],
+                lhs_indentation: IndentationChanges::default(),
+                rhs_indentation: IndentationChanges::default(),
+                lhs_offsets: OffsetMap::default(),
+                rhs_offsets: OffsetMap::default(),
+            };
+        }
+        Prepared::Text(prepared) => prepared,
+    };
+    let (lhs_src, rhs_src) = (&prepared.lhs_src, &prepared.rhs_src);
+    let language = prepared.language;
+
+    let mut lhs_parse_errors = None;
+    let mut rhs_parse_errors = None;
+    let mut lhs_novel_ranges = vec![];
+    let mut rhs_novel_ranges = vec![];
+    let mut timed_out_regions = vec![];
+    let mut lhs_definitions = vec![];
+    let mut rhs_definitions = vec![];
+    let mut moves = vec![];
+    let mut signature_changes = vec![];
+    let mut explanations = vec![];
+    let mut text_fallback = None;
+    let (lang_name, lhs_positions, rhs_positions) = match prepared.strategy {
+        Strategy::ByteLimit { has_parser } => {
+            if has_parser {
+                warnings::warn(format!(
+                    "{}: exceeded DFT_BYTE_LIMIT, using a text diff.",
+                    rhs_display_path
+                ));
+            }
+            text_fallback = Some("exceeded DFT_BYTE_LIMIT");
+            let lhs_positions =
+                line_parser::change_positions(lhs_src, rhs_src, word_split, ignore_case);
+            let rhs_positions =
+                line_parser::change_positions(rhs_src, lhs_src, word_split, ignore_case);
+            (Some("Text".into()), lhs_positions, rhs_positions)
+        }
+        Strategy::Prose => {
+            let (lhs_positions, rhs_positions) = prose_parser::change_positions(lhs_src, rhs_src);
+            (Some("Prose".into()), lhs_positions, rhs_positions)
+        }
+        Strategy::Truncated => {
+            let lhs_positions =
+                line_parser::change_positions(lhs_src, rhs_src, word_split, ignore_case);
+            let rhs_positions =
+                line_parser::change_positions(rhs_src, lhs_src, word_split, ignore_case);
+            (
+                Some("Text (truncated file)".into()),
+                lhs_positions,
+                rhs_positions,
+            )
+        }
+        Strategy::Syntax(ts_lang) => {
+            let arena = Arena::new();
+            let mut collapsed = vec![];
+            let parsed = parse_srcs(
+                &prepared,
+                ts_lang,
+                &arena,
+                diff_options,
+                &mut collapsed,
+                rhs_display_path,
+            );
+            lhs_parse_errors = Some(parsed.lhs_errors);
+            rhs_parse_errors = Some(parsed.rhs_errors);
+
+            match parsed.roots {
+                None => {
+                    warnings::warn(format!(
+                        "{}: exceeded DFT_PARSE_ERROR_LIMIT, using a text diff.",
+                        rhs_display_path
+                    ));
+                    text_fallback = Some("exceeded DFT_PARSE_ERROR_LIMIT");
+
+                    let lhs_positions =
+                        line_parser::change_positions(lhs_src, rhs_src, word_split, ignore_case);
+                    let rhs_positions =
+                        line_parser::change_positions(rhs_src, lhs_src, word_split, ignore_case);
+                    (Some("Text".into()), lhs_positions, rhs_positions)
+                }
+                Some((lhs, rhs)) => {
+                    let matching_start = phase_start();
+                    PEAK_ARENA_NODES.fetch_max(arena.len(), Ordering::SeqCst);
+                    lhs_definitions = syntax::definitions(&lhs, lhs_src);
+                    rhs_definitions = syntax::definitions(&rhs, rhs_src);
+
+                    let mut change_map = if explain.is_some() {
+                        ChangeMap::with_provenance()
+                    } else {
+                        ChangeMap::default()
+                    };
+                    let possibly_changed = if env::var("DFT_DBG_KEEP_UNCHANGED").is_ok() {
+                        vec![(lhs.clone(), rhs.clone())]
+                    } else {
+                        unchanged::mark_unchanged(&lhs, &rhs, &mut change_map)
+                    };
+
+                    let possibly_changed_max = max_num_nodes(&possibly_changed);
+                    if possibly_changed_max > node_limit {
+                        info!(
+                            "Found {} nodes, exceeding the limit {}",
+                            possibly_changed_max, node_limit
+                        );
+                        warnings::warn(format!(
+                            "{}: exceeded DFT_NODE_LIMIT, using a text diff.",
+                            rhs_display_path
+                        ));
+                        text_fallback = Some("exceeded DFT_NODE_LIMIT");
+
+                        let lhs_positions = line_parser::change_positions(
+                            lhs_src,
+                            rhs_src,
+                            word_split,
+                            ignore_case,
+                        );
+                        let rhs_positions = line_parser::change_positions(
+                            rhs_src,
+                            lhs_src,
+                            word_split,
+                            ignore_case,
+                        );
+                        (Some("Text".into()), lhs_positions, rhs_positions)
+                    } else {
+                        let search_start = Instant::now();
+                        let section_sizes: Vec<u32> = possibly_changed
+                            .iter()
+                            .map(|(lhs, rhs)| num_nodes(lhs) + num_nodes(rhs))
+                            .collect();
+                        let mut remaining_nodes: u32 = section_sizes.iter().sum();
+                        for ((lhs_section_nodes, rhs_section_nodes), section_size) in
+                            possibly_changed.into_iter().zip(section_sizes)
+                        {
+                            init_next_prev(&lhs_section_nodes);
+                            init_next_prev(&rhs_section_nodes);
+
+                            // Give each section a share of the
+                            // remaining time in proportion to its
+                            // size, so a slow section doesn't use up
+                            // the time of every later section.
+                            let deadline = time_limit.map(|time_limit| {
+                                let remaining = time_limit.saturating_sub(search_start.elapsed());
+                                let share =
+                                    f64::from(section_size) / f64::from(remaining_nodes.max(1));
+                                Instant::now() + remaining.mul_f64(share)
+                            });
+                            remaining_nodes -= section_size;
+
+                            let completed = mark_syntax(
+                                lhs_section_nodes.get(0).copied(),
+                                rhs_section_nodes.get(0).copied(),
+                                &mut change_map,
+                                &cost_model,
+                                deadline,
+                            );
+                            if !completed {
+                                // Keep the changes the search found,
+                                // and show the rest of this section as
+                                // a text diff. Every node still needs
+                                // a change state.
+                                let mut lhs_lines = None;
+                                let mut rhs_lines = None;
+                                mark_unreached(&lhs_section_nodes, &mut change_map, &mut lhs_lines);
+                                mark_unreached(&rhs_section_nodes, &mut change_map, &mut rhs_lines);
+                                if lhs_lines.is_some() || rhs_lines.is_some() {
+                                    timed_out_regions.push(TimedOutRegion {
+                                        lhs_lines,
+                                        rhs_lines,
+                                    });
+                                }
+                                continue;
+                            }
+
+                            let language = language.unwrap();
+                            fix_all_sliders(language, &lhs_section_nodes, &mut change_map);
+                            fix_all_sliders(language, &rhs_section_nodes, &mut change_map);
+                        }
+
+                        let mut lhs_positions = syntax::change_positions(&lhs, &change_map);
+                        let mut rhs_positions = syntax::change_positions(&rhs, &change_map);
+                        lhs_novel_ranges = syntax::novel_definition_ranges(&lhs, &change_map);
+                        rhs_novel_ranges = syntax::novel_definition_ranges(&rhs, &change_map);
+                        if timed_out_regions.is_empty() {
+                            moves = diff::moves::find_moves(&lhs, &rhs, &change_map);
+                        } else {
+                            let regions: Vec<String> =
+                                timed_out_regions.iter().map(|r| r.to_string()).collect();
+                            warnings::warn(format!(
+                                "{}: exceeded --time-limit, showing {} as a text diff.",
+                                rhs_display_path,
+                                regions.join("; ")
+                            ));
+                            let lhs_timed_out: Vec<_> = timed_out_regions
+                                .iter()
+                                .filter_map(|r| r.lhs_lines)
+                                .collect();
+                            let rhs_timed_out: Vec<_> = timed_out_regions
+                                .iter()
+                                .filter_map(|r| r.rhs_lines)
+                                .collect();
+                            splice_text_diff(
+                                &mut lhs_positions,
+                                &line_parser::change_positions(
+                                    lhs_src,
+                                    rhs_src,
+                                    word_split,
+                                    ignore_case,
+                                ),
+                                &lhs_timed_out,
+                            );
+                            splice_text_diff(
+                                &mut rhs_positions,
+                                &line_parser::change_positions(
+                                    rhs_src,
+                                    lhs_src,
+                                    word_split,
+                                    ignore_case,
+                                ),
+                                &rhs_timed_out,
+                            );
+                            // Nodes the search didn't reach were only marked
+                            // as novel so they had a change state, so don't
+                            // collapse them as added or removed code.
+                            lhs_novel_ranges.retain(|range| !overlaps_any(*range, &lhs_timed_out));
+                            rhs_novel_ranges.retain(|range| !overlaps_any(*range, &rhs_timed_out));
+                        }
+                        signature_changes =
+                            diff::signatures::find_signature_changes(&rhs, &change_map);
+                        log_phase("matching", rhs_display_path, matching_start);
+
+                        let collapsed_paths =
+                            syntax::changed_collapsed_paths(&collapsed, &change_map);
+                        if !collapsed_paths.is_empty() {
+                            warnings::warn(format!(
+                                "{}: changes below --max-depth are shown as whole nodes: {}",
+                                rhs_display_path,
+                                collapsed_paths.join(", ")
+                            ));
+                        }
+
+                        if let Some((line, col)) = explain {
+                            explanations.extend(diff::explain::explain(
+                                &lhs,
+                                &change_map,
+                                Side::Left,
+                                line,
+                                col,
+                            ));
+                            explanations.extend(diff::explain::explain(
+                                &rhs,
+                                &change_map,
+                                Side::Right,
+                                line,
+                                col,
+                            ));
+                        }
+                        (Some(ts_lang.name.into()), lhs_positions, rhs_positions)
+                    }
+                }
+            }
+        }
+        Strategy::Text => {
+            let lhs_positions =
+                line_parser::change_positions(lhs_src, rhs_src, word_split, ignore_case);
+            let rhs_positions =
+                line_parser::change_positions(rhs_src, lhs_src, word_split, ignore_case);
+            (prepared.api_lang_name.clone(), lhs_positions, rhs_positions)
+        }
+    };
+
+    if let Some((line, col)) = explain {
+        if explanations.is_empty() {
+            explanations.push(format!(
+                "No syntax node at line {} column {}. Only files diffed with a tree-sitter parser can be explained.",
+                line.one_indexed(),
+                col + 1
+            ));
+        }
+    }
+
+    // Ignored regions may still differ in length, which a text diff
+    // reports as novel blank lines.
+    let lhs_positions = suppress::remove_ignored(lhs_positions, &prepared.lhs_ignored);
+    let rhs_positions = suppress::remove_ignored(rhs_positions, &prepared.rhs_ignored);
+    let (lhs_src, rhs_src) = prepared
+        .unmasked_srcs
+        .unwrap_or((prepared.lhs_src, prepared.rhs_src));
+    let (lhs_offset_map, rhs_offset_map) = prepared.offset_maps;
+
+    DiffResult {
+        lhs_display_path: lhs_display_path.into(),
+        rhs_display_path: rhs_display_path.into(),
+        language: lang_name,
+        text_fallback,
+        encoding: prepared.encoding,
+        lhs_src: FileContent::Text(lhs_src),
+        rhs_src: FileContent::Text(rhs_src),
+        lhs_positions,
+        rhs_positions,
+        lhs_parse_errors,
+        rhs_parse_errors,
+        lhs_novel_ranges,
+        rhs_novel_ranges,
+        timed_out_regions,
+        lhs_definitions,
+        rhs_definitions,
+        moves,
+        copied: false,
+        status: prepared.truncated.map(FileStatus::Truncated),
+        profile: prepared.profile,
+        explanations,
+        signature_changes,
+        lhs_indentation: IndentationChanges::default(),
+        rhs_indentation: IndentationChanges::default(),
+        lhs_offsets: lhs_offset_map,
+        rhs_offsets: rhs_offset_map,
+    }
+}
+
+/// Mark the nodes in `nodes` that the search didn't reach as novel,
+/// and extend `lines` to cover them.
+fn mark_unreached<'a>(
+    nodes: &[&'a Syntax<'a>],
+    change_map: &mut ChangeMap<'a>,
+    lines: &mut Option<(LineNumber, LineNumber)>,
+) {
+    for &node in nodes {
+        if change_map.get(node).is_some() {
+            // We entered this list, but may not have reached all of
+            // its children.
+            if let Syntax::List { children, .. } = node {
+                mark_unreached(children, change_map, lines);
+            }
+            continue;
+        }
+
+        insert_deep_novel(node, change_map);
+        if let (Some(first), Some(last)) = (node.first_line(), node.last_line()) {
+            *lines = Some(match *lines {
+                Some((start, end)) => (start.min(first), end.max(last)),
+                None => (first, last),
+            });
+        }
+    }
+}
+
+fn overlaps_any(range: (LineNumber, LineNumber), ranges: &[(LineNumber, LineNumber)]) -> bool {
+    ranges
+        .iter()
+        .any(|(start, end)| range.0 <= *end && *start <= range.1)
+}
+
+/// Replace the positions on lines in `line_ranges` with the positions
+/// from a text diff.
+fn splice_text_diff(
+    positions: &mut Vec<MatchedPos>,
+    text_positions: &[MatchedPos],
+    line_ranges: &[(LineNumber, LineNumber)],
+) {
+    let inside = |mp: &MatchedPos| overlaps_any((mp.pos.line, mp.pos.line), line_ranges);
+    positions.retain(|mp| !inside(mp));
+    positions.extend(text_positions.iter().filter(|mp| inside(mp)).cloned());
+    positions.sort_by_key(|mp| mp.pos);
+}
+
+/// Given two trees that contain the files, such as directories,
+/// compare them pairwise. Returns an iterator, so we can print
+/// results incrementally.
+///
+/// When more than one file is modified, the hg extdiff extension passes directory
+/// paths with the all the modified files.
+fn diff_directories<'a>(
+    lhs_tree: &'a FileTree,
+    rhs_tree: &'a FileTree,
+    display_options: &DisplayOptions,
+    diff_options: &DiffOptions,
+) -> impl Iterator<Item = DiffResult> + 'a {
+    let display_options = display_options.clone();
+    let diff_options = diff_options.clone();
+
+    // We greedily list all files in the tree, and then diff them in
+    // parallel. This is assuming that diffing is slower than
+    // enumerating files, so it benefits more from parallelism.
+    let ignore = DifftIgnore::for_trees(lhs_tree, rhs_tree);
+    let paths = relative_paths_in_either(lhs_tree, rhs_tree, &ignore);
+    let copy_sources =
+        copies::find_copy_sources(lhs_tree, rhs_tree, &paths, diff_options.language_override);
+
+    diff_in_batches(paths, move |rel_path| {
+        info!("Relative path is {:?}", rel_path);
+
+        // Diff new files against the file they were copied from, if
+        // there is one.
+        let copied_from = copy_sources.get(&rel_path);
+        let lhs_rel_path = copied_from.unwrap_or(&rel_path);
+
+        let rhs_display_path = display_path(&rel_path);
+        let mut diff_result = recover_panic(&rhs_display_path, || {
+            diff_file_bytes(
+                &display_path(lhs_rel_path),
+                &rhs_display_path,
+                &lhs_tree.path(lhs_rel_path),
+                &rhs_tree.path(&rel_path),
+                &lhs_tree.read_or_die(lhs_rel_path),
+                &rhs_tree.read_or_die(&rel_path),
+                &display_options,
+                &diff_options,
+            )
+        })?;
+        diff_result.copied = copied_from.is_some();
+        ignore.apply_options(&rel_path, &mut diff_result.profile);
+        Some(diff_result)
+    })
+}
+
+/// Diff each pair of old and new paths, as listed by `--files-from`,
+/// in the order they were listed. Each pair has the options that
+/// `.difftignore` set for it.
+///
+/// A file that's missing from a pair of directories is always treated
+/// as empty, so it's shown as added or removed.
+fn diff_file_pairs(
+    pairs: Vec<FilePair>,
+    display_options: &DisplayOptions,
+    missing_as_empty: bool,
+    diff_options: &DiffOptions,
+) -> impl Iterator<Item = DiffResult> {
+    let display_options = display_options.clone();
+    let diff_options = diff_options.clone();
+
+    diff_in_batches(pairs, move |pair| {
+        let lhs_display_path = display_path(&pair.lhs_path);
+        let rhs_display_path = display_path(&pair.rhs_path);
+        let mut diff_result = recover_panic(&rhs_display_path, || {
+            diff_file(
+                &lhs_display_path,
+                &rhs_display_path,
+                &pair.lhs_path,
+                &pair.rhs_path,
+                &display_options,
+                missing_as_empty || pair.in_dir_pair,
+                &diff_options,
+            )
+        })?;
+        diff_result.profile.add(pair.profile);
+        Some(diff_result)
+    })
+}
+
+/// Call `diff_item` on each of `items` in parallel batches. This lets
+/// us start printing before we've diffed everything, but the results
+/// are always in the order of `items`.
+fn diff_in_batches<'a, T: Clone + Send + 'a>(
+    items: Vec<T>,
+    diff_item: impl Fn(T) -> Option<DiffResult> + Send + Sync + 'a,
+) -> impl Iterator<Item = DiffResult> + 'a {
+    let batches: Vec<Vec<T>> = items
+        .chunks(DIRECTORY_BATCH_SIZE)
+        .map(|batch| batch.to_vec())
+        .collect();
+
+    batches.into_iter().flat_map(move |batch| {
+        batch
+            .into_par_iter()
+            .filter_map(&diff_item)
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The number of files in a directory that we diff in parallel before
+/// printing them.
+const DIRECTORY_BATCH_SIZE: usize = 64;
+
+/// Call `f`, printing an error rather than exiting if it panics. This
+/// ensures a bug triggered by one file doesn't stop us diffing the
+/// other files.
+fn recover_panic<T>(display_path: &str, f: impl FnOnce() -> T) -> Option<T> {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(res) => Some(res),
+        Err(_) => {
+            eprintln!(
+                "error: Difftastic crashed when diffing {}, skipping it. Please report this bug.\n",
+                display_path
+            );
+            None
+        }
+    }
+}
+
+/// Compare every pair of files in `paths`, and print them as columns.
+fn diff_many(paths: &[&Path], display_options: &DisplayOptions, diff_options: &DiffOptions) {
+    let all_bytes: Vec<Vec<u8>> = paths.iter().map(|path| read_or_die(path)).collect();
+    if all_bytes
+        .iter()
+        .any(|bytes| is_probably_binary(bytes, diff_options.encoding_override))
+    {
+        eprintln!("error: Binary files cannot be compared with more than two files at once.");
+        std::process::exit(1);
+    }
+
+    let display_paths: Vec<String> = paths.iter().map(|p| display_path(p)).collect();
+    let srcs: Vec<String> = all_bytes
+        .iter()
+        .map(|bytes| {
+            clean_src(
+                bytes,
+                display_options.tab_width,
+                diff_options.strip_input_ansi,
+                diff_options.encoding_override,
+            )
+            .0
+        })
+        .collect();
+
+    // We show every file in the same table, so they can't have
+    // per-file tab widths or encodings.
+    let settings = FileSettings {
+        tab_width: display_options.tab_width,
+        encoding_override: diff_options.encoding_override,
+        language_hint: diff_options.language_override,
+    };
+
+    // This is quadratic in the number of files, but we only allow a
+    // handful of files.
+    let pairs: Vec<(usize, usize)> = (0..paths.len())
+        .flat_map(|i| (i + 1..paths.len()).map(move |j| (i, j)))
+        .collect();
+    let results: Vec<DiffResult> = pairs
+        .par_iter()
+        .map(|(i, j)| {
+            diff_buffers(
+                &Buffer {
+                    display_path: &display_paths[*i],
+                    path: Some(paths[*i]),
+                    language_hint: diff_options.language_override,
+                    content: &all_bytes[*i],
+                },
+                &Buffer {
+                    display_path: &display_paths[*j],
+                    path: Some(paths[*j]),
+                    language_hint: diff_options.language_override,
+                    content: &all_bytes[*j],
+                },
+                &settings,
+                diff_options,
+            )
+        })
+        .collect();
+
+    let lang_name = results
+        .get(0)
+        .and_then(|result| result.language.clone())
+        .unwrap_or_else(|| "Text".into());
+
+    // pairwise_positions[i][j] is the positions in file i when
+    // compared with file j.
+    let mut pairwise_positions: Vec<Vec<Vec<MatchedPos>>> =
+        vec![vec![vec![]; paths.len()]; paths.len()];
+    for ((i, j), result) in pairs.into_iter().zip(results) {
+        pairwise_positions[i][j] = result.lhs_positions;
+        pairwise_positions[j][i] = result.rhs_positions;
+    }
+
+    display::multi_pane::print(
+        &display_paths,
+        &srcs,
+        &pairwise_positions,
+        &lang_name,
+        display_options,
+    );
+}
+
+fn print_parse_errors(summary: &DiffResult) {
+    for (path, side, errors) in [
+        (&summary.lhs_display_path, "old", summary.lhs_parse_errors),
+        (&summary.rhs_display_path, "new", summary.rhs_parse_errors),
+    ] {
+        if let Some(errors) = errors {
+            warnings::warn(format!(
+                "{} ({}): {} parse error{} ({:.1}% of file)",
+                path,
+                side,
+                errors.count,
+                if errors.count == 1 { "" } else { "s" },
+                errors.ratio * 100.0
+            ));
+        }
+    }
+}
+
+fn print_diff_result(display_options: &DisplayOptions, summary: &DiffResult) {
+    // Explanations are debugging output, so they go to stderr and
+    // don't interfere with JSON or HTML output.
+    for explanation in &summary.explanations {
+        eprintln!("{}\n", explanation);
+    }
+
+    let profile = if display_options.use_profiles {
+        summary.profile
+    } else {
+        Profile::default()
+    };
+    let display_options = &DisplayOptions {
+        summary_only: display_options.summary_only || profile.summary_only,
+        collapse_definitions: display_options.collapse_definitions && !profile.expand_definitions,
+        ..display_options.clone()
+    };
+
+    // Profiles don't apply to churn metrics, so every file gets a row.
+    if let DisplayMode::ChurnCsv = display_options.display_mode {
+        display::churn::print(display_options, summary);
+        return;
+    }
+
+    if let DisplayMode::Json = display_options.display_mode {
+        display::json::print(display_options, summary);
+        return;
+    }
+    if let DisplayMode::Html = display_options.display_mode {
+        display::html::print(display_options, summary);
+        return;
+    }
+    if let DisplayMode::Markdown = display_options.display_mode {
+        display::markdown::print(display_options, summary);
+        return;
+    }
+    if let DisplayMode::RawSpans = display_options.display_mode {
+        display::raw_spans::print(display_options, summary);
+        return;
+    }
+    // diff_file has already written the patches.
+    if let DisplayMode::StageScript = display_options.display_mode {
+        return;
+    }
+
+    // Summaries are plain text, so machine-readable formats always
+    // get the full diff.
+    if display_options.summary_only {
+        display::stat::print_summary(summary);
+        return;
+    }
+
+    if profile.generated && display_options.collapse_generated && summary.lhs_src != summary.rhs_src
+    {
+        println!(
+            "{}\n",
+            display::style::generated_file_changed(&summary.rhs_display_path, display_options)
+        );
+        return;
+    }
+
+    if let Some(status) = summary.status {
+        println!(
+            "{}",
+            display::style::file_status(status, &summary.rhs_display_path, display_options)
+        );
+    }
+
+    if summary.copied {
+        println!(
+            "{}",
+            display::style::copied_from(
+                &summary.lhs_display_path,
+                &summary.rhs_display_path,
+                display_options
+            )
+        );
+    }
+
+    if display_options.report_parse_errors {
+        print_parse_errors(summary);
+    }
+
+    match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => {
+            let hunks_start = phase_start();
+            let mut hunks = file_hunks(
+                lhs_src,
+                rhs_src,
+                &summary.lhs_positions,
+                &summary.rhs_positions,
+            );
+            sort_hunks(
+                &mut hunks,
+                display_options.hunk_order,
+                &summary.lhs_positions,
+                &summary.rhs_positions,
+            );
+            log_phase("hunks", &summary.rhs_display_path, hunks_start);
+
+            let lang_name = summary.language.clone().unwrap_or_else(|| "Text".into());
+            // Show the encoding alongside the language in headers.
+            let header_name = match &summary.encoding {
+                Some(encoding) => format!("{} ({})", lang_name, encoding),
+                None => lang_name.clone(),
+            };
+            if hunks.is_empty() {
+                if display_options.print_unchanged {
+                    println!(
+                        "{}",
+                        display::style::header(
+                            &summary.lhs_display_path,
+                            &summary.rhs_display_path,
+                            1,
+                            1,
+                            &header_name,
+                            None,
+                            display_options
+                        )
+                    );
+                    if lang_name == "Text" || summary.lhs_src == summary.rhs_src {
+                        // TODO: there are other Text names now, so
+                        // they will hit the second case incorrectly.
+                        println!("{}\n", tr(Message::NoChanges));
+                    } else {
+                        println!("{}\n", tr(Message::NoSyntacticChanges));
+                    }
+                }
+                return;
+            }
+
+            // Added and removed files are shown in full, so there's
+            // nothing to group.
+            let grouped =
+                display_options.group_by_definition && !lhs_src.is_empty() && !rhs_src.is_empty();
+            let groups = if grouped {
+                group_by_definition(&hunks, &summary.lhs_definitions, &summary.rhs_definitions)
+            } else {
+                vec![(None, hunks)]
+            };
+
+            // Number hunks consecutively across all the groups, rather
+            // than restarting in each group.
+            let total = groups.iter().map(|(_, hunks)| hunks.len()).sum();
+            let mut first = 0;
+
+            let render_start = phase_start();
+            for (definition, hunks) in groups {
+                let numbering = HunkNumbering { first, total };
+                first += hunks.len();
+
+                if grouped {
+                    println!(
+                        "{}",
+                        display::style::definition_header(
+                            definition,
+                            hunks.len(),
+                            display_options.use_color
+                        )
+                    );
+                }
+
+                match display_options.display_mode {
+                    DisplayMode::Inline => {
+                        display::inline::print(
+                            lhs_src,
+                            rhs_src,
+                            display_options,
+                            &summary.lhs_positions,
+                            &summary.rhs_positions,
+                            &hunks,
+                            numbering,
+                            &summary.lhs_display_path,
+                            &summary.rhs_display_path,
+                            &header_name,
+                            &summary.lhs_novel_ranges,
+                            &summary.rhs_novel_ranges,
+                            &summary.moves,
+                            &summary.signature_changes,
+                            &summary.timed_out_regions,
+                            &summary.lhs_indentation,
+                            &summary.rhs_indentation,
+                        );
+                    }
+                    DisplayMode::SideBySide
+                    | DisplayMode::SideBySideShowBoth
+                    | DisplayMode::LhsOnly
+                    | DisplayMode::RhsOnly => {
+                        display::side_by_side::print(
+                            &hunks,
+                            numbering,
+                            display_options,
+                            &summary.lhs_display_path,
+                            &summary.rhs_display_path,
+                            &header_name,
+                            lhs_src,
+                            rhs_src,
+                            &summary.lhs_positions,
+                            &summary.rhs_positions,
+                            &summary.lhs_novel_ranges,
+                            &summary.rhs_novel_ranges,
+                            &summary.rhs_definitions,
+                            &summary.moves,
+                            &summary.signature_changes,
+                            &summary.timed_out_regions,
+                            &summary.lhs_indentation,
+                            &summary.rhs_indentation,
+                        );
+                    }
+                    DisplayMode::Json
+                    | DisplayMode::Html
+                    | DisplayMode::Markdown
+                    | DisplayMode::RawSpans
+                    | DisplayMode::ChurnCsv
+                    | DisplayMode::StageScript => {
+                        unreachable!(
+                            "JSON, HTML, Markdown, raw spans, churn and stage script output is handled above"
+                        )
+                    }
+                }
+            }
+            log_phase("render", &summary.rhs_display_path, render_start);
+        }
+        (FileContent::Binary(lhs_bytes), FileContent::Binary(rhs_bytes)) => {
+            let changed = lhs_bytes != rhs_bytes;
+            if display_options.print_unchanged || changed {
+                println!(
+                    "{}",
+                    display::style::header(
+                        &summary.lhs_display_path,
+                        &summary.rhs_display_path,
+                        1,
+                        1,
+                        "binary",
+                        None,
+                        display_options
+                    )
+                );
+                if changed {
+                    println!("{}", tr(Message::BinaryContentsChanged));
+                } else {
+                    println!("{}", tr(Message::NoChanges));
+                }
+            }
+        }
+        (_, FileContent::Binary(_)) | (FileContent::Binary(_), _) => {
+            // We're diffing a binary file against a text file.
+            println!(
+                "{}",
+                display::style::header(
+                    &summary.lhs_display_path,
+                    &summary.rhs_display_path,
+                    1,
+                    1,
+                    "binary",
+                    None,
+                    display_options
+                )
+            );
+            println!("{}", tr(Message::BinaryContentsChanged));
+        }
+    }
+}
+
+/// What is the total number of nodes in `roots`?
+fn num_nodes(roots: &[&syntax::Syntax]) -> u32 {
+    roots
+        .iter()
+        .map(|n| {
+            1 + match n {
+                syntax::Syntax::List {
+                    num_descendants, ..
+                } => *num_descendants,
+                syntax::Syntax::Atom { .. } => 0,
+            }
+        })
+        .sum()
+}
+
+fn max_num_nodes(roots_vec: &[(Vec<&syntax::Syntax>, Vec<&syntax::Syntax>)]) -> u32 {
+    roots_vec
+        .iter()
+        .map(|(lhs, rhs)| num_nodes(lhs) + num_nodes(rhs))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::DEFAULT_TAB_WIDTH;
+
+    #[test]
+    fn test_diff_identical_content() {
+        let s = "foo";
+        let buffer = Buffer {
+            display_path: "foo.el",
+            path: None,
+            language_hint: None,
+            content: s.as_bytes(),
+        };
+        let res = diff_file_content(&buffer, &buffer, DEFAULT_TAB_WIDTH, &DiffOptions::default());
+
+        assert_eq!(res.lhs_positions, vec![]);
+        assert_eq!(res.rhs_positions, vec![]);
+    }
+
+    /// The zero-indexed lines with novel content.
+    #[cfg(any(feature = "lang-c", feature = "lang-python"))]
+    fn novel_lines(positions: &[MatchedPos]) -> Vec<usize> {
+        let mut lines: Vec<usize> = positions
+            .iter()
+            .filter(|mp| mp.kind.is_novel())
+            .map(|mp| mp.pos.line.0)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Diff the sample files `before` and `after`.
+    #[cfg(any(feature = "lang-c", feature = "lang-python"))]
+    fn diff_sample(display_path: &str, before: &[u8], after: &[u8]) -> DiffResult {
+        let buffer = |content| Buffer {
+            display_path,
+            path: None,
+            language_hint: None,
+            content,
+        };
+        diff_file_content(
+            &buffer(before),
+            &buffer(after),
+            DEFAULT_TAB_WIDTH,
+            &DiffOptions::default(),
+        )
+    }
+
+    #[cfg(feature = "lang-python")]
+    #[test]
+    fn test_duplicate_rows_sample() {
+        let res = diff_sample(
+            "duplicate_rows_after.py",
+            include_bytes!("../sample_files/duplicate_rows_before.py"),
+            include_bytes!("../sample_files/duplicate_rows_after.py"),
+        );
+        assert_eq!(novel_lines(&res.lhs_positions), Vec::<usize>::new());
+        // The new row, and possibly the comma before it.
+        let rhs_novel = novel_lines(&res.rhs_positions);
+        assert!(rhs_novel.contains(&5));
+        assert!(rhs_novel.iter().all(|line| *line == 4 || *line == 5));
+    }
+
+    #[cfg(feature = "lang-c")]
+    #[test]
+    fn test_duplicate_cases_sample() {
+        let res = diff_sample(
+            "duplicate_cases_after.c",
+            include_bytes!("../sample_files/duplicate_cases_before.c"),
+            include_bytes!("../sample_files/duplicate_cases_after.c"),
+        );
+        assert_eq!(novel_lines(&res.lhs_positions), Vec::<usize>::new());
+        assert_eq!(novel_lines(&res.rhs_positions), vec![8, 9]);
+    }
+
+    #[test]
+    fn test_modeline_tab_width() {
+        let buffer = Buffer {
+            display_path: "foo.txt",
+            path: None,
+            language_hint: None,
+            content: b"# difft: tab-width=4\n",
+        };
+        // Mode lines apply even when .editorconfig is disabled.
+        let diff_options = DiffOptions::default();
+        assert!(!diff_options.editorconfig.tab_width);
+        let settings = file_settings(&buffer, &buffer, DEFAULT_TAB_WIDTH, &diff_options);
+        assert_eq!(settings.tab_width, 4);
+
+        // An explicit --tab-width takes precedence.
+        let diff_options = DiffOptions {
+            modeline_tab_width: false,
+            ..DiffOptions::default()
+        };
+        let settings = file_settings(&buffer, &buffer, 2, &diff_options);
+        assert_eq!(settings.tab_width, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_diff_file_dev_null() {
+        let path =
+            std::env::temp_dir().join(format!("difft-test-added-{}.txt", std::process::id()));
+        std::fs::write(&path, "foo\n").unwrap();
+        let dev_null = Path::new("/dev/null");
+        let display_options = DisplayOptions::default();
+        let diff_options = DiffOptions::default();
+
+        let added = diff_file(
+            "/dev/null",
+            "foo.txt",
+            dev_null,
+            &path,
+            &display_options,
+            false,
+            &diff_options,
+        );
+        let deleted = diff_file(
+            "foo.txt",
+            "/dev/null",
+            &path,
+            dev_null,
+            &display_options,
+            false,
+            &diff_options,
+        );
+        let changed = diff_file(
+            "foo.txt",
+            "foo.txt",
+            &path,
+            &path,
+            &display_options,
+            false,
+            &diff_options,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // The real path is shown on both sides, rather than /dev/null.
+        assert_eq!(added.status, Some(FileStatus::Added));
+        assert_eq!(added.lhs_display_path, "foo.txt");
+        assert_eq!(added.rhs_display_path, "foo.txt");
+
+        assert_eq!(deleted.status, Some(FileStatus::Deleted));
+        assert_eq!(deleted.lhs_display_path, "foo.txt");
+        assert_eq!(deleted.rhs_display_path, "foo.txt");
+
+        assert_eq!(changed.status, None);
+        // Language detection also uses the real path.
+        assert_eq!(added.language, changed.language);
+        assert_eq!(deleted.language, changed.language);
+    }
+
+    #[test]
+    fn test_diff_directory_against_snapshot() {
+        let dir = std::env::temp_dir().join(format!("difft-test-tree-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("same.txt"), "foo\n").unwrap();
+        std::fs::write(dir.join("changed.txt"), "foo\n").unwrap();
+        let lhs_tree = FileTree::Dir(dir.clone());
+
+        let mut files = std::collections::BTreeMap::new();
+        files.insert(PathBuf::from("same.txt"), b"foo\n".to_vec());
+        files.insert(PathBuf::from("changed.txt"), b"bar\n".to_vec());
+        files.insert(PathBuf::from("added.txt"), b"baz\n".to_vec());
+        let rhs_tree = FileTree::Snapshot(files);
+
+        let results: Vec<(String, bool)> = diff_directories(
+            &lhs_tree,
+            &rhs_tree,
+            &DisplayOptions::default(),
+            &DiffOptions::default(),
+        )
+        .map(|diff_result| {
+            (
+                diff_result.rhs_display_path.clone(),
+                assert_equal::is_equal(&diff_result, &[]),
+            )
+        })
+        .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                ("added.txt".to_owned(), false),
+                ("changed.txt".to_owned(), false),
+                ("same.txt".to_owned(), true),
+            ]
+        );
+    }
+}
@@ -1,6 +1,9 @@
 //! A fallback "parser" for plain text.
 
-use std::hash::Hash;
+use std::{
+    borrow::Cow,
+    hash::{Hash, Hasher},
+};
 
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -12,6 +15,58 @@ use crate::{
     parse::syntax::{split_words, AtomKind, MatchKind, MatchedPos, TokenKind},
 };
 
+/// Extra rules for splitting lines into words when diffing text. By
+/// default, a word is a run of alphanumeric characters and every
+/// other character is a word of its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WordSplit {
+    /// Split `fooBar` into `foo` and `Bar`.
+    pub case_transitions: bool,
+    /// Split `abc123` into `abc` and `123`.
+    pub digits: bool,
+    /// Keep runs of punctuation, such as `->` or `::`, together.
+    pub punctuation_runs: bool,
+}
+
+fn is_word_boundary(prev: char, c: char, word_split: WordSplit) -> bool {
+    match (prev.is_alphanumeric(), c.is_alphanumeric()) {
+        (true, true) => {
+            (word_split.digits && prev.is_numeric() != c.is_numeric())
+                || (word_split.case_transitions && prev.is_lowercase() && c.is_uppercase())
+        }
+        (false, false) => {
+            !(word_split.punctuation_runs
+                && prev.is_ascii_punctuation()
+                && c.is_ascii_punctuation())
+        }
+        _ => true,
+    }
+}
+
+fn split_text_words(s: &str, word_split: WordSplit) -> Vec<&str> {
+    if word_split == WordSplit::default() {
+        return split_words(s);
+    }
+
+    let mut res = vec![];
+    let mut word_start = 0;
+    let mut prev = None;
+    for (idx, c) in s.char_indices() {
+        if let Some(prev) = prev {
+            if is_word_boundary(prev, c, word_split) {
+                res.push(&s[word_start..idx]);
+                word_start = idx;
+            }
+        }
+        prev = Some(c);
+    }
+
+    if word_start < s.len() {
+        res.push(&s[word_start..]);
+    }
+    res
+}
+
 fn split_lines_keep_newline(s: &str) -> Vec<&str> {
     lazy_static! {
         static ref NEWLINE_RE: Regex = Regex::new("\n").unwrap();
@@ -31,6 +86,42 @@ fn split_lines_keep_newline(s: &str) -> Vec<&str> {
     res
 }
 
+/// A line or word that we're diffing. Tokens are compared by `key`,
+/// which has its case folded if we're ignoring case.
+#[derive(Debug, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    key: Cow<'a, str>,
+}
+
+impl PartialEq for Token<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Token<'_> {}
+
+impl Hash for Token<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+fn tokens(texts: Vec<&str>, ignore_case: bool) -> Vec<Token> {
+    texts
+        .into_iter()
+        .map(|text| Token {
+            text,
+            key: if ignore_case {
+                Cow::Owned(text.to_lowercase())
+            } else {
+                Cow::Borrowed(text)
+            },
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 enum TextChangeKind {
     Novel,
@@ -78,7 +169,7 @@ fn merge_novel<'a>(
 ///
 /// This is the decorate-sort-undecorate pattern, or Schwartzian
 /// transform, for diffing.
-fn diff_slice_by_hash<'a, T: Eq + Hash>(
+pub fn diff_slice_by_hash<'a, T: Eq + Hash>(
     lhs: &'a [T],
     rhs: &'a [T],
 ) -> Vec<myers_diff::DiffResult<&'a T>> {
@@ -133,21 +224,26 @@ fn diff_slice_by_hash<'a, T: Eq + Hash>(
 fn changed_parts<'a>(
     src: &'a str,
     opposite_src: &'a str,
+    ignore_case: bool,
 ) -> Vec<(TextChangeKind, Vec<&'a str>, Vec<&'a str>)> {
-    let src_lines = split_lines_keep_newline(src);
-    let opposite_src_lines = split_lines_keep_newline(opposite_src);
+    let src_lines = tokens(split_lines_keep_newline(src), ignore_case);
+    let opposite_src_lines = tokens(split_lines_keep_newline(opposite_src), ignore_case);
 
     let mut res: Vec<(TextChangeKind, Vec<&'a str>, Vec<&'a str>)> = vec![];
     for diff_res in diff_slice_by_hash(&src_lines, &opposite_src_lines) {
         match diff_res {
             myers_diff::DiffResult::Left(line) => {
-                res.push((TextChangeKind::Novel, vec![line], vec![]));
+                res.push((TextChangeKind::Novel, vec![line.text], vec![]));
             }
             myers_diff::DiffResult::Both(line, opposite_line) => {
-                res.push((TextChangeKind::Unchanged, vec![line], vec![opposite_line]));
+                res.push((
+                    TextChangeKind::Unchanged,
+                    vec![line.text],
+                    vec![opposite_line.text],
+                ));
             }
             myers_diff::DiffResult::Right(opposite_line) => {
-                res.push((TextChangeKind::Novel, vec![], vec![opposite_line]));
+                res.push((TextChangeKind::Novel, vec![], vec![opposite_line.text]));
             }
         }
     }
@@ -165,7 +261,14 @@ fn line_len_in_bytes(line: &str) -> usize {
 }
 
 // TODO: Prefer src/opposite_src nomenclature as this function is called from both sides.
-pub fn change_positions(lhs_src: &str, rhs_src: &str) -> Vec<MatchedPos> {
+/// If `ignore_case` is set, lines and words that only differ in case
+/// are treated as unchanged.
+pub fn change_positions(
+    lhs_src: &str,
+    rhs_src: &str,
+    word_split: WordSplit,
+    ignore_case: bool,
+) -> Vec<MatchedPos> {
     // TODO: If either side is "", don't split each line by words
     // pointlessly. This is common for file additions/removals.
     let lhs_nlp = NewlinePositions::from(lhs_src);
@@ -175,7 +278,7 @@ pub fn change_positions(lhs_src: &str, rhs_src: &str) -> Vec<MatchedPos> {
     let mut rhs_offset = 0;
 
     let mut res = vec![];
-    for (kind, lhs_lines, rhs_lines) in changed_parts(lhs_src, rhs_src) {
+    for (kind, lhs_lines, rhs_lines) in changed_parts(lhs_src, rhs_src, ignore_case) {
         match kind {
             TextChangeKind::Unchanged => {
                 for (lhs_line, rhs_line) in lhs_lines.iter().zip(rhs_lines) {
@@ -201,10 +304,12 @@ pub fn change_positions(lhs_src: &str, rhs_src: &str) -> Vec<MatchedPos> {
                 let lhs_part = lhs_lines.join("");
                 let rhs_part = rhs_lines.join("");
 
-                for diff_res in myers_diff::slice(&split_words(&lhs_part), &split_words(&rhs_part))
-                {
+                for diff_res in myers_diff::slice(
+                    &tokens(split_text_words(&lhs_part, word_split), ignore_case),
+                    &tokens(split_text_words(&rhs_part, word_split), ignore_case),
+                ) {
                     match diff_res {
-                        myers_diff::DiffResult::Left(lhs_word) => {
+                        myers_diff::DiffResult::Left(Token { text: lhs_word, .. }) => {
                             if *lhs_word != "\n" {
                                 let lhs_pos =
                                     lhs_nlp.from_offsets(lhs_offset, lhs_offset + lhs_word.len());
@@ -218,7 +323,10 @@ pub fn change_positions(lhs_src: &str, rhs_src: &str) -> Vec<MatchedPos> {
 
                             lhs_offset += lhs_word.len();
                         }
-                        myers_diff::DiffResult::Both(lhs_word, rhs_word) => {
+                        myers_diff::DiffResult::Both(
+                            Token { text: lhs_word, .. },
+                            Token { text: rhs_word, .. },
+                        ) => {
                             if *lhs_word != "\n" {
                                 let lhs_pos =
                                     lhs_nlp.from_offsets(lhs_offset, lhs_offset + lhs_word.len());
@@ -238,7 +346,7 @@ pub fn change_positions(lhs_src: &str, rhs_src: &str) -> Vec<MatchedPos> {
                             lhs_offset += lhs_word.len();
                             rhs_offset += rhs_word.len();
                         }
-                        myers_diff::DiffResult::Right(rhs_word) => {
+                        myers_diff::DiffResult::Right(Token { text: rhs_word, .. }) => {
                             rhs_offset += rhs_word.len();
                         }
                     }
@@ -257,6 +365,23 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn test_split_text_words() {
+        let word_split = WordSplit {
+            case_transitions: true,
+            digits: true,
+            punctuation_runs: true,
+        };
+        assert_eq!(
+            split_text_words("fooBar2 -> x", word_split),
+            vec!["foo", "Bar", "2", " ", "->", " ", "x"]
+        );
+        assert_eq!(
+            split_text_words("fooBar2", WordSplit::default(), false),
+            vec!["fooBar2"]
+        );
+    }
+
     #[test]
     fn test_split_newlines() {
         let s = "foo\nbar\nbaz";
@@ -266,7 +391,7 @@ mod tests {
 
     #[test]
     fn test_positions_no_changes() {
-        let positions = change_positions("foo", "foo");
+        let positions = change_positions("foo", "foo", WordSplit::default(), false);
 
         assert_eq!(positions.len(), 1);
         assert!(!positions[0].kind.is_novel());
@@ -285,13 +410,13 @@ mod tests {
         // Even though the word exists on both sides, it should still
         // be treated as a change. We're doing a line-based diff and
         // the lines are different.
-        let positions = change_positions("foo", " foo");
+        let positions = change_positions("foo", " foo", WordSplit::default(), false);
         assert!(positions[0].kind.is_novel());
     }
 
     #[test]
     fn test_no_changes_trailing_newlines() {
-        let positions = change_positions("foo\n", "foo\n");
+        let positions = change_positions("foo\n", "foo\n", WordSplit::default(), false);
 
         assert_eq!(positions.len(), 1);
         assert!(!positions[0].kind.is_novel());
@@ -307,7 +432,7 @@ mod tests {
 
     #[test]
     fn test_novel_lhs_trailing_newlines() {
-        let positions = change_positions("foo\n", "");
+        let positions = change_positions("foo\n", "", WordSplit::default(), false);
 
         assert_eq!(positions.len(), 1);
         assert!(positions[0].kind.is_novel());
@@ -315,9 +440,30 @@ mod tests {
 
     #[test]
     fn test_positions_novel_lhs() {
-        let positions = change_positions("foo", "");
+        let positions = change_positions("foo", "", WordSplit::default(), false);
 
         assert_eq!(positions.len(), 1);
         assert!(positions[0].kind.is_novel());
     }
+
+    #[test]
+    fn test_positions_ignore_case() {
+        let positions = change_positions("SELECT 1\n", "select 1\n", WordSplit::default(), true);
+        assert_eq!(positions.len(), 1);
+        assert!(!positions[0].kind.is_novel());
+
+        let positions = change_positions("SELECT 1\n", "select 1\n", WordSplit::default(), false);
+        assert!(positions.iter().any(|p| p.kind.is_novel()));
+    }
+
+    #[test]
+    fn test_positions_ignore_case_in_changed_line() {
+        let positions = change_positions("SELECT 1\n", "select 2\n", WordSplit::default(), true);
+        let novel: Vec<_> = positions
+            .iter()
+            .filter(|p| matches!(p.kind, MatchKind::NovelWord { .. }))
+            .map(|p| (p.pos.start_col, p.pos.end_col))
+            .collect();
+        assert_eq!(novel, vec![(7, 8)]);
+    }
 }
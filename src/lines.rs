@@ -141,6 +141,12 @@ impl NewlinePositions {
 
         res
     }
+
+    /// The byte offsets of the start and end of `line`, excluding the
+    /// newline.
+    pub fn line_offsets(&self, line: LineNumber) -> (usize, usize) {
+        self.positions[line.0]
+    }
 }
 
 /// Return the length of `s` in codepoints. This is important when
@@ -177,6 +183,13 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn test_line_offsets() {
+        let newline_positions: NewlinePositions = "foo\nbar baz\n".into();
+        assert_eq!(newline_positions.line_offsets(0.into()), (0, 3));
+        assert_eq!(newline_positions.line_offsets(1.into()), (4, 11));
+    }
+
     #[test]
     fn from_offsets_first_line() {
         let newline_positions: NewlinePositions = "foo".into();
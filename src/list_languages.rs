@@ -0,0 +1,81 @@
+//! Describing the languages that difftastic supports, for
+//! `--list-languages`.
+//!
+//! This is generated from the extension table and the parsers that
+//! were compiled in, so it can't drift from what difftastic actually
+//! does.
+
+use crate::parse::{guess_language::LANGUAGE_EXTENSIONS, tree_sitter_parser as tsp};
+
+/// One row per language: the name, its extensions, and whether its
+/// parser is available.
+fn rows() -> Vec<(String, String, String)> {
+    LANGUAGE_EXTENSIONS
+        .iter()
+        .map(|(language, extensions)| {
+            let extensions = extensions
+                .iter()
+                .map(|e| format!(".{}", e))
+                .collect::<Vec<_>>()
+                .join(" ");
+            match tsp::from_language(*language) {
+                Some(config) => (
+                    config.name.to_owned(),
+                    extensions,
+                    format!("built in (ABI {})", config.language.version()),
+                ),
+                None => (
+                    format!("{:?}", language),
+                    extensions,
+                    "not compiled in".to_owned(),
+                ),
+            }
+        })
+        .collect()
+}
+
+/// A table of every supported language.
+pub fn render() -> String {
+    let rows = rows();
+    let name_width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let status_width = rows
+        .iter()
+        .map(|(_, _, status)| status.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut res = String::new();
+    for (name, extensions, status) in rows {
+        res.push_str(&format!(
+            "{:name_width$}  {:status_width$}  {}\n",
+            name,
+            status,
+            extensions,
+            name_width = name_width,
+            status_width = status_width
+        ));
+    }
+    res.push_str(
+        "\nFiles are also detected by name (e.g. Gemfile), shebang and Emacs/Vim modelines.\n",
+    );
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_lists_extensions() {
+        let table = render();
+        assert!(table.lines().any(|line| line.ends_with(".rs")));
+        assert_eq!(
+            table.lines().filter(|l| !l.is_empty()).count(),
+            LANGUAGE_EXTENSIONS.len() + 1
+        );
+    }
+}
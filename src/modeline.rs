@@ -0,0 +1,92 @@
+//! Per-file options from a `difft:` comment, such as
+//!
+//! ```text
+//! # difft: tab-width=4 language=ruby
+//! ```
+//!
+//! This is useful for templates and embedded DSLs, where the file
+//! extension doesn't say how the file should be parsed.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::parse::guess_language::{self, Language};
+
+/// The options set by a mode line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modeline {
+    pub tab_width: Option<usize>,
+    pub language: Option<Language>,
+}
+
+/// Parse the `key=value` settings after `difft:` in `line`. Returns
+/// `Ok(None)` if there's no mode line.
+fn parse_line(line: &str) -> Result<Option<Modeline>, String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\bdifft:((?:\s+[a-z-]+=\S+)+)").unwrap();
+    }
+
+    let cap = match RE.captures(line) {
+        Some(cap) => cap,
+        None => return Ok(None),
+    };
+
+    let mut modeline = Modeline::default();
+    for setting in cap[1].split_whitespace() {
+        let (key, value) = setting.split_at(setting.find('=').expect("Matched by regex"));
+        let value = &value[1..];
+        match key {
+            "tab-width" => match value.parse::<usize>() {
+                Ok(tab_width) if tab_width > 0 => modeline.tab_width = Some(tab_width),
+                _ => return Err(format!("Invalid tab-width in mode line: {}", value)),
+            },
+            "language" => match guess_language::from_extension_or_name(value) {
+                Some(language) => modeline.language = Some(language),
+                None => return Err(format!("Unknown language in mode line: {}", value)),
+            },
+            _ => return Err(format!("Unknown mode line option: {}", key)),
+        }
+    }
+    Ok(Some(modeline))
+}
+
+/// Find the mode line in `bytes`. This must be on the first line, or
+/// on the second line if the first line is a shebang.
+pub fn find(bytes: &[u8]) -> Result<Option<Modeline>, String> {
+    let src = String::from_utf8_lossy(&bytes[..bytes.len().min(1000)]);
+    for (i, line) in src.lines().take(2).enumerate() {
+        if let Some(modeline) = parse_line(line)? {
+            return Ok(Some(modeline));
+        }
+        if i == 0 && !line.starts_with("#!") {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_find() {
+        assert_eq!(
+            find(b"<%# difft: tab-width=4 language=ruby %>\n"),
+            Ok(Some(Modeline {
+                tab_width: Some(4),
+                language: Some(Language::Ruby),
+            }))
+        );
+        assert_eq!(
+            find(b"#!/bin/sh\n# difft: language=bash\n"),
+            Ok(Some(Modeline {
+                tab_width: None,
+                language: Some(Language::Bash),
+            }))
+        );
+        assert_eq!(find(b"x = 1\n# difft: language=rb\n"), Ok(None));
+        assert!(find(b"// difft: colour=red\n").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Mapping byte offsets in the text we diff back to the original
+//! file.
+//!
+//! Before diffing, we decode each file to UTF-8, expand tabs to
+//! spaces and optionally strip ANSI escapes. Offsets in the cleaned
+//! text differ from the file on disk after any tab, and throughout
+//! files that aren't UTF-8, so `--show-offsets` converts them with an
+//! [`OffsetMap`].
+
+use cansi::categorise_text;
+use encoding_rs::{Encoding, UTF_8};
+
+/// A run of cleaned text starting at `clean`, which came from the
+/// original bytes starting at `original`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    clean: usize,
+    original: usize,
+    /// Does each byte in the run correspond to one original byte?
+    /// Otherwise the run replaced the original bytes, e.g. a tab
+    /// expanded to spaces, and every offset inside it maps to
+    /// `original`.
+    one_to_one: bool,
+}
+
+/// Converts byte offsets in cleaned text to byte offsets in the
+/// original file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    /// Segments sorted by `clean`. Offsets before the first segment
+    /// are unchanged.
+    segments: Vec<Segment>,
+}
+
+impl OffsetMap {
+    /// The byte offset in the original file of `offset` in the
+    /// cleaned text.
+    pub fn original(&self, offset: usize) -> usize {
+        let i = self.segments.partition_point(|s| s.clean <= offset);
+        match i.checked_sub(1).map(|i| self.segments[i]) {
+            Some(s) if s.one_to_one => s.original + (offset - s.clean),
+            Some(s) => s.original,
+            None => offset,
+        }
+    }
+
+    fn push(&mut self, segment: Segment) {
+        if let Some(last) = self.segments.last() {
+            let continues = if last.one_to_one {
+                segment.one_to_one
+                    && segment.original >= last.original
+                    && segment.clean - last.clean == segment.original - last.original
+            } else {
+                !segment.one_to_one && segment.original == last.original
+            };
+            if continues {
+                return;
+            }
+        }
+        self.segments.push(segment);
+    }
+
+    /// Append `c`, which came from `original_len` bytes at `original`,
+    /// to `src`.
+    fn push_char(
+        &mut self,
+        src: &mut String,
+        c: char,
+        original: usize,
+        original_len: usize,
+        tab_width: usize,
+    ) {
+        if c == '\t' {
+            self.push(Segment {
+                clean: src.len(),
+                original,
+                one_to_one: tab_width == 1,
+            });
+            src.push_str(&" ".repeat(tab_width));
+        } else {
+            self.push(Segment {
+                clean: src.len(),
+                original,
+                one_to_one: c.len_utf8() == original_len,
+            });
+            src.push(c);
+        }
+    }
+}
+
+/// Decode `bytes` as UTF-8 text, in the same way as
+/// `files::decode`, and expand tabs.
+fn decode_and_expand(
+    bytes: &[u8],
+    encoding: &'static Encoding,
+    bom_len: usize,
+    tab_width: usize,
+) -> (String, OffsetMap) {
+    let body = &bytes[bom_len..];
+    let mut src = String::with_capacity(body.len());
+    let mut map = OffsetMap::default();
+
+    match std::str::from_utf8(body) {
+        Ok(text) if encoding == UTF_8 => {
+            let mut chunk_start = 0;
+            for (i, _) in text.match_indices('\t') {
+                map.push(Segment {
+                    clean: src.len(),
+                    original: bom_len + chunk_start,
+                    one_to_one: true,
+                });
+                src.push_str(&text[chunk_start..i]);
+                map.push_char(&mut src, '\t', bom_len + i, 1, tab_width);
+                chunk_start = i + 1;
+            }
+            map.push(Segment {
+                clean: src.len(),
+                original: bom_len + chunk_start,
+                one_to_one: true,
+            });
+            src.push_str(&text[chunk_start..]);
+        }
+        _ => {
+            // Decode a byte at a time, so we know which bytes each
+            // character came from. This is slow, but we only need it
+            // for other encodings and invalid UTF-8.
+            let mut decoder = encoding.new_decoder_without_bom_handling();
+            let mut chars = String::new();
+            let mut char_start = 0;
+            for i in 0..=body.len() {
+                let last = i == body.len();
+                let input = if last { &[][..] } else { &body[i..i + 1] };
+                chars.clear();
+                chars.reserve(decoder.max_utf8_buffer_length(input.len()).unwrap_or(16));
+                decoder.decode_to_string(input, &mut chars, last);
+
+                let char_end = if last { body.len() } else { i + 1 };
+                for c in chars.chars() {
+                    map.push_char(
+                        &mut src,
+                        c,
+                        bom_len + char_start,
+                        char_end - char_start,
+                        tab_width,
+                    );
+                }
+                if !chars.is_empty() {
+                    char_start = char_end;
+                }
+            }
+        }
+    }
+
+    (src, map)
+}
+
+/// Remove ANSI escapes from `src`, whose offsets are mapped by `map`.
+fn strip_ansi_escapes(src: &str, map: &OffsetMap) -> (String, OffsetMap) {
+    let mut stripped = String::with_capacity(src.len());
+    let mut stripped_map = OffsetMap::default();
+    for slice in categorise_text(src) {
+        let slice_start = slice.text.as_ptr() as usize - src.as_ptr() as usize;
+        for (i, c) in slice.text.char_indices() {
+            let offset = slice_start + i;
+            let one_to_one = match map.segments.partition_point(|s| s.clean <= offset) {
+                0 => true,
+                n => map.segments[n - 1].one_to_one,
+            };
+            stripped_map.push(Segment {
+                clean: stripped.len(),
+                original: map.original(offset),
+                one_to_one,
+            });
+            stripped.push(c);
+        }
+    }
+    (stripped, stripped_map)
+}
+
+/// Decode `bytes`, expand tabs to `tab_width` spaces and, if
+/// `strip_input_ansi` is set, remove ANSI escapes.
+///
+/// Returns the text, the encoding that we used, and the map from
+/// offsets in the text to offsets in `bytes`.
+pub fn clean(
+    bytes: &[u8],
+    tab_width: usize,
+    strip_input_ansi: bool,
+    encoding_override: Option<&'static Encoding>,
+) -> (String, &'static Encoding, OffsetMap) {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, bom_len),
+        None => (encoding_override.unwrap_or(UTF_8), 0),
+    };
+
+    let (mut src, mut map) = decode_and_expand(bytes, encoding, bom_len, tab_width);
+    if strip_input_ansi && src.contains('\x1b') {
+        let (stripped, stripped_map) = strip_ansi_escapes(&src, &map);
+        src = stripped;
+        map = stripped_map;
+    }
+    map.push(Segment {
+        clean: src.len(),
+        original: bytes.len(),
+        one_to_one: true,
+    });
+
+    (src, encoding, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_clean_utf8() {
+        let (src, _, map) = clean(b"foo bar", 4, false, None);
+        assert_eq!(src, "foo bar");
+        assert_eq!(map.original(4), 4);
+        assert_eq!(map.original(7), 7);
+    }
+
+    #[test]
+    fn test_clean_tabs() {
+        let bytes = b"\tfoo\n\t\tbar";
+        let (src, _, map) = clean(bytes, 4, false, None);
+        assert_eq!(src, "    foo\n        bar");
+
+        // Offsets of "foo" and "bar".
+        assert_eq!(map.original(4), 1);
+        assert_eq!(map.original(7), 4);
+        assert_eq!(map.original(16), 7);
+        assert_eq!(map.original(19), bytes.len());
+
+        // Offsets inside an expanded tab map to the tab.
+        assert_eq!(map.original(2), 0);
+        assert_eq!(map.original(13), 6);
+    }
+
+    #[test]
+    fn test_clean_latin1() {
+        // "café = 1" in Latin-1, where é is the single byte 0xE9.
+        let bytes = b"caf\xe9 = 1\n\xe9t\xe9";
+        let (src, encoding, map) = clean(bytes, 4, false, Some(WINDOWS_1252));
+        assert_eq!(src, "café = 1\nété");
+        assert_eq!(encoding, WINDOWS_1252);
+
+        // é is two bytes in UTF-8, so later offsets are shifted.
+        assert_eq!(map.original(3), 3);
+        assert_eq!(map.original(5), 4);
+        assert_eq!(map.original(src.find('1').unwrap()), 7);
+        assert_eq!(map.original(src.find("t").unwrap()), 10);
+        assert_eq!(map.original(src.len()), bytes.len());
+    }
+
+    #[test]
+    fn test_clean_invalid_utf8() {
+        let (src, _, map) = clean(b"a\xffb", 4, false, None);
+        assert_eq!(src, "a\u{fffd}b");
+        assert_eq!(map.original(src.find('b').unwrap()), 2);
+    }
+
+    #[test]
+    fn test_clean_bom() {
+        let (src, _, map) = clean(b"\xef\xbb\xbffoo", 4, false, None);
+        assert_eq!(src, "foo");
+        assert_eq!(map.original(0), 3);
+    }
+
+    #[test]
+    fn test_clean_ansi() {
+        let (src, _, _) = clean(b"\x1b[31mfoo\x1b[0m bar", 4, true, None);
+        assert_eq!(src, "foo bar");
+
+        let (src, _, _) = clean(b"\x1b[31mfoo\x1b[0m bar", 4, false, None);
+        assert_eq!(src, "\x1b[31mfoo\x1b[0m bar");
+    }
+
+    #[test]
+    fn test_clean_ansi_and_tabs() {
+        let (src, _, map) = clean(b"\x1b[31m\tfoo\x1b[0m bar", 2, true, None);
+        assert_eq!(src, "  foo bar");
+        assert_eq!(map.original(0), 5);
+        assert_eq!(map.original(2), 6);
+        assert_eq!(map.original(6), 14);
+    }
+}
@@ -1,16 +1,57 @@
 //! CLI option parsing.
 
-use std::{borrow::Borrow, env, ffi::OsString};
+use std::{
+    borrow::Borrow,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use atty::Stream;
-use clap::{crate_authors, crate_description, crate_version, Arg, Command};
+use clap::{
+    crate_authors, crate_description, crate_version, Arg, ArgMatches, Command, ValueSource,
+};
 use const_format::formatcp;
+use encoding_rs::Encoding;
+use regex::Regex;
 
-use crate::{display::style::BackgroundColor, parse::guess_language};
+use crate::{
+    assert_equal::token_regex,
+    compare_trees::IgnorePath,
+    completions::{Shell, SHELL_NAMES},
+    diff::graph::CostModel,
+    display::context::Alignment,
+    display::hunks::HunkOrder,
+    display::side_by_side::SeparatorStyle,
+    display::style::{expand_placeholders, BackgroundColor, ColorDepth, HEADER_PLACEHOLDERS},
+    editorconfig::EditorConfigUse,
+    files::display_path,
+    i18n::{self, Locale, LOCALE_NAMES},
+    line_parser::WordSplit,
+    lines::LineNumber,
+    parse::guess_language,
+    session::Recording,
+    warnings,
+};
 
 pub const DEFAULT_NODE_LIMIT: u32 = 30_000;
 pub const DEFAULT_BYTE_LIMIT: usize = 1_000_000;
 pub const DEFAULT_TAB_WIDTH: usize = 8;
+pub const DEFAULT_PARSE_ERROR_LIMIT: f64 = 1.0;
+
+/// The narrowest display that we show side-by-side. On narrower
+/// displays, each column would only have room for a few characters.
+pub const MIN_SIDE_BY_SIDE_WIDTH: usize = 60;
+
+/// The number of files we can compare at once in multi-pane
+/// mode. Larger numbers would clash with the arguments that git
+/// passes to external diff tools.
+const MIN_MULTI_PANE_FILES: usize = 3;
+const MAX_MULTI_PANE_FILES: usize = 6;
 
 const USAGE: &str = concat!(env!("CARGO_BIN_NAME"), " [OPTIONS] OLD-PATH NEW-PATH");
 
@@ -25,15 +66,282 @@ pub enum ColorOutput {
 pub struct DisplayOptions {
     pub background_color: BackgroundColor,
     pub use_color: bool,
+    pub color_depth: ColorDepth,
     pub display_mode: DisplayMode,
     pub print_unchanged: bool,
     pub tab_width: usize,
     pub display_width: usize,
     pub in_vcs: bool,
     pub syntax_highlight: bool,
+    pub report_parse_errors: bool,
+    pub show_minimap: bool,
+    /// Print a line explaining the colors before the first diff.
+    pub show_legend: bool,
+    /// Draw indentation with visible characters on lines where only
+    /// the indentation changed.
+    pub show_indentation: bool,
+    /// Show large definitions that were entirely added or removed as a
+    /// single summary line.
+    pub collapse_definitions: bool,
+    /// Show hunks under a heading for the top-level definition that
+    /// contains them.
+    pub group_by_definition: bool,
+    /// Only show this many lines of files that have been added or
+    /// removed.
+    pub preview_lines: Option<usize>,
+    pub summary_only: bool,
+    /// Apply the default options for each kind of file, such as
+    /// only summarising lockfiles.
+    pub use_profiles: bool,
+    /// Fill the background of novel lines when only one side of a
+    /// hunk has changed.
+    pub line_background: bool,
+    /// Don't end lines with unstyled padding.
+    pub trim_trailing_spaces: bool,
+    /// A template for hunk headers, overriding the default header.
+    pub header_format: Option<String>,
+    /// Omit line numbers and separate side-by-side columns with a
+    /// delimiter, so code can be copied out of the diff.
+    pub copyable: bool,
+    /// Printed between side-by-side columns instead of the default
+    /// separator.
+    pub separator: Option<String>,
+    pub separator_style: SeparatorStyle,
+    /// How to align lines that only occur on one side in side-by-side
+    /// display.
+    pub alignment: Alignment,
+    /// Include byte offsets in JSON output.
+    pub show_offsets: bool,
+    /// Add a `schema_version` field to JSON output, and follow that
+    /// version of the schema.
+    pub json_schema_version: Option<u32>,
+    /// Only say that a generated file changed, rather than showing
+    /// its changes.
+    pub collapse_generated: bool,
+    pub hunk_order: HunkOrder,
+    /// The object hashes, modes and sizes passed by git, if we're
+    /// running as a git external diff tool.
+    pub vcs_metadata: Option<VcsMetadata>,
+    /// Where to write patches for `--display stage-script`.
+    pub stage_dir: Option<PathBuf>,
+}
+
+impl Default for DisplayOptions {
+    /// The options we'd use for an 80 column terminal without color
+    /// if no flags were given.
+    fn default() -> Self {
+        Self {
+            background_color: BackgroundColor::Dark,
+            use_color: false,
+            color_depth: ColorDepth::Extended,
+            display_mode: DisplayMode::SideBySide,
+            print_unchanged: true,
+            tab_width: DEFAULT_TAB_WIDTH,
+            display_width: 80,
+            in_vcs: false,
+            syntax_highlight: true,
+            report_parse_errors: false,
+            show_minimap: false,
+            show_legend: false,
+            show_indentation: false,
+            collapse_definitions: false,
+            group_by_definition: false,
+            preview_lines: None,
+            summary_only: false,
+            use_profiles: true,
+            line_background: true,
+            trim_trailing_spaces: false,
+            header_format: None,
+            copyable: false,
+            separator: None,
+            separator_style: SeparatorStyle::Plain,
+            alignment: Alignment::Compact,
+            show_offsets: false,
+            json_schema_version: None,
+            collapse_generated: false,
+            hunk_order: HunkOrder::Position,
+            vcs_metadata: None,
+            stage_dir: None,
+        }
+    }
+}
+
+/// Options that control how two files are diffed, as opposed to how
+/// the result is displayed.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub node_limit: u32,
+    pub cost_model: CostModel,
+    pub byte_limit: usize,
+    pub parse_error_limit: f64,
+    pub strip_input_ansi: bool,
+    pub c_preprocessor: bool,
+    pub api_only: bool,
+    pub diff_mode: DiffMode,
+    pub ignore_case: bool,
+    pub max_depth: Option<usize>,
+    pub time_limit: Option<Duration>,
+    pub word_split: WordSplit,
+    /// Explain how the token at this line and column was matched.
+    pub explain: Option<(LineNumber, usize)>,
+    pub encoding_override: Option<&'static Encoding>,
+    pub editorconfig: EditorConfigUse,
+    /// Use the tab width from a `difft:` mode line. This is false if
+    /// the user set the tab width explicitly.
+    pub modeline_tab_width: bool,
+    pub language_override: Option<guess_language::Language>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            node_limit: DEFAULT_NODE_LIMIT,
+            cost_model: CostModel::default(),
+            byte_limit: DEFAULT_BYTE_LIMIT,
+            parse_error_limit: DEFAULT_PARSE_ERROR_LIMIT,
+            strip_input_ansi: false,
+            c_preprocessor: false,
+            api_only: false,
+            diff_mode: DiffMode::Auto,
+            ignore_case: false,
+            max_depth: None,
+            time_limit: None,
+            word_split: WordSplit::default(),
+            explain: None,
+            encoding_override: None,
+            editorconfig: EditorConfigUse::default(),
+            modeline_tab_width: true,
+            language_override: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VcsMetadata {
+    pub lhs_hash: String,
+    pub lhs_mode: String,
+    pub lhs_size: u64,
+    pub rhs_hash: String,
+    pub rhs_mode: String,
+    pub rhs_size: u64,
+}
+
+impl VcsMetadata {
+    fn new(
+        lhs_tmp_file: &OsStr,
+        lhs_hash: &OsStr,
+        lhs_mode: &OsStr,
+        rhs_tmp_file: &OsStr,
+        rhs_hash: &OsStr,
+        rhs_mode: &OsStr,
+    ) -> Self {
+        let size = |path: &OsStr| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        Self {
+            lhs_hash: lhs_hash.to_string_lossy().into(),
+            lhs_mode: lhs_mode.to_string_lossy().into(),
+            lhs_size: size(lhs_tmp_file),
+            rhs_hash: rhs_hash.to_string_lossy().into(),
+            rhs_mode: rhs_mode.to_string_lossy().into(),
+            rhs_size: size(rhs_tmp_file),
+        }
+    }
+
+    fn reversed(self) -> Self {
+        Self {
+            lhs_hash: self.rhs_hash,
+            lhs_mode: self.rhs_mode,
+            lhs_size: self.rhs_size,
+            rhs_hash: self.lhs_hash,
+            rhs_mode: self.lhs_mode,
+            rhs_size: self.lhs_size,
+        }
+    }
+}
+
+/// One file from a batch of git external diff arguments.
+#[derive(Debug, Clone)]
+pub struct VcsFile {
+    pub lhs_display_path: String,
+    pub rhs_display_path: String,
+    pub lhs_path: OsString,
+    pub rhs_path: OsString,
+    pub vcs_metadata: VcsMetadata,
+}
+
+impl VcsFile {
+    /// Build from the 7 arguments git passes for a modified file, or
+    /// the 9 arguments for a renamed file.
+    fn new(args: &[&OsStr]) -> Self {
+        let (old_name, new_name) = match args {
+            [name, _, _, _, _, _, _] => (name, name),
+            [old_name, _, _, _, _, _, _, new_name, _] => (old_name, new_name),
+            _ => panic!("Expected 7 or 9 arguments, got {}", args.len()),
+        };
+        Self {
+            lhs_display_path: display_path(Path::new(old_name)),
+            rhs_display_path: display_path(Path::new(new_name)),
+            lhs_path: args[1].to_owned(),
+            rhs_path: args[4].to_owned(),
+            vcs_metadata: VcsMetadata::new(args[1], args[2], args[3], args[4], args[5], args[6]),
+        }
+    }
+
+    fn reversed(self) -> Self {
+        Self {
+            lhs_display_path: self.rhs_display_path,
+            rhs_display_path: self.lhs_display_path,
+            lhs_path: self.rhs_path,
+            rhs_path: self.lhs_path,
+            vcs_metadata: self.vcs_metadata.reversed(),
+        }
+    }
+}
+
+fn looks_like_hash(arg: &OsStr) -> bool {
+    match arg.to_str() {
+        Some(".") => true,
+        Some(s) => s.len() >= 7 && s.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+fn looks_like_mode(arg: &OsStr) -> bool {
+    match arg.to_str() {
+        Some(".") => true,
+        Some(s) => s.len() == 6 && s.chars().all(|c| ('0'..='7').contains(&c)),
+        None => false,
+    }
+}
+
+/// Split the arguments of a batched git external diff into the
+/// arguments for each file. Each file has 7 arguments, or 9 if it was
+/// renamed, so we check the hashes and modes to find the boundaries.
+fn split_vcs_batch<'a>(args: &'a [&'a OsStr]) -> Option<Vec<&'a [&'a OsStr]>> {
+    if args.is_empty() {
+        return Some(vec![]);
+    }
+    if args.len() < 7
+        || !looks_like_hash(args[2])
+        || !looks_like_mode(args[3])
+        || !looks_like_hash(args[5])
+        || !looks_like_mode(args[6])
+    {
+        return None;
+    }
+
+    for len in [7, 9] {
+        if args.len() < len {
+            continue;
+        }
+        if let Some(mut rest) = split_vcs_batch(&args[len..]) {
+            rest.insert(0, &args[..len]);
+            return Some(rest);
+        }
+    }
+    None
 }
 
-fn app() -> clap::Command<'static> {
+pub fn app() -> clap::Command<'static> {
     Command::new("Difftastic")
         .override_usage(USAGE)
         .version(crate_version!())
@@ -48,6 +356,10 @@ fn app() -> clap::Command<'static> {
             "$ ",
             env!("CARGO_BIN_NAME"),
             " old/ new/\n\n",
+            "You can compare between three and six files at once. Each file is shown as a column.\n\n",
+            "$ ",
+            env!("CARGO_BIN_NAME"),
+            " a.js b.js c.js\n\n",
             "Difftastic can also be invoked with 7 arguments in the format that GIT_EXTERNAL_DIFF expects.\n\n",
             "See the full manual at: https://difftastic.wilfred.me.uk/")
         )
@@ -60,6 +372,15 @@ fn app() -> clap::Command<'static> {
                     "Parse a single file with tree-sitter and display the difftastic syntax tree.",
                 ).help_heading("DEBUG OPTIONS"),
         )
+        .arg(
+            Arg::new("dump-syntax-dot")
+                .long("dump-syntax-dot")
+                .takes_value(true)
+                .value_name("PATH")
+                .long_help(
+                    "Parse a single file with tree-sitter and display the difftastic syntax tree as a Graphviz graph.",
+                ).help_heading("DEBUG OPTIONS"),
+        )
         .arg(
             Arg::new("dump-ts")
                 .long("dump-ts")
@@ -69,6 +390,43 @@ fn app() -> clap::Command<'static> {
                     "Parse a single file with tree-sitter and display the tree-sitter parse tree.",
                 ).help_heading("DEBUG OPTIONS"),
         )
+        .arg(
+            Arg::new("compare-diffs")
+                .long("compare-diffs")
+                .number_of_values(2)
+                .value_names(&["OLD", "NEW"])
+                .help("Compare two files of --display json output, and report whether each file has the same changes.")
+                .long_help(concat!(
+                    "Compare two files of --display json output, and report whether each file has the same changes.\n\n",
+                    "This is useful for checking that a change is unaltered after a rebase or cherry-pick. ",
+                    "Line numbers are ignored, so hunks that have moved within a file are still considered the same."))
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            Arg::new("list-languages")
+                .long("list-languages")
+                .help("Print the supported languages, their file extensions, and whether each parser is compiled in.")
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .takes_value(true)
+                .value_name("SOCKET")
+                .allow_invalid_utf8(true)
+                .help("Listen on the unix socket SOCKET and diff on behalf of other difft processes. Set DFT_DAEMON_SOCKET=SOCKET to use it. Unix only.")
+                .long_help(concat!(
+                    "Listen on the unix socket SOCKET and diff on behalf of other difft processes. Unix only.\n\n",
+                    "When DFT_DAEMON_SOCKET is set to SOCKET, difft sends its arguments to the daemon instead of diffing itself, ",
+                    "which avoids startup costs when diffing many commits, e.g. with `git log -p --ext-diff`. ",
+                    "This only happens when output isn't going directly to a terminal."))
+        )
+        .arg(
+            Arg::new("self-test")
+                .long("self-test")
+                .long_help(
+                    "Diff a small built-in example for every supported language, and report any parsing or diffing problems.",
+                ),
+        )
         .arg(
             Arg::new("width")
                 .long("width")
@@ -76,7 +434,7 @@ fn app() -> clap::Command<'static> {
                 .value_name("COLUMNS")
                 .long_help("Use this many columns when calculating line wrapping. If not specified, difftastic will detect the terminal width.")
                 .env("DFT_WIDTH")
-                .validator(|s| s.parse::<usize>())
+                .validator(|s| whole_number::<usize>(s, 1, 120))
                 .required(false),
         )
         .arg(
@@ -87,21 +445,35 @@ fn app() -> clap::Command<'static> {
                 .long_help("Treat a tab as this many spaces.")
                 .env("DFT_TAB_WIDTH")
                 .default_value(formatcp!("{}", DEFAULT_TAB_WIDTH))
-                .validator(|s| s.parse::<usize>())
+                .validator(|s| whole_number::<usize>(s, 0, 4))
                 .required(false),
         )
         .arg(
             Arg::new("display").long("display")
-                .possible_values(["side-by-side", "side-by-side-show-both", "inline", ])
+                .alias("format")
+                .possible_values(["side-by-side", "side-by-side-show-both", "inline", "lhs-only", "rhs-only", "json", "html", "markdown", "raw-spans", "churn-csv", "stage-script"])
                 .value_name("MODE")
                 .env("DFT_DISPLAY")
                 .help("Display mode for showing results.")
+                .long_help(concat!(
+                    "Display mode for showing results.\n\n",
+                    "side-by-side: Show the old file on the left and the new file on the right. Hunks that only add or only remove lines are shown as a single column.\n\n",
+                    "side-by-side-show-both: Like side-by-side, but always show both columns.\n\n",
+                    "inline: Show removed lines followed by added lines, similar to a traditional unified diff.\n\n",
+                    "lhs-only: Only show the old file, with removed content highlighted.\n\n",
+                    "rhs-only: Only show the new file, with added content highlighted.\n\n",
+                    "json: Print one JSON object per file, describing the changed line ranges and any code that was moved or copied.\n\n",
+                    "html: Print an HTML fragment per file, with a table for each hunk.\n\n",
+                    "markdown: Print a collapsed section per file, with a unified diff for each hunk and changed tokens in bold. This is suitable for pull request comments.\n\n",
+                    "raw-spans: Print a unified diff without colours, with changed tokens marked as [-removed-] and {+added+}. This is intended for other tools to format.\n\n",
+                    "churn-csv: Print a CSV row per file with the tokens added and removed, code moved and definitions touched, for churn analysis.\n\n",
+                    "stage-script: Print nothing, but write each hunk as a separate patch in --stage-dir, and list them in its index file. You can stage a single hunk with `git apply --cached DIR/PATCH`."))
         )
         .arg(
             Arg::new("color").long("color")
-                .possible_values(["always", "auto", "never"])
+                .possible_values(["always", "auto", "never", "ci"])
                 .value_name("WHEN")
-                .help("When to use color output.")
+                .help("When to use color output. 'ci' always uses color, but only the 16 basic colors and no backgrounds, which suits CI log viewers.")
         )
         .arg(
             Arg::new("background").long("background")
@@ -110,6 +482,14 @@ fn app() -> clap::Command<'static> {
                 .possible_values(["dark", "light"])
                 .help("Set the background brightness. Difftastic will prefer brighter colours on dark backgrounds.")
         )
+        .arg(
+            Arg::new("color-depth").long("color-depth")
+                .value_name("COLORS")
+                .env("DFT_COLOR_DEPTH")
+                .possible_values(["auto", "8", "16", "256"])
+                .default_value("auto")
+                .help("The number of colors the terminal supports. With fewer than 256 colors, changed lines don't have a background. With 8 colors, bright colors aren't used. The default uses $TERM and $COLORTERM.")
+        )
         .arg(
             Arg::new("syntax-highlight").long("syntax-highlight")
                 .value_name("ON/OFF")
@@ -118,14 +498,283 @@ fn app() -> clap::Command<'static> {
                 .default_value("on")
                 .help("Enable or disable syntax highlighting.")
         )
+        .arg(
+            Arg::new("alignment").long("alignment")
+                .possible_values(["compact", "filler"])
+                .default_value("compact")
+                .value_name("STRATEGY")
+                .env("DFT_ALIGNMENT")
+                .help("How to align lines that only occur on one side in side-by-side display. compact shows unrelated added and removed lines on the same row, so hunks are shorter. filler shows each of them opposite a blank row.")
+        )
+        .arg(
+            Arg::new("show-offsets").long("show-offsets")
+                .help("Include byte offsets in --display json output, alongside line numbers and columns. Offsets are counted in bytes from the start of the file as it is on disk, before expanding tabs or decoding it.")
+        )
+        .arg(
+            Arg::new("show-generated").long("show-generated")
+                .help("Show the changes in generated files. By default, when diffing directories or running under git, files with a marker such as @generated or DO NOT EDIT in their first lines are only reported as changed.")
+        )
+        .arg(
+            Arg::new("schema-version").long("schema-version")
+                .takes_value(true)
+                .possible_values(["1"])
+                .value_name("VERSION")
+                .help("Follow version VERSION of the --display json schema, and include it as a schema_version field in each object. Print the schema with --print-schema.")
+        )
+        .arg(
+            Arg::new("print-schema").long("print-schema")
+                .help("Print the JSON Schema for --display json output with --schema-version 1.")
+        )
+        .arg(
+            Arg::new("order").long("order")
+                .possible_values(["position", "size", "semantic"])
+                .default_value("position")
+                .value_name("ORDER")
+                .env("DFT_ORDER")
+                .help("The order to show hunks in. position uses file order. size shows hunks with the most changed lines first. semantic shows hunks that change code before hunks that only change comments.")
+        )
+        .arg(
+            Arg::new("copyable").long("copyable")
+                .help("Don't show line numbers, and separate side-by-side columns with │. This makes it easy to copy code out of a diff, or to extract a column with `awk -F' │ ' '{print $2}'`.")
+        )
+        .arg(
+            Arg::new("separator").long("separator")
+                .takes_value(true)
+                .value_name("TEXT")
+                .env("DFT_SEPARATOR")
+                .validator(validate_separator)
+                .help("Print TEXT between side-by-side columns, such as ' │ '. A visible separator helps on terminals without background colors. TEXT can't contain control characters, such as tabs or escape sequences.")
+        )
+        .arg(
+            Arg::new("separator-style").long("separator-style")
+                .possible_values(["plain", "dim", "bold"])
+                .default_value("plain")
+                .value_name("STYLE")
+                .env("DFT_SEPARATOR_STYLE")
+                .help("How to style the separator between side-by-side columns, when color is enabled.")
+        )
+        .arg(
+            Arg::new("minimap").long("minimap")
+                .help("Show where each hunk occurs in the file, as a bar after the hunk header.")
+        )
+        .arg(
+            Arg::new("show-indentation").long("show-indentation")
+                .help("On lines where only the indentation changed, show tabs as » and spaces as ·.")
+        )
+        .arg(
+            Arg::new("legend").long("legend")
+                .help("Print a line to stderr explaining the colors before the diff. This is also shown the first time difftastic prints to a terminal on a machine, unless CI is set.")
+        )
+        .arg(
+            Arg::new("collapse-definitions").long("collapse-definitions")
+                .help("Show large functions and other top-level definitions that were entirely added or removed as a single line, such as `fn foo(...) { … 84 lines }`. With --display side-by-side-show-both, also hide the bodies of unchanged definitions.")
+        )
+        .arg(
+            Arg::new("preview-lines").long("preview-lines")
+                .takes_value(true)
+                .value_name("LINES")
+                .validator(|s| whole_number::<usize>(s, 1, 20))
+                .help("Only show the first LINES lines of files that have been added or removed, followed by the number of lines hidden. By default, the whole file is shown.")
+        )
+        .arg(
+            Arg::new("header-format").long("header-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .env("DFT_HEADER_FORMAT")
+                .validator(validate_header_format)
+                .help(concat!(
+                    "Use FORMAT for hunk headers, e.g. '{path} ({lang}) hunk {n}/{total}'. ",
+                    "Supported placeholders: {path}, {old_path}, {lang}, {n}, {total}, {changes}, and, when run by git, ",
+                    "{old_hash}, {new_hash}, {old_mode}, {new_mode}, {old_size} and {new_size}."
+                ))
+        )
+        .arg(
+            Arg::new("stage-dir").long("stage-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .allow_invalid_utf8(true)
+                .required_if_eq("display", "stage-script")
+                .help("Where --display stage-script writes its patches.")
+        )
+        .arg(
+            Arg::new("assert-equal").long("assert-equal")
+                .help("Check that every pair of files has the same content, ignoring formatting, as `difft assert-equal` does for two files. Useful with --files-from, --vcs-batch and `compare-trees`.")
+        )
+        .arg(
+            Arg::new("files-from").long("files-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .conflicts_with_all(&["paths", "estimate"])
+                .help("Read pairs of old and new paths from FILE, or from stdin if FILE is -, and diff each pair. Paths are separated by newlines, or by NUL bytes if there are any. A pair of directories is compared file by file.")
+        )
+        .arg(
+            Arg::new("vcs-batch").long("vcs-batch")
+                .requires("paths")
+                .conflicts_with_all(&["files-from", "estimate", "interactive"])
+                .help("Treat the arguments as git external diff arguments for several files, 7 per file or 9 for a renamed file, and diff them all in one process.")
+        )
+        .arg(
+            Arg::new("record").long("record")
+                .takes_value(true)
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .conflicts_with_all(&["files-from", "estimate"])
+                .help("Save both input files, the options and the difftastic version to FILE, then show the diff. Attach FILE to a bug report so it can be reproduced with --replay.")
+        )
+        .arg(
+            Arg::new("replay").long("replay")
+                .takes_value(true)
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .conflicts_with_all(&["paths", "files-from", "record"])
+                .help("Show the diff saved in FILE by --record, with the options that were recorded.")
+        )
+        .arg(
+            Arg::new("interactive").long("interactive")
+                .takes_value(true)
+                .value_name("REVIEW_FILE")
+                .allow_invalid_utf8(true)
+                .conflicts_with_all(&["assert-equal", "estimate"])
+                .help("Show one hunk at a time and ask whether to approve it, reject it or add a note. Decisions are written to REVIEW_FILE as JSON. Hunks are shown side-by-side, unless --display inline is set.")
+        )
+        .arg(
+            Arg::new("metrics").long("metrics")
+                .conflicts_with("estimate")
+                .help("After diffing, print the most syntax nodes allocated for a single file, and the size of the arena holding them, to stderr. The size doesn't include each node's children, positions or text, which are allocated separately.")
+        )
+        .arg(
+            Arg::new("estimate").long("estimate")
+                .conflicts_with("assert-equal")
+                .help("Parse both files and report the size of their syntax trees, and whether a structural diff would be fast, slow, or fall back to a text diff. Doesn't run the diff itself.")
+        )
+        .arg(
+            Arg::new("ignore-token-regex").long("ignore-token-regex")
+                .takes_value(true)
+                .value_name("REGEX")
+                .multiple_occurrences(true)
+                .requires("assert-equal")
+                .validator(validate_token_regex)
+                .help("With --assert-equal, ignore changed tokens that entirely match REGEX, such as timestamps. May be given more than once.")
+        )
+        .arg(
+            Arg::new("no-line-background").long("no-line-background")
+                .help("Don't fill the background of changed lines. Only the changed tokens are colored.")
+        )
+        .arg(
+            Arg::new("trim-trailing-spaces").long("trim-trailing-spaces")
+                .help("Don't pad lines with trailing spaces, unless they're needed to draw a background color. This keeps redirected output clean for documentation and golden files.")
+        )
+        .arg(
+            Arg::new("summary-only").long("summary-only")
+                .help("Print a single line for each changed file, with the number of hunks and the number of added and removed tokens. Useful in git hooks. This is ignored for JSON, HTML, Markdown and raw span output.")
+        )
+        .arg(
+            Arg::new("group-by-definition").long("group-by-definition")
+                .help("Group hunks under the top-level definition that contains them, such as a function or class.")
+        )
+        .arg(
+            Arg::new("no-profiles").long("no-profiles")
+                .help("Don't use per-language default options, such as only printing a summary for lockfiles and expanding definitions in JSON, TOML and YAML.")
+        )
         .arg(
             Arg::new("skip-unchanged").long("skip-unchanged")
                 .help("Don't display anything if a file is unchanged.")
         )
+        .arg(
+            Arg::new("encoding").long("encoding")
+                .takes_value(true)
+                .value_name("ENCODING")
+                .env("DFT_ENCODING")
+                .validator(|s| {
+                    Encoding::for_label(s.as_bytes())
+                        .ok_or_else(|| format!("Unknown encoding: {}", s))
+                })
+                .help("Decode input files with this encoding, such as latin1, shift_jis or utf-16le. Files with a byte order mark are always decoded according to the mark. Defaults to UTF-8.")
+        )
+        .arg(
+            Arg::new("no-editorconfig").long("no-editorconfig")
+                .help("Ignore .editorconfig files. By default, difftastic uses the tab_width and charset that .editorconfig sets for each file, unless --tab-width or --encoding is given.")
+        )
+        .arg(
+            Arg::new("lhs-label").long("lhs-label")
+                .takes_value(true)
+                .value_name("LABEL")
+                .conflicts_with("files-from")
+                .help("Show LABEL instead of the first path in headers, e.g. when the path is a temporary file. Applies after --reverse.")
+        )
+        .arg(
+            Arg::new("rhs-label").long("rhs-label")
+                .takes_value(true)
+                .value_name("LABEL")
+                .conflicts_with("files-from")
+                .help("Show LABEL instead of the second path in headers. Applies after --reverse.")
+        )
+        .arg(
+            Arg::new("reverse").long("reverse")
+                .help("Treat the second file as the old file and the first file as the new file. Useful when reviewing reverts.")
+        )
         .arg(
             Arg::new("missing-as-empty").long("missing-as-empty")
                 .help("Treat paths that don't exist as equivalent to an empty file. Only applies when diffing files, not directories.")
         )
+        .arg(
+            Arg::new("snapshots").long("snapshots")
+                .help("Allow either path to be a tarball (.tar, .tar.gz or .tgz), a zip file, or a git tree-ish such as v1.0:src, and diff it file by file like a directory.")
+        )
+        .arg(
+            Arg::new("strip-input-ansi").long("strip-input-ansi")
+                .help("Remove ANSI escape sequences (e.g. colours) from the input files before diffing. This is useful when comparing logs that were captured from a terminal.")
+        )
+        .arg(
+            Arg::new("c-preprocessor").long("c-preprocessor")
+                .help("For C and C++ files, parse the active branch of each #if as code, and treat the other branches as comments. This avoids parse errors when each branch contains half of a construct. If a condition can't be evaluated, the first branch that may be true is active.")
+        )
+        .arg(
+            Arg::new("no-pager").long("no-pager")
+                .help("Don't send output through a pager. By default, difftastic uses $GIT_PAGER, git's core.pager, $PAGER or less when writing to a terminal, unless git is already paging the output.")
+        )
+        .arg(
+            Arg::new("no-warnings").long("no-warnings")
+                .help("Don't print warnings, such as when falling back to a text diff. Warnings are always written to stderr, so they never appear in the diff output.")
+        )
+        .arg(
+            Arg::new("lang-ui").long("lang-ui")
+                .takes_value(true)
+                .value_name("LANG")
+                .env("DFT_LANG_UI")
+                .possible_values(LOCALE_NAMES)
+                .help("Translate messages such as 'File added' into this language. Other output, such as warnings and hunk headers, stays in English. Defaults to the language of $LC_ALL, $LC_MESSAGES or $LANG.")
+        )
+        .arg(
+            Arg::new("api-only").long("api-only")
+                .help("Only diff the public declarations of each file, such as function signatures and types. Supported for Go, Java, JavaScript, Python, Rust and TypeScript.")
+        )
+        .arg(
+            Arg::new("mode").long("mode")
+                .possible_values(["auto", "syntax", "prose"])
+                .default_value("auto")
+                .value_name("MODE")
+                .env("DFT_MODE")
+                .help("How to diff files.")
+                .long_help(concat!(
+                    "How to diff files.\n\n",
+                    "auto: Parse files in supported languages, and diff .txt, .md, .markdown and .rst files as prose. Diff other files by line.\n\n",
+                    "syntax: Parse files in supported languages, and diff other files by line.\n\n",
+                    "prose: Diff every file as paragraphs of words, ignoring line breaks within paragraphs."))
+        )
+        .arg(
+            Arg::new("ignore-case").long("ignore-case")
+                .help("Treat atoms that only differ in case as unchanged, e.g. SELECT and select. Useful for case-insensitive languages such as SQL. Strings and comments are still case-sensitive. In text diffs, lines and words that only differ in case are unchanged.")
+        )
+        .arg(
+            Arg::new("explain").long("explain")
+                .takes_value(true)
+                .value_name("LINE:COLUMN")
+                .validator(parse_position)
+                .help("Explain how the token at LINE:COLUMN was matched, on either side, including the graph edge and path cost that chose the match. Lines and columns start at 1.")
+                .help_heading("DEBUG OPTIONS"),
+        )
         .arg(
             Arg::new("language").long("language")
                 .value_name("EXT")
@@ -137,12 +786,61 @@ fn app() -> clap::Command<'static> {
             Arg::new("node-limit").long("node-limit")
                 .takes_value(true)
                 .value_name("LIMIT")
-                .help(concat!("Use a text diff if the number of syntax nodes exceeds this number."))
-                .default_value(formatcp!("{}", DEFAULT_NODE_LIMIT))
+                .help(formatcp!("Use a text diff if the number of syntax nodes exceeds this number. The default is {} with --diff-quality=default.", DEFAULT_NODE_LIMIT))
                 .env("DFT_NODE_LIMIT")
-                .validator(|s| s.parse::<u32>())
+                .validator(|s| whole_number::<u32>(s, 0, DEFAULT_NODE_LIMIT))
                 .required(false),
         )
+        .arg(
+            Arg::new("diff-quality").long("diff-quality")
+                .value_name("PRESET")
+                .env("DFT_DIFF_QUALITY")
+                .possible_values(["fast", "default", "thorough"])
+                .default_value("default")
+                .help(concat!(
+                    "Trade diff quality for speed.\n\n",
+                    "fast: Fall back to a text diff on smaller inputs.\n\n",
+                    "thorough: Allow larger inputs, and avoid matching unrelated code that only shares brackets or punctuation."
+                ))
+        )
+        .arg(
+            Arg::new("delimiter-cost").long("delimiter-cost")
+                .takes_value(true)
+                .value_name("COST")
+                .validator(|s| whole_number::<u64>(s, 0, 100))
+                .help("Advanced: the cost of matching a pair of delimiters on both sides, overriding --diff-quality. Higher values match fewer delimiters. The default is 100.")
+        )
+        .arg(
+            Arg::new("punctuation-cost").long("punctuation-cost")
+                .takes_value(true)
+                .value_name("COST")
+                .validator(|s| whole_number::<u64>(s, 0, 10))
+                .help("Advanced: the extra cost of matching punctuation such as , or ; on both sides, overriding --diff-quality. The default is 0.")
+        )
+        .arg(
+            Arg::new("max-depth").long("max-depth")
+                .takes_value(true)
+                .value_name("DEPTH")
+                .env("DFT_MAX_DEPTH")
+                .validator(|s| whole_number::<usize>(s, 0, 5))
+                .help("Treat syntax nested more than DEPTH levels deep as a single token, where top-level syntax is at depth 0. This makes large, deeply nested data files such as JSON much faster to diff, but any change inside a collapsed node highlights the whole node. A warning lists the changed nodes that were collapsed.")
+        )
+        .arg(
+            Arg::new("time-limit").long("time-limit")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .env("DFT_TIME_LIMIT")
+                .validator(|s| whole_number::<u64>(s, 1, 2000))
+                .help("Stop the structural diff of a file after MILLISECONDS. Parts that were already matched are shown as a structural diff, and the rest as a text diff.")
+        )
+        .arg(
+            Arg::new("word-split").long("word-split")
+                .takes_value(true)
+                .value_name("RULES")
+                .env("DFT_WORD_SPLIT")
+                .validator(word_split)
+                .help("Extra rules for splitting words when diffing text, as a comma-separated list. 'case' splits fooBar into foo and Bar, 'digits' splits abc123 into abc and 123, and 'punctuation' keeps runs of punctuation such as -> together.")
+        )
         .arg(
             Arg::new("byte-limit").long("byte-limit")
                 .takes_value(true)
@@ -150,9 +848,31 @@ fn app() -> clap::Command<'static> {
                 .help(concat!("Use a text diff if either input file exceeds this size."))
                 .default_value(formatcp!("{}", DEFAULT_BYTE_LIMIT))
                 .env("DFT_BYTE_LIMIT")
-                .validator(|s| s.parse::<usize>())
+                .validator(|s| whole_number::<usize>(s, 0, DEFAULT_BYTE_LIMIT))
+                .required(false),
+        )
+        .arg(
+            Arg::new("parse-error-limit").long("parse-error-limit")
+                .takes_value(true)
+                .value_name("RATIO")
+                .help("Use a text diff if more than this proportion of either input file is inside tree-sitter parse errors. Must be between 0.0 and 1.0.")
+                .default_value("1.0")
+                .env("DFT_PARSE_ERROR_LIMIT")
+                .validator(|s| match s.parse::<f64>() {
+                    Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(()),
+                    Ok(ratio) => Err(format!("must be between 0.0 and 1.0, but got {}", ratio)),
+                    Err(_) => Err("must be a number between 0.0 and 1.0, e.g. 0.5".to_owned()),
+                })
                 .required(false),
         )
+        .arg(
+            Arg::new("print-config").long("print-config")
+                .help("Print the value of every option after applying environment variables and defaults, and where each value came from, then exit.")
+        )
+        .arg(
+            Arg::new("report-parse-errors").long("report-parse-errors")
+                .help("Print the number of tree-sitter parse errors in each file, and how much of the file they cover.")
+        )
         .arg(
             Arg::new("paths")
                 .value_name("PATHS")
@@ -160,23 +880,131 @@ fn app() -> clap::Command<'static> {
                 .hide(true)
                 .allow_invalid_utf8(true),
         )
+        .subcommand(
+            Command::new("verify")
+                .about("Diff each pair of files listed in MANIFEST, and check each outcome is the expected one: equal, differs or differs-only-in-comments. Exits with status 1 if any outcome is unexpected. Other options go before `verify`.")
+                .arg(
+                    Arg::new("manifest")
+                        .value_name("MANIFEST")
+                        .required(true)
+                        .allow_invalid_utf8(true),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Diff every pair of files named NAME_before.EXT and NAME_after.EXT in DIR, and report timings for each language, and peak memory for the whole run on Linux. Other options go before `bench`.")
+                .arg(
+                    Arg::new("dir")
+                        .value_name("DIR")
+                        .required(true)
+                        .allow_invalid_utf8(true),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print shell completions for difftastic, for packagers.")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .possible_values(SHELL_NAMES)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("man")
+                .about("Print a man page for difftastic, for packagers.")
+        )
+        .subcommand(
+            Command::new("assert-equal")
+                .about("Check that EXPECTED and ACTUAL have the same content, ignoring formatting. If they differ, print the diff and exit with status 1, otherwise print nothing. Useful in test suites in place of `diff -u`. Other options go before `assert-equal`.")
+                .arg(
+                    Arg::new("ignore-token-regex").long("ignore-token-regex")
+                        .takes_value(true)
+                        .value_name("REGEX")
+                        .multiple_occurrences(true)
+                        .validator(validate_token_regex)
+                        .help("Ignore changed tokens that entirely match REGEX, such as timestamps. May be given more than once.")
+                )
+                .arg(
+                    Arg::new("expected")
+                        .value_name("EXPECTED")
+                        .required(true)
+                        .allow_invalid_utf8(true),
+                )
+                .arg(
+                    Arg::new("actual")
+                        .value_name("ACTUAL")
+                        .required(true)
+                        .allow_invalid_utf8(true),
+                ),
+        )
+        .subcommand(
+            Command::new("compare-trees")
+                .about("Compare the pairs of directories or files listed in MAPPING, file by file. This keeps vendored copies in sync with their upstream checkouts, even when the trees are rooted differently. Other options go before `compare-trees`.")
+                .arg(
+                    Arg::new("ignore-path").long("ignore-path")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .multiple_occurrences(true)
+                        .allow_invalid_utf8(true)
+                        .help("Don't compare files under PATH. Use OLD=NEW to ignore OLD in old trees and NEW in new trees. May be given more than once.")
+                )
+                .arg(
+                    Arg::new("mapping")
+                        .value_name("MAPPING")
+                        .required(true)
+                        .allow_invalid_utf8(true),
+                ),
+        )
         .arg_required_else_help(true)
 }
 
+/// Where `Mode::DiffFilesFrom` gets its pairs of paths.
+#[derive(Debug, Clone)]
+pub enum PairSource {
+    /// A `--files-from` list of paths, or `-` for stdin.
+    List(OsString),
+    /// A `difft compare-trees` mapping file, and the paths not to
+    /// compare.
+    Trees {
+        mapping_path: OsString,
+        ignore_paths: Vec<IgnorePath>,
+    },
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum DisplayMode {
     Inline,
     SideBySide,
     SideBySideShowBoth,
+    LhsOnly,
+    RhsOnly,
+    Json,
+    Html,
+    Markdown,
+    RawSpans,
+    ChurnCsv,
+    StageScript,
+}
+
+/// How to diff files that we can't parse, or that are natural-language
+/// documents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Diff documentation files as prose, and other files by syntax
+    /// or by line.
+    Auto,
+    Syntax,
+    Prose,
 }
 
 pub enum Mode {
     Diff {
-        node_limit: u32,
-        byte_limit: usize,
+        diff_options: DiffOptions,
         display_options: DisplayOptions,
         missing_as_empty: bool,
-        language_override: Option<guess_language::Language>,
+        /// Diff archives and git trees file by file, like directories.
+        snapshots: bool,
         /// The path where we can read the LHS file. This is often a
         /// temporary file generated by source control.
         lhs_path: OsString,
@@ -187,6 +1015,74 @@ pub enum Mode {
         lhs_display_path: String,
         /// The path that we should display for the RHS file.
         rhs_display_path: String,
+        /// Shown in headers instead of `lhs_display_path`. Unlike the
+        /// display path, this isn't used for language detection.
+        lhs_label: Option<String>,
+        /// Shown in headers instead of `rhs_display_path`.
+        rhs_label: Option<String>,
+        /// Save the inputs and options to a session file.
+        record: Option<Recording>,
+        /// Send output through a pager when writing to a terminal.
+        use_pager: bool,
+        /// Only print the diff if the files differ, and exit with
+        /// status 1 if they do.
+        assert_equal: bool,
+        /// With `assert_equal`, ignore novel tokens matching these.
+        ignore_token_regexes: Vec<Regex>,
+        /// Print resource usage after diffing.
+        metrics: bool,
+        /// Ask for a decision on each hunk, and write them to this
+        /// review file.
+        review_path: Option<OsString>,
+    },
+    /// Predict how expensive a structural diff of two files will be.
+    Estimate {
+        diff_options: DiffOptions,
+        display_options: DisplayOptions,
+        lhs_path: OsString,
+        rhs_path: OsString,
+        lhs_display_path: String,
+        rhs_display_path: String,
+    },
+    /// Diff each pair of paths listed in a file.
+    DiffFilesFrom {
+        diff_options: DiffOptions,
+        display_options: DisplayOptions,
+        /// Treat a path that doesn't exist as an empty file.
+        missing_as_empty: bool,
+        source: PairSource,
+        use_pager: bool,
+        assert_equal: bool,
+        ignore_token_regexes: Vec<Regex>,
+        /// Print resource usage after diffing.
+        metrics: bool,
+        /// Ask for a decision on each hunk, and write them to this
+        /// review file.
+        review_path: Option<OsString>,
+    },
+    /// Diff each pair of files listed in a manifest, and check the
+    /// outcome is the one expected.
+    Verify {
+        diff_options: DiffOptions,
+        display_options: DisplayOptions,
+        manifest_path: OsString,
+    },
+    /// Compare several files at once, showing each file as a column.
+    DiffMany {
+        diff_options: DiffOptions,
+        display_options: DisplayOptions,
+        paths: Vec<OsString>,
+        use_pager: bool,
+    },
+    /// Diff every file in a batch of git external diff arguments, so
+    /// a whole `git show` only needs one process.
+    DiffVcsBatch {
+        diff_options: DiffOptions,
+        display_options: DisplayOptions,
+        files: Vec<VcsFile>,
+        use_pager: bool,
+        assert_equal: bool,
+        ignore_token_regexes: Vec<Regex>,
     },
     DumpTreeSitter {
         path: String,
@@ -195,89 +1091,159 @@ pub enum Mode {
     DumpSyntax {
         path: String,
         language_override: Option<guess_language::Language>,
+        /// Print the tree in Graphviz DOT format.
+        as_dot: bool,
+    },
+    /// Print shell completions.
+    Completions {
+        shell: Shell,
+    },
+    /// Print a man page.
+    ManPage,
+    /// Print the supported languages.
+    ListLanguages,
+    /// Print the JSON Schema for `--display json` output.
+    PrintSchema,
+    /// Print the effective value of every option.
+    PrintConfig {
+        config: String,
+    },
+    /// Diff the inputs saved by `--record`.
+    Replay {
+        session_path: OsString,
+    },
+    /// Diff on behalf of other difft processes.
+    Daemon {
+        socket_path: OsString,
+    },
+    /// Compare two saved JSON diffs.
+    CompareDiffs {
+        old_path: OsString,
+        new_path: OsString,
+    },
+    SelfTest,
+    Bench {
+        path: OsString,
+        language_override: Option<guess_language::Language>,
     },
 }
 
-/// Parse CLI arguments passed to the binary.
-pub fn parse_args() -> Mode {
-    let matches = app().get_matches();
+/// Parse CLI arguments passed to the binary. `args` includes the
+/// binary name.
+pub fn parse_args(args: Vec<OsString>) -> Mode {
+    let matches = match app().try_get_matches_from(&args) {
+        Ok(matches) => matches,
+        Err(e) => {
+            if !e.use_stderr() {
+                // --help or --version.
+                e.exit();
+            }
+            let _ = e.print();
+            // Invalid values often come from an environment variable
+            // the user has forgotten about, so say where they came from.
+            for note in env_notes(&app(), &e.to_string()) {
+                eprintln!("{}", note);
+            }
+            std::process::exit(2);
+        }
+    };
+
+    warnings::set_enabled(!matches.is_present("no-warnings"));
+    i18n::set_locale(match matches.value_of("lang-ui") {
+        Some(name) => Locale::from_name(name),
+        None => Locale::from_env(),
+    });
 
     let language_override = match matches.value_of_os("language") {
         Some(lang_str) => {
             if let Some(lang) = guess_language::from_extension(lang_str) {
                 Some(lang)
             } else {
-                eprintln!(
+                warnings::warn(format!(
                     "No language is associated with extension: {}",
                     lang_str.to_string_lossy()
-                );
+                ));
                 None
             }
         }
         None => None,
     };
 
-    if let Some(path) = matches.value_of("dump-syntax") {
-        return Mode::DumpSyntax {
-            path: path.to_string(),
-            language_override,
+    if let Some(completions_matches) = matches.subcommand_matches("completions") {
+        let shell = completions_matches
+            .value_of("shell")
+            .expect("Required by clap");
+        return Mode::Completions {
+            shell: Shell::from_name(shell).expect("Already validated by clap"),
         };
     }
 
-    if let Some(path) = matches.value_of("dump-ts") {
-        return Mode::DumpTreeSitter {
-            path: path.to_string(),
+    if matches.subcommand_matches("man").is_some() {
+        return Mode::ManPage;
+    }
+
+    if matches.is_present("list-languages") {
+        return Mode::ListLanguages;
+    }
+
+    if matches.is_present("print-schema") {
+        return Mode::PrintSchema;
+    }
+
+    if let Some(mut paths) = matches.values_of_os("compare-diffs") {
+        let old_path = paths.next().expect("clap requires two values").to_owned();
+        let new_path = paths.next().expect("clap requires two values").to_owned();
+        return Mode::CompareDiffs { old_path, new_path };
+    }
+
+    if let Some(session_path) = matches.value_of_os("replay") {
+        return Mode::Replay {
+            session_path: session_path.to_owned(),
+        };
+    }
+
+    if let Some(socket_path) = matches.value_of_os("daemon") {
+        return Mode::Daemon {
+            socket_path: socket_path.to_owned(),
+        };
+    }
+
+    if matches.is_present("self-test") {
+        return Mode::SelfTest;
+    }
+
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        return Mode::Bench {
+            path: bench_matches
+                .value_of_os("dir")
+                .expect("Required by clap")
+                .to_owned(),
             language_override,
         };
     }
 
-    let args: Vec<_> = matches.values_of_os("paths").unwrap_or_default().collect();
-    info!("CLI arguments: {:?}", args);
+    if let Some(path) = matches.value_of("dump-syntax") {
+        return Mode::DumpSyntax {
+            path: path.to_string(),
+            language_override,
+            as_dot: false,
+        };
+    }
 
-    // TODO: document these different ways of calling difftastic.
-    let (lhs_display_path, rhs_display_path, lhs_path, rhs_path, in_vcs) = match &args[..] {
-        [lhs_path, rhs_path] => (
-            lhs_path.to_owned(),
-            rhs_path.to_owned(),
-            lhs_path.to_owned(),
-            rhs_path.to_owned(),
-            false,
-        ),
-        [display_path, lhs_tmp_file, _lhs_hash, _lhs_mode, rhs_tmp_file, _rhs_hash, _rhs_mode] => {
-            // https://git-scm.com/docs/git#Documentation/git.txt-codeGITEXTERNALDIFFcode
-            (
-                display_path.to_owned(),
-                display_path.to_owned(),
-                lhs_tmp_file.to_owned(),
-                rhs_tmp_file.to_owned(),
-                true,
-            )
-        }
-        [old_name, lhs_tmp_file, _lhs_hash, _lhs_mode, rhs_tmp_file, _rhs_hash, _rhs_mode, new_name, _similarity] =>
-        {
-            // Rename file.
-            // TODO: where does git document these 9 arguments?
-            (
-                old_name.to_owned(),
-                new_name.to_owned(),
-                lhs_tmp_file.to_owned(),
-                rhs_tmp_file.to_owned(),
-                true,
-            )
-        }
-        _ => {
-            if !args.is_empty() {
-                eprintln!(
-                    "error: Difftastic does not support being called with {} argument{}.\n",
-                    args.len(),
-                    if args.len() == 1 { "" } else { "s" }
-                );
-            }
-            eprintln!("USAGE:\n\n    {}\n", USAGE);
-            eprintln!("For more information try --help");
-            std::process::exit(1);
-        }
-    };
+    if let Some(path) = matches.value_of("dump-syntax-dot") {
+        return Mode::DumpSyntax {
+            path: path.to_string(),
+            language_override,
+            as_dot: true,
+        };
+    }
+
+    if let Some(path) = matches.value_of("dump-ts") {
+        return Mode::DumpTreeSitter {
+            path: path.to_string(),
+            language_override,
+        };
+    }
 
     let display_width = if let Some(arg_width) = matches.value_of("width") {
         arg_width
@@ -292,6 +1258,14 @@ pub fn parse_args() -> Mode {
             "side-by-side" => DisplayMode::SideBySide,
             "side-by-side-show-both" => DisplayMode::SideBySideShowBoth,
             "inline" => DisplayMode::Inline,
+            "lhs-only" => DisplayMode::LhsOnly,
+            "rhs-only" => DisplayMode::RhsOnly,
+            "json" => DisplayMode::Json,
+            "html" => DisplayMode::Html,
+            "markdown" => DisplayMode::Markdown,
+            "raw-spans" => DisplayMode::RawSpans,
+            "churn-csv" => DisplayMode::ChurnCsv,
+            "stage-script" => DisplayMode::StageScript,
             _ => {
                 unreachable!("clap has already validated display")
             }
@@ -299,9 +1273,11 @@ pub fn parse_args() -> Mode {
     } else {
         DisplayMode::SideBySide
     };
+    let display_mode =
+        narrow_display_fallback(display_mode, display_width, matches.is_present("display"));
 
     let color_output = if let Some(color_when) = matches.value_of("color") {
-        if color_when == "always" {
+        if color_when == "always" || color_when == "ci" {
             ColorOutput::Always
         } else if color_when == "never" {
             ColorOutput::Never
@@ -322,13 +1298,35 @@ pub fn parse_args() -> Mode {
         BackgroundColor::Dark
     };
 
+    let color_depth = match matches.value_of("color-depth") {
+        _ if matches.value_of("color") == Some("ci") => ColorDepth::Ci,
+        Some("8") => ColorDepth::Basic,
+        Some("16") => ColorDepth::Bright,
+        Some("256") => ColorDepth::Extended,
+        _ => detect_color_depth(),
+    };
+
     let syntax_highlight = matches.value_of("syntax-highlight") == Some("on");
 
+    let (mut cost_model, default_node_limit) = diff_quality_preset(
+        matches
+            .value_of("diff-quality")
+            .expect("Always present as we've given clap a default"),
+    );
+    if let Some(cost) = matches.value_of("delimiter-cost") {
+        cost_model.unchanged_delimiter = cost.parse().expect("Value already validated by clap");
+    }
+    if let Some(cost) = matches.value_of("punctuation-cost") {
+        cost_model.unchanged_punctuation = cost.parse().expect("Value already validated by clap");
+    }
+
     let node_limit = matches
         .value_of("node-limit")
-        .expect("Always present as we've given clap a default")
-        .parse::<u32>()
-        .expect("Value already validated by clap");
+        .map_or(default_node_limit, |limit| {
+            limit
+                .parse::<u32>()
+                .expect("Value already validated by clap")
+        });
 
     let byte_limit = matches
         .value_of("byte-limit")
@@ -336,6 +1334,12 @@ pub fn parse_args() -> Mode {
         .parse::<usize>()
         .expect("Value already validated by clap");
 
+    let parse_error_limit = matches
+        .value_of("parse-error-limit")
+        .expect("Always present as we've given clap a default")
+        .parse::<f64>()
+        .expect("Value already validated by clap");
+
     let tab_width = matches
         .value_of("tab-width")
         .expect("Always present as we've given clap a default")
@@ -344,30 +1348,726 @@ pub fn parse_args() -> Mode {
 
     let print_unchanged = !matches.is_present("skip-unchanged");
     let missing_as_empty = matches.is_present("missing-as-empty");
+    let snapshots = matches.is_present("snapshots");
+    let reverse = matches.is_present("reverse");
+    let report_parse_errors = matches.is_present("report-parse-errors");
+    let show_minimap = matches.is_present("minimap");
+    let show_indentation = matches.is_present("show-indentation");
+    let show_legend = matches.is_present("legend");
+    let collapse_definitions = matches.is_present("collapse-definitions");
+    let group_by_definition = matches.is_present("group-by-definition");
+    let preview_lines = matches
+        .value_of("preview-lines")
+        .map(|s| s.parse::<usize>().expect("Already validated by clap"));
+    let summary_only = matches.is_present("summary-only");
+    let use_profiles = !matches.is_present("no-profiles");
+    let line_background = !matches.is_present("no-line-background");
+    let trim_trailing_spaces = matches.is_present("trim-trailing-spaces");
+    let header_format = matches.value_of("header-format").map(|s| s.to_string());
+    let copyable = matches.is_present("copyable");
+    let separator = matches.value_of("separator").map(|s| s.to_owned());
+    let separator_style = match matches
+        .value_of("separator-style")
+        .expect("Has a default value")
+    {
+        "dim" => SeparatorStyle::Dim,
+        "bold" => SeparatorStyle::Bold,
+        _ => SeparatorStyle::Plain,
+    };
+    let show_offsets = matches.is_present("show-offsets");
+    let show_generated = matches.is_present("show-generated");
+    let json_schema_version = matches
+        .value_of("schema-version")
+        .map(|s| s.parse::<u32>().expect("Already validated by clap"));
+    let hunk_order = match matches.value_of("order").expect("Has a default value") {
+        "size" => HunkOrder::Size,
+        "semantic" => HunkOrder::Semantic,
+        _ => HunkOrder::Position,
+    };
+    let alignment = match matches.value_of("alignment").expect("Has a default value") {
+        "filler" => Alignment::Filler,
+        _ => Alignment::Compact,
+    };
+    let strip_input_ansi = matches.is_present("strip-input-ansi");
+    let c_preprocessor = matches.is_present("c-preprocessor");
+    let api_only = matches.is_present("api-only");
+    let ignore_case = matches.is_present("ignore-case");
+    let max_depth = matches
+        .value_of("max-depth")
+        .map(|s| s.parse::<usize>().expect("Value already validated by clap"));
+    let time_limit = matches
+        .value_of("time-limit")
+        .map(|s| Duration::from_millis(s.parse::<u64>().expect("Value already validated by clap")));
+    let word_split = matches
+        .value_of("word-split")
+        .map(|s| word_split(s).expect("Value already validated by clap"))
+        .unwrap_or_default();
+    let diff_mode = match matches.value_of("mode").expect("Has a default value") {
+        "syntax" => DiffMode::Syntax,
+        "prose" => DiffMode::Prose,
+        _ => DiffMode::Auto,
+    };
+    let review_path = matches.value_of_os("interactive").map(|s| s.to_owned());
+    // The pager would swallow our prompts.
+    let use_pager = !matches.is_present("no-pager") && review_path.is_none();
+    let explain = matches
+        .value_of("explain")
+        .map(|s| parse_position(s).expect("Already validated by clap"));
+    let encoding_override = matches
+        .value_of("encoding")
+        .map(|label| Encoding::for_label(label.as_bytes()).expect("Already validated by clap"));
+    // Options set by the user take precedence over mode lines and
+    // .editorconfig.
+    let modeline_tab_width = matches.value_source("tab-width") == Some(ValueSource::DefaultValue);
+    let use_editorconfig = !matches.is_present("no-editorconfig");
+    let editorconfig = EditorConfigUse {
+        tab_width: use_editorconfig && modeline_tab_width,
+        charset: use_editorconfig && encoding_override.is_none(),
+    };
 
     let use_color = should_use_color(color_output);
 
-    let display_options = DisplayOptions {
+    let mut display_options = DisplayOptions {
         background_color,
         use_color,
+        color_depth,
         print_unchanged,
         tab_width,
         display_mode,
         display_width,
         syntax_highlight,
-        in_vcs,
+        in_vcs: false,
+        report_parse_errors,
+        show_minimap,
+        show_indentation,
+        show_legend,
+        collapse_definitions,
+        group_by_definition,
+        preview_lines,
+        summary_only,
+        use_profiles,
+        line_background,
+        trim_trailing_spaces,
+        header_format,
+        copyable,
+        separator,
+        separator_style,
+        alignment,
+        show_offsets,
+        json_schema_version,
+        collapse_generated: !show_generated,
+        hunk_order,
+        vcs_metadata: None,
+        stage_dir: matches.value_of_os("stage-dir").map(PathBuf::from),
     };
 
-    Mode::Diff {
+    let diff_options = DiffOptions {
         node_limit,
+        cost_model,
         byte_limit,
+        parse_error_limit,
+        strip_input_ansi,
+        c_preprocessor,
+        api_only,
+        diff_mode,
+        ignore_case,
+        max_depth,
+        time_limit,
+        word_split,
+        explain,
+        encoding_override,
+        editorconfig,
+        modeline_tab_width,
+        language_override,
+    };
+
+    if matches.is_present("print-config") {
+        return Mode::PrintConfig {
+            config: describe_config(&app(), &matches, &display_options, &diff_options),
+        };
+    }
+
+    let assert_matches = matches.subcommand_matches("assert-equal");
+    let assert_equal = matches.is_present("assert-equal") || assert_matches.is_some();
+    let metrics = matches.is_present("metrics");
+    let record = matches
+        .value_of_os("record")
+        .map(|path| Recording::new(path.to_owned(), &args));
+    // `--ignore-token-regex` is an option of the subcommand when
+    // there is one.
+    let ignore_token_regexes: Vec<Regex> = assert_matches
+        .unwrap_or(&matches)
+        .values_of("ignore-token-regex")
+        .map(|values| {
+            values
+                .map(|re| token_regex(re).expect("Already validated by clap"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        return Mode::Verify {
+            diff_options,
+            display_options,
+            manifest_path: verify_matches
+                .value_of_os("manifest")
+                .expect("Required by clap")
+                .to_owned(),
+        };
+    }
+
+    let source = if let Some(trees_matches) = matches.subcommand_matches("compare-trees") {
+        Some(PairSource::Trees {
+            mapping_path: trees_matches
+                .value_of_os("mapping")
+                .expect("Required by clap")
+                .to_owned(),
+            ignore_paths: trees_matches
+                .values_of_os("ignore-path")
+                .map(|paths| paths.map(IgnorePath::parse).collect())
+                .unwrap_or_default(),
+        })
+    } else {
+        matches
+            .value_of_os("files-from")
+            .map(|list_path| PairSource::List(list_path.to_owned()))
+    };
+    if let Some(source) = source {
+        return Mode::DiffFilesFrom {
+            diff_options,
+            display_options,
+            missing_as_empty,
+            source,
+            use_pager,
+            assert_equal,
+            ignore_token_regexes,
+            metrics,
+            review_path,
+        };
+    }
+
+    let args: Vec<_> = match assert_matches {
+        Some(assert_matches) => vec![
+            assert_matches
+                .value_of_os("expected")
+                .expect("Required by clap"),
+            assert_matches
+                .value_of_os("actual")
+                .expect("Required by clap"),
+        ],
+        None => matches.values_of_os("paths").unwrap_or_default().collect(),
+    };
+    info!("CLI arguments: {:?}", args);
+
+    if matches.is_present("vcs-batch") {
+        let files = match split_vcs_batch(&args) {
+            Some(files) => files,
+            None => {
+                eprintln!(
+                    "error: --vcs-batch needs 7 arguments for each file, or 9 for a renamed file, but got {} arguments.",
+                    args.len()
+                );
+                std::process::exit(2);
+            }
+        };
+        let files = files
+            .into_iter()
+            .map(|args| {
+                let file = VcsFile::new(args);
+                if reverse {
+                    file.reversed()
+                } else {
+                    file
+                }
+            })
+            .collect();
+
+        display_options.in_vcs = true;
+        return Mode::DiffVcsBatch {
+            diff_options,
+            display_options,
+            files,
+            use_pager,
+            assert_equal,
+            ignore_token_regexes,
+        };
+    }
+
+    // TODO: document these different ways of calling difftastic.
+    let (lhs_display_path, rhs_display_path, lhs_path, rhs_path, vcs_metadata) = match &args[..] {
+        [lhs_path, rhs_path] => (
+            lhs_path.to_owned(),
+            rhs_path.to_owned(),
+            lhs_path.to_owned(),
+            rhs_path.to_owned(),
+            None,
+        ),
+        [display_path, lhs_tmp_file, lhs_hash, lhs_mode, rhs_tmp_file, rhs_hash, rhs_mode] => {
+            // https://git-scm.com/docs/git#Documentation/git.txt-codeGITEXTERNALDIFFcode
+            (
+                display_path.to_owned(),
+                display_path.to_owned(),
+                lhs_tmp_file.to_owned(),
+                rhs_tmp_file.to_owned(),
+                Some(VcsMetadata::new(
+                    lhs_tmp_file,
+                    lhs_hash,
+                    lhs_mode,
+                    rhs_tmp_file,
+                    rhs_hash,
+                    rhs_mode,
+                )),
+            )
+        }
+        [old_name, lhs_tmp_file, lhs_hash, lhs_mode, rhs_tmp_file, rhs_hash, rhs_mode, new_name, _similarity] =>
+        {
+            // Rename file.
+            // TODO: where does git document these 9 arguments?
+            (
+                old_name.to_owned(),
+                new_name.to_owned(),
+                lhs_tmp_file.to_owned(),
+                rhs_tmp_file.to_owned(),
+                Some(VcsMetadata::new(
+                    lhs_tmp_file,
+                    lhs_hash,
+                    lhs_mode,
+                    rhs_tmp_file,
+                    rhs_hash,
+                    rhs_mode,
+                )),
+            )
+        }
+        paths if (MIN_MULTI_PANE_FILES..=MAX_MULTI_PANE_FILES).contains(&paths.len()) => {
+            let mut paths: Vec<_> = paths.iter().map(|path| path.to_os_string()).collect();
+            if reverse {
+                paths.reverse();
+            }
+
+            return Mode::DiffMany {
+                diff_options,
+                display_options,
+                paths,
+                use_pager,
+            };
+        }
+        _ => {
+            if !args.is_empty() {
+                eprintln!(
+                    "error: Difftastic does not support being called with {} argument{}.\n",
+                    args.len(),
+                    if args.len() == 1 { "" } else { "s" }
+                );
+            }
+            eprintln!("USAGE:\n\n    {}\n", USAGE);
+            eprintln!("For more information try --help");
+            std::process::exit(1);
+        }
+    };
+
+    let (lhs_display_path, rhs_display_path, lhs_path, rhs_path, vcs_metadata) = if reverse {
+        (
+            rhs_display_path,
+            lhs_display_path,
+            rhs_path,
+            lhs_path,
+            vcs_metadata.map(VcsMetadata::reversed),
+        )
+    } else {
+        (
+            lhs_display_path,
+            rhs_display_path,
+            lhs_path,
+            rhs_path,
+            vcs_metadata,
+        )
+    };
+
+    display_options.in_vcs = vcs_metadata.is_some();
+    display_options.vcs_metadata = vcs_metadata;
+
+    if matches.is_present("estimate") {
+        return Mode::Estimate {
+            diff_options,
+            display_options,
+            lhs_path: lhs_path.to_owned(),
+            rhs_path: rhs_path.to_owned(),
+            lhs_display_path: display_path(Path::new(&lhs_display_path)),
+            rhs_display_path: display_path(Path::new(&rhs_display_path)),
+        };
+    }
+
+    Mode::Diff {
+        diff_options,
         display_options,
         missing_as_empty,
-        language_override,
+        snapshots,
         lhs_path: lhs_path.to_owned(),
         rhs_path: rhs_path.to_owned(),
-        lhs_display_path: lhs_display_path.to_string_lossy().to_string(),
-        rhs_display_path: rhs_display_path.to_string_lossy().to_string(),
+        lhs_display_path: display_path(Path::new(&lhs_display_path)),
+        rhs_display_path: display_path(Path::new(&rhs_display_path)),
+        lhs_label: matches.value_of("lhs-label").map(|s| s.to_owned()),
+        rhs_label: matches.value_of("rhs-label").map(|s| s.to_owned()),
+        record,
+        use_pager,
+        assert_equal,
+        ignore_token_regexes,
+        metrics,
+        review_path,
+    }
+}
+
+/// Parse a `--word-split` value, such as `case,digits`.
+fn word_split(s: &str) -> Result<WordSplit, String> {
+    let mut word_split = WordSplit::default();
+    for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+        match rule {
+            "case" => word_split.case_transitions = true,
+            "digits" => word_split.digits = true,
+            "punctuation" => word_split.punctuation_runs = true,
+            _ => {
+                return Err(format!(
+                    "Unknown rule '{}', expected 'case', 'digits' or 'punctuation'.",
+                    rule
+                ))
+            }
+        }
+    }
+    Ok(word_split)
+}
+
+/// The cost model and default node limit for a `--diff-quality`
+/// preset.
+fn diff_quality_preset(quality: &str) -> (CostModel, u32) {
+    match quality {
+        "fast" => (CostModel::default(), 10_000),
+        "thorough" => (
+            CostModel {
+                unchanged_delimiter: 200,
+                unchanged_punctuation: 20,
+            },
+            100_000,
+        ),
+        _ => (CostModel::default(), DEFAULT_NODE_LIMIT),
+    }
+}
+
+/// Use inline display rather than side-by-side if the display is too
+/// narrow for two columns. If the user explicitly asked for
+/// side-by-side, warn them.
+fn narrow_display_fallback(
+    display_mode: DisplayMode,
+    display_width: usize,
+    explicit: bool,
+) -> DisplayMode {
+    match display_mode {
+        DisplayMode::SideBySide | DisplayMode::SideBySideShowBoth
+            if display_width < MIN_SIDE_BY_SIDE_WIDTH =>
+        {
+            if explicit {
+                warnings::warn(format!(
+                    "The display is {} columns wide, but side-by-side display needs at least {}. Using inline display.",
+                    display_width, MIN_SIDE_BY_SIDE_WIDTH
+                ));
+            } else {
+                info!(
+                    "Display width {} is narrower than {}, using inline display",
+                    display_width, MIN_SIDE_BY_SIDE_WIDTH
+                );
+            }
+            DisplayMode::Inline
+        }
+        _ => display_mode,
+    }
+}
+
+/// A clap validator for whole numbers that are at least `min`. The
+/// error message includes `example`, so users know what's expected.
+fn whole_number<T>(s: &str, min: T, example: T) -> Result<(), String>
+where
+    T: FromStr + PartialOrd + Display,
+{
+    match s.parse::<T>() {
+        Ok(n) if n >= min => Ok(()),
+        Ok(n) => Err(format!("must be at least {}, but got {}", min, n)),
+        Err(_) => Err(format!("must be a whole number, e.g. {}", example)),
+    }
+}
+
+/// Notes about environment variables that set an option mentioned in
+/// `error`.
+/// Does `text` mention `--long` itself, rather than only a longer
+/// option that starts with the same name?
+fn mentions_flag(text: &str, long: &str) -> bool {
+    let flag = format!("--{}", long);
+    text.match_indices(&flag).any(|(i, _)| {
+        !text[i + flag.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '-')
+    })
+}
+
+fn env_notes(cmd: &Command, error: &str) -> Vec<String> {
+    let mut notes = vec![];
+    for arg in cmd.get_arguments() {
+        let (long, env_name) = match (arg.get_long(), arg.get_env()) {
+            (Some(long), Some(env_name)) => (long, env_name),
+            _ => continue,
+        };
+        if !mentions_flag(error, long) {
+            continue;
+        }
+        if let Some(value) = env::var_os(env_name) {
+            notes.push(format!(
+                "note: --{} was set by the environment variable {}={}",
+                long,
+                env_name.to_string_lossy(),
+                value.to_string_lossy()
+            ));
+        }
+    }
+    notes
+}
+
+/// `value`'s `Debug` name in kebab case, as option values are
+/// written, e.g. `side-by-side` for `SideBySide`.
+fn kebab_case(value: impl std::fmt::Debug) -> String {
+    let mut res = String::new();
+    for (i, c) in format!("{:?}", value).chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            res.push('-');
+        }
+        res.extend(c.to_lowercase());
+    }
+    res
+}
+
+/// Describe the value of every option in `matches`, and where the
+/// value came from, followed by the settings we resolved from them.
+fn describe_config(
+    cmd: &Command,
+    matches: &ArgMatches,
+    display_options: &DisplayOptions,
+    diff_options: &DiffOptions,
+) -> String {
+    let mut rows: Vec<(String, String, String)> = vec![];
+    for arg in cmd.get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) => long,
+            None => continue,
+        };
+        if arg.is_hide_set()
+            || arg.get_help_heading().is_some()
+            || ["print-config", "list-languages", "print-schema"].contains(&long)
+        {
+            continue;
+        }
+
+        let value = if arg.is_takes_value_set() {
+            match matches.values_of_os(arg.get_id()) {
+                Some(values) => values
+                    .map(|v| v.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                None => "(unset)".to_owned(),
+            }
+        } else if matches.is_present(arg.get_id()) {
+            "on".to_owned()
+        } else {
+            "off".to_owned()
+        };
+
+        let source = match matches.value_source(arg.get_id()) {
+            Some(ValueSource::CommandLine) => "command line".to_owned(),
+            Some(ValueSource::EnvVariable) => match arg.get_env() {
+                Some(env_name) => format!("${}", env_name.to_string_lossy()),
+                None => "environment".to_owned(),
+            },
+            _ => "default".to_owned(),
+        };
+
+        rows.push((format!("--{}", long), value, source));
+    }
+
+    // Whether we're running under git isn't known until we've looked
+    // at the paths, so that isn't shown.
+    let resolved = |name: &str, value: String| (name.to_owned(), value, "resolved".to_owned());
+    let on_off = |on: bool| if on { "on" } else { "off" }.to_owned();
+    let or_unset = |value: Option<String>| value.unwrap_or_else(|| "(unset)".to_owned());
+    let d = display_options;
+    rows.extend(vec![
+        resolved("display mode", kebab_case(d.display_mode)),
+        resolved("display width", d.display_width.to_string()),
+        resolved("color", on_off(d.use_color)),
+        resolved("color depth", kebab_case(d.color_depth)),
+        resolved("background", kebab_case(d.background_color)),
+        resolved("syntax highlight", on_off(d.syntax_highlight)),
+        resolved("tab width", d.tab_width.to_string()),
+        resolved("unchanged files", on_off(d.print_unchanged)),
+        resolved("report parse errors", on_off(d.report_parse_errors)),
+        resolved("minimap", on_off(d.show_minimap)),
+        resolved("legend", on_off(d.show_legend)),
+        resolved("show indentation", on_off(d.show_indentation)),
+        resolved("collapse definitions", on_off(d.collapse_definitions)),
+        resolved("group by definition", on_off(d.group_by_definition)),
+        resolved(
+            "preview lines",
+            or_unset(d.preview_lines.map(|n| n.to_string())),
+        ),
+        resolved("summary only", on_off(d.summary_only)),
+        resolved("profiles", on_off(d.use_profiles)),
+        resolved("line background", on_off(d.line_background)),
+        resolved("trim trailing spaces", on_off(d.trim_trailing_spaces)),
+        resolved("header format", or_unset(d.header_format.clone())),
+        resolved("copyable", on_off(d.copyable)),
+        resolved(
+            "separator",
+            or_unset(d.separator.as_ref().map(|s| format!("{:?}", s))),
+        ),
+        resolved("separator style", kebab_case(d.separator_style)),
+        resolved("alignment", kebab_case(d.alignment)),
+        resolved("show offsets", on_off(d.show_offsets)),
+        resolved(
+            "schema version",
+            or_unset(d.json_schema_version.map(|v| v.to_string())),
+        ),
+        resolved("show generated", on_off(!d.collapse_generated)),
+        resolved("hunk order", kebab_case(d.hunk_order)),
+        resolved(
+            "stage dir",
+            or_unset(d.stage_dir.as_ref().map(|dir| display_path(dir))),
+        ),
+    ]);
+
+    let d = diff_options;
+    let word_split: Vec<&str> = [
+        ("case", d.word_split.case_transitions),
+        ("digits", d.word_split.digits),
+        ("punctuation", d.word_split.punctuation_runs),
+    ]
+    .iter()
+    .filter(|(_, on)| *on)
+    .map(|(name, _)| *name)
+    .collect();
+    rows.extend(vec![
+        resolved("diff mode", kebab_case(d.diff_mode)),
+        resolved("node limit", d.node_limit.to_string()),
+        resolved(
+            "delimiter cost",
+            d.cost_model.unchanged_delimiter.to_string(),
+        ),
+        resolved(
+            "punctuation cost",
+            d.cost_model.unchanged_punctuation.to_string(),
+        ),
+        resolved("byte limit", d.byte_limit.to_string()),
+        resolved("parse error limit", d.parse_error_limit.to_string()),
+        resolved(
+            "time limit",
+            or_unset(d.time_limit.map(|t| format!("{}ms", t.as_millis()))),
+        ),
+        resolved(
+            "max depth",
+            or_unset(d.max_depth.map(|depth| depth.to_string())),
+        ),
+        resolved("ignore case", on_off(d.ignore_case)),
+        resolved("strip input ansi", on_off(d.strip_input_ansi)),
+        resolved("c preprocessor", on_off(d.c_preprocessor)),
+        resolved("api only", on_off(d.api_only)),
+        resolved(
+            "word split",
+            if word_split.is_empty() {
+                "(unset)".to_owned()
+            } else {
+                word_split.join(",")
+            },
+        ),
+        resolved(
+            "explain",
+            or_unset(
+                d.explain
+                    .map(|(line, column)| format!("{}:{}", line.one_indexed(), column + 1)),
+            ),
+        ),
+        resolved(
+            "encoding",
+            or_unset(d.encoding_override.map(|e| e.name().to_owned())),
+        ),
+        resolved("editorconfig tab width", on_off(d.editorconfig.tab_width)),
+        resolved("editorconfig charset", on_off(d.editorconfig.charset)),
+        resolved("mode line tab width", on_off(d.modeline_tab_width)),
+        resolved(
+            "language",
+            or_unset(d.language_override.map(|l| format!("{:?}", l))),
+        ),
+    ]);
+
+    let name_width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let value_width = rows
+        .iter()
+        .map(|(_, value, _)| value.len())
+        .max()
+        .unwrap_or(0);
+    let mut res = String::new();
+    for (name, value, source) in rows {
+        res.push_str(&format!(
+            "{:name_width$}  {:value_width$}  {}\n",
+            name,
+            value,
+            source,
+            name_width = name_width,
+            value_width = value_width
+        ));
+    }
+    res
+}
+
+/// Parse a one-indexed `LINE:COLUMN` into a zero-indexed line number
+/// and column.
+fn parse_position(s: &str) -> Result<(LineNumber, usize), String> {
+    let invalid = || format!("Expected LINE:COLUMN, got: {}", s);
+    let (line, col) = s.split_once(':').ok_or_else(invalid)?;
+    let line = line.parse::<usize>().map_err(|_| invalid())?;
+    let col = col.parse::<usize>().map_err(|_| invalid())?;
+    if line == 0 || col == 0 {
+        return Err("Lines and columns start at 1".into());
+    }
+    Ok(((line - 1).into(), col - 1))
+}
+
+fn validate_token_regex(s: &str) -> Result<(), String> {
+    token_regex(s).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Check that a `--separator` doesn't contain control characters,
+/// whose width we can't know, so the columns stay aligned.
+fn validate_separator(separator: &str) -> Result<(), String> {
+    match separator.chars().find(|c| c.is_control()) {
+        Some(c) => Err(format!(
+            "must not contain control characters, but contains {:?}",
+            c
+        )),
+        None => Ok(()),
+    }
+}
+
+fn validate_header_format(format: &str) -> Result<(), String> {
+    let mut unknown = vec![];
+    expand_placeholders(format, |name| {
+        if HEADER_PLACEHOLDERS.contains(&name) {
+            Some(String::new())
+        } else {
+            unknown.push(format!("{{{}}}", name));
+            None
+        }
+    });
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Unknown placeholder: {}", unknown.join(", ")))
     }
 }
 
@@ -377,6 +2077,38 @@ fn detect_display_width() -> usize {
     term_size::dimensions().map_or(80, |(w, _)| w)
 }
 
+/// Guess how many colors the terminal supports from the name in
+/// `$TERM`. If we don't recognise the terminal, assume it's a modern
+/// terminal with 256 colors.
+fn color_depth_for_term(term: &str) -> ColorDepth {
+    if term.contains("256color") {
+        ColorDepth::Extended
+    } else if term.contains("16color") || term == "linux" {
+        ColorDepth::Bright
+    } else if term.ends_with("-color")
+        || term.contains("8color")
+        || term.starts_with("vt")
+        || term == "ansi"
+        || term == "cons25"
+    {
+        ColorDepth::Basic
+    } else {
+        ColorDepth::Extended
+    }
+}
+
+fn detect_color_depth() -> ColorDepth {
+    // Terminals that set $COLORTERM support at least 256 colors.
+    if env::var_os("COLORTERM").is_some() {
+        return ColorDepth::Extended;
+    }
+
+    match env::var("TERM") {
+        Ok(term) => color_depth_for_term(&term),
+        Err(_) => ColorDepth::Extended,
+    }
+}
+
 pub fn should_use_color(color_output: ColorOutput) -> bool {
     match color_output {
         ColorOutput::Always => true,
@@ -399,6 +2131,197 @@ mod tests {
         app().debug_assert();
     }
 
+    #[test]
+    fn test_assert_equal_subcommand() {
+        let matches = app()
+            .try_get_matches_from([
+                "difft",
+                "--display",
+                "inline",
+                "assert-equal",
+                "--ignore-token-regex",
+                "[0-9]+",
+                "expected.json",
+                "actual.json",
+            ])
+            .unwrap();
+        assert_eq!(matches.value_of("display"), Some("inline"));
+
+        let assert_matches = matches.subcommand_matches("assert-equal").unwrap();
+        assert_eq!(
+            assert_matches.value_of_os("expected"),
+            Some(OsStr::new("expected.json"))
+        );
+        assert_eq!(
+            assert_matches.value_of_os("actual"),
+            Some(OsStr::new("actual.json"))
+        );
+        assert_eq!(
+            assert_matches
+                .values_of("ignore-token-regex")
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec!["[0-9]+"]
+        );
+    }
+
+    #[test]
+    fn test_mentions_flag() {
+        let error = "error: \"xx\" isn't a valid value for '--lang-ui <LANG>'";
+        assert!(mentions_flag(error, "lang-ui"));
+        assert!(!mentions_flag(error, "lang"));
+        assert!(!mentions_flag(
+            "error: Invalid value for '--language <EXT>'",
+            "lang"
+        ));
+        assert!(mentions_flag("--width and --tab-width", "width"));
+    }
+
+    #[test]
+    fn test_split_vcs_batch() {
+        let modified = [
+            "a.rs", "/tmp/a", "1234567", "100644", "a.rs", "89abcde", "100644",
+        ];
+        let renamed = [
+            "b.rs",
+            "/tmp/b",
+            "1234567",
+            "100644",
+            "/tmp/c",
+            "89abcde",
+            "100644",
+            "c.rs",
+            "similarity index 90%",
+        ];
+        let args: Vec<&OsStr> = modified
+            .iter()
+            .chain(renamed.iter())
+            .chain(modified.iter())
+            .map(OsStr::new)
+            .collect();
+
+        let lens: Vec<usize> = split_vcs_batch(&args)
+            .unwrap()
+            .iter()
+            .map(|file| file.len())
+            .collect();
+        assert_eq!(lens, vec![7, 9, 7]);
+
+        let paths: Vec<&OsStr> = ["x"; 14].iter().map(OsStr::new).collect();
+        assert!(split_vcs_batch(&paths).is_none());
+    }
+
+    #[test]
+    fn test_narrow_display_fallback() {
+        assert!(matches!(
+            narrow_display_fallback(DisplayMode::SideBySide, 40, false),
+            DisplayMode::Inline
+        ));
+        assert!(matches!(
+            narrow_display_fallback(DisplayMode::SideBySide, 120, false),
+            DisplayMode::SideBySide
+        ));
+        assert!(matches!(
+            narrow_display_fallback(DisplayMode::Json, 40, false),
+            DisplayMode::Json
+        ));
+    }
+
+    #[test]
+    fn test_whole_number() {
+        assert!(whole_number::<usize>("80", 1, 120).is_ok());
+        assert_eq!(
+            whole_number::<usize>("0", 1, 120),
+            Err("must be at least 1, but got 0".to_owned())
+        );
+        assert_eq!(
+            whole_number::<usize>("wide", 1, 120),
+            Err("must be a whole number, e.g. 120".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_describe_config() {
+        let cmd = Command::new("test")
+            .arg(Arg::new("tab-width").long("tab-width").default_value("8"))
+            .arg(Arg::new("reverse").long("reverse"))
+            .arg(
+                Arg::new("header-format")
+                    .long("header-format")
+                    .takes_value(true),
+            );
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(["test", "--reverse"])
+            .unwrap();
+        let display_options = DisplayOptions {
+            display_mode: DisplayMode::SideBySideShowBoth,
+            ..DisplayOptions::default()
+        };
+        let diff_options = DiffOptions {
+            time_limit: Some(Duration::from_millis(500)),
+            ..DiffOptions::default()
+        };
+
+        let config = describe_config(&cmd, &matches, &display_options, &diff_options);
+        // Columns are separated by at least two spaces.
+        let rows: Vec<Vec<&str>> = config
+            .lines()
+            .map(|line| {
+                line.split("  ")
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows[..3],
+            [
+                vec!["--tab-width", "8", "default"],
+                vec!["--reverse", "on", "command line"],
+                vec!["--header-format", "(unset)", "default"],
+            ]
+        );
+        for row in [
+            vec!["display mode", "side-by-side-show-both", "resolved"],
+            vec!["color", "off", "resolved"],
+            vec!["color depth", "extended", "resolved"],
+            vec!["time limit", "500ms", "resolved"],
+            vec!["ignore case", "off", "resolved"],
+            vec!["language", "(unset)", "resolved"],
+        ] {
+            assert!(rows.contains(&row), "missing {:?}", row);
+        }
+    }
+
+    #[test]
+    fn test_validate_separator() {
+        assert!(validate_separator(" │ ").is_ok());
+        assert!(validate_separator("\t").is_err());
+        assert!(validate_separator("\x1b[31m|").is_err());
+    }
+
+    #[test]
+    fn test_validate_header_format() {
+        assert!(validate_header_format("{path} hunk {n}/{total}").is_ok());
+        assert!(validate_header_format("{path} {bogus}").is_err());
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(parse_position("12:5"), Ok((11.into(), 4)));
+        assert!(parse_position("0:5").is_err());
+        assert!(parse_position("12").is_err());
+    }
+
+    #[test]
+    fn test_color_depth_for_term() {
+        assert_eq!(color_depth_for_term("xterm-256color"), ColorDepth::Extended);
+        assert_eq!(color_depth_for_term("linux"), ColorDepth::Bright);
+        assert_eq!(color_depth_for_term("vt100"), ColorDepth::Basic);
+        assert_eq!(color_depth_for_term("xterm-color"), ColorDepth::Basic);
+    }
+
     #[test]
     fn test_detect_display_width() {
         // Basic smoke test.
@@ -0,0 +1,159 @@
+//! Piping output through a pager, like git does.
+//!
+//! We only start a pager when writing to a terminal, and never when
+//! git is running us: git already pipes external diff output through
+//! its own pager, so starting another would page twice.
+//!
+//! Only supported on Unix.
+
+use std::{
+    env,
+    io::{self, Write},
+    os::unix::io::AsRawFd,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use crate::warnings;
+
+/// Choose the pager command, following git's precedence:
+/// `$GIT_PAGER`, then `core.pager`, then `$PAGER`, then `less`.
+///
+/// `core_pager` runs git, so it's only called if `$GIT_PAGER` isn't
+/// set. Returns `None` if the pager is disabled, e.g. with `cat` or an
+/// empty string.
+fn pager_command(
+    git_pager: Option<String>,
+    core_pager: impl FnOnce() -> Option<String>,
+    pager: Option<String>,
+) -> Option<String> {
+    let command = git_pager
+        .or_else(core_pager)
+        .or(pager)
+        .unwrap_or_else(|| "less".into());
+
+    let command = command.trim();
+    if command.is_empty() || command == "cat" {
+        None
+    } else {
+        Some(command.to_owned())
+    }
+}
+
+/// Read `core.pager` from the user's git configuration, if git is
+/// installed.
+fn git_core_pager() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["config", "--get", "core.pager"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// The process ID of the pager, or 0 if there isn't one.
+static PAGER_PID: AtomicI32 = AtomicI32::new(0);
+
+/// Close our end of the pipe, so the pager sees EOF, then wait for
+/// the user to quit it. This runs at exit, so it also applies when
+/// we call `std::process::exit`.
+extern "C" fn wait_for_pager() {
+    let pid = PAGER_PID.load(Ordering::SeqCst);
+    if pid == 0 {
+        return;
+    }
+
+    let _ = io::stdout().flush();
+    unsafe {
+        libc::close(libc::STDOUT_FILENO);
+        let mut status = 0;
+        libc::waitpid(pid, &mut status, 0);
+    }
+}
+
+fn spawn(command: &str) {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command).stdin(Stdio::piped());
+    // Like git, tell less to pass through colours and to exit if
+    // the output fits on one screen. Users can override this by
+    // setting LESS themselves.
+    if env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    if env::var_os("LV").is_none() {
+        cmd.env("LV", "-c");
+    }
+
+    let child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warnings::warn(format!("Could not start pager {:?}: {}", command, e));
+            return;
+        }
+    };
+    let stdin = child.stdin.as_ref().expect("stdin is piped");
+
+    unsafe {
+        if libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) == -1 {
+            warnings::warn(format!("Could not redirect output to pager {:?}", command));
+            return;
+        }
+    }
+    PAGER_PID.store(child.id() as i32, Ordering::SeqCst);
+    unsafe {
+        libc::atexit(wait_for_pager);
+    }
+}
+
+/// Send the rest of our output through a pager, if appropriate. The
+/// pager is waited for when the process exits.
+pub fn start(use_pager: bool, in_vcs: bool) {
+    if !use_pager
+        || in_vcs
+        || env::var_os("GIT_PAGER_IN_USE").is_some()
+        || !atty::is(atty::Stream::Stdout)
+    {
+        return;
+    }
+
+    if let Some(command) = pager_command(
+        env::var("GIT_PAGER").ok(),
+        git_core_pager,
+        env::var("PAGER").ok(),
+    ) {
+        spawn(&command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command_disabled() {
+        assert_eq!(pager_command(None, || Some("cat".into()), None), None);
+        assert_eq!(pager_command(None, || Some("".into()), None), None);
+    }
+
+    #[test]
+    fn test_pager_command_core_pager() {
+        assert_eq!(
+            pager_command(None, || Some("less -S".into()), Some("more".into())),
+            Some("less -S".into())
+        );
+    }
+
+    #[test]
+    fn test_pager_command_git_pager_skips_git() {
+        assert_eq!(
+            pager_command(
+                Some("more".into()),
+                || panic!("git shouldn't run when GIT_PAGER is set"),
+                None
+            ),
+            Some("more".into())
+        );
+    }
+}
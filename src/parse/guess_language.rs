@@ -16,7 +16,7 @@ use std::{borrow::Borrow, ffi::OsStr, path::Path};
 
 /// Languages supported by difftastic. Each language here has a
 /// corresponding tree-sitter parser.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Language {
     Bash,
     C,
@@ -64,6 +64,9 @@ pub fn guess(path: &Path, src: &str) -> Option<Language> {
     if let Some(lang) = from_emacs_mode_header(src) {
         return Some(lang);
     }
+    if let Some(lang) = from_vim_modeline(src) {
+        return Some(lang);
+    }
     if let Some(lang) = from_shebang(src) {
         return Some(lang);
     }
@@ -71,12 +74,92 @@ pub fn guess(path: &Path, src: &str) -> Option<Language> {
         return Some(lang);
     }
 
-    match path.extension() {
+    let from_path = match path.extension() {
         Some(extension) => match from_extension(extension) {
-            Some(Language::Php) if src.starts_with("<?hh") => None,
+            Some(Language::Php) if src.starts_with("<?hh") => return None,
             language => language,
         },
         None => None,
+    };
+
+    // The path may not be meaningful, e.g. /dev/fd/63 when using
+    // process substitution, so look at the content too.
+    from_path.or_else(|| {
+        if src.starts_with("<?php") {
+            Some(Php)
+        } else {
+            None
+        }
+    })
+}
+
+/// Try to guess the language based on a vim modeline, such as `vim:
+/// set ft=rust:`, in the first or last lines of the file.
+fn from_vim_modeline(src: &str) -> Option<Language> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\bvim?:.*\b(?:ft|filetype)=([A-Za-z0-9+]+)").unwrap();
+    }
+
+    let lines: Vec<&str> = src.lines().collect();
+    let num_edge_lines = 5;
+    let last_lines_start = lines
+        .len()
+        .saturating_sub(num_edge_lines)
+        .max(num_edge_lines);
+
+    for line in lines
+        .iter()
+        .take(num_edge_lines)
+        .chain(lines.iter().skip(last_lines_start))
+    {
+        if let Some(cap) = RE.captures(line) {
+            let lang = from_vim_filetype(&cap[1]);
+            if lang.is_some() {
+                return lang;
+            }
+        }
+    }
+
+    None
+}
+
+/// The language for a vim `filetype` name, such as `rust`.
+fn from_vim_filetype(name: &str) -> Option<Language> {
+    match name {
+        "bash" | "sh" | "zsh" => Some(Bash),
+        "c" => Some(C),
+        "clojure" => Some(Clojure),
+        "cpp" => Some(CPlusPlus),
+        "cs" => Some(CSharp),
+        "css" => Some(Css),
+        "dart" => Some(Dart),
+        "elixir" => Some(Elixir),
+        "elm" => Some(Elm),
+        "go" => Some(Go),
+        "haskell" => Some(Haskell),
+        "hcl" => Some(Hcl),
+        "java" => Some(Java),
+        "javascript" => Some(JavaScript),
+        "javascriptreact" => Some(Jsx),
+        "json" => Some(Json),
+        "kotlin" => Some(Kotlin),
+        "lisp" => Some(CommonLisp),
+        "lua" => Some(Lua),
+        "nix" => Some(Nix),
+        "ocaml" => Some(OCaml),
+        "perl" => Some(Perl),
+        "php" => Some(Php),
+        "python" => Some(Python),
+        "ruby" => Some(Ruby),
+        "rust" => Some(Rust),
+        "scala" => Some(Scala),
+        "swift" => Some(Swift),
+        "toml" => Some(Toml),
+        "typescript" => Some(TypeScript),
+        "typescriptreact" => Some(Tsx),
+        "yaml" => Some(Yaml),
+        "zig" => Some(Zig),
+        _ => None,
     }
 }
 
@@ -197,57 +280,92 @@ fn from_name(path: &Path) -> Option<Language> {
     }
 }
 
+/// Every language, with the file extensions that use it.
+pub const LANGUAGE_EXTENSIONS: &[(Language, &[&str])] = &[
+    (
+        Bash,
+        &[
+            "sh", "bash", "bats", "cgi", "command", "env", "fcgi", "ksh", "sh.in", "tmux", "tool",
+            "zsh",
+        ],
+    ),
+    (C, &["c"]),
+    // Treat .h as C++ rather than C. This is an arbitrary choice,
+    // but C++ is more widely used than C according to
+    // https://madnight.github.io/githut/
+    (CPlusPlus, &["cc", "cpp", "h", "hh", "hpp", "cxx"]),
+    (
+        Clojure,
+        &[
+            "bb", "boot", "clj", "cljc", "clje", "cljs", "cljx", "edn", "joke", "joker",
+        ],
+    ),
+    (CommonLisp, &["lisp", "lsp", "asd"]),
+    (CSharp, &["cs"]),
+    (Css, &["css"]),
+    (Dart, &["dart"]),
+    (EmacsLisp, &["el"]),
+    (Elm, &["elm"]),
+    (Elixir, &["ex", "exs"]),
+    (Elvish, &["elv"]),
+    (Gleam, &["gleam"]),
+    (Go, &["go"]),
+    (Haskell, &["hs"]),
+    (Hcl, &["hcl", "nomad", "tf", "tfvars", "worfklow"]),
+    (Janet, &["janet", "jdn"]),
+    (Java, &["java"]),
+    (JavaScript, &["cjs", "js", "mjs"]),
+    (Jsx, &["jsx"]),
+    (
+        Json,
+        &[
+            "json",
+            "avsc",
+            "geojson",
+            "gltf",
+            "har",
+            "ice",
+            "JSON-tmLanguage",
+            "jsonl",
+            "mcmeta",
+            "tfstate",
+            "tfstate.backup",
+            "topojson",
+            "webapp",
+            "webmanifest",
+        ],
+    ),
+    (Kotlin, &["kt", "ktm", "kts"]),
+    (Lua, &["lua"]),
+    (Nix, &["nix"]),
+    (OCaml, &["ml"]),
+    (OCamlInterface, &["mli"]),
+    (Php, &["php"]),
+    (Perl, &["pm", "pl"]),
+    (Python, &["py", "py3", "pyi", "bzl"]),
+    (Ruby, &["rb", "builder", "spec", "rake"]),
+    (Rust, &["rs"]),
+    (Scala, &["scala", "sbt", "sc"]),
+    (Swift, &["swift"]),
+    (Toml, &["toml"]),
+    (TypeScript, &["ts"]),
+    (Tsx, &["tsx"]),
+    (Yaml, &["yaml", "yml"]),
+    (Zig, &["zig"]),
+];
+
 pub fn from_extension(extension: &OsStr) -> Option<Language> {
-    match extension.to_string_lossy().borrow() {
-        "sh" | "bash" | "bats" | "cgi" | "command" | "env" | "fcgi" | "ksh" | "sh.in" | "tmux"
-        | "tool" | "zsh" => Some(Bash),
-        "c" => Some(C),
-        // Treat .h as C++ rather than C. This is an arbitrary choice,
-        // but C++ is more widely used than C according to
-        // https://madnight.github.io/githut/
-        "cc" | "cpp" | "h" | "hh" | "hpp" | "cxx" => Some(CPlusPlus),
-        "bb" | "boot" | "clj" | "cljc" | "clje" | "cljs" | "cljx" | "edn" | "joke" | "joker" => {
-            Some(Clojure)
-        }
-        "lisp" | "lsp" | "asd" => Some(CommonLisp),
-        "cs" => Some(CSharp),
-        "css" => Some(Css),
-        "dart" => Some(Dart),
-        "el" => Some(EmacsLisp),
-        "elm" => Some(Elm),
-        "ex" | "exs" => Some(Elixir),
-        "elv" => Some(Elvish),
-        "gleam" => Some(Gleam),
-        "go" => Some(Go),
-        "hs" => Some(Haskell),
-        "hcl" | "nomad" | "tf" | "tfvars" | "worfklow" => Some(Hcl),
-        "janet" | "jdn" => Some(Janet),
-        "java" => Some(Java),
-        "cjs" | "js" | "mjs" => Some(JavaScript),
-        "jsx" => Some(Jsx),
-        "json" | "avsc" | "geojson" | "gltf" | "har" | "ice" | "JSON-tmLanguage" | "jsonl"
-        | "mcmeta" | "tfstate" | "tfstate.backup" | "topojson" | "webapp" | "webmanifest" => {
-            Some(Json)
-        }
-        "kt" | "ktm" | "kts" => Some(Kotlin),
-        "lua" => Some(Lua),
-        "nix" => Some(Nix),
-        "ml" => Some(OCaml),
-        "mli" => Some(OCamlInterface),
-        "php" => Some(Php),
-        "pm" | "pl" => Some(Perl),
-        "py" | "py3" | "pyi" | "bzl" => Some(Python),
-        "rb" | "builder" | "spec" | "rake" => Some(Ruby),
-        "rs" => Some(Rust),
-        "scala" | "sbt" | "sc" => Some(Scala),
-        "swift" => Some(Swift),
-        "toml" => Some(Toml),
-        "ts" => Some(TypeScript),
-        "tsx" => Some(Tsx),
-        "yaml" | "yml" => Some(Yaml),
-        "zig" => Some(Zig),
-        _ => None,
-    }
+    let extension = extension.to_string_lossy();
+    LANGUAGE_EXTENSIONS
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&&*extension))
+        .map(|(language, _)| *language)
+}
+
+/// The language for a file extension, such as `rb`, or a vim
+/// filetype name, such as `ruby`.
+pub fn from_extension_or_name(name: &str) -> Option<Language> {
+    from_extension(OsStr::new(name)).or_else(|| from_vim_filetype(name))
 }
 
 #[cfg(test)]
@@ -309,9 +427,31 @@ mod tests {
         assert_eq!(guess(path, "# -*-python-*-"), Some(Python));
     }
 
+    #[test]
+    fn test_guess_by_vim_modeline() {
+        let path = Path::new("/dev/fd/63");
+        assert_eq!(guess(path, "x = 1\n# vim: set ft=python:"), Some(Python));
+    }
+
+    #[test]
+    fn test_guess_php_by_content() {
+        let path = Path::new("/dev/fd/63");
+        assert_eq!(guess(path, "<?php\necho 1;"), Some(Php));
+    }
+
     #[test]
     fn test_guess_unknown() {
         let path = Path::new("jfkdlsjfkdsljfkdsljf");
         assert_eq!(guess(path, ""), None);
     }
+
+    #[test]
+    fn test_extensions_are_unique() {
+        let mut seen = std::collections::HashSet::new();
+        for (_, extensions) in LANGUAGE_EXTENSIONS {
+            for extension in *extensions {
+                assert!(seen.insert(extension), "Duplicate extension {}", extension);
+            }
+        }
+    }
 }
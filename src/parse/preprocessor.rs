@@ -0,0 +1,291 @@
+//! Handling C preprocessor conditionals before parsing.
+//!
+//! Code such as
+//!
+//! ```text
+//! #ifdef DEBUG
+//! if (x && check(x)) {
+//! #else
+//! if (x) {
+//! #endif
+//!     run(x);
+//! }
+//! ```
+//!
+//! confuses tree-sitter, because neither branch of the `#ifdef` is
+//! well-formed on its own. We parse the active branch of each
+//! conditional as code, and turn the directives and the other
+//! branches into line comments.
+//!
+//! A branch is inactive if its condition is known to be false. We
+//! know about integer literals, `defined`, `!` and macros that are
+//! `#define`d or `#undef`ed earlier in the file. Other conditions
+//! may be true, so the first branch that may be true is active.
+
+use rustc_hash::FxHashMap;
+
+/// Macros defined earlier in the file, with their values. `None`
+/// means the macro was `#undef`ed.
+type Macros<'a> = FxHashMap<&'a str, Option<&'a str>>;
+
+/// Macro values that refer to other macros are expanded at most this
+/// many times, so recursive macros terminate.
+const MAX_EXPANSION_DEPTH: usize = 8;
+
+/// Turn `line` into a line comment without changing its length, so
+/// positions in the masked source match the original source.
+fn comment_out(line: &str) -> String {
+    let indent_len = line.len() - line.trim_start().len();
+    let content_len = line.len() - indent_len;
+
+    let mut res = String::with_capacity(line.len());
+    res.push_str(&line[..indent_len]);
+
+    if content_len >= 2 && line.is_char_boundary(indent_len + 2) {
+        res.push_str("//");
+        res.push_str(&line[indent_len + 2..]);
+    } else {
+        // Too short to hold a comment marker, so blank it out.
+        res.push_str(&" ".repeat(content_len));
+    }
+
+    // A trailing backslash would continue the comment onto the next
+    // line.
+    if res.ends_with('\\') {
+        res.pop();
+        res.push(' ');
+    }
+
+    res
+}
+
+/// Split an identifier from the start of `s`.
+fn split_identifier(s: &str) -> Option<(&str, &str)> {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    if end == 0 {
+        None
+    } else {
+        Some((&s[..end], &s[end..]))
+    }
+}
+
+/// The name and arguments of a preprocessor directive, e.g. `("if",
+/// "X > 1")` for `#if X > 1`.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let (name, args) = split_identifier(rest)?;
+    Some((name, args.trim()))
+}
+
+/// Is the macro named at the start of `args` defined? Returns `None`
+/// if it isn't mentioned earlier in the file, as it may be defined
+/// when compiling.
+fn is_defined(args: &str, macros: &Macros) -> Option<bool> {
+    let (name, _) = split_identifier(args.trim())?;
+    macros.get(name).map(Option::is_some)
+}
+
+/// Evaluate the condition of an `#if` or `#elif`. Returns `None` if
+/// we don't know whether it's true.
+fn eval_condition(condition: &str, macros: &Macros, depth: usize) -> Option<bool> {
+    if depth > MAX_EXPANSION_DEPTH {
+        return None;
+    }
+
+    let condition = condition
+        .split("//")
+        .next()
+        .unwrap_or_default()
+        .split("/*")
+        .next()
+        .unwrap_or_default()
+        .trim();
+
+    if let Some(rest) = condition.strip_prefix('!') {
+        return eval_condition(rest, macros, depth).map(|value| !value);
+    }
+    if let Some(inner) = condition
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        return eval_condition(inner, macros, depth);
+    }
+    if let Ok(value) = condition.parse::<i64>() {
+        return Some(value != 0);
+    }
+
+    let (name, rest) = split_identifier(condition)?;
+    if name == "defined" {
+        let rest = rest.trim();
+        let args = rest
+            .strip_prefix('(')
+            .and_then(|args| args.strip_suffix(')'))
+            .unwrap_or(rest);
+        return match split_identifier(args.trim()) {
+            Some((_, after)) if after.trim().is_empty() => is_defined(args, macros),
+            _ => None,
+        };
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+    match macros.get(name)? {
+        Some(value) => eval_condition(value, macros, depth + 1),
+        // An undefined macro is 0 in a condition.
+        None => Some(false),
+    }
+}
+
+/// An `#if` that encloses the current line.
+struct Conditional {
+    /// Has a branch of this conditional been active? No later branch
+    /// can be.
+    taken: bool,
+    /// Is the current branch active?
+    active: bool,
+}
+
+/// Return a copy of `src` where only the active branch of each
+/// preprocessor conditional is code. The result has the same length
+/// and line structure as `src`.
+pub fn mask_inactive_branches(src: &str) -> String {
+    let mut conditionals: Vec<Conditional> = vec![];
+    let mut macros = Macros::default();
+    let all_active = |conditionals: &[Conditional]| conditionals.iter().all(|c| c.active);
+
+    let mut res = String::with_capacity(src.len());
+    for line in src.split_inclusive('\n') {
+        let (line, newline) = match line.strip_suffix('\n') {
+            Some(line) => (line, "\n"),
+            None => (line, ""),
+        };
+
+        match directive(line) {
+            Some((name @ ("if" | "ifdef" | "ifndef"), args)) => {
+                let enclosing_active = all_active(&conditionals);
+                let condition = match name {
+                    "ifdef" => is_defined(args, &macros),
+                    "ifndef" => is_defined(args, &macros).map(|defined| !defined),
+                    _ => eval_condition(args, &macros, 0),
+                };
+                let active = enclosing_active && condition != Some(false);
+                conditionals.push(Conditional {
+                    taken: active || !enclosing_active,
+                    active,
+                });
+                res.push_str(&comment_out(line));
+            }
+            Some((name @ ("elif" | "else"), args)) => {
+                if let Some(conditional) = conditionals.last_mut() {
+                    let condition = if name == "elif" {
+                        eval_condition(args, &macros, 0)
+                    } else {
+                        Some(true)
+                    };
+                    conditional.active = !conditional.taken && condition != Some(false);
+                    conditional.taken |= conditional.active;
+                }
+                res.push_str(&comment_out(line));
+            }
+            Some(("endif", _)) => {
+                conditionals.pop();
+                res.push_str(&comment_out(line));
+            }
+            _ if !all_active(&conditionals) => res.push_str(&comment_out(line)),
+            Some(("define", args)) => {
+                if let Some((name, value)) = split_identifier(args) {
+                    macros.insert(name, Some(value.trim()));
+                }
+                res.push_str(line);
+            }
+            Some(("undef", args)) => {
+                if let Some((name, _)) = split_identifier(args) {
+                    macros.insert(name, None);
+                }
+                res.push_str(line);
+            }
+            _ => res.push_str(line),
+        }
+
+        res.push_str(newline);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_mask_inactive_branches() {
+        let src = "#ifdef X\nif (a) {\n#else\nif (b) {\n#endif\n}\n";
+        assert_eq!(
+            mask_inactive_branches(src),
+            "//fdef X\nif (a) {\n//lse\n//(b) {\n//ndif\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_mask_preserves_length() {
+        let src = "#if A\n  x\n#else\n  }\n#endif";
+        let masked = mask_inactive_branches(src);
+        assert_eq!(masked.len(), src.len());
+        assert_eq!(masked, "//f A\n  x\n//lse\n   \n//ndif");
+    }
+
+    #[test]
+    fn test_mask_ignores_other_directives() {
+        let src = "#include <stdio.h>\n#define IF 1\n";
+        assert_eq!(mask_inactive_branches(src), src);
+    }
+
+    #[test]
+    fn test_mask_keeps_active_branch() {
+        let src = "#if 0\nold();\n#else\nnew();\n#endif\n";
+        assert_eq!(
+            mask_inactive_branches(src),
+            "//f 0\n//d();\n//lse\nnew();\n//ndif\n"
+        );
+    }
+
+    #[test]
+    fn test_mask_uses_defines() {
+        let src = "#define DEBUG 1\n#ifndef DEBUG\na();\n#elif DEBUG\nb();\n#endif\n";
+        assert_eq!(
+            mask_inactive_branches(src),
+            "#define DEBUG 1\n//fndef DEBUG\n//();\n//lif DEBUG\nb();\n//ndif\n"
+        );
+    }
+
+    #[test]
+    fn test_mask_nested_in_inactive_branch() {
+        let src = "#if 0\n#if 1\nx\n#endif\n#endif";
+        assert_eq!(
+            mask_inactive_branches(src),
+            "//f 0\n//f 1\n \n//ndif\n//ndif"
+        );
+    }
+
+    #[test]
+    fn test_eval_condition() {
+        let mut macros = Macros::default();
+        macros.insert("ON", Some("1"));
+        macros.insert("OFF", None);
+        assert_eq!(eval_condition("0 // disabled", &macros, 0), Some(false));
+        assert_eq!(eval_condition("!defined(ON)", &macros, 0), Some(false));
+        assert_eq!(eval_condition("defined OFF", &macros, 0), Some(false));
+        assert_eq!(eval_condition("(ON)", &macros, 0), Some(true));
+        assert_eq!(eval_condition("OFF", &macros, 0), Some(false));
+        assert_eq!(eval_condition("UNKNOWN", &macros, 0), None);
+        assert_eq!(eval_condition("ON && UNKNOWN", &macros, 0), None);
+    }
+
+    #[test]
+    fn test_comment_out_trailing_backslash() {
+        assert_eq!(comment_out("foo \\"), "//o  ");
+    }
+}
@@ -28,6 +28,13 @@ impl<'a> fmt::Debug for ChangeKind<'a> {
                     rhs_node.id()
                 )
             }
+            ReplacedString(lhs_node, rhs_node) => {
+                format!(
+                    "ReplacedString(lhs ID: {}, rhs ID: {})",
+                    lhs_node.id(),
+                    rhs_node.id()
+                )
+            }
             Novel => "Novel".to_owned(),
         };
         f.write_str(&desc)
@@ -60,6 +67,8 @@ pub struct SyntaxInfo<'a> {
     ///
     /// Values are sequential, not hashes. Collisions never occur.
     content_id: Cell<u32>,
+    /// Does another node with the same parent have the same content?
+    has_duplicate_sibling: Cell<bool>,
 }
 
 impl<'a> SyntaxInfo<'a> {
@@ -73,6 +82,7 @@ impl<'a> SyntaxInfo<'a> {
             num_ancestors: Cell::new(0),
             unique_id: Cell::new(NonZeroU32::new(u32::MAX).unwrap()),
             content_id: Cell::new(0),
+            has_duplicate_sibling: Cell::new(false),
         }
     }
 }
@@ -151,7 +161,7 @@ impl<'a> fmt::Debug for Syntax<'a> {
                 content,
                 position,
                 info,
-                kind: highlight,
+                kind,
                 ..
             } => {
                 let mut ds = f.debug_struct(&format!(
@@ -161,9 +171,9 @@ impl<'a> fmt::Debug for Syntax<'a> {
                 ));
                 ds.field("content", &content);
                 ds.field("position", &dbg_pos(position));
+                ds.field("kind", kind);
 
                 if env::var("DFT_VERBOSE").is_ok() {
-                    ds.field("highlight", highlight);
                     let next_sibling_s = match info.next_sibling.get() {
                         Some(List { .. }) => "Some(List)",
                         Some(Atom { .. }) => "Some(Atom)",
@@ -178,6 +188,211 @@ impl<'a> fmt::Debug for Syntax<'a> {
     }
 }
 
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_dot_node(node: &Syntax, out: &mut String) {
+    let label = match node {
+        List {
+            open_content,
+            open_position,
+            close_content,
+            close_position,
+            ..
+        } => format!(
+            "List {} {}\n{} {}",
+            open_content,
+            close_content,
+            dbg_pos(open_position),
+            dbg_pos(close_position)
+        ),
+        Atom {
+            content,
+            position,
+            kind,
+            ..
+        } => format!("Atom {:?} {}\n{}", kind, content, dbg_pos(position)),
+    };
+    out.push_str(&format!(
+        "  n{} [label=\"{}\"];\n",
+        node.id(),
+        dot_escape(&label)
+    ));
+
+    if let List { children, .. } = node {
+        for child in children {
+            out.push_str(&format!("  n{} -> n{};\n", node.id(), child.id()));
+            write_dot_node(child, out);
+        }
+    }
+}
+
+/// Describe the syntax tree as a Graphviz graph, for
+/// `--dump-syntax-dot`. Nodes must have had their IDs set by
+/// `init_all_info`.
+pub fn to_dot(roots: &[&Syntax]) -> String {
+    let mut out = String::from("digraph syntax {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for root in roots {
+        write_dot_node(root, &mut out);
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Push the content and positions of every token in `node`.
+fn flatten_tokens(node: &Syntax, contents: &mut Vec<String>, positions: &mut Vec<SingleLineSpan>) {
+    match node {
+        List {
+            open_content,
+            open_position,
+            children,
+            close_content,
+            close_position,
+            ..
+        } => {
+            if !open_content.is_empty() {
+                contents.push(open_content.clone());
+            }
+            positions.extend(open_position);
+            for child in children {
+                flatten_tokens(child, contents, positions);
+            }
+            if !close_content.is_empty() {
+                contents.push(close_content.clone());
+            }
+            positions.extend(close_position);
+        }
+        Atom {
+            content, position, ..
+        } => {
+            contents.push(content.clone());
+            positions.extend(position);
+        }
+    }
+}
+
+/// An atom that `collapse_deep_lists` made from a list.
+pub struct CollapsedList<'a> {
+    pub node: &'a Syntax<'a>,
+    /// Where the list was, such as `"a" > "b"` for the value of
+    /// `{"a": {"b": ...}}`.
+    pub path: String,
+}
+
+/// The label of a list in a `CollapsedList` path: its first token,
+/// if the list has no delimiters and starts with an atom. This is the
+/// key of a JSON or YAML pair.
+fn list_label(open_content: &str, children: &[&Syntax]) -> Option<String> {
+    match children.first() {
+        Some(Atom { content, .. }) if open_content.is_empty() => Some(content.clone()),
+        _ => None,
+    }
+}
+
+fn collapse_deep_list<'a>(
+    arena: &'a Arena<Syntax<'a>>,
+    node: &'a Syntax<'a>,
+    depth: usize,
+    max_depth: usize,
+    labels: &mut Vec<String>,
+    collapsed: &mut Vec<CollapsedList<'a>>,
+) -> &'a Syntax<'a> {
+    match node {
+        List {
+            open_content,
+            open_position,
+            children,
+            close_content,
+            close_position,
+            ..
+        } => {
+            let label = list_label(open_content, children);
+            let has_label = label.is_some();
+            labels.extend(label);
+
+            let res = if depth > max_depth {
+                let mut contents = vec![];
+                let mut positions = vec![];
+                flatten_tokens(node, &mut contents, &mut positions);
+                let path = if labels.is_empty() {
+                    match positions.first() {
+                        Some(pos) => format!("line {}", pos.line.one_indexed()),
+                        None => "?".into(),
+                    }
+                } else {
+                    labels.join(" > ")
+                };
+
+                let atom =
+                    Syntax::new_atom(arena, positions, &contents.join(" "), AtomKind::Normal);
+                collapsed.push(CollapsedList { node: atom, path });
+                atom
+            } else {
+                let children = children
+                    .iter()
+                    .map(|child| {
+                        collapse_deep_list(arena, child, depth + 1, max_depth, labels, collapsed)
+                    })
+                    .collect();
+                Syntax::new_list(
+                    arena,
+                    open_content,
+                    open_position.clone(),
+                    children,
+                    close_content,
+                    close_position.clone(),
+                )
+            };
+
+            if has_label {
+                labels.pop();
+            }
+            res
+        }
+        Atom { .. } => node,
+    }
+}
+
+/// Replace every list that is nested more than `max_depth` levels
+/// deep, i.e. inside more than `max_depth` other lists, with a single
+/// atom containing all its tokens. Top-level lists are at depth 0.
+///
+/// This makes deeply nested data files much cheaper to diff. A change
+/// anywhere inside a collapsed list marks the whole list as novel,
+/// but the enclosing lists are still diffed normally, so we still
+/// show which part of the file changed. The collapsed lists are added
+/// to `collapsed`.
+///
+/// Must be called before `init_all_info`.
+pub fn collapse_deep_lists<'a>(
+    arena: &'a Arena<Syntax<'a>>,
+    roots: &[&'a Syntax<'a>],
+    max_depth: usize,
+    collapsed: &mut Vec<CollapsedList<'a>>,
+) -> Vec<&'a Syntax<'a>> {
+    roots
+        .iter()
+        .map(|root| collapse_deep_list(arena, root, 0, max_depth, &mut vec![], collapsed))
+        .collect()
+}
+
+/// The paths of collapsed lists that changed, without duplicates.
+pub fn changed_collapsed_paths<'a>(
+    collapsed: &[CollapsedList<'a>],
+    change_map: &ChangeMap<'a>,
+) -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    for list in collapsed {
+        if !matches!(change_map.get(list.node), Some(Unchanged(_))) && !paths.contains(&list.path) {
+            paths.push(list.path.clone());
+        }
+    }
+    paths
+}
+
 impl<'a> Syntax<'a> {
     pub fn new_list(
         arena: &'a Arena<Syntax<'a>>,
@@ -260,6 +475,10 @@ impl<'a> Syntax<'a> {
         self.info().next_sibling.get()
     }
 
+    pub fn previous_sibling(&self) -> Option<&'a Syntax<'a>> {
+        self.info().previous_sibling.get()
+    }
+
     pub fn prev_is_contiguous(&self) -> bool {
         self.info().prev_is_contiguous.get()
     }
@@ -281,6 +500,10 @@ impl<'a> Syntax<'a> {
         self.info().num_ancestors.get()
     }
 
+    pub fn has_duplicate_sibling(&self) -> bool {
+        self.info().has_duplicate_sibling.get()
+    }
+
     pub fn dbg_content(&self) -> String {
         match self {
             List {
@@ -327,24 +550,47 @@ impl<'a> Syntax<'a> {
 
 /// Initialise all the fields in `SyntaxInfo`.
 pub fn init_all_info<'a>(lhs_roots: &[&'a Syntax<'a>], rhs_roots: &[&'a Syntax<'a>]) {
-    init_info(lhs_roots, rhs_roots);
+    init_all_info_with_case(lhs_roots, rhs_roots, false);
+}
+
+/// Initialise all the fields in `SyntaxInfo`. If `ignore_case` is
+/// true, atoms and delimiters that only differ in case have the same
+/// content ID, so they're treated as unchanged.
+pub fn init_all_info_with_case<'a>(
+    lhs_roots: &[&'a Syntax<'a>],
+    rhs_roots: &[&'a Syntax<'a>],
+    ignore_case: bool,
+) {
+    init_info(lhs_roots, rhs_roots, ignore_case);
     init_next_prev(lhs_roots);
     init_next_prev(rhs_roots);
 }
 
-fn init_info<'a>(lhs_roots: &[&'a Syntax<'a>], rhs_roots: &[&'a Syntax<'a>]) {
+fn init_info<'a>(lhs_roots: &[&'a Syntax<'a>], rhs_roots: &[&'a Syntax<'a>], ignore_case: bool) {
     let mut id = NonZeroU32::new(1).unwrap();
     init_info_single(lhs_roots, &mut id);
     init_info_single(rhs_roots, &mut id);
 
     let mut existing = HashMap::new();
-    set_content_id(lhs_roots, &mut existing);
-    set_content_id(rhs_roots, &mut existing);
+    set_content_id(lhs_roots, &mut existing, ignore_case);
+    set_content_id(rhs_roots, &mut existing, ignore_case);
+
+    set_has_duplicate_sibling(lhs_roots);
+    set_has_duplicate_sibling(rhs_roots);
+}
+
+/// Fold the case of `content`, if we're ignoring case.
+fn case_key(content: &str, ignore_case: bool) -> String {
+    if ignore_case {
+        content.to_lowercase()
+    } else {
+        content.to_owned()
+    }
 }
 
 type ContentKey = (Option<String>, Option<String>, Vec<u32>, bool, bool);
 
-fn set_content_id(nodes: &[&Syntax], existing: &mut HashMap<ContentKey, u32>) {
+fn set_content_id(nodes: &[&Syntax], existing: &mut HashMap<ContentKey, u32>, ignore_case: bool) {
     for node in nodes {
         let key: ContentKey = match node {
             List {
@@ -354,14 +600,14 @@ fn set_content_id(nodes: &[&Syntax], existing: &mut HashMap<ContentKey, u32>) {
                 ..
             } => {
                 // Recurse first, so children all have their content_id set.
-                set_content_id(children, existing);
+                set_content_id(children, existing, ignore_case);
 
                 let children_content_ids: Vec<_> =
                     children.iter().map(|c| c.info().content_id.get()).collect();
 
                 (
-                    Some(open_content.clone()),
-                    Some(close_content.clone()),
+                    Some(case_key(open_content, ignore_case)),
+                    Some(case_key(close_content, ignore_case)),
                     children_content_ids,
                     true,
                     true,
@@ -380,8 +626,12 @@ fn set_content_id(nodes: &[&Syntax], existing: &mut HashMap<ContentKey, u32>) {
                         .collect::<Vec<_>>()
                         .join("\n")
                         .to_string()
-                } else {
+                } else if is_comment || *highlight == AtomKind::String {
+                    // Case is significant in comments and strings,
+                    // even in case-insensitive languages.
                     content.clone()
+                } else {
+                    case_key(content, ignore_case)
                 };
                 (Some(clean_content), None, vec![], false, is_comment)
             }
@@ -395,6 +645,24 @@ fn set_content_id(nodes: &[&Syntax], existing: &mut HashMap<ContentKey, u32>) {
     }
 }
 
+/// Mark nodes whose content occurs more than once among their
+/// siblings. Must be called after `set_content_id`.
+fn set_has_duplicate_sibling(nodes: &[&Syntax]) {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for node in nodes {
+        *counts.entry(node.content_id()).or_insert(0) += 1;
+    }
+
+    for node in nodes {
+        node.info()
+            .has_duplicate_sibling
+            .set(counts[&node.content_id()] > 1);
+        if let List { children, .. } = node {
+            set_has_duplicate_sibling(children);
+        }
+    }
+}
+
 pub fn init_next_prev<'a>(roots: &[&'a Syntax<'a>]) {
     set_prev_sibling(roots);
     set_next_sibling(roots);
@@ -594,11 +862,14 @@ pub fn split_words(s: &str) -> Vec<&str> {
     res
 }
 
-fn split_comment_words(
+/// Diff the words in a comment or string atom against the words in
+/// the atom it replaced, so only the changed words are novel.
+fn split_atom_words(
     content: &str,
     pos: SingleLineSpan,
     opposite_content: &str,
     opposite_pos: SingleLineSpan,
+    highlight: TokenKind,
 ) -> Vec<MatchedPos> {
     // TODO: merge adjacent single-line comments unless there are
     // blank lines between them.
@@ -617,9 +888,7 @@ fn split_comment_words(
             myers_diff::DiffResult::Left(word) => {
                 // This word is novel to this side.
                 res.push(MatchedPos {
-                    kind: MatchKind::NovelWord {
-                        highlight: TokenKind::Atom(AtomKind::Comment),
-                    },
+                    kind: MatchKind::NovelWord { highlight },
                     pos: content_newlines.from_offsets_relative_to(
                         pos,
                         offset,
@@ -640,7 +909,7 @@ fn split_comment_words(
 
                 res.push(MatchedPos {
                     kind: MatchKind::NovelLinePart {
-                        highlight: TokenKind::Atom(AtomKind::Comment),
+                        highlight,
                         self_pos: word_pos,
                         opposite_pos: opposite_word_pos,
                     },
@@ -667,7 +936,7 @@ impl MatchedPos {
         is_close: bool,
     ) -> Vec<Self> {
         match ck {
-            ReplacedComment(this, opposite) => {
+            ReplacedComment(this, opposite) | ReplacedString(this, opposite) => {
                 let this_content = match this {
                     List { .. } => unreachable!(),
                     Atom { content, .. } => content,
@@ -679,12 +948,13 @@ impl MatchedPos {
                     } => (content, position),
                 };
 
-                split_comment_words(
+                split_atom_words(
                     this_content,
                     // TODO: handle the whole pos here.
                     pos[0],
                     opposite_content,
                     opposite_pos[0],
+                    highlight,
                 )
             }
             Unchanged(opposite) => {
@@ -815,6 +1085,123 @@ fn change_positions_<'a>(
     }
 }
 
+/// Is `node`, and everything inside it, novel?
+fn is_deep_novel<'a>(node: &'a Syntax<'a>, change_map: &ChangeMap<'a>) -> bool {
+    if change_map.get(node) != Some(Novel) {
+        return false;
+    }
+
+    match node {
+        List { children, .. } => children
+            .iter()
+            .all(|child| is_deep_novel(child, change_map)),
+        Atom { .. } => true,
+    }
+}
+
+/// Find the outermost nodes in `nodes` that are entirely novel.
+pub fn outermost_novel<'a>(
+    nodes: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+) -> Vec<&'a Syntax<'a>> {
+    let mut res = vec![];
+    outermost_novel_(nodes, change_map, &mut res);
+    res
+}
+
+fn outermost_novel_<'a>(
+    nodes: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+    res: &mut Vec<&'a Syntax<'a>>,
+) {
+    for node in nodes {
+        if is_deep_novel(node, change_map) {
+            res.push(node);
+        } else if let List { children, .. } = node {
+            outermost_novel_(children, change_map, res);
+        }
+    }
+}
+
+/// Find the top-level definitions, such as functions and classes,
+/// that are entirely novel and span more than one line. Returns the
+/// first and last line of each definition.
+pub fn novel_definition_ranges<'a>(
+    nodes: &[&'a Syntax<'a>],
+    change_map: &ChangeMap<'a>,
+) -> Vec<(LineNumber, LineNumber)> {
+    nodes
+        .iter()
+        .filter(|node| is_deep_novel(node, change_map))
+        .filter_map(|node| match (node.first_line(), node.last_line()) {
+            (Some(first_line), Some(last_line)) if first_line < last_line => {
+                Some((first_line, last_line))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// A top-level item in a file, such as a function or a class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    /// The first line of the definition, up to its body.
+    pub name: String,
+    pub start: LineNumber,
+    /// The last line of the signature, which opens the body. This is
+    /// `start` unless the signature spans several lines.
+    pub signature_end: LineNumber,
+    pub end: LineNumber,
+}
+
+/// A short name for a definition that starts with `line`, such as
+/// `pub fn foo(x: u32)` for `pub fn foo(x: u32) {`.
+pub fn definition_name(line: &str) -> String {
+    let line = line.trim();
+    let name = match line.find(|c| c == '{' || c == '=' || c == ';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let name = name.trim().trim_end_matches(':').trim_end();
+    if name.is_empty() {
+        line.to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+/// The line in `lines[start..=end]` that opens the body of a
+/// definition, such as `) -> u32 {` or `def foo(x):`.
+fn signature_end(lines: &[&str], start: LineNumber, end: LineNumber) -> LineNumber {
+    (start.0..=end.0)
+        .find(|i| {
+            lines.get(*i).map_or(false, |line| {
+                let line = line.trim_end();
+                line.contains('{') || line.ends_with(':')
+            })
+        })
+        .map_or(start, LineNumber::from)
+}
+
+/// The top-level definitions in `nodes`, parsed from `src`.
+pub fn definitions(nodes: &[&Syntax], src: &str) -> Vec<Definition> {
+    let lines: Vec<&str> = src.lines().collect();
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let start = node.first_line()?;
+            let end = node.last_line()?;
+            let line = lines.get(start.0)?;
+            Some(Definition {
+                name: definition_name(line),
+                start,
+                signature_end: signature_end(&lines, start, end),
+                end,
+            })
+        })
+        .collect()
+}
+
 pub fn zip_pad_shorter<Tx: Clone, Ty: Clone>(
     lhs: &[Tx],
     rhs: &[Ty],
@@ -866,6 +1253,29 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn test_definition_name() {
+        assert_eq!(
+            definition_name("pub fn foo(x: u32) -> u32 {"),
+            "pub fn foo(x: u32) -> u32"
+        );
+        assert_eq!(definition_name("def foo(x):"), "def foo(x)");
+        assert_eq!(definition_name("const x = 1;"), "const x");
+        assert_eq!(definition_name("{"), "{");
+    }
+
+    #[test]
+    fn test_signature_end() {
+        let lines = vec!["fn foo(", "    x: u32,", ") -> u32 {", "    x", "}"];
+        assert_eq!(signature_end(&lines, 0.into(), 4.into()), 2.into());
+
+        let lines = vec!["def foo(x):", "    return x"];
+        assert_eq!(signature_end(&lines, 0.into(), 1.into()), 0.into());
+
+        let lines = vec!["const X: u32 = 1;"];
+        assert_eq!(signature_end(&lines, 0.into(), 0.into()), 0.into());
+    }
+
     /// Consider comment atoms as distinct to other atoms even if the
     /// content matches otherwise.
     #[test]
@@ -905,6 +1315,166 @@ mod tests {
         assert_eq!(type_atom, atom);
     }
 
+    fn col_helper(line: usize, col: usize) -> Vec<SingleLineSpan> {
+        vec![SingleLineSpan {
+            line: line.into(),
+            start_col: col,
+            end_col: col + 1,
+        }]
+    }
+
+    #[test]
+    fn test_collapse_deep_lists() {
+        let arena = Arena::new();
+        let inner = Syntax::new_list(
+            &arena,
+            "[",
+            col_helper(1, 0),
+            vec![Syntax::new_atom(
+                &arena,
+                col_helper(1, 1),
+                "1",
+                AtomKind::Normal,
+            )],
+            "]",
+            col_helper(1, 2),
+        );
+        let outer = Syntax::new_list(
+            &arena,
+            "{",
+            col_helper(0, 0),
+            vec![inner],
+            "}",
+            col_helper(2, 0),
+        );
+
+        let collapsed = collapse_deep_lists(&arena, &[outer], 0, &mut vec![]);
+        match collapsed[0] {
+            List { children, .. } => match children[0] {
+                Atom {
+                    content, position, ..
+                } => {
+                    assert_eq!(content, "[ 1 ]");
+                    assert_eq!(position.len(), 3);
+                }
+                List { .. } => panic!("Inner list should be collapsed"),
+            },
+            Atom { .. } => panic!("Outer list should be kept"),
+        }
+    }
+
+    #[test]
+    fn test_collapse_deep_lists_boundary() {
+        let arena = Arena::new();
+        let inner = Syntax::new_list(
+            &arena,
+            "[",
+            col_helper(1, 0),
+            vec![Syntax::new_atom(
+                &arena,
+                col_helper(1, 1),
+                "1",
+                AtomKind::Normal,
+            )],
+            "]",
+            col_helper(1, 2),
+        );
+        let pair = Syntax::new_list(
+            &arena,
+            "",
+            vec![],
+            vec![
+                Syntax::new_atom(&arena, col_helper(0, 1), "\"a\"", AtomKind::String),
+                inner,
+            ],
+            "",
+            vec![],
+        );
+
+        // The pair is at depth 0 and the inner list at depth 1, so
+        // nothing is nested more than 1 level deep.
+        let mut collapsed = vec![];
+        let res = collapse_deep_lists(&arena, &[pair], 1, &mut collapsed);
+        assert!(collapsed.is_empty());
+        match res[0] {
+            List { children, .. } => assert!(matches!(children[1], List { .. })),
+            Atom { .. } => panic!("Pair should be kept"),
+        }
+
+        let res = collapse_deep_lists(&arena, &[pair], 0, &mut collapsed);
+        assert_eq!(collapsed.len(), 1);
+        assert_eq!(collapsed[0].path, "\"a\"");
+        match res[0] {
+            List { children, .. } => assert!(matches!(children[1], Atom { .. })),
+            Atom { .. } => panic!("Pair should be kept"),
+        }
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let pos = vec![SingleLineSpan {
+            line: 0.into(),
+            start_col: 0,
+            end_col: 6,
+        }];
+
+        let arena = Arena::new();
+        let lhs = Syntax::new_atom(&arena, pos.clone(), "SELECT", AtomKind::Keyword);
+        let rhs = Syntax::new_atom(&arena, pos.clone(), "select", AtomKind::Keyword);
+        init_all_info_with_case(&[lhs], &[rhs], true);
+        assert_eq!(lhs, rhs);
+
+        let lhs = Syntax::new_atom(&arena, pos.clone(), "'Foo'", AtomKind::String);
+        let rhs = Syntax::new_atom(&arena, pos, "'foo'", AtomKind::String);
+        init_all_info_with_case(&[lhs], &[rhs], true);
+        assert_ne!(lhs, rhs);
+    }
+
+    #[test]
+    fn test_has_duplicate_sibling() {
+        let pos = vec![SingleLineSpan {
+            line: 0.into(),
+            start_col: 0,
+            end_col: 1,
+        }];
+
+        let arena = Arena::new();
+        let a = Syntax::new_atom(&arena, pos.clone(), "a", AtomKind::Normal);
+        let b = Syntax::new_atom(&arena, pos.clone(), "b", AtomKind::Normal);
+        let other_a = Syntax::new_atom(&arena, pos, "a", AtomKind::Normal);
+        init_all_info(&[a, b, other_a], &[]);
+
+        assert!(a.has_duplicate_sibling());
+        assert!(!b.has_duplicate_sibling());
+        assert!(other_a.has_duplicate_sibling());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let pos = vec![SingleLineSpan {
+            line: 0.into(),
+            start_col: 1,
+            end_col: 4,
+        }];
+
+        let arena = Arena::new();
+        let atom = Syntax::new_atom(&arena, pos, "\"a\"", AtomKind::String);
+        let list = Syntax::new_list(&arena, "(", vec![], vec![atom], ")", vec![]);
+        init_all_info(&[list], &[]);
+
+        assert_eq!(
+            to_dot(&[list]),
+            concat!(
+                "digraph syntax {\n",
+                "  node [shape=box, fontname=\"monospace\"];\n",
+                "  n1 [label=\"List ( )\\n- -\"];\n",
+                "  n1 -> n2;\n",
+                "  n2 [label=\"Atom String \\\"a\\\"\\n0:1-4\"];\n",
+                "}\n"
+            )
+        );
+    }
+
     #[test]
     fn test_flatten_trivial_list() {
         let pos = vec![SingleLineSpan {
@@ -942,6 +1512,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_novel_definition_ranges() {
+        let span = |line: usize| {
+            vec![SingleLineSpan {
+                line: line.into(),
+                start_col: 0,
+                end_col: 1,
+            }]
+        };
+
+        let arena = Arena::new();
+        let multiline_atom = Syntax::new_atom(&arena, span(1), "x", AtomKind::Normal);
+        let multiline = Syntax::new_list(&arena, "(", span(0), vec![multiline_atom], ")", span(2));
+        let single_line_atom = Syntax::new_atom(&arena, span(3), "y", AtomKind::Normal);
+        let single_line =
+            Syntax::new_list(&arena, "(", span(3), vec![single_line_atom], ")", span(3));
+        init_all_info(&[multiline, single_line], &[]);
+
+        let mut change_map = ChangeMap::default();
+        for node in [multiline, multiline_atom, single_line, single_line_atom] {
+            change_map.insert(node, Novel);
+        }
+
+        assert_eq!(
+            novel_definition_ranges(&[multiline, single_line], &change_map),
+            vec![(0.into(), 2.into())]
+        );
+    }
+
+    #[test]
+    fn test_novel_definition_ranges_ignores_nested() {
+        let span = |line: usize| {
+            vec![SingleLineSpan {
+                line: line.into(),
+                start_col: 0,
+                end_col: 1,
+            }]
+        };
+
+        let arena = Arena::new();
+        let inner_atom = Syntax::new_atom(&arena, span(2), "x", AtomKind::Normal);
+        let inner = Syntax::new_list(&arena, "{", span(1), vec![inner_atom], "}", span(3));
+        let outer = Syntax::new_list(&arena, "{", span(0), vec![inner], "}", span(4));
+        init_all_info(&[outer], &[]);
+
+        // Only the nested node is novel, so it isn't a definition.
+        let mut change_map = ChangeMap::default();
+        change_map.insert(outer, Unchanged(outer));
+        change_map.insert(inner, Novel);
+        change_map.insert(inner_atom, Novel);
+
+        assert!(novel_definition_ranges(&[outer], &change_map).is_empty());
+    }
+
     #[test]
     fn test_multiline_comment_ignores_leading_whitespace() {
         let pos = vec![SingleLineSpan {
@@ -960,7 +1584,7 @@ mod tests {
     }
 
     #[test]
-    fn test_split_comment_words_basic() {
+    fn test_split_atom_words_basic() {
         let content = "abc";
         let pos = SingleLineSpan {
             line: 0.into(),
@@ -975,7 +1599,13 @@ mod tests {
             end_col: 3,
         };
 
-        let res = split_comment_words(content, pos, opposite_content, opposite_pos);
+        let res = split_atom_words(
+            content,
+            pos,
+            opposite_content,
+            opposite_pos,
+            TokenKind::Atom(AtomKind::Comment),
+        );
         assert_eq!(
             res,
             vec![MatchedPos {
@@ -1,8 +1,12 @@
 //! Load and configure parsers written with tree-sitter.
 
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
 
 use crate::parse::guess_language as guess;
+use lazy_static::lazy_static;
 use tree_sitter as ts;
 use typed_arena::Arena;
 
@@ -43,46 +47,84 @@ pub struct TreeSitterConfig {
 }
 
 extern "C" {
+    #[cfg(feature = "lang-bash")]
     fn tree_sitter_bash() -> ts::Language;
+    #[cfg(feature = "lang-c")]
     fn tree_sitter_c() -> ts::Language;
+    #[cfg(feature = "lang-c-sharp")]
     fn tree_sitter_c_sharp() -> ts::Language;
+    #[cfg(feature = "lang-clojure")]
     fn tree_sitter_clojure() -> ts::Language;
+    #[cfg(feature = "lang-cpp")]
     fn tree_sitter_cpp() -> ts::Language;
+    #[cfg(feature = "lang-commonlisp")]
     fn tree_sitter_commonlisp() -> ts::Language;
+    #[cfg(feature = "lang-css")]
     fn tree_sitter_css() -> ts::Language;
+    #[cfg(feature = "lang-dart")]
     fn tree_sitter_dart() -> ts::Language;
+    #[cfg(feature = "lang-elisp")]
     fn tree_sitter_elisp() -> ts::Language;
+    #[cfg(feature = "lang-elixir")]
     fn tree_sitter_elixir() -> ts::Language;
+    #[cfg(feature = "lang-elm")]
     fn tree_sitter_elm() -> ts::Language;
+    #[cfg(feature = "lang-elvish")]
     fn tree_sitter_elvish() -> ts::Language;
+    #[cfg(feature = "lang-gleam")]
     fn tree_sitter_gleam() -> ts::Language;
+    #[cfg(feature = "lang-go")]
     fn tree_sitter_go() -> ts::Language;
+    #[cfg(feature = "lang-haskell")]
     fn tree_sitter_haskell() -> ts::Language;
+    #[cfg(feature = "lang-hcl")]
     fn tree_sitter_hcl() -> ts::Language;
+    #[cfg(feature = "lang-janet")]
     fn tree_sitter_janet_simple() -> ts::Language;
+    #[cfg(feature = "lang-java")]
     fn tree_sitter_java() -> ts::Language;
+    #[cfg(feature = "lang-javascript")]
     fn tree_sitter_javascript() -> ts::Language;
+    #[cfg(feature = "lang-json")]
     fn tree_sitter_json() -> ts::Language;
+    #[cfg(feature = "lang-kotlin")]
     fn tree_sitter_kotlin() -> ts::Language;
+    #[cfg(feature = "lang-lua")]
     fn tree_sitter_lua() -> ts::Language;
+    #[cfg(feature = "lang-nix")]
     fn tree_sitter_nix() -> ts::Language;
+    #[cfg(feature = "lang-ocaml")]
     fn tree_sitter_ocaml() -> ts::Language;
+    #[cfg(feature = "lang-ocaml")]
     fn tree_sitter_ocaml_interface() -> ts::Language;
+    #[cfg(feature = "lang-php")]
     fn tree_sitter_php() -> ts::Language;
+    #[cfg(feature = "lang-perl")]
     fn tree_sitter_perl() -> ts::Language;
+    #[cfg(feature = "lang-python")]
     fn tree_sitter_python() -> ts::Language;
+    #[cfg(feature = "lang-ruby")]
     fn tree_sitter_ruby() -> ts::Language;
+    #[cfg(feature = "lang-rust")]
     fn tree_sitter_rust() -> ts::Language;
+    #[cfg(feature = "lang-scala")]
     fn tree_sitter_scala() -> ts::Language;
+    #[cfg(feature = "lang-swift")]
     fn tree_sitter_swift() -> ts::Language;
+    #[cfg(feature = "lang-toml")]
     fn tree_sitter_toml() -> ts::Language;
+    #[cfg(feature = "lang-typescript")]
     fn tree_sitter_tsx() -> ts::Language;
+    #[cfg(feature = "lang-typescript")]
     fn tree_sitter_typescript() -> ts::Language;
+    #[cfg(feature = "lang-yaml")]
     fn tree_sitter_yaml() -> ts::Language;
+    #[cfg(feature = "lang-zig")]
     fn tree_sitter_zig() -> ts::Language;
 }
 
 // TODO: begin/end and object/end.
+#[cfg(feature = "lang-ocaml")]
 const OCAML_ATOM_NODES: [&str; 6] = [
     "character",
     "string",
@@ -92,9 +134,32 @@ const OCAML_ATOM_NODES: [&str; 6] = [
     "attribute_id",
 ];
 
-pub fn from_language(language: guess::Language) -> TreeSitterConfig {
+/// Nodes inside string literals that contain code, such as `${x}` in
+/// JavaScript template literals, `{x}` in Python f-strings and `#{x}`
+/// in Ruby strings.
+const INTERPOLATION_NODES: [&str; 2] = ["interpolation", "template_substitution"];
+
+lazy_static! {
+    static ref CONFIGS: Mutex<HashMap<guess::Language, Option<&'static TreeSitterConfig>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Like `from_language`, but each configuration is only built once
+/// per process. Compiling the highlight query is slow for some
+/// languages, so this matters when diffing many files.
+pub fn cached_from_language(language: guess::Language) -> Option<&'static TreeSitterConfig> {
+    let mut configs = CONFIGS.lock().unwrap();
+    *configs
+        .entry(language)
+        .or_insert_with(|| from_language(language).map(|config| &*Box::leak(Box::new(config))))
+}
+
+/// The parser configuration for `language`, or `None` if difftastic
+/// was built without the feature for that language.
+pub fn from_language(language: guess::Language) -> Option<TreeSitterConfig> {
     use guess::Language::*;
-    match language {
+    let config = match language {
+        #[cfg(feature = "lang-bash")]
         Bash => {
             let language = unsafe { tree_sitter_bash() };
             TreeSitterConfig {
@@ -111,6 +176,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-c")]
         C => {
             let language = unsafe { tree_sitter_c() };
             TreeSitterConfig {
@@ -125,6 +191,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-cpp")]
         CPlusPlus => {
             let language = unsafe { tree_sitter_cpp() };
             TreeSitterConfig {
@@ -144,6 +211,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-clojure")]
         Clojure => {
             let language = unsafe { tree_sitter_clojure() };
             TreeSitterConfig {
@@ -160,6 +228,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-commonlisp")]
         CommonLisp => {
             let language = unsafe { tree_sitter_commonlisp() };
             TreeSitterConfig {
@@ -170,6 +239,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 highlight_query: ts::Query::new(language, "").unwrap(),
             }
         }
+        #[cfg(feature = "lang-c-sharp")]
         CSharp => {
             let language = unsafe { tree_sitter_c_sharp() };
             TreeSitterConfig {
@@ -190,6 +260,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-css")]
         Css => {
             let language = unsafe { tree_sitter_css() };
             TreeSitterConfig {
@@ -204,6 +275,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-dart")]
         Dart => {
             let language = unsafe { tree_sitter_dart() };
             TreeSitterConfig {
@@ -218,6 +290,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-elisp")]
         EmacsLisp => {
             let language = unsafe { tree_sitter_elisp() };
             TreeSitterConfig {
@@ -234,6 +307,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-elixir")]
         Elixir => {
             let language = unsafe { tree_sitter_elixir() };
             TreeSitterConfig {
@@ -250,6 +324,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-elm")]
         Elm => {
             let language = unsafe { tree_sitter_elm() };
             TreeSitterConfig {
@@ -264,6 +339,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-elvish")]
         Elvish => {
             let language = unsafe { tree_sitter_elvish() };
             TreeSitterConfig {
@@ -278,6 +354,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-gleam")]
         Gleam => {
             let language = unsafe { tree_sitter_gleam() };
             TreeSitterConfig {
@@ -292,6 +369,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-go")]
         Go => {
             let language = unsafe { tree_sitter_go() };
             TreeSitterConfig {
@@ -310,6 +388,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-haskell")]
         Haskell => {
             let language = unsafe { tree_sitter_haskell() };
             TreeSitterConfig {
@@ -324,6 +403,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-hcl")]
         Hcl => {
             let language = unsafe { tree_sitter_hcl() };
             TreeSitterConfig {
@@ -345,6 +425,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-janet")]
         Janet => {
             let language = unsafe { tree_sitter_janet_simple() };
             TreeSitterConfig {
@@ -368,6 +449,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-java")]
         Java => {
             let language = unsafe { tree_sitter_java() };
             TreeSitterConfig {
@@ -382,6 +464,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-javascript")]
         JavaScript | Jsx => {
             let language = unsafe { tree_sitter_javascript() };
             TreeSitterConfig {
@@ -406,6 +489,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-json")]
         Json => {
             let language = unsafe { tree_sitter_json() };
             TreeSitterConfig {
@@ -420,6 +504,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-kotlin")]
         Kotlin => {
             let language = unsafe { tree_sitter_kotlin() };
             TreeSitterConfig {
@@ -438,6 +523,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-lua")]
         Lua => {
             let language = unsafe { tree_sitter_lua() };
             TreeSitterConfig {
@@ -454,6 +540,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-nix")]
         Nix => {
             let language = unsafe { tree_sitter_nix() };
             TreeSitterConfig {
@@ -470,6 +557,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-ocaml")]
         OCaml => {
             let language = unsafe { tree_sitter_ocaml() };
             TreeSitterConfig {
@@ -484,6 +572,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-ocaml")]
         OCamlInterface => {
             let language = unsafe { tree_sitter_ocaml_interface() };
             TreeSitterConfig {
@@ -498,6 +587,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-php")]
         Php => {
             let language = unsafe { tree_sitter_php() };
             TreeSitterConfig {
@@ -512,6 +602,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-perl")]
         Perl => {
             let language = unsafe { tree_sitter_perl() };
             TreeSitterConfig {
@@ -537,6 +628,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-python")]
         Python => {
             let language = unsafe { tree_sitter_python() };
             TreeSitterConfig {
@@ -551,6 +643,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-ruby")]
         Ruby => {
             let language = unsafe { tree_sitter_ruby() };
             TreeSitterConfig {
@@ -575,6 +668,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-rust")]
         Rust => {
             let language = unsafe { tree_sitter_rust() };
             TreeSitterConfig {
@@ -589,6 +683,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-scala")]
         Scala => {
             let language = unsafe { tree_sitter_scala() };
             TreeSitterConfig {
@@ -603,6 +698,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-swift")]
         Swift => {
             let language = unsafe { tree_sitter_swift() };
             TreeSitterConfig {
@@ -617,6 +713,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-toml")]
         Toml => {
             let language = unsafe { tree_sitter_toml() };
             TreeSitterConfig {
@@ -631,6 +728,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-typescript")]
         Tsx => {
             let language = unsafe { tree_sitter_tsx() };
             TreeSitterConfig {
@@ -648,6 +746,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-typescript")]
         TypeScript => {
             let language = unsafe { tree_sitter_typescript() };
             TreeSitterConfig {
@@ -667,6 +766,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-yaml")]
         Yaml => {
             let language = unsafe { tree_sitter_yaml() };
             TreeSitterConfig {
@@ -688,6 +788,7 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
+        #[cfg(feature = "lang-zig")]
         Zig => {
             let language = unsafe { tree_sitter_zig() };
             TreeSitterConfig {
@@ -706,7 +807,10 @@ pub fn from_language(language: guess::Language) -> TreeSitterConfig {
                 .unwrap(),
             }
         }
-    }
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+    Some(config)
 }
 
 /// Parse `src` with tree-sitter.
@@ -838,6 +942,17 @@ pub fn parse<'a>(
     arena: &'a Arena<Syntax<'a>>,
     src: &str,
     config: &TreeSitterConfig,
+) -> Vec<&'a Syntax<'a>> {
+    let tree = parse_to_tree(src, config);
+    to_syntax(&tree, src, arena, config)
+}
+
+/// Convert a tree-sitter parse tree of `src` to difftastic Syntax.
+pub fn to_syntax<'a>(
+    tree: &tree_sitter::Tree,
+    src: &str,
+    arena: &'a Arena<Syntax<'a>>,
+    config: &TreeSitterConfig,
 ) -> Vec<&'a Syntax<'a>> {
     // Don't return anything on an empty input. Most parsers return a
     // zero-width top-level AST node on empty files, which is
@@ -846,8 +961,7 @@ pub fn parse<'a>(
         return vec![];
     }
 
-    let tree = parse_to_tree(src, config);
-    let highlights = tree_highlights(&tree, src, config);
+    let highlights = tree_highlights(tree, src, config);
 
     let nl_pos = NewlinePositions::from(src);
     let mut cursor = tree.walk();
@@ -859,6 +973,62 @@ pub fn parse<'a>(
     all_syntaxes_from_cursor(arena, src, &nl_pos, &mut cursor, config, &highlights)
 }
 
+/// The syntax errors that tree-sitter found when parsing a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseErrors {
+    /// The number of ERROR and MISSING nodes in the tree.
+    pub count: usize,
+    /// The proportion of the source bytes that are inside an ERROR
+    /// node, from 0.0 to 1.0.
+    pub ratio: f64,
+}
+
+/// Count the syntax errors in `tree`, the parse tree of `src`.
+pub fn parse_errors(src: &str, tree: &tree_sitter::Tree) -> ParseErrors {
+    let mut count = 0;
+    let mut error_bytes = 0;
+
+    let mut cursor = tree.walk();
+    count_errors(&mut cursor, false, &mut count, &mut error_bytes);
+
+    let ratio = if src.is_empty() {
+        0.0
+    } else {
+        error_bytes as f64 / src.len() as f64
+    };
+
+    ParseErrors { count, ratio }
+}
+
+fn count_errors(
+    cursor: &mut ts::TreeCursor,
+    inside_error: bool,
+    count: &mut usize,
+    error_bytes: &mut usize,
+) {
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            *count += 1;
+        }
+        // ERROR nodes can be nested, so only count the bytes of the
+        // outermost one.
+        if node.is_error() && !inside_error {
+            *error_bytes += node.end_byte() - node.start_byte();
+        }
+
+        // Only descend into subtrees that contain errors.
+        if node.has_error() && cursor.goto_first_child() {
+            count_errors(cursor, inside_error || node.is_error(), count, error_bytes);
+            cursor.goto_parent();
+        }
+
+        if !cursor.goto_next_sibling() {
+            break;
+        }
+    }
+}
+
 fn child_tokens<'a>(src: &'a str, cursor: &mut ts::TreeCursor) -> Vec<Option<&'a str>> {
     let mut tokens = vec![];
 
@@ -964,9 +1134,15 @@ fn syntax_from_cursor<'a>(
     }
 
     if config.atom_nodes.contains(node.kind()) {
-        // Treat nodes like string literals as atoms, regardless
-        // of whether they have children.
-        atom_from_cursor(arena, src, nl_pos, cursor, highlights)
+        if has_interpolation(&node) {
+            vec![interpolated_string_from_cursor(
+                arena, src, nl_pos, cursor, config, highlights,
+            )]
+        } else {
+            // Treat nodes like string literals as atoms, regardless
+            // of whether they have children.
+            atom_from_cursor(arena, src, nl_pos, cursor, highlights)
+        }
     } else if node.child_count() > 0 {
         vec![list_from_cursor(
             arena, src, nl_pos, cursor, config, highlights,
@@ -976,6 +1152,69 @@ fn syntax_from_cursor<'a>(
     }
 }
 
+fn has_interpolation(node: &ts::Node) -> bool {
+    (0..node.child_count())
+        .filter_map(|i| node.child(i))
+        .any(|child| INTERPOLATION_NODES.contains(&child.kind()))
+}
+
+/// Convert a string literal node with interpolated expressions to a
+/// difftastic list. The literal text between the expressions becomes
+/// string atoms, and the expressions are converted as normal code.
+///
+/// Grammars don't always have nodes for the literal text, so we use
+/// the source between the interpolations, including the quotes.
+fn interpolated_string_from_cursor<'a>(
+    arena: &'a Arena<Syntax<'a>>,
+    src: &str,
+    nl_pos: &NewlinePositions,
+    cursor: &mut ts::TreeCursor,
+    config: &TreeSitterConfig,
+    highlights: &HighlightedNodeIds,
+) -> &'a Syntax<'a> {
+    let root_node = cursor.node();
+
+    let mut children = vec![];
+    let mut text_start = root_node.start_byte();
+    let push_text = |children: &mut Vec<&'a Syntax<'a>>, start: usize, end: usize| {
+        if start < end {
+            children.push(Syntax::new_atom(
+                arena,
+                nl_pos.from_offsets(start, end),
+                &src[start..end],
+                AtomKind::String,
+            ));
+        }
+    };
+
+    cursor.goto_first_child();
+    loop {
+        let node = cursor.node();
+        if INTERPOLATION_NODES.contains(&node.kind()) {
+            push_text(&mut children, text_start, node.start_byte());
+            children.extend(syntax_from_cursor(
+                arena, src, nl_pos, cursor, config, highlights,
+            ));
+            text_start = node.end_byte();
+        }
+
+        if !cursor.goto_next_sibling() {
+            break;
+        }
+    }
+    cursor.goto_parent();
+    push_text(&mut children, text_start, root_node.end_byte());
+
+    Syntax::new_list(
+        arena,
+        "",
+        nl_pos.from_offsets(root_node.start_byte(), root_node.start_byte()),
+        children,
+        "",
+        nl_pos.from_offsets(root_node.end_byte(), root_node.end_byte()),
+    )
+}
+
 /// Convert the tree-sitter node at `cursor` to a difftastic list
 /// node.
 fn list_from_cursor<'a>(
@@ -1139,20 +1378,67 @@ mod tests {
     /// Simple smoke test for tree-sitter parsing. Having a test also
     /// ensures that this file has its coverage measured.
     /// <https://github.com/mozilla/grcov/issues/617>
+    #[cfg(feature = "lang-css")]
     #[test]
     fn test_parse() {
         let arena = Arena::new();
-        let css_config = from_language(guess::Language::Css);
+        let css_config = from_language(guess::Language::Css).unwrap();
         parse(&arena, ".foo {}", &css_config);
     }
 
+    #[cfg(feature = "lang-elisp")]
     #[test]
     fn test_parse_empty_file() {
         let arena = Arena::new();
-        let config = from_language(guess::Language::EmacsLisp);
+        let config = from_language(guess::Language::EmacsLisp).unwrap();
         let res = parse(&arena, "", &config);
 
         let expected: Vec<&Syntax> = vec![];
         assert_eq!(res, expected);
     }
+
+    #[cfg(feature = "lang-javascript")]
+    fn contains_atom(nodes: &[&Syntax], expected: &str) -> bool {
+        nodes.iter().any(|node| match node {
+            Syntax::List { children, .. } => contains_atom(children, expected),
+            Syntax::Atom { content, .. } => content == expected,
+        })
+    }
+
+    #[cfg(feature = "lang-javascript")]
+    #[test]
+    fn test_parse_template_literal() {
+        let arena = Arena::new();
+        let config = from_language(guess::Language::JavaScript).unwrap();
+        let res = parse(&arena, "`a ${foo + 1} b`;", &config);
+
+        assert!(contains_atom(&res, "foo"));
+        assert!(contains_atom(&res, "`a "));
+        assert!(contains_atom(&res, " b`"));
+    }
+
+    #[cfg(feature = "lang-css")]
+    #[test]
+    fn test_parse_errors_valid() {
+        let config = from_language(guess::Language::Css).unwrap();
+        let src = ".foo {}";
+        let tree = parse_to_tree(src, &config);
+
+        let errors = parse_errors(src, &tree);
+        assert_eq!(errors.count, 0);
+        assert_eq!(errors.ratio, 0.0);
+    }
+
+    #[cfg(feature = "lang-json")]
+    #[test]
+    fn test_parse_errors_invalid() {
+        let config = from_language(guess::Language::Json).unwrap();
+        let src = "{\"foo\": }}}";
+        let tree = parse_to_tree(src, &config);
+
+        let errors = parse_errors(src, &tree);
+        assert!(errors.count > 0);
+        assert!(errors.ratio > 0.0);
+        assert!(errors.ratio <= 1.0);
+    }
 }
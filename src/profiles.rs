@@ -0,0 +1,123 @@
+//! Default options for particular languages and kinds of file.
+//!
+//! A profile can only turn options on. Options passed on the command
+//! line always apply, and `--no-profiles` ignores profiles entirely.
+
+use std::path::Path;
+
+use crate::parse::guess_language::Language;
+
+/// Lockfiles are generated by package managers, so the individual
+/// changes are rarely worth reading.
+const LOCKFILE_NAMES: &[&str] = &[
+    "Cargo.lock",
+    "Gemfile.lock",
+    "Pipfile.lock",
+    "composer.lock",
+    "flake.lock",
+    "go.sum",
+    "mix.lock",
+    "package-lock.json",
+    "packages.lock.json",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "pubspec.lock",
+    "yarn.lock",
+];
+
+/// Markers that code generators put near the start of a file.
+const GENERATED_MARKERS: &[&str] = &["@generated", "DO NOT EDIT", "Code generated by"];
+
+/// How many lines at the start of a file we search for
+/// `GENERATED_MARKERS`.
+const GENERATED_MARKER_LINES: usize = 5;
+
+fn is_generated(src: &str) -> bool {
+    src.lines()
+        .take(GENERATED_MARKER_LINES)
+        .any(|line| GENERATED_MARKERS.iter().any(|marker| line.contains(marker)))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// Only print a summary of the changes, as if `--summary-only`
+    /// was passed.
+    pub summary_only: bool,
+    /// Show added and removed definitions in full, even with
+    /// `--collapse-definitions`.
+    pub expand_definitions: bool,
+    /// The file has a generated code marker, so only say that it
+    /// changed, unless `--show-generated` was passed.
+    pub generated: bool,
+}
+
+impl Profile {
+    /// Turn on every option that is set in `other`.
+    pub fn add(&mut self, other: Profile) {
+        self.summary_only |= other.summary_only;
+        self.expand_definitions |= other.expand_definitions;
+        self.generated |= other.generated;
+    }
+}
+
+/// The default options for the file at `path`, written in `language`
+/// and containing `src`.
+pub fn for_file(path: &Path, language: Option<Language>, src: &str) -> Profile {
+    if is_generated(src) {
+        return Profile {
+            generated: true,
+            ..Profile::default()
+        };
+    }
+
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    if matches!(file_name, Some(name) if LOCKFILE_NAMES.contains(&name)) {
+        return Profile {
+            summary_only: true,
+            ..Profile::default()
+        };
+    }
+
+    match language {
+        // Data files don't have definitions that are worth
+        // collapsing, so show everything that was added.
+        Some(Language::Json) | Some(Language::Toml) | Some(Language::Yaml) => Profile {
+            expand_definitions: true,
+            ..Profile::default()
+        },
+        _ => Profile::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_lockfile_profile() {
+        let profile = for_file(Path::new("foo/Cargo.lock"), Some(Language::Toml), "");
+        assert!(profile.summary_only);
+    }
+
+    #[test]
+    fn test_language_profile() {
+        let profile = for_file(Path::new("config.json"), Some(Language::Json), "{}");
+        assert!(profile.expand_definitions);
+        assert!(!profile.summary_only);
+    }
+
+    #[test]
+    fn test_default_profile() {
+        assert_eq!(
+            for_file(Path::new("main.rs"), Some(Language::Rust), "fn main() {}"),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn test_generated_profile() {
+        let src = "// Code generated by protoc-gen-go. DO NOT EDIT.\npackage foo\n";
+        assert!(for_file(Path::new("foo.pb.go"), Some(Language::Go), src).generated);
+    }
+}
@@ -0,0 +1,335 @@
+//! Diffing natural-language documents, such as documentation and
+//! translations, as sequences of words rather than lines.
+//!
+//! Paragraphs are separated by blank lines, and line breaks within a
+//! paragraph are ignored, so reflowing a paragraph isn't a change.
+//! We diff paragraphs first, then the sentences of changed
+//! paragraphs, then the words of changed sentences.
+//!
+//! Markdown and reStructuredText markup such as `#`, `**` and `` ` ``
+//! are separate tokens from the words they decorate, so adding
+//! emphasis only highlights the markup.
+
+use std::path::Path;
+
+use crate::{
+    diff::myers_diff,
+    line_parser::diff_slice_by_hash,
+    parse::syntax::{AtomKind, MatchKind, MatchedPos, TokenKind},
+    positions::SingleLineSpan,
+};
+
+/// Extensions of files that are diffed as prose by default.
+const PROSE_EXTENSIONS: &[&str] = &["txt", "md", "markdown", "rst"];
+
+/// Should we diff the file at `path` as prose by default?
+pub fn is_prose_path(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => PROSE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    pos: SingleLineSpan,
+    kind: AtomKind,
+}
+
+/// Characters that are used for markup rather than punctuation.
+fn is_markup(c: char) -> bool {
+    matches!(c, '#' | '*' | '_' | '`' | '>' | '=' | '~' | '|')
+}
+
+fn is_sentence_end(text: &str) -> bool {
+    matches!(text, "." | "!" | "?")
+}
+
+/// Split `line` into words, runs of the same markup character, and
+/// individual punctuation characters.
+fn tokenize_line<'a>(line: &'a str, line_num: usize, tokens: &mut Vec<Token<'a>>) {
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+        if c.is_alphanumeric() {
+            // Apostrophes are part of words, e.g. "don't".
+            while let Some(&(i, next)) = chars.peek() {
+                if next.is_alphanumeric() || next == '\'' {
+                    end = i + next.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        } else if is_markup(c) {
+            while let Some(&(i, next)) = chars.peek() {
+                if next == c {
+                    end = i + next.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        tokens.push(Token {
+            text: &line[start..end],
+            pos: SingleLineSpan {
+                line: line_num.into(),
+                start_col: start,
+                end_col: end,
+            },
+            kind: if is_markup(c) {
+                AtomKind::Keyword
+            } else {
+                AtomKind::Normal
+            },
+        });
+    }
+}
+
+/// Split `src` into paragraphs, each of which is a list of sentences.
+fn paragraphs(src: &str) -> Vec<Vec<Vec<Token<'_>>>> {
+    let mut res = vec![];
+    let mut paragraph_tokens = vec![];
+    for (line_num, line) in src.lines().enumerate() {
+        if line.trim().is_empty() {
+            if !paragraph_tokens.is_empty() {
+                res.push(sentences(&paragraph_tokens));
+                paragraph_tokens.clear();
+            }
+        } else {
+            tokenize_line(line, line_num, &mut paragraph_tokens);
+        }
+    }
+    if !paragraph_tokens.is_empty() {
+        res.push(sentences(&paragraph_tokens));
+    }
+    res
+}
+
+fn sentences<'a>(tokens: &[Token<'a>]) -> Vec<Vec<Token<'a>>> {
+    let mut res = vec![];
+    let mut sentence = vec![];
+    for token in tokens {
+        sentence.push(*token);
+        if is_sentence_end(token.text) {
+            res.push(sentence);
+            sentence = vec![];
+        }
+    }
+    if !sentence.is_empty() {
+        res.push(sentence);
+    }
+    res
+}
+
+fn texts<'a>(tokens: &[Token<'a>]) -> Vec<&'a str> {
+    tokens.iter().map(|t| t.text).collect()
+}
+
+fn paragraph_texts<'a>(paragraph: &[Vec<Token<'a>>]) -> Vec<&'a str> {
+    paragraph.iter().flat_map(|s| texts(s)).collect()
+}
+
+fn unchanged(token: &Token, opposite: &Token) -> MatchedPos {
+    MatchedPos {
+        kind: MatchKind::UnchangedToken {
+            highlight: TokenKind::Atom(token.kind),
+            self_pos: vec![token.pos],
+            opposite_pos: vec![opposite.pos],
+        },
+        pos: token.pos,
+    }
+}
+
+fn novel(token: &Token) -> MatchedPos {
+    MatchedPos {
+        kind: MatchKind::NovelWord {
+            highlight: TokenKind::Atom(token.kind),
+        },
+        pos: token.pos,
+    }
+}
+
+/// The positions of each side of a diff.
+#[derive(Default)]
+struct Positions {
+    lhs: Vec<MatchedPos>,
+    rhs: Vec<MatchedPos>,
+}
+
+impl Positions {
+    fn push_unchanged(&mut self, lhs_tokens: &[Token], rhs_tokens: &[Token]) {
+        for (lhs_token, rhs_token) in lhs_tokens.iter().zip(rhs_tokens) {
+            self.lhs.push(unchanged(lhs_token, rhs_token));
+            self.rhs.push(unchanged(rhs_token, lhs_token));
+        }
+    }
+
+    fn push_novel_words(&mut self, lhs_tokens: &[Token], rhs_tokens: &[Token]) {
+        let lhs_texts = texts(lhs_tokens);
+        let rhs_texts = texts(rhs_tokens);
+
+        let mut lhs_i = 0;
+        let mut rhs_i = 0;
+        for diff_res in myers_diff::slice(&lhs_texts, &rhs_texts) {
+            match diff_res {
+                myers_diff::DiffResult::Left(_) => {
+                    self.lhs.push(novel(&lhs_tokens[lhs_i]));
+                    lhs_i += 1;
+                }
+                myers_diff::DiffResult::Both(_, _) => {
+                    self.push_unchanged(&lhs_tokens[lhs_i..=lhs_i], &rhs_tokens[rhs_i..=rhs_i]);
+                    lhs_i += 1;
+                    rhs_i += 1;
+                }
+                myers_diff::DiffResult::Right(_) => {
+                    self.rhs.push(novel(&rhs_tokens[rhs_i]));
+                    rhs_i += 1;
+                }
+            }
+        }
+    }
+
+    /// Diff two runs of sentences, then the words of any sentences
+    /// that changed.
+    fn push_novel_sentences(&mut self, lhs: &[Vec<Token>], rhs: &[Vec<Token>]) {
+        let lhs_keys: Vec<Vec<&str>> = lhs.iter().map(|s| texts(s)).collect();
+        let rhs_keys: Vec<Vec<&str>> = rhs.iter().map(|s| texts(s)).collect();
+
+        let mut lhs_i = 0;
+        let mut rhs_i = 0;
+        let mut lhs_novel: Vec<Token> = vec![];
+        let mut rhs_novel: Vec<Token> = vec![];
+        for diff_res in diff_slice_by_hash(&lhs_keys, &rhs_keys) {
+            match diff_res {
+                myers_diff::DiffResult::Left(_) => {
+                    lhs_novel.extend(&lhs[lhs_i]);
+                    lhs_i += 1;
+                }
+                myers_diff::DiffResult::Both(_, _) => {
+                    self.push_novel_words(&lhs_novel, &rhs_novel);
+                    lhs_novel.clear();
+                    rhs_novel.clear();
+
+                    self.push_unchanged(&lhs[lhs_i], &rhs[rhs_i]);
+                    lhs_i += 1;
+                    rhs_i += 1;
+                }
+                myers_diff::DiffResult::Right(_) => {
+                    rhs_novel.extend(&rhs[rhs_i]);
+                    rhs_i += 1;
+                }
+            }
+        }
+        self.push_novel_words(&lhs_novel, &rhs_novel);
+    }
+}
+
+/// Diff `lhs_src` and `rhs_src` as prose, returning the positions
+/// for each side.
+pub fn change_positions(lhs_src: &str, rhs_src: &str) -> (Vec<MatchedPos>, Vec<MatchedPos>) {
+    let lhs = paragraphs(lhs_src);
+    let rhs = paragraphs(rhs_src);
+
+    let lhs_keys: Vec<Vec<&str>> = lhs.iter().map(|p| paragraph_texts(p)).collect();
+    let rhs_keys: Vec<Vec<&str>> = rhs.iter().map(|p| paragraph_texts(p)).collect();
+
+    let mut positions = Positions::default();
+    let mut lhs_i = 0;
+    let mut rhs_i = 0;
+    let mut lhs_novel: Vec<Vec<Token>> = vec![];
+    let mut rhs_novel: Vec<Vec<Token>> = vec![];
+    for diff_res in diff_slice_by_hash(&lhs_keys, &rhs_keys) {
+        match diff_res {
+            myers_diff::DiffResult::Left(_) => {
+                lhs_novel.extend(lhs[lhs_i].iter().cloned());
+                lhs_i += 1;
+            }
+            myers_diff::DiffResult::Both(_, _) => {
+                positions.push_novel_sentences(&lhs_novel, &rhs_novel);
+                lhs_novel.clear();
+                rhs_novel.clear();
+
+                let lhs_tokens: Vec<Token> = lhs[lhs_i].concat();
+                let rhs_tokens: Vec<Token> = rhs[rhs_i].concat();
+                positions.push_unchanged(&lhs_tokens, &rhs_tokens);
+                lhs_i += 1;
+                rhs_i += 1;
+            }
+            myers_diff::DiffResult::Right(_) => {
+                rhs_novel.extend(rhs[rhs_i].iter().cloned());
+                rhs_i += 1;
+            }
+        }
+    }
+    positions.push_novel_sentences(&lhs_novel, &rhs_novel);
+
+    (positions.lhs, positions.rhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn novel_texts<'a>(src: &'a str, positions: &[MatchedPos]) -> Vec<&'a str> {
+        let lines: Vec<&str> = src.lines().collect();
+        positions
+            .iter()
+            .filter(|mp| mp.kind.is_novel())
+            .map(|mp| &lines[mp.pos.line.0][mp.pos.start_col..mp.pos.end_col])
+            .collect()
+    }
+
+    #[test]
+    fn test_is_prose_path() {
+        assert!(is_prose_path(Path::new("README.md")));
+        assert!(is_prose_path(Path::new("docs/index.RST")));
+        assert!(!is_prose_path(Path::new("main.rs")));
+        assert!(!is_prose_path(Path::new("LICENSE")));
+    }
+
+    #[test]
+    fn test_tokenize_markup() {
+        let mut tokens = vec![];
+        tokenize_line("## Don't **panic**.", 0, &mut tokens);
+        assert_eq!(
+            texts(&tokens),
+            vec!["##", "Don't", "**", "panic", "**", "."]
+        );
+    }
+
+    #[test]
+    fn test_reflow_is_unchanged() {
+        let lhs = "The quick brown fox\njumps over the dog.\n";
+        let rhs = "The quick brown\nfox jumps over\nthe dog.\n";
+        let (lhs_positions, rhs_positions) = change_positions(lhs, rhs);
+        assert!(lhs_positions.iter().all(|mp| !mp.kind.is_novel()));
+        assert!(rhs_positions.iter().all(|mp| !mp.kind.is_novel()));
+    }
+
+    #[test]
+    fn test_changed_word() {
+        let lhs = "First sentence. The quick fox.\n\nAnother paragraph.\n";
+        let rhs = "First sentence. The slow fox.\n\nAnother paragraph.\n";
+        let (lhs_positions, rhs_positions) = change_positions(lhs, rhs);
+        assert_eq!(novel_texts(lhs, &lhs_positions), vec!["quick"]);
+        assert_eq!(novel_texts(rhs, &rhs_positions), vec!["slow"]);
+    }
+
+    #[test]
+    fn test_added_emphasis() {
+        let lhs = "Do not panic.\n";
+        let rhs = "Do *not* panic.\n";
+        let (lhs_positions, rhs_positions) = change_positions(lhs, rhs);
+        assert!(novel_texts(lhs, &lhs_positions).is_empty());
+        assert_eq!(novel_texts(rhs, &rhs_positions), vec!["*", "*"]);
+    }
+}
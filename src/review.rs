@@ -0,0 +1,260 @@
+//! Reviewing hunks one at a time with `--interactive`.
+//!
+//! After each hunk we ask whether to approve it, reject it or add a
+//! note, and write the decisions so far to the review file:
+//!
+//! ```text
+//! [
+//! {"path":"src/main.rs","hunk":1,"lhs_lines":[10,12],"rhs_lines":[10,13],"decision":"approve","note":null}
+//! ]
+//! ```
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::{
+    display::{
+        self,
+        hunks::{file_hunks, Hunk, HunkNumbering},
+        json::{json_string, line_range},
+    },
+    files::display_path,
+    options::{DisplayMode, DisplayOptions},
+    summary::{DiffResult, FileContent},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Decision {
+    Approve,
+    Reject,
+    Note(String),
+}
+
+/// What the user chose at the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Answer {
+    Decide(Decision),
+    Skip,
+    Quit,
+}
+
+/// Interpret a line typed at the prompt. Returns `None` if we don't
+/// recognise it.
+fn parse_answer(line: &str) -> Option<Answer> {
+    match line.trim() {
+        "a" | "approve" => Some(Answer::Decide(Decision::Approve)),
+        "r" | "reject" => Some(Answer::Decide(Decision::Reject)),
+        // The note text is read separately.
+        "n" | "note" => Some(Answer::Decide(Decision::Note(String::new()))),
+        "s" | "skip" | "" => Some(Answer::Skip),
+        "q" | "quit" => Some(Answer::Quit),
+        _ => None,
+    }
+}
+
+fn entry_json(path: &str, hunk_num: usize, hunk: &Hunk, decision: &Decision) -> String {
+    let mut lhs_lines: Vec<_> = hunk.novel_lhs.iter().copied().collect();
+    let mut rhs_lines: Vec<_> = hunk.novel_rhs.iter().copied().collect();
+    lhs_lines.sort_unstable();
+    rhs_lines.sort_unstable();
+
+    let (decision, note) = match decision {
+        Decision::Approve => ("approve", "null".to_owned()),
+        Decision::Reject => ("reject", "null".to_owned()),
+        Decision::Note(note) => ("note", json_string(note)),
+    };
+    format!(
+        "{{\"path\":{},\"hunk\":{},\"lhs_lines\":{},\"rhs_lines\":{},\"decision\":\"{}\",\"note\":{}}}",
+        json_string(path),
+        hunk_num,
+        line_range(&lhs_lines),
+        line_range(&rhs_lines),
+        decision,
+        note
+    )
+}
+
+/// The state of an interactive review, across all the files diffed.
+pub struct Review {
+    path: OsString,
+    entries: Vec<String>,
+    input: Box<dyn BufRead>,
+}
+
+impl Review {
+    /// Start a review that writes decisions to `path`. We read
+    /// answers from the terminal, so this still works when stdin is
+    /// a list of files.
+    pub fn new(path: OsString) -> Self {
+        let input: Box<dyn BufRead> = match fs::File::open("/dev/tty") {
+            Ok(tty) => Box::new(BufReader::new(tty)),
+            Err(_) => Box::new(BufReader::new(io::stdin())),
+        };
+        Self {
+            path,
+            entries: vec![],
+            input,
+        }
+    }
+
+    fn write_or_die(&self) {
+        let json = format!("[\n{}\n]\n", self.entries.join(",\n"));
+        if let Err(e) = fs::write(&self.path, json) {
+            eprintln!(
+                "error: Could not write review to {}: {}",
+                display_path(Path::new(&self.path)),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+
+    /// Read a line from the user, or `None` at end of input.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(&['\r', '\n'][..]).to_owned()),
+        }
+    }
+
+    fn ask(&mut self, path: &str, hunk_num: usize, num_hunks: usize) -> Answer {
+        let prompt = format!(
+            "Hunk {}/{} of {}: [a]pprove, [r]eject, [n]ote, [s]kip, [q]uit? ",
+            hunk_num, num_hunks, path
+        );
+        loop {
+            let line = match self.read_line(&prompt) {
+                Some(line) => line,
+                None => return Answer::Quit,
+            };
+            match parse_answer(&line) {
+                Some(Answer::Decide(Decision::Note(_))) => {
+                    return match self.read_line("Note: ") {
+                        Some(note) => Answer::Decide(Decision::Note(note)),
+                        None => Answer::Quit,
+                    };
+                }
+                Some(answer) => return answer,
+                None => println!("Please answer a, r, n, s or q."),
+            }
+        }
+    }
+
+    /// Show each hunk in `summary` and record the user's decision.
+    /// Files without hunks, such as binary files, are printed as
+    /// usual with `print_file`.
+    pub fn review_file(
+        &mut self,
+        display_options: &DisplayOptions,
+        summary: &DiffResult,
+        print_file: impl Fn(&DiffResult),
+    ) {
+        let (lhs_src, rhs_src) = match (&summary.lhs_src, &summary.rhs_src) {
+            (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => (lhs_src, rhs_src),
+            _ => return print_file(summary),
+        };
+        let hunks = file_hunks(
+            lhs_src,
+            rhs_src,
+            &summary.lhs_positions,
+            &summary.rhs_positions,
+        );
+        if hunks.is_empty() {
+            return print_file(summary);
+        }
+
+        let lang_name = summary.language.as_deref().unwrap_or("Text");
+        for (i, hunk) in hunks.iter().enumerate() {
+            let hunk = std::slice::from_ref(hunk);
+            let numbering = HunkNumbering {
+                first: i,
+                total: hunks.len(),
+            };
+            if let DisplayMode::Inline = display_options.display_mode {
+                display::inline::print(
+                    lhs_src,
+                    rhs_src,
+                    display_options,
+                    &summary.lhs_positions,
+                    &summary.rhs_positions,
+                    hunk,
+                    numbering,
+                    &summary.lhs_display_path,
+                    &summary.rhs_display_path,
+                    lang_name,
+                    &summary.lhs_novel_ranges,
+                    &summary.rhs_novel_ranges,
+                    &summary.moves,
+                    &summary.signature_changes,
+                    &summary.timed_out_regions,
+                    &summary.lhs_indentation,
+                    &summary.rhs_indentation,
+                );
+            } else {
+                display::side_by_side::print(
+                    hunk,
+                    numbering,
+                    display_options,
+                    &summary.lhs_display_path,
+                    &summary.rhs_display_path,
+                    lang_name,
+                    lhs_src,
+                    rhs_src,
+                    &summary.lhs_positions,
+                    &summary.rhs_positions,
+                    &summary.lhs_novel_ranges,
+                    &summary.rhs_novel_ranges,
+                    &summary.rhs_definitions,
+                    &summary.moves,
+                    &summary.signature_changes,
+                    &summary.timed_out_regions,
+                    &summary.lhs_indentation,
+                    &summary.rhs_indentation,
+                );
+            }
+
+            match self.ask(&summary.rhs_display_path, i + 1, hunks.len()) {
+                Answer::Decide(decision) => {
+                    self.entries.push(entry_json(
+                        &summary.rhs_display_path,
+                        i + 1,
+                        &hunk[0],
+                        &decision,
+                    ));
+                    self.write_or_die();
+                }
+                Answer::Skip => {}
+                Answer::Quit => {
+                    self.write_or_die();
+                    std::process::exit(0);
+                }
+            }
+        }
+    }
+
+    /// Write the review file, even if no hunks were decided.
+    pub fn finish(&self) {
+        self.write_or_die();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(parse_answer("a\n"), Some(Answer::Decide(Decision::Approve)));
+        assert_eq!(parse_answer(""), Some(Answer::Skip));
+        assert_eq!(parse_answer("x"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! A built-in check that every parser is working.
+//!
+//! This is useful for packagers, and for users who want to confirm
+//! that their installation of difftastic works. Every language that
+//! difftastic knows about must have a sample, so a newly added parser
+//! can't go unchecked.
+
+use std::time::Instant;
+
+use crate::{
+    buffer::Buffer,
+    diff_file_content,
+    display::hunks::matched_pos_to_hunks,
+    options::{DiffOptions, DEFAULT_TAB_WIDTH},
+    parse::{
+        guess_language::{
+            Language::{self, *},
+            LANGUAGE_EXTENSIONS,
+        },
+        syntax::MatchedPos,
+        tree_sitter_parser as tsp,
+    },
+    summary::FileContent,
+};
+
+/// A small before/after pair for every language. Each pair should
+/// parse without errors and contain at least one change.
+const SAMPLES: &[(Language, &str, &str)] = &[
+    (Bash, "echo hello\n", "echo hello world\n"),
+    (C, "int x = 1;\n", "int x = 2;\n"),
+    (Clojure, "(def x 1)\n", "(def x 2)\n"),
+    (CommonLisp, "(defvar x 1)\n", "(defvar x 2)\n"),
+    (CPlusPlus, "int x = 1;\n", "int x = 2;\n"),
+    (
+        CSharp,
+        "class A { int x = 1; }\n",
+        "class A { int x = 2; }\n",
+    ),
+    (Css, ".foo { color: red; }\n", ".foo { color: blue; }\n"),
+    (Dart, "var x = 1;\n", "var x = 2;\n"),
+    (Elixir, "x = 1\n", "x = 2\n"),
+    (Elm, "x = 1\n", "x = 2\n"),
+    (Elvish, "echo hello\n", "echo hello world\n"),
+    (EmacsLisp, "(setq x 1)\n", "(setq x 2)\n"),
+    (Gleam, "const x = 1\n", "const x = 2\n"),
+    (Go, "package main\nvar x = 1\n", "package main\nvar x = 2\n"),
+    (Haskell, "x = 1\n", "x = 2\n"),
+    (Hcl, "x = 1\n", "x = 2\n"),
+    (Janet, "(def x 1)\n", "(def x 2)\n"),
+    (Java, "class A { int x = 1; }\n", "class A { int x = 2; }\n"),
+    (JavaScript, "let x = 1;\n", "let x = 2;\n"),
+    (Json, "{\"x\": 1}\n", "{\"x\": 2}\n"),
+    (Jsx, "let x = <div>1</div>;\n", "let x = <div>2</div>;\n"),
+    (Kotlin, "val x = 1\n", "val x = 2\n"),
+    (Lua, "local x = 1\n", "local x = 2\n"),
+    (Nix, "{ x = 1; }\n", "{ x = 2; }\n"),
+    (OCaml, "let x = 1\n", "let x = 2\n"),
+    (OCamlInterface, "val x : int\n", "val x : string\n"),
+    (Php, "<?php $x = 1;\n", "<?php $x = 2;\n"),
+    (Perl, "my $x = 1;\n", "my $x = 2;\n"),
+    (Python, "x = 1\n", "x = 2\n"),
+    (Ruby, "x = 1\n", "x = 2\n"),
+    (Rust, "const X: u32 = 1;\n", "const X: u32 = 2;\n"),
+    (
+        Scala,
+        "object A { val x = 1 }\n",
+        "object A { val x = 2 }\n",
+    ),
+    (Swift, "let x = 1\n", "let x = 2\n"),
+    (Toml, "x = 1\n", "x = 2\n"),
+    (Tsx, "let x = <div>1</div>;\n", "let x = <div>2</div>;\n"),
+    (TypeScript, "let x: number = 1;\n", "let x: number = 2;\n"),
+    (Yaml, "x: 1\n", "x: 2\n"),
+    (Zig, "const x = 1;\n", "const x = 2;\n"),
+];
+
+/// Do all the positions in `mps` refer to valid locations in `src`?
+/// Rendering will panic otherwise.
+fn positions_in_bounds(src: &str, mps: &[MatchedPos]) -> bool {
+    let lines: Vec<_> = src.lines().collect();
+    mps.iter().all(|mp| match lines.get(mp.pos.line.0) {
+        Some(line) => {
+            mp.pos.start_col <= mp.pos.end_col
+                && mp.pos.end_col <= line.len()
+                && line.is_char_boundary(mp.pos.start_col)
+                && line.is_char_boundary(mp.pos.end_col)
+        }
+        None => false,
+    })
+}
+
+/// Diff `lhs_src` against `rhs_src`, returning a description of the
+/// first problem found.
+fn check_sample(language: Language, lhs_src: &str, rhs_src: &str) -> Result<(), String> {
+    let config = match tsp::from_language(language) {
+        Some(config) => config,
+        None => return Err("not included in this build".into()),
+    };
+    for src in [lhs_src, rhs_src] {
+        let tree = tsp::parse_to_tree(src, &config);
+        let errors = tsp::parse_errors(src, &tree);
+        if errors.count > 0 {
+            return Err(format!("{} parse errors in {:?}", errors.count, src));
+        }
+    }
+
+    let result = diff_file_content(
+        &Buffer {
+            display_path: "before",
+            path: None,
+            language_hint: Some(language),
+            content: lhs_src.as_bytes(),
+        },
+        &Buffer {
+            display_path: "after",
+            path: None,
+            language_hint: Some(language),
+            content: rhs_src.as_bytes(),
+        },
+        DEFAULT_TAB_WIDTH,
+        &DiffOptions::default(),
+    );
+
+    if result.language.as_deref() != Some(config.name) {
+        return Err(format!(
+            "diffed as {}",
+            result.language.unwrap_or_else(|| "Text".into())
+        ));
+    }
+
+    let (lhs_src, rhs_src) = match (&result.lhs_src, &result.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => (lhs_src, rhs_src),
+        _ => return Err("treated as binary".into()),
+    };
+
+    if matched_pos_to_hunks(&result.lhs_positions, &result.rhs_positions).is_empty() {
+        return Err("no changes found".into());
+    }
+
+    if !positions_in_bounds(lhs_src, &result.lhs_positions)
+        || !positions_in_bounds(rhs_src, &result.rhs_positions)
+    {
+        return Err("positions outside of the source".into());
+    }
+
+    Ok(())
+}
+
+/// The built-in sample for `language`, if there is one.
+fn sample(language: Language) -> Option<(&'static str, &'static str)> {
+    SAMPLES
+        .iter()
+        .find(|(sample_language, _, _)| *sample_language == language)
+        .map(|(_, lhs_src, rhs_src)| (*lhs_src, *rhs_src))
+}
+
+/// Check every language, printing a line per language. Returns true
+/// if all languages passed.
+pub fn run() -> bool {
+    let mut num_checked = 0;
+    let mut num_failed = 0;
+
+    for (language, _) in LANGUAGE_EXTENSIONS {
+        // Skip languages that were disabled with cargo features.
+        let name = match tsp::from_language(*language) {
+            Some(config) => config.name,
+            None => continue,
+        };
+        num_checked += 1;
+
+        let start = Instant::now();
+        let res = match sample(*language) {
+            Some((lhs_src, rhs_src)) => check_sample(*language, lhs_src, rhs_src),
+            None => Err("no built-in sample".into()),
+        };
+        let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+        match res {
+            Ok(()) => println!("{:<20} ok     {:>8.2}ms", name, elapsed_ms),
+            Err(e) => {
+                num_failed += 1;
+                println!("{:<20} FAILED {:>8.2}ms {}", name, elapsed_ms, e);
+            }
+        }
+    }
+
+    println!();
+    if num_failed == 0 {
+        println!("All {} languages passed.", num_checked);
+    } else {
+        println!("{} of {} languages failed.", num_failed, num_checked);
+    }
+
+    num_failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_pass() {
+        for (language, lhs_src, rhs_src) in SAMPLES {
+            if tsp::from_language(*language).is_none() {
+                continue;
+            }
+            assert_eq!(
+                check_sample(*language, lhs_src, rhs_src),
+                Ok(()),
+                "{:?}",
+                language
+            );
+        }
+    }
+
+    #[test]
+    fn test_every_language_has_sample() {
+        for (language, _) in LANGUAGE_EXTENSIONS {
+            assert!(sample(*language).is_some(), "{:?}", language);
+        }
+    }
+}
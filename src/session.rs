@@ -0,0 +1,799 @@
+//! Recording a diff, with its inputs and options, into a single file
+//! that can be replayed elsewhere. This lets users attach a minimal
+//! reproduction to a bug report without sharing a whole repository.
+//!
+//! A session file starts with `difftastic-session 1`, followed by
+//! records of the form `KEY LENGTH\nBYTES\n`.
+
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    files::{display_path, is_dev_null, read_or_die},
+    options, warnings,
+};
+
+const MAGIC: &str = "difftastic-session 1\n";
+
+/// The environment variable that `--daemon` uses. This says how to
+/// run difftastic rather than how to diff, so we don't record it.
+const DAEMON_SOCKET_VAR: &str = "DFT_DAEMON_SOCKET";
+
+#[cfg(unix)]
+fn os_to_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    s.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn os_to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn os_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Options that only change how the recorded files are diffed and
+/// shown. A session file may come from anyone, so replaying drops
+/// every other option, such as `--stage-dir`, `--interactive`,
+/// `--record` or `--daemon`, because they write files or start
+/// processes.
+const REPLAY_OPTIONS: &[&str] = &[
+    "width",
+    "tab-width",
+    "display",
+    "format",
+    "color",
+    "background",
+    "color-depth",
+    "syntax-highlight",
+    "alignment",
+    "show-offsets",
+    "show-generated",
+    "schema-version",
+    "order",
+    "copyable",
+    "separator",
+    "separator-style",
+    "minimap",
+    "show-indentation",
+    "legend",
+    "collapse-definitions",
+    "preview-lines",
+    "header-format",
+    "assert-equal",
+    "metrics",
+    "estimate",
+    "ignore-token-regex",
+    "no-line-background",
+    "trim-trailing-spaces",
+    "summary-only",
+    "group-by-definition",
+    "no-profiles",
+    "skip-unchanged",
+    "encoding",
+    "no-editorconfig",
+    "lhs-label",
+    "rhs-label",
+    "reverse",
+    "missing-as-empty",
+    "strip-input-ansi",
+    "c-preprocessor",
+    "no-pager",
+    "no-warnings",
+    "lang-ui",
+    "api-only",
+    "mode",
+    "ignore-case",
+    "explain",
+    "language",
+    "node-limit",
+    "diff-quality",
+    "delimiter-cost",
+    "punctuation-cost",
+    "max-depth",
+    "time-limit",
+    "word-split",
+    "byte-limit",
+    "parse-error-limit",
+    "report-parse-errors",
+];
+
+/// Can the option `--long` be replayed with `value`?
+fn is_replayable(long: &str, value: Option<&OsStr>) -> bool {
+    // --display stage-script writes patches to --stage-dir.
+    let writes_files =
+        matches!(long, "display" | "format") && value == Some(OsStr::new("stage-script"));
+    REPLAY_OPTIONS.contains(&long) && !writes_files
+}
+
+/// How many values follow `--long` when its value isn't given with
+/// `=`.
+fn num_values(app: &clap::Command, long: &str) -> usize {
+    app.get_arguments()
+        .find(|arg| arg.get_long() == Some(long))
+        .filter(|arg| arg.is_takes_value_set())
+        .map_or(0, |arg| arg.get_num_vals().unwrap_or(1))
+}
+
+/// The indexes in `args` of positional arguments, rather than options
+/// or option values.
+fn positional_indexes(args: &[OsString]) -> Vec<usize> {
+    let app = options::app();
+
+    let mut res = vec![];
+    let mut only_positional = false;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy();
+        if only_positional || arg == "-" || !arg.starts_with('-') {
+            res.push(i);
+        } else if arg == "--" {
+            only_positional = true;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            // With --foo=bar, the value is part of the same argument.
+            if !long.contains('=') {
+                i += num_values(&app, long);
+            }
+        }
+        i += 1;
+    }
+    res
+}
+
+/// Where to record a session, and the arguments to record.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub path: OsString,
+    /// The command line arguments, without the program name or
+    /// `--record`.
+    pub args: Vec<OsString>,
+}
+
+impl Recording {
+    pub fn new(path: OsString, args: &[OsString]) -> Self {
+        let mut recorded_args = vec![];
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy();
+            if text == "--record" {
+                args.next();
+            } else if !text.starts_with("--record=") {
+                recorded_args.push(arg.clone());
+            }
+        }
+
+        Self {
+            path,
+            args: recorded_args,
+        }
+    }
+}
+
+/// Everything needed to reproduce a diff.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Session {
+    /// The difftastic version that recorded this session.
+    version: String,
+    args: Vec<OsString>,
+    /// The indexes in `args` of the two paths, which are replaced by
+    /// the recorded content when replaying.
+    lhs_arg: Option<usize>,
+    rhs_arg: Option<usize>,
+    /// `DFT_` environment variables, which set defaults for options.
+    vars: Vec<(String, String)>,
+    lhs_path: OsString,
+    rhs_path: OsString,
+    lhs_content: Vec<u8>,
+    rhs_content: Vec<u8>,
+}
+
+fn push_record(bytes: &mut Vec<u8>, key: &str, value: &[u8]) {
+    bytes.extend(format!("{} {}\n", key, value.len()).into_bytes());
+    bytes.extend_from_slice(value);
+    bytes.push(b'\n');
+}
+
+fn encode(session: &Session) -> Vec<u8> {
+    let mut bytes = MAGIC.as_bytes().to_vec();
+    push_record(&mut bytes, "version", session.version.as_bytes());
+    for arg in &session.args {
+        push_record(&mut bytes, "arg", &os_to_bytes(arg));
+    }
+    if let Some(i) = session.lhs_arg {
+        push_record(&mut bytes, "lhs_arg", i.to_string().as_bytes());
+    }
+    if let Some(i) = session.rhs_arg {
+        push_record(&mut bytes, "rhs_arg", i.to_string().as_bytes());
+    }
+    for (key, value) in &session.vars {
+        push_record(&mut bytes, "var", format!("{}={}", key, value).as_bytes());
+    }
+    push_record(&mut bytes, "lhs_path", &os_to_bytes(&session.lhs_path));
+    push_record(&mut bytes, "rhs_path", &os_to_bytes(&session.rhs_path));
+    push_record(&mut bytes, "lhs", &session.lhs_content);
+    push_record(&mut bytes, "rhs", &session.rhs_content);
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<Session, String> {
+    let mut rest = bytes
+        .strip_prefix(MAGIC.as_bytes())
+        .ok_or("not a difftastic session file")?;
+
+    let mut session = Session::default();
+    while !rest.is_empty() {
+        let header_end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or("truncated record header")?;
+        let header = String::from_utf8_lossy(&rest[..header_end]).into_owned();
+        let (key, len) = match header.split_once(' ') {
+            Some((key, len)) => (key, len.parse::<usize>().map_err(|_| "invalid length")?),
+            None => return Err(format!("invalid record header: {}", header)),
+        };
+        rest = &rest[header_end + 1..];
+
+        if rest.len() < len + 1 || rest[len] != b'\n' {
+            return Err(format!("truncated {} record", key));
+        }
+        let value = rest[..len].to_vec();
+        rest = &rest[len + 1..];
+
+        let text = || String::from_utf8_lossy(&value).into_owned();
+        let index = || {
+            text()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid {} record", key))
+        };
+        match key {
+            "version" => session.version = text(),
+            "arg" => session.args.push(os_from_bytes(value)),
+            "lhs_arg" => session.lhs_arg = Some(index()?),
+            "rhs_arg" => session.rhs_arg = Some(index()?),
+            "var" => {
+                let var = text();
+                if let Some((key, value)) = var.split_once('=') {
+                    session.vars.push((key.to_owned(), value.to_owned()));
+                }
+            }
+            "lhs_path" => session.lhs_path = os_from_bytes(value),
+            "rhs_path" => session.rhs_path = os_from_bytes(value),
+            "lhs" => session.lhs_content = value,
+            "rhs" => session.rhs_content = value,
+            // Ignore records from newer versions.
+            _ => {}
+        }
+    }
+    Ok(session)
+}
+
+/// The indexes of `lhs_path` and `rhs_path` in `args`. Only
+/// positional arguments are considered, so an option value that
+/// happens to be the same text as a path is left alone.
+fn path_indexes(
+    args: &[OsString],
+    lhs_path: &OsStr,
+    rhs_path: &OsStr,
+) -> (Option<usize>, Option<usize>) {
+    let positional = positional_indexes(args);
+    let lhs_arg = positional.iter().copied().find(|i| args[*i] == lhs_path);
+    let rhs_arg = positional
+        .iter()
+        .copied()
+        .find(|i| args[*i] == rhs_path && Some(*i) != lhs_arg);
+    (lhs_arg, rhs_arg)
+}
+
+/// Write a session file for diffing `lhs_path` against `rhs_path`.
+pub fn record_or_die(recording: &Recording, lhs_path: &Path, rhs_path: &Path) {
+    if lhs_path.is_dir() || rhs_path.is_dir() {
+        eprintln!("error: --record only supports diffing two files.");
+        std::process::exit(1);
+    }
+
+    let (lhs_arg, rhs_arg) =
+        path_indexes(&recording.args, lhs_path.as_os_str(), rhs_path.as_os_str());
+    let session = Session {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        args: recording.args.clone(),
+        lhs_arg,
+        rhs_arg,
+        vars: env::vars()
+            .filter(|(key, _)| key.starts_with("DFT_") && key != DAEMON_SOCKET_VAR)
+            .collect(),
+        lhs_path: lhs_path.as_os_str().to_owned(),
+        rhs_path: rhs_path.as_os_str().to_owned(),
+        lhs_content: read_or_die(lhs_path),
+        rhs_content: read_or_die(rhs_path),
+    };
+
+    if let Err(e) = fs::write(&recording.path, encode(&session)) {
+        eprintln!(
+            "error: Could not write session to {}: {}",
+            display_path(Path::new(&recording.path)),
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+/// A temporary directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create the directory at `path`, readable only by the current
+    /// user. Fails if `path` already exists, so another user can't
+    /// plant files or symlinks in it beforehand.
+    fn create(path: PathBuf) -> std::io::Result<Self> {
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+
+            builder.mode(0o700);
+        }
+        builder.create(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write `content` to a file in `dir` with the same name as `path`,
+/// so language detection still works. `/dev/null` is used as-is, so
+/// added and deleted files are still reported as such.
+fn write_input(dir: &Path, path: &OsStr, content: &[u8]) -> std::io::Result<PathBuf> {
+    if is_dev_null(Path::new(path)) {
+        return Ok(PathBuf::from(path));
+    }
+
+    fs::create_dir(dir)?;
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|| OsString::from("input"));
+    let tmp_path = dir.join(file_name);
+    fs::write(&tmp_path, content)?;
+    Ok(tmp_path)
+}
+
+/// The arguments to replay `session` with, reading the inputs from
+/// `lhs_tmp_path` and `rhs_tmp_path`, and the options that were
+/// dropped because they aren't in `REPLAY_OPTIONS`.
+///
+/// Positional arguments other than the two paths, such as the extra
+/// arguments from git, are dropped too.
+fn replay_args(
+    session: &Session,
+    lhs_tmp_path: &Path,
+    rhs_tmp_path: &Path,
+) -> (Vec<OsString>, Vec<String>) {
+    let app = options::app();
+    let mut args: Vec<OsString> = vec![];
+    let mut dropped = vec![];
+    let mut only_positional = false;
+    let mut i = 0;
+    while i < session.args.len() {
+        let arg = &session.args[i];
+        let text = arg.to_string_lossy();
+        if only_positional || text == "-" || !text.starts_with('-') {
+            if Some(i) == session.lhs_arg {
+                args.push(lhs_tmp_path.as_os_str().to_owned());
+            } else if Some(i) == session.rhs_arg {
+                args.push(rhs_tmp_path.as_os_str().to_owned());
+            } else if text == "assert-equal" && !only_positional {
+                // The subcommand's options are also top-level options
+                // of --assert-equal, so the flag replays the same check
+                // without splitting the arguments around it.
+                args.push("--assert-equal".into());
+            }
+            i += 1;
+            continue;
+        }
+        if text == "--" {
+            only_positional = true;
+            args.push(arg.clone());
+            i += 1;
+            continue;
+        }
+
+        let option = text.trim_start_matches('-');
+        // With --foo=bar, the value is part of the same argument.
+        let (long, value, end) = match option.split_once('=') {
+            Some((long, value)) => (long, Some(OsString::from(value)), i + 1),
+            None => {
+                let end = (i + 1 + num_values(&app, option)).min(session.args.len());
+                (option, session.args[i + 1..end].first().cloned(), end)
+            }
+        };
+        if is_replayable(long, value.as_deref()) {
+            args.extend_from_slice(&session.args[i..end]);
+        } else {
+            dropped.push(format!("--{}", long));
+        }
+        i = end;
+    }
+
+    // Show the original paths rather than the temporary files. The
+    // labels go first, in case the paths follow `--`.
+    let mut labels: Vec<OsString> = vec![];
+    let has_option = |name: &str| {
+        session
+            .args
+            .iter()
+            .any(|arg| arg.to_string_lossy().starts_with(name))
+    };
+    if !has_option("--lhs-label") {
+        labels.push("--lhs-label".into());
+        labels.push(display_path(Path::new(&session.lhs_path)).into());
+    }
+    if !has_option("--rhs-label") {
+        labels.push("--rhs-label".into());
+        labels.push(display_path(Path::new(&session.rhs_path)).into());
+    }
+    labels.extend(args);
+    (labels, dropped)
+}
+
+/// The recorded `DFT_` variables that set an option in
+/// `REPLAY_OPTIONS`.
+fn replay_vars(session: &Session) -> Vec<(String, String)> {
+    let app = options::app();
+    session
+        .vars
+        .iter()
+        .filter(|(key, value)| {
+            app.get_arguments().any(|arg| {
+                arg.get_env() == Some(OsStr::new(key))
+                    && arg
+                        .get_long()
+                        .map_or(false, |long| is_replayable(long, Some(OsStr::new(value))))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Run the diff recorded in `session` in a child process, with its
+/// inputs written to `tmp_dir`. Returns the exit code.
+fn run_session(session: &Session, tmp_dir: &TempDir) -> i32 {
+    let (lhs_tmp_path, rhs_tmp_path) = match (
+        write_input(
+            &tmp_dir.0.join("old"),
+            &session.lhs_path,
+            &session.lhs_content,
+        ),
+        write_input(
+            &tmp_dir.0.join("new"),
+            &session.rhs_path,
+            &session.rhs_content,
+        ),
+    ) {
+        (Ok(lhs_tmp_path), Ok(rhs_tmp_path)) => (lhs_tmp_path, rhs_tmp_path),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!(
+                "error: Could not write inputs to {}: {}",
+                display_path(&tmp_dir.0),
+                e
+            );
+            return 1;
+        }
+    };
+
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("error: Could not find the difftastic executable: {}", e);
+            return 1;
+        }
+    };
+    let (args, mut dropped) = replay_args(session, &lhs_tmp_path, &rhs_tmp_path);
+    let vars = replay_vars(session);
+    for (key, _) in session.vars.iter().filter(|var| !vars.contains(var)) {
+        dropped.push(key.clone());
+    }
+    if !dropped.is_empty() {
+        warnings::warn(format!(
+            "Ignoring {} from the session, because only display and diff options are replayed.",
+            dropped.join(", ")
+        ));
+    }
+
+    let mut cmd = Command::new(exe);
+    cmd.args(args);
+    // Use the recorded DFT_ variables, and only those.
+    for (key, _) in env::vars_os() {
+        if key.to_string_lossy().starts_with("DFT_") {
+            cmd.env_remove(key);
+        }
+    }
+    cmd.envs(vars);
+
+    match cmd.status() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("error: Could not run difftastic: {}", e);
+            1
+        }
+    }
+}
+
+/// Diff the inputs in the session file at `path`, with the options
+/// that were recorded, then exit with the status of that diff.
+///
+/// The diff runs in a child process, so its exit paths (e.g.
+/// `difft assert-equal`) can't skip deleting the temporary inputs.
+pub fn replay(path: &Path) {
+    let session = match decode(&read_or_die(path)) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!(
+                "error: Could not read session {}: {}",
+                display_path(path),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    if session.version != env!("CARGO_PKG_VERSION") {
+        warnings::warn(format!(
+            "{} was recorded with difftastic {}, but this is {}.",
+            display_path(path),
+            session.version,
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+
+    let tmp_path = env::temp_dir().join(format!("difft-replay-{}", std::process::id()));
+    let tmp_dir = match TempDir::create(tmp_path.clone()) {
+        Ok(tmp_dir) => tmp_dir,
+        Err(e) => {
+            eprintln!("error: Could not create {}: {}", display_path(&tmp_path), e);
+            std::process::exit(1);
+        }
+    };
+    let code = run_session(&session, &tmp_dir);
+    // Exiting doesn't run destructors.
+    drop(tmp_dir);
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn os_strings(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let session = Session {
+            version: "0.1.0".into(),
+            args: os_strings(&["--display", "inline", "old.rs", "new.rs"]),
+            lhs_arg: Some(2),
+            rhs_arg: Some(3),
+            vars: vec![("DFT_TAB_WIDTH".into(), "4".into())],
+            lhs_path: "old.rs".into(),
+            rhs_path: "new.rs".into(),
+            lhs_content: b"fn foo() {}\n".to_vec(),
+            rhs_content: b"fn bar() {}\n\n".to_vec(),
+        };
+        assert_eq!(decode(&encode(&session)), Ok(session));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encode_decode_non_utf8_path() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = OsString::from_vec(b"caf\xe9.rs".to_vec());
+        let session = Session {
+            args: vec![path.clone()],
+            lhs_arg: Some(0),
+            lhs_path: path,
+            ..Session::default()
+        };
+        assert_eq!(decode(&encode(&session)), Ok(session));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        assert!(decode(b"difftastic-session 1\nlhs 10\nfoo\n").is_err());
+        assert!(decode(b"not a session").is_err());
+    }
+
+    #[test]
+    fn test_recorded_args() {
+        let args = os_strings(&["difft", "--record", "s.dft", "a.rs", "--record=x", "b.rs"]);
+        assert_eq!(
+            Recording::new("s.dft".into(), &args).args,
+            os_strings(&["a.rs", "b.rs"])
+        );
+    }
+
+    #[test]
+    fn test_path_indexes_skip_option_values() {
+        let args = os_strings(&["--lhs-label", "a.rs", "a.rs", "b.rs"]);
+        assert_eq!(
+            path_indexes(&args, OsStr::new("a.rs"), OsStr::new("b.rs")),
+            (Some(2), Some(3))
+        );
+
+        let args = os_strings(&["--tab-width=4", "a.rs", "a.rs"]);
+        assert_eq!(
+            path_indexes(&args, OsStr::new("a.rs"), OsStr::new("a.rs")),
+            (Some(1), Some(2))
+        );
+    }
+
+    #[test]
+    fn test_replay_args() {
+        let session = Session {
+            args: os_strings(&["--lhs-label", "a.rs", "a.rs", "b.rs"]),
+            lhs_arg: Some(2),
+            rhs_arg: Some(3),
+            lhs_path: "a.rs".into(),
+            rhs_path: "b.rs".into(),
+            ..Session::default()
+        };
+        assert_eq!(
+            replay_args(
+                &session,
+                Path::new("/tmp/old/a.rs"),
+                Path::new("/tmp/new/b.rs")
+            ),
+            (
+                os_strings(&[
+                    "--rhs-label",
+                    "b.rs",
+                    "--lhs-label",
+                    "a.rs",
+                    "/tmp/old/a.rs",
+                    "/tmp/new/b.rs",
+                ]),
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn test_replay_args_assert_equal() {
+        let session = Session {
+            args: os_strings(&[
+                "assert-equal",
+                "--ignore-token-regex",
+                "[0-9]+",
+                "a.json",
+                "b.json",
+            ]),
+            lhs_arg: Some(3),
+            rhs_arg: Some(4),
+            lhs_path: "a.json".into(),
+            rhs_path: "b.json".into(),
+            ..Session::default()
+        };
+        assert_eq!(
+            replay_args(
+                &session,
+                Path::new("/tmp/old/a.json"),
+                Path::new("/tmp/new/b.json")
+            )
+            .0,
+            os_strings(&[
+                "--lhs-label",
+                "a.json",
+                "--rhs-label",
+                "b.json",
+                "--assert-equal",
+                "--ignore-token-regex",
+                "[0-9]+",
+                "/tmp/old/a.json",
+                "/tmp/new/b.json",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_replay_args_drops_unsafe_options() {
+        let session = Session {
+            args: os_strings(&[
+                "--daemon",
+                "/tmp/sock",
+                "--stage-dir=/tmp/stage",
+                "--display",
+                "stage-script",
+                "--interactive",
+                "review.json",
+                "--tab-width",
+                "2",
+                "--lhs-label=old",
+                "--rhs-label=new",
+                "--",
+                "a.rs",
+                "b.rs",
+            ]),
+            lhs_arg: Some(12),
+            rhs_arg: Some(13),
+            lhs_path: "a.rs".into(),
+            rhs_path: "b.rs".into(),
+            ..Session::default()
+        };
+        assert_eq!(
+            replay_args(
+                &session,
+                Path::new("/tmp/old/a.rs"),
+                Path::new("/tmp/new/b.rs")
+            ),
+            (
+                os_strings(&[
+                    "--tab-width",
+                    "2",
+                    "--lhs-label=old",
+                    "--rhs-label=new",
+                    "--",
+                    "/tmp/old/a.rs",
+                    "/tmp/new/b.rs",
+                ]),
+                vec![
+                    "--daemon".to_owned(),
+                    "--stage-dir".to_owned(),
+                    "--display".to_owned(),
+                    "--interactive".to_owned(),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_replay_vars() {
+        let session = Session {
+            vars: vec![
+                ("DFT_TAB_WIDTH".into(), "4".into()),
+                ("DFT_DISPLAY".into(), "stage-script".into()),
+                ("DFT_DAEMON_SOCKET".into(), "/tmp/sock".into()),
+            ],
+            ..Session::default()
+        };
+        assert_eq!(
+            replay_vars(&session),
+            vec![("DFT_TAB_WIDTH".to_owned(), "4".to_owned())]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_temp_dir_is_private_and_new() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("difft-test-replay-{}", std::process::id()));
+        let tmp_dir = TempDir::create(path.clone()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(TempDir::create(path.clone()).is_err());
+
+        drop(tmp_dir);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,365 @@
+//! Writing each hunk as a separate patch, so users can stage
+//! individual syntactic hunks with `git apply --cached`.
+//!
+//! For every hunk, we write `PATH-N.patch` to the staging directory,
+//! and add a line to `index` describing it:
+//!
+//! ```text
+//! src%2Fmain.rs-1.patch	src/main.rs	-10,7 +10,8
+//! ```
+
+use std::{fs, io, path::Path, sync::Mutex};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    display::{
+        context::all_matched_lines_filled,
+        hunks::{file_hunks, Hunk},
+        side_by_side::split_on_newlines,
+    },
+    lines::LineNumber,
+    summary::{DiffResult, FileContent},
+};
+
+/// The number of unchanged lines to include before and after each
+/// hunk, matching the git default.
+const CONTEXT_LINES: usize = 3;
+
+/// A region of the old file and the new file, as zero-indexed
+/// half-open line ranges.
+#[derive(Debug, PartialEq, Eq)]
+struct Region {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+/// Find the lines on one side that are inside the rows `start..=end`
+/// of `matched_lines`. If there aren't any, return an empty range
+/// after the last line before `start`.
+fn side_range(
+    matched_lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    start: usize,
+    end: usize,
+    side: impl Fn(&(Option<LineNumber>, Option<LineNumber>)) -> Option<LineNumber>,
+) -> (usize, usize) {
+    let inside: Vec<usize> = matched_lines[start..=end]
+        .iter()
+        .filter_map(&side)
+        .map(|line| line.0)
+        .collect();
+
+    match (inside.iter().min(), inside.iter().max()) {
+        (Some(min), Some(max)) => (*min, *max + 1),
+        _ => {
+            let insert_at = matched_lines[..start]
+                .iter()
+                .rev()
+                .find_map(&side)
+                .map_or(0, |line| line.0 + 1);
+            (insert_at, insert_at)
+        }
+    }
+}
+
+fn hunk_region(
+    matched_lines: &[(Option<LineNumber>, Option<LineNumber>)],
+    hunk: &Hunk,
+) -> Option<Region> {
+    let is_novel = |(lhs, rhs): &(Option<LineNumber>, Option<LineNumber>)| {
+        lhs.map_or(false, |l| hunk.novel_lhs.contains(&l))
+            || rhs.map_or(false, |r| hunk.novel_rhs.contains(&r))
+    };
+    let start = matched_lines.iter().position(is_novel)?;
+    let end = matched_lines.iter().rposition(is_novel)?;
+
+    let (old_start, old_end) = side_range(matched_lines, start, end, |(lhs, _)| *lhs);
+    let (new_start, new_end) = side_range(matched_lines, start, end, |(_, rhs)| *rhs);
+    Some(Region {
+        old_start,
+        old_end,
+        new_start,
+        new_end,
+    })
+}
+
+/// Append `line` with `prefix`, marking the final line of a file
+/// that doesn't end with a newline.
+fn push_line(patch: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    patch.push(prefix);
+    patch.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        patch.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/// Format `region` as a unified diff hunk that applies to `old_lines`.
+/// We work on the original bytes, so the patch applies regardless of
+/// the file's encoding.
+fn hunk_patch(region: &Region, old_lines: &[&[u8]], new_lines: &[&[u8]]) -> Vec<u8> {
+    let context_start = region.old_start.saturating_sub(CONTEXT_LINES);
+    let context_end = (region.old_end + CONTEXT_LINES).min(old_lines.len());
+    let num_before = region.old_start - context_start;
+    let num_after = context_end - region.old_end;
+
+    let old_count = num_before + (region.old_end - region.old_start) + num_after;
+    let new_count = num_before + (region.new_end - region.new_start) + num_after;
+    // Unified diffs use the line before the hunk when a side is
+    // empty.
+    let old_first = if old_count == 0 {
+        context_start
+    } else {
+        context_start + 1
+    };
+    let new_first = region.new_start - num_before;
+    let new_first = if new_count == 0 {
+        new_first
+    } else {
+        new_first + 1
+    };
+
+    let mut patch = format!(
+        "@@ -{},{} +{},{} @@\n",
+        old_first, old_count, new_first, new_count
+    )
+    .into_bytes();
+    for line in &old_lines[context_start..region.old_start] {
+        push_line(&mut patch, b' ', line);
+    }
+    for line in &old_lines[region.old_start..region.old_end] {
+        push_line(&mut patch, b'-', line);
+    }
+    for line in &new_lines[region.new_start..region.new_end] {
+        push_line(&mut patch, b'+', line);
+    }
+    for line in &old_lines[region.old_end..context_end] {
+        push_line(&mut patch, b' ', line);
+    }
+
+    patch
+}
+
+/// Split `bytes` into lines, keeping the newlines.
+fn split_lines(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// The patch name column of an index entry.
+fn entry_name(entry: &str) -> &str {
+    entry.split('\t').next().unwrap_or_default()
+}
+
+/// The path column of an index entry.
+fn entry_path(entry: &str) -> Option<&str> {
+    entry.split('\t').nth(1)
+}
+
+/// The file name of the patch for the `n`th hunk of `path`. `/` and
+/// `%` are percent-encoded, so different paths never share a name.
+fn patch_name(path: &str, n: usize) -> String {
+    let escaped = path.replace('%', "%25").replace('/', "%2F");
+    format!("{}-{}.patch", escaped, n)
+}
+
+/// Replace the entries for `path` in `dir/index` with `entries`,
+/// deleting any patches that are no longer listed. Entries for other
+/// paths are kept, because git runs a separate difftastic process for
+/// each file.
+fn update_index(dir: &Path, path: &str, entries: &[String]) -> io::Result<()> {
+    lazy_static! {
+        // Directory diffs stage files in parallel.
+        static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+    }
+    let _guard = INDEX_LOCK.lock().unwrap();
+
+    let index_path = dir.join("index");
+    let old_index = match fs::read_to_string(&index_path) {
+        Ok(old_index) => old_index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let new_names: Vec<&str> = entries.iter().map(|entry| entry_name(entry)).collect();
+    let mut index = String::new();
+    for entry in old_index.lines() {
+        if entry_path(entry) == Some(path) {
+            let name = entry_name(entry);
+            if !new_names.contains(&name) {
+                match fs::remove_file(dir.join(name)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+        } else {
+            index.push_str(entry);
+            index.push('\n');
+        }
+    }
+    for entry in entries {
+        index.push_str(entry);
+        index.push('\n');
+    }
+
+    fs::write(index_path, index)
+}
+
+/// Write a patch for every hunk in `summary` to `dir`, and update the
+/// index. `lhs_bytes` and `rhs_bytes` are the original file contents,
+/// because the diffed source has had tabs expanded and may have been
+/// transcoded.
+pub fn write_patches(
+    dir: &Path,
+    summary: &DiffResult,
+    lhs_bytes: &[u8],
+    rhs_bytes: &[u8],
+) -> io::Result<()> {
+    let (lhs_src, rhs_src) = match (&summary.lhs_src, &summary.rhs_src) {
+        (FileContent::Text(lhs_src), FileContent::Text(rhs_src)) => (lhs_src, rhs_src),
+        _ => return Ok(()),
+    };
+
+    let hunks = file_hunks(
+        lhs_src,
+        rhs_src,
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+    );
+    let matched_lines = all_matched_lines_filled(
+        &summary.lhs_positions,
+        &summary.rhs_positions,
+        &split_on_newlines(lhs_src),
+        &split_on_newlines(rhs_src),
+    );
+
+    let old_lines = split_lines(lhs_bytes);
+    let new_lines = split_lines(rhs_bytes);
+
+    fs::create_dir_all(dir)?;
+
+    let path = &summary.rhs_display_path;
+    let mut entries = vec![];
+    for (i, hunk) in hunks.iter().enumerate() {
+        let region = match hunk_region(&matched_lines, hunk) {
+            Some(region) => region,
+            None => continue,
+        };
+        // Ignore hunks that only touch lines beyond the end of the
+        // raw file, such as a final blank line.
+        if region.old_end > old_lines.len() || region.new_end > new_lines.len() {
+            continue;
+        }
+
+        let name = patch_name(path, i + 1);
+        let mut patch = format!(
+            "diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n",
+            path = path
+        )
+        .into_bytes();
+        patch.extend(hunk_patch(&region, &old_lines, &new_lines));
+        fs::write(dir.join(&name), patch)?;
+
+        entries.push(format!(
+            "{}\t{}\t-{},{} +{},{}",
+            name,
+            path,
+            region.old_start + 1,
+            region.old_end - region.old_start,
+            region.new_start + 1,
+            region.new_end - region.new_start
+        ));
+    }
+
+    update_index(dir, path, &entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_side_range_insertion() {
+        let matched_lines = vec![
+            (Some(0.into()), Some(0.into())),
+            (None, Some(1.into())),
+            (Some(1.into()), Some(2.into())),
+        ];
+        assert_eq!(side_range(&matched_lines, 1, 1, |(lhs, _)| *lhs), (1, 1));
+        assert_eq!(side_range(&matched_lines, 1, 1, |(_, rhs)| *rhs), (1, 2));
+    }
+
+    #[test]
+    fn test_hunk_patch() {
+        let old_lines = split_lines(b"a\nb\nc\n");
+        let new_lines = split_lines(b"a\nB\nc\n");
+        let region = Region {
+            old_start: 1,
+            old_end: 2,
+            new_start: 1,
+            new_end: 2,
+        };
+        assert_eq!(
+            hunk_patch(&region, &old_lines, &new_lines),
+            b"@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
+    #[test]
+    fn test_hunk_patch_no_trailing_newline() {
+        let old_lines = split_lines(b"a");
+        let new_lines = split_lines(b"b");
+        let region = Region {
+            old_start: 0,
+            old_end: 1,
+            new_start: 0,
+            new_end: 1,
+        };
+        assert_eq!(
+            hunk_patch(&region, &old_lines, &new_lines),
+            b"@@ -1,1 +1,1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_patch_name() {
+        assert_eq!(patch_name("src/main.rs", 2), "src%2Fmain.rs-2.patch");
+        // These would both be src_main.rs if / became _.
+        assert_ne!(patch_name("src_main.rs", 1), patch_name("src/main.rs", 1));
+        assert_ne!(patch_name("a%2Fb", 1), patch_name("a/b", 1));
+    }
+
+    #[test]
+    fn test_update_index_replaces_entries() {
+        let dir = std::env::temp_dir().join(format!("difft-test-stage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.rs-1.patch"), "").unwrap();
+        fs::write(dir.join("a.rs-2.patch"), "").unwrap();
+
+        update_index(
+            &dir,
+            "a.rs",
+            &[
+                "a.rs-1.patch\ta.rs\t-1,1 +1,1".into(),
+                "a.rs-2.patch\ta.rs\t-5,1 +5,1".into(),
+            ],
+        )
+        .unwrap();
+        update_index(&dir, "b.rs", &["b.rs-1.patch\tb.rs\t-1,0 +1,2".into()]).unwrap();
+        // Rerunning on a.rs replaces its entries and stale patches.
+        update_index(&dir, "a.rs", &["a.rs-1.patch\ta.rs\t-2,1 +2,1".into()]).unwrap();
+
+        let index = fs::read_to_string(dir.join("index")).unwrap();
+        let a_rs_1_exists = dir.join("a.rs-1.patch").exists();
+        let a_rs_2_exists = dir.join("a.rs-2.patch").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            index,
+            "b.rs-1.patch\tb.rs\t-1,0 +1,2\na.rs-1.patch\ta.rs\t-2,1 +2,1\n"
+        );
+        assert!(a_rs_1_exists);
+        assert!(!a_rs_2_exists);
+    }
+}
@@ -1,6 +1,48 @@
 //! Data types summarising the result of diffing content.
 
-use crate::parse::syntax::MatchedPos;
+use std::fmt;
+
+use crate::{
+    constants::Side,
+    diff::{moves::Move, signatures::SignatureChange},
+    display::indentation::IndentationChanges,
+    lines::LineNumber,
+    offsets::OffsetMap,
+    parse::{
+        syntax::{Definition, MatchedPos},
+        tree_sitter_parser::ParseErrors,
+    },
+    profiles::Profile,
+};
+
+/// Lines that we diffed as text, because the structural diff of
+/// their section ran out of time. A side is `None` if all of its
+/// nodes were matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOutRegion {
+    pub lhs_lines: Option<(LineNumber, LineNumber)>,
+    pub rhs_lines: Option<(LineNumber, LineNumber)>,
+}
+
+impl fmt::Display for TimedOutRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |side: &str, lines: Option<(LineNumber, LineNumber)>| {
+            lines.map(|(start, end)| {
+                format!(
+                    "{} lines {}-{}",
+                    side,
+                    start.one_indexed(),
+                    end.one_indexed()
+                )
+            })
+        };
+        let sides: Vec<String> = describe("old", self.lhs_lines)
+            .into_iter()
+            .chain(describe("new", self.rhs_lines))
+            .collect();
+        write!(f, "{}", sides.join(", "))
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum FileContent {
@@ -8,13 +50,65 @@ pub enum FileContent {
     Binary(Vec<u8>),
 }
 
+/// Whether a whole file was added or deleted, or one side looks
+/// truncated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Added,
+    Deleted,
+    /// This side stops partway through the content of the other side.
+    Truncated(Side),
+}
+
 #[derive(Debug)]
 pub struct DiffResult {
     pub lhs_display_path: String,
     pub rhs_display_path: String,
     pub language: Option<String>,
+    /// Why we diffed the files as text despite having a parser, such
+    /// as `exceeded DFT_NODE_LIMIT`.
+    pub text_fallback: Option<&'static str>,
+    /// The encoding used to decode the files, if it wasn't UTF-8.
+    pub encoding: Option<String>,
     pub lhs_src: FileContent,
     pub rhs_src: FileContent,
     pub lhs_positions: Vec<MatchedPos>,
     pub rhs_positions: Vec<MatchedPos>,
+    /// Syntax errors found when parsing, if we used a tree-sitter
+    /// parser.
+    pub lhs_parse_errors: Option<ParseErrors>,
+    pub rhs_parse_errors: Option<ParseErrors>,
+    /// The first and last lines of top-level definitions that are
+    /// entirely novel, such as functions that were added or removed.
+    pub lhs_novel_ranges: Vec<(LineNumber, LineNumber)>,
+    pub rhs_novel_ranges: Vec<(LineNumber, LineNumber)>,
+    /// Lines that we diffed as text after exceeding `--time-limit`.
+    pub timed_out_regions: Vec<TimedOutRegion>,
+    /// The top-level definitions in each file, such as functions.
+    pub lhs_definitions: Vec<Definition>,
+    pub rhs_definitions: Vec<Definition>,
+    /// Syntax nodes that were moved or copied from the LHS to the
+    /// RHS.
+    pub moves: Vec<Move>,
+    /// Definitions where only the signature, or only the body,
+    /// changed.
+    pub signature_changes: Vec<SignatureChange>,
+    /// Lines where only the indentation changed, when using
+    /// `--show-indentation`.
+    pub lhs_indentation: IndentationChanges,
+    pub rhs_indentation: IndentationChanges,
+    /// Whether the RHS is a new file that was copied from the LHS
+    /// file.
+    pub copied: bool,
+    /// Set when one side was `/dev/null`, which git uses for added
+    /// and deleted files, or when one side was truncated.
+    pub status: Option<FileStatus>,
+    /// The default options for this kind of file.
+    pub profile: Profile,
+    /// Why the token requested with `--explain` was matched.
+    pub explanations: Vec<String>,
+    /// Maps from byte offsets in `lhs_src` and `rhs_src` to offsets in
+    /// the files, which may have tabs or another encoding.
+    pub lhs_offsets: OffsetMap,
+    pub rhs_offsets: OffsetMap,
 }
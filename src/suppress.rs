@@ -0,0 +1,157 @@
+//! Regions of a file that the user has asked us not to diff.
+//!
+//! A line containing `difft:ignore-start` begins a region, and a line
+//! containing `difft:ignore-end` ends it. This is useful for
+//! generated code embedded in a file that is otherwise written by
+//! hand.
+//!
+//! Ignored regions are blanked out before diffing, so they don't
+//! count towards the time or node limits.
+
+use crate::{lines::LineNumber, parse::syntax::MatchedPos};
+
+const IGNORE_START: &str = "difft:ignore-start";
+const IGNORE_END: &str = "difft:ignore-end";
+
+/// Find the regions of `src` enclosed by ignore markers, including
+/// the marker lines themselves. A start marker without a matching end
+/// marker ignores the rest of the file.
+pub fn ignored_lines(src: &str) -> Vec<(LineNumber, LineNumber)> {
+    let mut ranges = vec![];
+    let mut region_start = None;
+    let mut last_line = None;
+
+    for (i, line) in src.lines().enumerate() {
+        let line_num: LineNumber = i.into();
+        last_line = Some(line_num);
+
+        match region_start {
+            None => {
+                if line.contains(IGNORE_START) {
+                    region_start = Some(line_num);
+                }
+            }
+            Some(start) => {
+                if line.contains(IGNORE_END) {
+                    ranges.push((start, line_num));
+                    region_start = None;
+                }
+            }
+        }
+    }
+
+    if let (Some(start), Some(end)) = (region_start, last_line) {
+        ranges.push((start, end));
+    }
+
+    ranges
+}
+
+/// Replace the text on lines in `ranges` with spaces. The result has
+/// the same length and line structure as `src`, so positions in it
+/// are also positions in `src`.
+pub fn mask_ignored(src: String, ranges: &[(LineNumber, LineNumber)]) -> String {
+    if ranges.is_empty() {
+        return src;
+    }
+
+    let mut res = String::with_capacity(src.len());
+    for (i, line) in src.split_inclusive('\n').enumerate() {
+        let line_num: LineNumber = i.into();
+        if ranges
+            .iter()
+            .any(|(start, end)| *start <= line_num && line_num <= *end)
+        {
+            for c in line.chars() {
+                if c == '\n' {
+                    res.push(c);
+                } else {
+                    res.push_str(&" ".repeat(c.len_utf8()));
+                }
+            }
+        } else {
+            res.push_str(line);
+        }
+    }
+    res
+}
+
+/// Discard any changes in `positions` that occur inside `ranges`, so
+/// they don't produce hunks.
+pub fn remove_ignored(
+    positions: Vec<MatchedPos>,
+    ranges: &[(LineNumber, LineNumber)],
+) -> Vec<MatchedPos> {
+    if ranges.is_empty() {
+        return positions;
+    }
+
+    positions
+        .into_iter()
+        .filter(|mp| {
+            !mp.kind.is_novel()
+                || !ranges
+                    .iter()
+                    .any(|(start, end)| *start <= mp.pos.line && mp.pos.line <= *end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        positions::SingleLineSpan,
+        syntax::{AtomKind, MatchKind, TokenKind},
+    };
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_ignored_lines() {
+        let src = "a\n// difft:ignore-start\nb\n// difft:ignore-end\nc\n";
+        assert_eq!(ignored_lines(src), vec![(1.into(), 3.into())]);
+    }
+
+    #[test]
+    fn test_ignored_lines_unterminated() {
+        let src = "a\n# difft:ignore-start\nb\nc";
+        assert_eq!(ignored_lines(src), vec![(1.into(), 3.into())]);
+    }
+
+    #[test]
+    fn test_ignored_lines_none() {
+        assert_eq!(ignored_lines("a\nb\n"), vec![]);
+    }
+
+    #[test]
+    fn test_mask_ignored() {
+        let src = "a\n// difft:ignore-start\né\n// difft:ignore-end\nc\n".to_owned();
+        let ranges = ignored_lines(&src);
+        let masked = mask_ignored(src.clone(), &ranges);
+
+        assert_eq!(masked.len(), src.len());
+        assert_eq!(
+            masked,
+            format!("a\n{}\n  \n{}\nc\n", " ".repeat(21), " ".repeat(19))
+        );
+    }
+
+    #[test]
+    fn test_remove_ignored() {
+        let novel_on_line = |line: usize| MatchedPos {
+            kind: MatchKind::Novel {
+                highlight: TokenKind::Atom(AtomKind::Normal),
+            },
+            pos: SingleLineSpan {
+                line: line.into(),
+                start_col: 0,
+                end_col: 1,
+            },
+        };
+
+        let positions = vec![novel_on_line(0), novel_on_line(2), novel_on_line(4)];
+        let res = remove_ignored(positions, &[(1.into(), 3.into())]);
+
+        assert_eq!(res, vec![novel_on_line(0), novel_on_line(4)]);
+    }
+}
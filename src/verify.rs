@@ -0,0 +1,316 @@
+//! Checking pairs of files against expected outcomes, for `difft
+//! verify`.
+//!
+//! The manifest is a TOML file with a `[[check]]` table per pair:
+//!
+//! ```toml
+//! [[check]]
+//! lhs = "expected/config.json"
+//! rhs = "build/config.json"
+//! expect = "equal" # or "differs", "differs-only-in-comments"
+//! ```
+//!
+//! We only support the subset of TOML needed for this. Each line is
+//! one of:
+//!
+//! * blank, or a comment starting with `#`
+//! * a `[[check]]` header, starting a new check
+//! * `KEY = VALUE`, where KEY is `lhs`, `rhs` or `expect`
+//!
+//! VALUE is a basic string (`"..."`, with the escapes `\\`, `\"`,
+//! `\n` and `\t`) or a literal string (`'...'`, without escapes).
+//! Headers and values may be followed by a `#` comment. Relative
+//! paths are relative to the directory containing the manifest.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assert_equal::is_equal,
+    display::hunks::is_novel_comment,
+    files::{display_path, read_or_die},
+    summary::{DiffResult, FileContent},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// No syntactic changes. Formatting changes are allowed.
+    Equal,
+    /// Every changed token is in a comment.
+    DiffersOnlyInComments,
+    Differs,
+}
+
+const OUTCOME_NAMES: &[&str] = &["equal", "differs", "differs-only-in-comments"];
+
+impl Outcome {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "equal" => Some(Outcome::Equal),
+            "differs" => Some(Outcome::Differs),
+            "differs-only-in-comments" => Some(Outcome::DiffersOnlyInComments),
+            _ => None,
+        }
+    }
+
+    pub fn of(diff_result: &DiffResult) -> Self {
+        if is_equal(diff_result, &[]) {
+            return Outcome::Equal;
+        }
+
+        let only_comments = diff_result.status.is_none()
+            && matches!(
+                (&diff_result.lhs_src, &diff_result.rhs_src),
+                (FileContent::Text(_), FileContent::Text(_))
+            )
+            && diff_result
+                .lhs_positions
+                .iter()
+                .chain(&diff_result.rhs_positions)
+                .filter(|mp| mp.kind.is_novel())
+                .all(|mp| is_novel_comment(&mp.kind));
+        if only_comments {
+            Outcome::DiffersOnlyInComments
+        } else {
+            Outcome::Differs
+        }
+    }
+
+    /// Does an actual outcome of `self` satisfy `expected`? Comment
+    /// changes are still differences.
+    pub fn satisfies(self, expected: Outcome) -> bool {
+        self == expected || (self == Outcome::DiffersOnlyInComments && expected == Outcome::Differs)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Outcome::Equal => "equal",
+            Outcome::DiffersOnlyInComments => "differs-only-in-comments",
+            Outcome::Differs => "differs",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub lhs_path: PathBuf,
+    pub rhs_path: PathBuf,
+    pub expected: Outcome,
+}
+
+/// Is `rest` empty or a comment, so nothing else follows a header
+/// or value?
+pub fn is_line_end(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Parse a TOML string value, either `"basic"` with escapes or
+/// `'literal'`, which may be followed by a comment.
+pub fn parse_string(value: &str) -> Result<String, String> {
+    let mut chars = value.chars();
+    let quote = match chars.next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return Err(format!("expected a quoted string, got {}", value)),
+    };
+
+    let mut res = String::with_capacity(value.len());
+    loop {
+        match chars.next() {
+            None => return Err(format!("unterminated string {}", value)),
+            Some(c) if c == quote => break,
+            Some('\\') if quote == '"' => match chars.next() {
+                Some('\\') => res.push('\\'),
+                Some('"') => res.push('"'),
+                Some('n') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                other => {
+                    return Err(format!(
+                        "unsupported escape \\{}",
+                        other.map(String::from).unwrap_or_default()
+                    ))
+                }
+            },
+            Some(c) => res.push(c),
+        }
+    }
+
+    if !is_line_end(chars.as_str()) {
+        return Err(format!(
+            "unexpected text after string: {}",
+            chars.as_str().trim()
+        ));
+    }
+    Ok(res)
+}
+
+/// A `[[check]]` table that we're still reading.
+#[derive(Default)]
+struct PartialCheck {
+    line_num: usize,
+    lhs_path: Option<PathBuf>,
+    rhs_path: Option<PathBuf>,
+    expected: Option<Outcome>,
+}
+
+impl PartialCheck {
+    fn finish(self) -> Result<Check, String> {
+        let line_num = self.line_num;
+        let missing = |key: &str| format!("line {}: [[check]] has no {}", line_num, key);
+        Ok(Check {
+            lhs_path: self.lhs_path.ok_or_else(|| missing("lhs"))?,
+            rhs_path: self.rhs_path.ok_or_else(|| missing("rhs"))?,
+            expected: self.expected.ok_or_else(|| missing("expect"))?,
+        })
+    }
+}
+
+/// Parse the checks in a manifest. Relative paths are joined to
+/// `base_dir`.
+fn parse_manifest(src: &str, base_dir: &Path) -> Result<Vec<Check>, String> {
+    let mut checks = vec![];
+    let mut current: Option<PartialCheck> = None;
+
+    for (i, line) in src.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.strip_prefix("[[check]]").map_or(false, is_line_end) {
+            if let Some(check) = current.take() {
+                checks.push(check.finish()?);
+            }
+            current = Some(PartialCheck {
+                line_num,
+                ..PartialCheck::default()
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected [[check]] or KEY = VALUE", line_num))?;
+        let check = current
+            .as_mut()
+            .ok_or_else(|| format!("line {}: {} is outside a [[check]]", line_num, key.trim()))?;
+        let value = parse_string(value.trim()).map_err(|e| format!("line {}: {}", line_num, e))?;
+
+        match key.trim() {
+            "lhs" => check.lhs_path = Some(base_dir.join(value)),
+            "rhs" => check.rhs_path = Some(base_dir.join(value)),
+            "expect" => {
+                check.expected = Some(Outcome::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "line {}: unknown expectation '{}', expected one of: {}",
+                        line_num,
+                        value,
+                        OUTCOME_NAMES.join(", ")
+                    )
+                })?)
+            }
+            key => return Err(format!("line {}: unknown key '{}'", line_num, key)),
+        }
+    }
+
+    if let Some(check) = current {
+        checks.push(check.finish()?);
+    }
+    Ok(checks)
+}
+
+/// Read the checks in the manifest at `path`, exiting if it isn't
+/// valid or lists a file that doesn't exist.
+pub fn read_manifest_or_die(path: &Path) -> Vec<Check> {
+    let src = String::from_utf8_lossy(&read_or_die(path)).into_owned();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let checks = match parse_manifest(&src, base_dir) {
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("error: {}: {}", display_path(path), e);
+            std::process::exit(2);
+        }
+    };
+
+    for check in &checks {
+        for path in [&check.lhs_path, &check.rhs_path] {
+            if !path.is_file() {
+                eprintln!("error: {}: no such file", display_path(path));
+                std::process::exit(2);
+            }
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_manifest() {
+        let src = r#"
+# Generated configs.
+[[check]]
+lhs = "expected/a.json"
+rhs = 'out/a.json'
+expect = "differs-only-in-comments"
+"#;
+        assert_eq!(
+            parse_manifest(src, Path::new("tests")),
+            Ok(vec![Check {
+                lhs_path: PathBuf::from("tests/expected/a.json"),
+                rhs_path: PathBuf::from("tests/out/a.json"),
+                expected: Outcome::DiffersOnlyInComments,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_manifest_missing_key() {
+        let src = "[[check]]\nlhs = \"a\"\nexpect = \"equal\"\n";
+        assert_eq!(
+            parse_manifest(src, Path::new("")),
+            Err("line 1: [[check]] has no rhs".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_manifest_inline_comments() {
+        let src = r#"
+[[check]] # Formatting only.
+lhs = "a # b.json" # The hash is part of the path.
+rhs = 'c.json'  # Literal string.
+expect = "equal"
+"#;
+        assert_eq!(
+            parse_manifest(src, Path::new("")),
+            Ok(vec![Check {
+                lhs_path: PathBuf::from("a # b.json"),
+                rhs_path: PathBuf::from("c.json"),
+                expected: Outcome::Equal,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_string_trailing_text() {
+        assert!(parse_string(r#""a" "b""#).is_err());
+        assert_eq!(parse_string(r#""a\"b""#), Ok("a\"b".to_owned()));
+    }
+
+    #[test]
+    fn test_satisfies() {
+        assert!(Outcome::DiffersOnlyInComments.satisfies(Outcome::Differs));
+        assert!(!Outcome::Differs.satisfies(Outcome::DiffersOnlyInComments));
+        assert!(!Outcome::Equal.satisfies(Outcome::Differs));
+    }
+}
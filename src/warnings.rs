@@ -0,0 +1,41 @@
+//! Warnings about how difftastic handled its input, such as falling
+//! back to a text diff.
+//!
+//! Warnings always go to stderr with a `difft:` prefix, so they don't
+//! break tools that parse the diff itself.
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Set whether warnings are printed. This is `--no-warnings`.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn format_warning(message: impl Display) -> String {
+    format!("difft: warning: {}", message)
+}
+
+/// Print `message` to stderr, unless warnings are disabled.
+pub fn warn(message: impl Display) {
+    if ENABLED.load(Ordering::Relaxed) {
+        eprintln!("{}", format_warning(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_warning() {
+        assert_eq!(
+            format_warning("foo.rs: exceeded DFT_NODE_LIMIT"),
+            "difft: warning: foo.rs: exceeded DFT_NODE_LIMIT"
+        );
+    }
+}
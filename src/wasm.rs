@@ -0,0 +1,103 @@
+//! Entry points for running difftastic in a browser, when compiled
+//! for `wasm32-unknown-unknown`.
+//!
+//! These are plain `extern "C"` functions, so no bindings generator
+//! is required. `wasm/difftastic.js` wraps them in a JavaScript API.
+//!
+//! Strings are passed as a pointer and a length in the module's
+//! memory. The caller allocates inputs with `difft_alloc`, and frees
+//! inputs and outputs with `difft_free`.
+//!
+//! Only the parse, diff and HTML/JSON rendering steps are available
+//! here. Terminal-specific code, such as the pager and the daemon, is
+//! compiled out with `cfg` rather than abstracted behind traits, so
+//! there is no terminal display in the browser.
+//!
+//! Build the module from the library target. Only this build needs a
+//! `cdylib`, so it's requested here rather than in Cargo.toml:
+//!
+//! ```text
+//! cargo rustc --release --lib --target wasm32-unknown-unknown --crate-type cdylib
+//! ```
+
+use std::slice;
+
+use crate::{
+    buffer::Buffer,
+    diff_file_content, display,
+    options::{DiffOptions, DEFAULT_TAB_WIDTH},
+};
+
+/// The `format` for `difft_diff` to return JSON. Any other value
+/// returns HTML.
+const FORMAT_JSON: u32 = 1;
+
+fn into_raw(bytes: Vec<u8>) -> *mut u8 {
+    Box::into_raw(bytes.into_boxed_slice()) as *mut u8
+}
+
+/// Allocate `len` zeroed bytes.
+#[no_mangle]
+pub extern "C" fn difft_alloc(len: usize) -> *mut u8 {
+    into_raw(vec![0; len])
+}
+
+/// Free `len` bytes at `ptr`, which must have been returned by
+/// `difft_alloc` or `difft_diff`.
+///
+/// # Safety
+///
+/// `ptr` must not be used after it is freed.
+#[no_mangle]
+pub unsafe extern "C" fn difft_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(slice::from_raw_parts_mut(ptr, len)));
+}
+
+/// Diff the LHS and RHS bytes, and return the result as HTML or JSON
+/// depending on `format`. The length of the result is written to
+/// `out_len`, which needn't be aligned.
+///
+/// `path` is only used for language detection and headers.
+///
+/// # Safety
+///
+/// Every pointer must point to the given number of bytes, allocated
+/// with `difft_alloc`. `out_len` must point to
+/// `size_of::<usize>()` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn difft_diff(
+    path_ptr: *const u8,
+    path_len: usize,
+    lhs_ptr: *const u8,
+    lhs_len: usize,
+    rhs_ptr: *const u8,
+    rhs_len: usize,
+    format: u32,
+    out_len: *mut usize,
+) -> *mut u8 {
+    let path = String::from_utf8_lossy(slice::from_raw_parts(path_ptr, path_len));
+    let lhs = Buffer {
+        display_path: &path,
+        path: None,
+        language_hint: None,
+        content: slice::from_raw_parts(lhs_ptr, lhs_len),
+    };
+    let rhs = Buffer {
+        display_path: &path,
+        path: None,
+        language_hint: None,
+        content: slice::from_raw_parts(rhs_ptr, rhs_len),
+    };
+
+    let summary = diff_file_content(&lhs, &rhs, DEFAULT_TAB_WIDTH, &DiffOptions::default());
+
+    let output = if format == FORMAT_JSON {
+        display::json::render(&summary, true, false, None)
+    } else {
+        display::html::render(&summary, true, true)
+    };
+    let output = output.unwrap_or_default().into_bytes();
+
+    out_len.write_unaligned(output.len());
+    into_raw(output)
+}
@@ -0,0 +1,73 @@
+// A JavaScript API for difftastic compiled to WebAssembly.
+//
+//     import { load } from "./difftastic.js";
+//
+//     const difft = await load("difftastic.wasm");
+//     const html = difft.diffHtml("main.rs", oldSrc, newSrc);
+//     const json = JSON.parse(difft.diffJson("main.rs", oldSrc, newSrc));
+//
+// See src/wasm.rs for the functions exported by the module.
+
+const FORMAT_HTML = 0;
+const FORMAT_JSON = 1;
+
+class Difftastic {
+  constructor(exports) {
+    this.exports = exports;
+  }
+
+  // Copy `s` into the module's memory, returning a pointer and a
+  // length. The caller must free the result with `difft_free`.
+  writeString(s) {
+    const bytes = new TextEncoder().encode(s);
+    const ptr = this.exports.difft_alloc(bytes.length);
+    new Uint8Array(this.exports.memory.buffer, ptr, bytes.length).set(bytes);
+    return [ptr, bytes.length];
+  }
+
+  diff(path, lhs, rhs, format) {
+    const inputs = [path, lhs, rhs].map((s) => this.writeString(s));
+    // `usize` is 4 bytes on wasm32. difft_alloc only guarantees
+    // byte alignment, so read it with a DataView.
+    const outLenPtr = this.exports.difft_alloc(4);
+
+    const args = inputs.flat();
+    const outPtr = this.exports.difft_diff(...args, format, outLenPtr);
+
+    // Memory may have grown during the diff, so create new views
+    // after the call.
+    const outLen = new DataView(this.exports.memory.buffer).getUint32(
+      outLenPtr,
+      true
+    );
+    const output = new TextDecoder().decode(
+      new Uint8Array(this.exports.memory.buffer, outPtr, outLen)
+    );
+
+    for (const [ptr, len] of inputs) {
+      this.exports.difft_free(ptr, len);
+    }
+    this.exports.difft_free(outLenPtr, 4);
+    this.exports.difft_free(outPtr, outLen);
+
+    return output;
+  }
+
+  // Diff `lhs` against `rhs`, returning an HTML fragment. `path` is
+  // used to detect the language.
+  diffHtml(path, lhs, rhs) {
+    return this.diff(path, lhs, rhs, FORMAT_HTML);
+  }
+
+  // Diff `lhs` against `rhs`, returning a JSON string in the same
+  // format as `difft --display json`.
+  diffJson(path, lhs, rhs) {
+    return this.diff(path, lhs, rhs, FORMAT_JSON);
+  }
+}
+
+// Fetch and instantiate the difftastic WebAssembly module at `url`.
+export async function load(url) {
+  const { instance } = await WebAssembly.instantiateStreaming(fetch(url), {});
+  return new Difftastic(instance.exports);
+}